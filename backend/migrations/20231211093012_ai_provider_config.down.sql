@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS ai_usage;
+ALTER TABLE workspace_settings DROP COLUMN IF EXISTS ai_config;
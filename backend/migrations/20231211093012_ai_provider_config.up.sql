@@ -0,0 +1,14 @@
+-- Add up migration script here
+ALTER TABLE workspace_settings ADD COLUMN IF NOT EXISTS ai_config JSONB;
+
+CREATE TABLE IF NOT EXISTS ai_usage (
+    workspace_id VARCHAR(50) NOT NULL REFERENCES workspace(id),
+    username VARCHAR(255) NOT NULL,
+    provider VARCHAR(50) NOT NULL,
+    model VARCHAR(255) NOT NULL DEFAULT '',
+    day DATE NOT NULL DEFAULT CURRENT_DATE,
+    prompt_tokens BIGINT NOT NULL DEFAULT 0,
+    completion_tokens BIGINT NOT NULL DEFAULT 0,
+    requests BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (workspace_id, username, provider, model, day)
+);
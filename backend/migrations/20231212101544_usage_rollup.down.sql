@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE workspace_settings DROP COLUMN IF EXISTS usage_quotas;
+DROP TABLE IF EXISTS job_usage_rollup;
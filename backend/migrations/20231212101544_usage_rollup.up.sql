@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS job_usage_rollup (
+    workspace_id VARCHAR(50) NOT NULL REFERENCES workspace(id),
+    day DATE NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    runnable_path VARCHAR(255) NOT NULL DEFAULT '',
+    job_count BIGINT NOT NULL DEFAULT 0,
+    failed_count BIGINT NOT NULL DEFAULT 0,
+    execution_ms BIGINT NOT NULL DEFAULT 0,
+    mem_peak INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (workspace_id, day, username, runnable_path)
+);
+
+ALTER TABLE workspace_settings ADD COLUMN IF NOT EXISTS usage_quotas JSONB;
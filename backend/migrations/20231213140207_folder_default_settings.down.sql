@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE folder DROP COLUMN IF EXISTS default_settings;
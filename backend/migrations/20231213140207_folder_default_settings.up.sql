@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE folder ADD COLUMN IF NOT EXISTS default_settings JSONB;
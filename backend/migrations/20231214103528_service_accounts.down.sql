@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE schedule DROP COLUMN IF EXISTS service_account;
+ALTER TABLE flow DROP COLUMN IF EXISTS service_account;
+DROP TABLE IF EXISTS service_account;
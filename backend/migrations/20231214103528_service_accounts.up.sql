@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS service_account (
+    workspace_id VARCHAR(50) NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (workspace_id, name),
+    FOREIGN KEY (workspace_id, name) REFERENCES usr(workspace_id, username) ON DELETE CASCADE
+);
+
+ALTER TABLE flow ADD COLUMN IF NOT EXISTS service_account VARCHAR(50);
+ALTER TABLE schedule ADD COLUMN IF NOT EXISTS service_account VARCHAR(50);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE completed_job DROP COLUMN IF EXISTS cache_hit;
+ALTER TABLE script DROP COLUMN IF EXISTS cache_key_args;
+DROP TABLE IF EXISTS job_result_cache;
@@ -0,0 +1,30 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS job_result_cache (
+    workspace_id VARCHAR(50) NOT NULL,
+    cache_key TEXT NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    result JSONB NOT NULL,
+    job_id UUID,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expire TIMESTAMP WITH TIME ZONE NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0,
+    last_hit_at TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (workspace_id, cache_key)
+);
+
+CREATE INDEX IF NOT EXISTS job_result_cache_path_idx ON job_result_cache (workspace_id, path);
+CREATE INDEX IF NOT EXISTS job_result_cache_expire_idx ON job_result_cache (expire);
+
+-- cached results used to be stored as resources of type cache
+INSERT INTO job_result_cache (workspace_id, cache_key, path, result, expire)
+SELECT workspace_id, path, regexp_replace(path, '/cache/.*$', ''), value->'value',
+    to_timestamp((value->>'expire')::bigint)
+FROM resource
+WHERE resource_type = 'cache' AND value ? 'value' AND value ? 'expire'
+    AND to_timestamp((value->>'expire')::bigint) > now()
+ON CONFLICT DO NOTHING;
+
+DELETE FROM resource WHERE resource_type = 'cache';
+
+ALTER TABLE script ADD COLUMN IF NOT EXISTS cache_key_args VARCHAR(255)[];
+ALTER TABLE completed_job ADD COLUMN IF NOT EXISTS cache_hit BOOLEAN NOT NULL DEFAULT false;
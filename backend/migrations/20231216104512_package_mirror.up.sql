@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS package_mirror (
+    kind VARCHAR(10) NOT NULL,
+    path VARCHAR(1000) NOT NULL,
+    name VARCHAR(500) NOT NULL,
+    version VARCHAR(255) NOT NULL,
+    sha256 VARCHAR(64) NOT NULL,
+    size BIGINT NOT NULL,
+    content BYTEA NOT NULL,
+    metadata JSONB,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (kind, path)
+);
+
+CREATE INDEX IF NOT EXISTS package_mirror_name_idx ON package_mirror (kind, name);
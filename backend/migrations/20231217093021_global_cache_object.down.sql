@@ -0,0 +1,3 @@
+-- Add down migration script here
+SELECT lo_unlink(oid) FROM global_cache_object;
+DROP TABLE IF EXISTS global_cache_object;
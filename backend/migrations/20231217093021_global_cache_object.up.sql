@@ -0,0 +1,7 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS global_cache_object (
+    key VARCHAR(1000) PRIMARY KEY,
+    oid OID NOT NULL,
+    size BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
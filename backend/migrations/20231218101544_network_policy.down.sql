@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS network_policy;
+DROP TYPE IF EXISTS NETWORK_MODE;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TYPE NETWORK_MODE AS ENUM ('none', 'allowlist', 'full');
+
+CREATE TABLE IF NOT EXISTS network_policy (
+    workspace_id VARCHAR(50) NOT NULL REFERENCES workspace(id),
+    path VARCHAR(255) NOT NULL,
+    mode NETWORK_MODE NOT NULL,
+    allowlist TEXT[] NOT NULL DEFAULT '{}',
+    edited_by VARCHAR(255) NOT NULL,
+    edited_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (workspace_id, path)
+);
@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS job_artifact (
+    workspace_id VARCHAR(50) NOT NULL,
+    job_id UUID NOT NULL,
+    name VARCHAR(1000) NOT NULL,
+    size BIGINT NOT NULL,
+    s3_key VARCHAR(1200),
+    content BYTEA,
+    permissioned_as VARCHAR(55) NOT NULL,
+    email VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (job_id, name),
+    CHECK ((s3_key IS NULL) <> (content IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS job_artifact_created_at_idx ON job_artifact (created_at);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE variable DROP CONSTRAINT variable_secret_provider_check;
+ALTER TABLE variable DROP COLUMN secret_provider;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE variable ADD COLUMN secret_provider VARCHAR(50);
+ALTER TABLE variable ADD CONSTRAINT variable_secret_provider_check
+    CHECK (secret_provider IS NULL OR is_secret);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE workspace_key DROP COLUMN rotated_at;
+ALTER TABLE workspace_key DROP COLUMN previous_key;
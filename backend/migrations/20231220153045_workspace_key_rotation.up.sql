@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE workspace_key ADD COLUMN previous_key VARCHAR(255);
+ALTER TABLE workspace_key ADD COLUMN rotated_at TIMESTAMPTZ;
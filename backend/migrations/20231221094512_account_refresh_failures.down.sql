@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE account DROP COLUMN refresh_broken_notified;
+ALTER TABLE account DROP COLUMN refresh_failed_at;
+ALTER TABLE account DROP COLUMN refresh_failures;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE account ADD COLUMN refresh_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE account ADD COLUMN refresh_failed_at TIMESTAMPTZ;
+ALTER TABLE account ADD COLUMN refresh_broken_notified BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE password ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX audit_timestamp_idx;
+DROP INDEX audit_id_idx;
+DROP TABLE audit_export_cursor;
+-- the values added to ACTION_KIND cannot be removed from the type
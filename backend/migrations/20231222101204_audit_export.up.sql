@@ -0,0 +1,15 @@
+-- Add up migration script here
+ALTER TYPE ACTION_KIND ADD VALUE 'login';
+ALTER TYPE ACTION_KIND ADD VALUE 'token_creation';
+ALTER TYPE ACTION_KIND ADD VALUE 'permission_change';
+ALTER TYPE ACTION_KIND ADD VALUE 'secret_read';
+
+CREATE TABLE audit_export_cursor (
+    sink VARCHAR(255) PRIMARY KEY,
+    last_id INTEGER NOT NULL DEFAULT 0,
+    exported_at TIMESTAMPTZ
+);
+
+-- for the export cursor and the retention period
+CREATE INDEX audit_id_idx ON audit (id);
+CREATE INDEX audit_timestamp_idx ON audit (timestamp);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX audit_secret_read_idx;
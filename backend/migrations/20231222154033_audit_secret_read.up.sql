@@ -0,0 +1,3 @@
+-- Add up migration script here
+CREATE INDEX audit_secret_read_idx ON audit (workspace_id, resource, timestamp)
+    WHERE operation = 'variables.decrypt_secret';
@@ -0,0 +1,22 @@
+-- Add up migration script here
+CREATE TYPE ACCESS_REQUEST_STATUS AS ENUM ('pending', 'approved', 'denied', 'revoked', 'expired');
+
+CREATE TABLE access_request (
+    id BIGSERIAL PRIMARY KEY,
+    workspace_id VARCHAR(50) NOT NULL REFERENCES workspace(id),
+    requester VARCHAR(50) NOT NULL,
+    kind VARCHAR(50) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    write BOOLEAN NOT NULL DEFAULT false,
+    hours INTEGER NOT NULL,
+    reason TEXT,
+    status ACCESS_REQUEST_STATUS NOT NULL DEFAULT 'pending',
+    previous_perm JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    decided_by VARCHAR(50),
+    decided_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX access_request_workspace_idx ON access_request (workspace_id, created_at DESC);
+CREATE INDEX access_request_expires_at_idx ON access_request (expires_at) WHERE status = 'approved';
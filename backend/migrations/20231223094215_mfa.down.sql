@@ -0,0 +1,11 @@
+-- Add down migration script here
+ALTER TABLE token DROP COLUMN mfa_verified_at;
+
+DROP TABLE mfa_recovery_code;
+
+ALTER TABLE password DROP COLUMN mfa_failed_at;
+ALTER TABLE password DROP COLUMN mfa_failures;
+ALTER TABLE password DROP COLUMN mfa_last_step;
+ALTER TABLE password DROP COLUMN mfa_pending_secret;
+ALTER TABLE password DROP COLUMN mfa_secret;
+ALTER TABLE password DROP COLUMN mfa_enabled;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+ALTER TABLE password ADD COLUMN mfa_enabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE password ADD COLUMN mfa_secret VARCHAR(64);
+ALTER TABLE password ADD COLUMN mfa_pending_secret VARCHAR(64);
+ALTER TABLE password ADD COLUMN mfa_last_step BIGINT;
+ALTER TABLE password ADD COLUMN mfa_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE password ADD COLUMN mfa_failed_at TIMESTAMPTZ;
+
+CREATE TABLE mfa_recovery_code (
+    email VARCHAR(255) NOT NULL REFERENCES password(email) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    PRIMARY KEY (email, code_hash)
+);
+
+ALTER TABLE token ADD COLUMN mfa_verified_at TIMESTAMPTZ;
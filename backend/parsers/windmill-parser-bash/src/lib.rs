@@ -32,6 +32,214 @@ lazy_static::lazy_static! {
 
     static ref RE_POWERSHELL_PARAM: Regex = Regex::new(r#"(?m)param[\t ]*\(([^)]*)\)"#).unwrap();
     static ref RE_POWERSHELL_ARGS: Regex = Regex::new(r#"(?:\[(\w+)\])?\$(\w+)[\t ]*(?:=[\t ]*(?:(?:(?:"|')([^"\n\r\$]*)(?:"|'))|([\d.]+)))?"#).unwrap();
+
+    static ref RE_POWERSHELL_IMPORT: Regex = Regex::new(r#"(?mi)^[\t ]*Import-Module[\t ]+(?:-Name[\t ]+)?['"]?([A-Za-z][\w.\-]*)['"]?(?:[\t ]+(.*))?$"#).unwrap();
+    static ref RE_POWERSHELL_REQUIRED_VERSION: Regex = Regex::new(r#"(?i)-RequiredVersion[\t ]+['"]?([\w.\-]+)"#).unwrap();
+    static ref RE_POWERSHELL_REQUIRES: Regex = Regex::new(r#"(?mi)^#Requires[\t ]+-Modules[\t ]+(.+)$"#).unwrap();
+    static ref RE_POWERSHELL_MODULE_SPEC: Regex = Regex::new(r#"@\{([^}]*)\}"#).unwrap();
+    static ref RE_POWERSHELL_MODULE_SPEC_KEY: Regex = Regex::new(r#"(?i)(ModuleName|RequiredVersion|ModuleVersion)[\t ]*=[\t ]*['"]?([\w.\-]+)['"]?"#).unwrap();
+
+    static ref RE_BASH_REQUIREMENT: Regex = Regex::new(r#"^\#\s?([A-Za-z_][\w.+\-]*)(?:==([\w.+\-]+))?\s*$"#).unwrap();
+
+    static ref RE_OUTPUT_HEADER: Regex = Regex::new(r#"^\#\s?output:\s*(\S+)\s*$"#).unwrap();
+    static ref RE_OUTPUT_FIELD: Regex = Regex::new(r#"^\#\s?([A-Za-z_][\w\-]*)(\?)?:\s*(\S+)\s*$"#).unwrap();
+}
+
+/// A module imported by a powershell script with `Import-Module` or `#Requires -Modules`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PowershellModule {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+fn is_gallery_module(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    !lower.starts_with("microsoft.powershell.")
+        && !lower.ends_with(".psm1")
+        && !lower.ends_with(".psd1")
+        && !lower.ends_with(".dll")
+}
+
+pub fn parse_powershell_imports(code: &str) -> anyhow::Result<Vec<PowershellModule>> {
+    let mut modules: Vec<PowershellModule> = vec![];
+    for cap in RE_POWERSHELL_IMPORT.captures_iter(code) {
+        let version = cap
+            .get(2)
+            .and_then(|rest| RE_POWERSHELL_REQUIRED_VERSION.captures(rest.as_str()))
+            .map(|x| x[1].to_string());
+        modules.push(PowershellModule { name: cap[1].to_string(), version });
+    }
+    for cap in RE_POWERSHELL_REQUIRES.captures_iter(code) {
+        let specs = &cap[1];
+        for spec in RE_POWERSHELL_MODULE_SPEC.captures_iter(specs) {
+            let mut name = None;
+            let mut version = None;
+            for kv in RE_POWERSHELL_MODULE_SPEC_KEY.captures_iter(&spec[1]) {
+                if kv[1].eq_ignore_ascii_case("ModuleName") {
+                    name = Some(kv[2].to_string());
+                } else if version.is_none() || kv[1].eq_ignore_ascii_case("RequiredVersion") {
+                    version = Some(kv[2].to_string());
+                }
+            }
+            let name = name.ok_or_else(|| anyhow!("Module specification without ModuleName"))?;
+            modules.push(PowershellModule { name, version });
+        }
+        for name in RE_POWERSHELL_MODULE_SPEC
+            .replace_all(specs, "")
+            .split(',')
+            .map(|x| x.trim().trim_matches(|c| c == '\'' || c == '"'))
+            .filter(|x| !x.is_empty())
+        {
+            modules.push(PowershellModule { name: name.to_string(), version: None });
+        }
+    }
+    modules.retain(|m| is_gallery_module(&m.name));
+    modules.sort();
+    modules.dedup();
+    Ok(modules)
+}
+
+/// A tool listed in the `# requirements:` header of a bash script, one `#<tool>[==<version>]` per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BashRequirement {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+pub fn parse_bash_requirements(code: &str) -> Vec<BashRequirement> {
+    let header = code
+        .lines()
+        .position(|x| x.starts_with("#requirements:") || x.starts_with("# requirements:"));
+    if let Some(pos) = header {
+        code.lines()
+            .skip(pos + 1)
+            .map_while(|x| {
+                RE_BASH_REQUIREMENT.captures(x).map(|cap| BashRequirement {
+                    name: cap[1].to_string(),
+                    version: cap.get(2).map(|x| x.as_str().to_string()),
+                })
+            })
+            .collect()
+    } else {
+        vec![]
+    }
+}
+
+/// How the stdout of a bash or powershell script is turned into its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// the whole stdout is a single JSON value
+    Json,
+    /// a header line followed by one line per row, each row becoming an object
+    Csv,
+    /// one `key=value` pair per line, together forming an object
+    KeyValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Object,
+    Array,
+}
+
+impl OutputType {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "string" => Some(OutputType::String),
+            "integer" | "int" => Some(OutputType::Integer),
+            "number" | "float" => Some(OutputType::Number),
+            "boolean" | "bool" => Some(OutputType::Boolean),
+            "object" => Some(OutputType::Object),
+            "array" => Some(OutputType::Array),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputType::String => "string",
+            OutputType::Integer => "integer",
+            OutputType::Number => "number",
+            OutputType::Boolean => "boolean",
+            OutputType::Object => "object",
+            OutputType::Array => "array",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputField {
+    pub name: String,
+    pub typ: OutputType,
+    pub required: bool,
+}
+
+/// Output schema declared in the `# output: <json|csv|kv>` header of a script, followed by one
+/// `#<field>[?]: <type>` line per field of the result, `?` marking the optional ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSchema {
+    pub format: OutputFormat,
+    pub fields: Vec<OutputField>,
+}
+
+impl OutputSchema {
+    /// JSON schema of the results of the script, of its rows for the csv format
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        if self.format == OutputFormat::Json && self.fields.is_empty() {
+            return json!({});
+        }
+        let properties = self
+            .fields
+            .iter()
+            .map(|f| (f.name.clone(), json!({ "type": f.typ.as_str() })))
+            .collect::<serde_json::Map<String, serde_json::Value>>();
+        let required = self
+            .fields
+            .iter()
+            .filter(|f| f.required)
+            .map(|f| f.name.clone())
+            .collect::<Vec<_>>();
+        let object = json!({ "type": "object", "properties": properties, "required": required });
+        if self.format == OutputFormat::Csv {
+            json!({ "type": "array", "items": object })
+        } else {
+            object
+        }
+    }
+}
+
+pub fn parse_output_schema(code: &str) -> anyhow::Result<Option<OutputSchema>> {
+    let mut lines = code.lines().skip_while(|x| !RE_OUTPUT_HEADER.is_match(x));
+    let format = match lines.next().and_then(|x| RE_OUTPUT_HEADER.captures(x)) {
+        Some(cap) => match &cap[1] {
+            "json" => OutputFormat::Json,
+            "csv" => OutputFormat::Csv,
+            "kv" => OutputFormat::KeyValue,
+            format => {
+                return Err(anyhow!(
+                    "Unknown output format `{format}`, expected json, csv or kv"
+                ))
+            }
+        },
+        None => return Ok(None),
+    };
+    let mut fields = vec![];
+    for cap in lines.map_while(|x| RE_OUTPUT_FIELD.captures(x)) {
+        let typ = OutputType::parse(&cap[3]).ok_or_else(|| {
+            anyhow!(
+                "Unknown type `{}` of output field `{}`, expected string, integer, number, \
+                 boolean, object or array",
+                &cap[3],
+                &cap[1]
+            )
+        })?;
+        fields.push(OutputField { name: cap[1].to_string(), typ, required: cap.get(2).is_none() });
+    }
+    Ok(Some(OutputSchema { format, fields }))
 }
 
 fn parse_bash_file(code: &str) -> anyhow::Result<Option<Vec<Arg>>> {
@@ -165,4 +373,96 @@ non_required="${5:-}"
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_bash_requirements() -> anyhow::Result<()> {
+        let code = r#"
+# requirements:
+#jq==1.7.1
+# yq-go
+#
+curl -s "$1" | jq .
+"#;
+        assert_eq!(
+            parse_bash_requirements(code),
+            vec![
+                BashRequirement { name: "jq".to_string(), version: Some("1.7.1".to_string()) },
+                BashRequirement { name: "yq-go".to_string(), version: None },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_output_schema() -> anyhow::Result<()> {
+        let code = r#"
+# output: csv
+#name: string
+# age: integer
+#email?: string
+
+name="$1"
+"#;
+        let schema = parse_output_schema(code)?.unwrap();
+        assert_eq!(schema.format, OutputFormat::Csv);
+        assert_eq!(
+            schema.fields,
+            vec![
+                OutputField { name: "name".to_string(), typ: OutputType::String, required: true },
+                OutputField { name: "age".to_string(), typ: OutputType::Integer, required: true },
+                OutputField { name: "email".to_string(), typ: OutputType::String, required: false },
+            ]
+        );
+        assert_eq!(
+            schema.to_json_schema(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "age": { "type": "integer" },
+                        "email": { "type": "string" }
+                    },
+                    "required": ["name", "age"]
+                }
+            })
+        );
+
+        assert_eq!(parse_output_schema("name=\"$1\"")?, None);
+        assert!(parse_output_schema("# output: yaml").is_err());
+        assert!(parse_output_schema("# output: kv\n#count: uint").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_powershell_imports() -> anyhow::Result<()> {
+        let code = r#"
+#Requires -Modules PSReadLine, @{ ModuleName = 'Az.Accounts'; ModuleVersion = '2.13.2' }
+Import-Module -Name ImportExcel -RequiredVersion 7.8.6
+Import-Module 'Pester'
+Import-Module Microsoft.PowerShell.Utility
+Import-Module ./helpers.psm1
+param($Msg)
+"#;
+        assert_eq!(
+            parse_powershell_imports(code)?,
+            vec![
+                PowershellModule {
+                    name: "Az.Accounts".to_string(),
+                    version: Some("2.13.2".to_string())
+                },
+                PowershellModule {
+                    name: "ImportExcel".to_string(),
+                    version: Some("7.8.6".to_string())
+                },
+                PowershellModule { name: "PSReadLine".to_string(), version: None },
+                PowershellModule { name: "Pester".to_string(), version: None },
+            ]
+        );
+
+        Ok(())
+    }
 }
@@ -22,11 +22,12 @@ use tokio::{
 use windmill_api::HTTP_CLIENT;
 use windmill_common::{
     global_settings::{
-        BASE_URL_SETTING, CUSTOM_TAGS_SETTING, DISABLE_STATS_SETTING, ENV_SETTINGS,
-        EXPOSE_DEBUG_METRICS_SETTING, EXPOSE_METRICS_SETTING, EXTRA_PIP_INDEX_URL_SETTING,
-        KEEP_JOB_DIR_SETTING, LICENSE_KEY_SETTING, NPM_CONFIG_REGISTRY_SETTING, OAUTH_SETTING,
-        REQUEST_SIZE_LIMIT_SETTING, REQUIRE_PREEXISTING_USER_FOR_OAUTH_SETTING,
-        RETENTION_PERIOD_SECS_SETTING,
+        AUDIT_RETENTION_SECS_SETTING, BASE_URL_SETTING, CUSTOM_TAGS_SETTING, DISABLE_STATS_SETTING,
+        ENV_SETTINGS, EXPOSE_DEBUG_METRICS_SETTING, EXPOSE_METRICS_SETTING,
+        EXTRA_PIP_INDEX_URL_SETTING, KEEP_JOB_DIR_SETTING, LICENSE_KEY_SETTING,
+        MIRROR_CAPTURE_SETTING, NPM_CONFIG_REGISTRY_SETTING, OAUTH_SETTING, OFFLINE_MODE_SETTING,
+        REQUEST_SIZE_LIMIT_SETTING, REQUIRE_MFA_SETTING,
+        REQUIRE_PREEXISTING_USER_FOR_OAUTH_SETTING, RETENTION_PERIOD_SECS_SETTING,
     },
     stats::schedule_stats,
     utils::{rd_string, Mode},
@@ -34,14 +35,17 @@ use windmill_common::{
     DB, METRICS_ADDR, METRICS_ENABLED,
 };
 use windmill_worker::{
-    BUN_CACHE_DIR, BUN_TMP_CACHE_DIR, DENO_CACHE_DIR, DENO_CACHE_DIR_DEPS, DENO_CACHE_DIR_NPM,
-    DENO_TMP_CACHE_DIR, DENO_TMP_CACHE_DIR_DEPS, DENO_TMP_CACHE_DIR_NPM, GO_BIN_CACHE_DIR,
-    GO_CACHE_DIR, GO_TMP_CACHE_DIR, HUB_CACHE_DIR, HUB_TMP_CACHE_DIR, LOCK_CACHE_DIR,
-    PIP_CACHE_DIR, ROOT_TMP_CACHE_DIR, TAR_PIP_TMP_CACHE_DIR,
+    BASH_CACHE_DIR, BUN_CACHE_DIR, BUN_TMP_CACHE_DIR, DENO_CACHE_DIR, DENO_CACHE_DIR_DEPS,
+    DENO_CACHE_DIR_NPM, DENO_TMP_CACHE_DIR, DENO_TMP_CACHE_DIR_DEPS, DENO_TMP_CACHE_DIR_NPM,
+    GO_BIN_CACHE_DIR, GO_CACHE_DIR, GO_TMP_CACHE_DIR, HUB_CACHE_DIR, HUB_TMP_CACHE_DIR,
+    LOCK_CACHE_DIR, PIP_CACHE_DIR, POWERSHELL_CACHE_DIR, POWERSHELL_TMP_CACHE_DIR,
+    ROOT_TMP_CACHE_DIR, TAR_PIP_BLOBS_TMP_CACHE_DIR, TAR_PIP_INDEX_TMP_CACHE_DIR,
+    TAR_PIP_TMP_CACHE_DIR,
 };
 
 use crate::monitor::{
-    initial_load, load_keep_job_dir, load_require_preexisting_user, monitor_db, monitor_pool,
+    initial_load, load_keep_job_dir, load_mirror_capture, load_offline_mode, load_require_mfa,
+    load_require_preexisting_user, monitor_db, monitor_pool, reload_audit_retention_setting,
     reload_base_url_setting, reload_extra_pip_index_url_setting, reload_license_key,
     reload_npm_config_registry_setting, reload_retention_period_setting, reload_server_config,
     reload_worker_config,
@@ -410,6 +414,9 @@ Windmill Community Edition {GIT_VERSION}
                                                 RETENTION_PERIOD_SECS_SETTING => {
                                                     reload_retention_period_setting(&db).await
                                                 },
+                                                AUDIT_RETENTION_SECS_SETTING => {
+                                                    reload_audit_retention_setting(&db).await
+                                                },
                                                 EXTRA_PIP_INDEX_URL_SETTING => {
                                                     reload_extra_pip_index_url_setting(&db).await
                                                 },
@@ -419,9 +426,18 @@ Windmill Community Edition {GIT_VERSION}
                                                 KEEP_JOB_DIR_SETTING => {
                                                     load_keep_job_dir(&db).await;
                                                 },
+                                                OFFLINE_MODE_SETTING => {
+                                                    load_offline_mode(&db).await;
+                                                },
+                                                MIRROR_CAPTURE_SETTING => {
+                                                    load_mirror_capture(&db).await;
+                                                },
                                                 REQUIRE_PREEXISTING_USER_FOR_OAUTH_SETTING => {
                                                     load_require_preexisting_user(&db).await;
                                                 }
+                                                REQUIRE_MFA_SETTING => {
+                                                    load_require_mfa(&db).await;
+                                                }
                                                 EXPOSE_METRICS_SETTING | EXPOSE_DEBUG_METRICS_SETTING => {
                                                     if n.payload() != EXPOSE_DEBUG_METRICS_SETTING || worker_mode {
                                                         tracing::info!("Metrics setting changed, restarting");
@@ -598,16 +614,21 @@ pub async fn run_workers<R: rsmq_async::RsmqConnection + Send + Sync + Clone + '
         DENO_CACHE_DIR_DEPS,
         DENO_CACHE_DIR_NPM,
         BUN_CACHE_DIR,
+        BASH_CACHE_DIR,
         GO_CACHE_DIR,
         GO_BIN_CACHE_DIR,
         HUB_CACHE_DIR,
+        POWERSHELL_CACHE_DIR,
         TAR_PIP_TMP_CACHE_DIR,
+        TAR_PIP_INDEX_TMP_CACHE_DIR,
+        TAR_PIP_BLOBS_TMP_CACHE_DIR,
         DENO_TMP_CACHE_DIR,
         DENO_TMP_CACHE_DIR_DEPS,
         DENO_TMP_CACHE_DIR_NPM,
         BUN_TMP_CACHE_DIR,
         GO_TMP_CACHE_DIR,
         HUB_TMP_CACHE_DIR,
+        POWERSHELL_TMP_CACHE_DIR,
     ] {
         DirBuilder::new()
             .recursive(true)
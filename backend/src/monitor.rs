@@ -3,7 +3,10 @@ use std::{
     fmt::Display,
     ops::Mul,
     str::FromStr,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -15,27 +18,34 @@ use tokio::{
 };
 use uuid::Uuid;
 use windmill_api::{
-    oauth2::{build_oauth_clients, OAuthClient},
+    access_requests::expire_access_requests,
+    mfa::{RequireMfa, REQUIRE_MFA},
+    oauth2::{_refresh_token, build_oauth_clients, OAuthClient},
     DEFAULT_BODY_LIMIT, IS_SECURE, OAUTH_CLIENTS, REQUEST_SIZE_LIMIT,
 };
+use windmill_audit::export::{delete_expired_audit_logs, export_audit_logs};
 use windmill_common::{
     error,
     global_settings::{
-        BASE_URL_SETTING, EXPOSE_DEBUG_METRICS_SETTING, EXPOSE_METRICS_SETTING,
-        EXTRA_PIP_INDEX_URL_SETTING, KEEP_JOB_DIR_SETTING, LICENSE_KEY_SETTING,
-        NPM_CONFIG_REGISTRY_SETTING, OAUTH_SETTING, REQUEST_SIZE_LIMIT_SETTING,
+        AUDIT_RETENTION_SECS_SETTING, BASE_URL_SETTING, EXPOSE_DEBUG_METRICS_SETTING,
+        EXPOSE_METRICS_SETTING, EXTRA_PIP_INDEX_URL_SETTING, KEEP_JOB_DIR_SETTING,
+        LICENSE_KEY_SETTING, MIRROR_CAPTURE_SETTING, NPM_CONFIG_REGISTRY_SETTING, OAUTH_SETTING,
+        OFFLINE_MODE_SETTING, REQUEST_SIZE_LIMIT_SETTING, REQUIRE_MFA_SETTING,
         REQUIRE_PREEXISTING_USER_FOR_OAUTH_SETTING, RETENTION_PERIOD_SECS_SETTING,
     },
     jobs::{JobKind, QueuedJob},
     oauth2::REQUIRE_PREEXISTING_USER_FOR_OAUTH,
     server::load_server_config,
     users::truncate_token,
-    worker::{load_worker_config, reload_custom_tags_setting, SERVER_CONFIG, WORKER_CONFIG},
+    worker::{
+        load_worker_config, reload_custom_tags_setting, to_raw_value, SERVER_CONFIG, WORKER_CONFIG,
+    },
     BASE_URL, DB, METRICS_DEBUG_ENABLED, METRICS_ENABLED,
 };
+use windmill_queue::send_event_error_to_workspace_handler;
 use windmill_worker::{
-    create_token_for_owner, handle_job_error, AuthedClient, KEEP_JOB_DIR, NPM_CONFIG_REGISTRY,
-    PIP_EXTRA_INDEX_URL, SCRIPT_TOKEN_EXPIRY,
+    create_token_for_owner, handle_job_error, AuthedClient, KEEP_JOB_DIR, MIRROR_CAPTURE,
+    NPM_CONFIG_REGISTRY, OFFLINE_MODE, PIP_EXTRA_INDEX_URL, SCRIPT_TOKEN_EXPIRY,
 };
 
 #[cfg(feature = "enterprise")]
@@ -76,6 +86,20 @@ lazy_static::lazy_static! {
     ).unwrap();
 
     static ref JOB_RETENTION_SECS: Arc<RwLock<i64>> = Arc::new(RwLock::new(0));
+    static ref AUDIT_RETENTION_SECS: Arc<RwLock<i64>> = Arc::new(RwLock::new(0));
+    // exports run in their own task, which keeps running across monitor runs when the sink is slow
+    static ref AUDIT_EXPORT_RUNNING: AtomicBool = AtomicBool::new(false);
+
+    static ref OAUTH_REFRESH_BEFORE_EXPIRY_SECS: i64 =
+        std::env::var("OAUTH_REFRESH_BEFORE_EXPIRY_SECS")
+            .ok()
+            .and_then(|x| x.parse::<i64>().ok())
+            .unwrap_or(600);
+    // consecutive refresh failures after which an oauth connection is considered broken
+    static ref OAUTH_REFRESH_MAX_FAILURES: i32 = std::env::var("OAUTH_REFRESH_MAX_FAILURES")
+        .ok()
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(5);
 
 }
 
@@ -95,12 +119,18 @@ pub async fn initial_load(
 
     if server_mode {
         load_require_preexisting_user(db).await;
+        load_require_mfa(db).await;
     }
 
     if worker_mode {
         load_keep_job_dir(db).await;
     }
 
+    if worker_mode {
+        load_offline_mode(db).await;
+        load_mirror_capture(db).await;
+    }
+
     if worker_mode {
         reload_worker_config(&db, tx, false).await;
     }
@@ -121,6 +151,7 @@ pub async fn initial_load(
 
     if server_mode {
         reload_retention_period_setting(&db).await;
+        reload_audit_retention_setting(&db).await;
     }
     if server_mode {
         reload_request_size(&db).await;
@@ -183,6 +214,40 @@ pub async fn load_keep_job_dir(db: &DB) {
     };
 }
 
+pub async fn load_offline_mode(db: &DB) {
+    let value = sqlx::query_scalar!(
+        "SELECT value FROM global_settings WHERE name = $1",
+        OFFLINE_MODE_SETTING
+    )
+    .fetch_optional(db)
+    .await;
+    match value {
+        Ok(Some(serde_json::Value::Bool(t))) => OFFLINE_MODE.store(t, Ordering::Relaxed),
+        Ok(None) => OFFLINE_MODE.store(false, Ordering::Relaxed),
+        Err(e) => {
+            tracing::error!("Error loading offline mode: {e}");
+        }
+        _ => (),
+    };
+}
+
+pub async fn load_mirror_capture(db: &DB) {
+    let value = sqlx::query_scalar!(
+        "SELECT value FROM global_settings WHERE name = $1",
+        MIRROR_CAPTURE_SETTING
+    )
+    .fetch_optional(db)
+    .await;
+    match value {
+        Ok(Some(serde_json::Value::Bool(t))) => MIRROR_CAPTURE.store(t, Ordering::Relaxed),
+        Ok(None) => MIRROR_CAPTURE.store(false, Ordering::Relaxed),
+        Err(e) => {
+            tracing::error!("Error loading mirror capture: {e}");
+        }
+        _ => (),
+    };
+}
+
 pub async fn load_require_preexisting_user(db: &DB) {
     let value = sqlx::query_scalar!(
         "SELECT value FROM global_settings WHERE name = $1",
@@ -201,6 +266,27 @@ pub async fn load_require_preexisting_user(db: &DB) {
     };
 }
 
+pub async fn load_require_mfa(db: &DB) {
+    let value = sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT value FROM global_settings WHERE name = $1",
+    )
+    .bind(REQUIRE_MFA_SETTING)
+    .fetch_optional(db)
+    .await;
+    match value {
+        Ok(Some(serde_json::Value::String(s))) => match RequireMfa::parse(&s) {
+            Some(require_mfa) => *REQUIRE_MFA.write().await = require_mfa,
+            None => tracing::error!(
+                "Invalid require mfa setting {s}, expected none, super_admins or all"
+            ),
+        },
+        Err(e) => {
+            tracing::error!("Error loading require mfa setting: {e}");
+        }
+        _ => (),
+    };
+}
+
 pub async fn delete_expired_items(db: &DB) -> () {
     let tokens_deleted_r: std::result::Result<Vec<String>, _> = sqlx::query_scalar(
         "DELETE FROM token WHERE expiration <= now()
@@ -233,19 +319,19 @@ pub async fn delete_expired_items(db: &DB) -> () {
         Err(e) => tracing::error!("Error deleting pip_resolution: {}", e.to_string()),
     }
 
-    let deleted_cache = sqlx::query_scalar!(
-            "DELETE FROM resource WHERE resource_type = 'cache' AND to_timestamp((value->>'expire')::int) < now() RETURNING path",
-        )
-        .fetch_all(db)
-        .await;
+    let deleted_cache = sqlx::query_scalar::<_, String>(
+        "DELETE FROM job_result_cache WHERE expire <= now() RETURNING cache_key",
+    )
+    .fetch_all(db)
+    .await;
 
     match deleted_cache {
         Ok(res) => {
             if res.len() > 0 {
-                tracing::info!("deleted {} cache resource: {:?}", res.len(), res)
+                tracing::info!("deleted {} cached results: {:?}", res.len(), res)
             }
         }
-        Err(e) => tracing::error!("Error deleting cache resource {}", e.to_string()),
+        Err(e) => tracing::error!("Error deleting cached results {}", e.to_string()),
     }
 
     let job_retention_secs = *JOB_RETENTION_SECS.read().await;
@@ -271,6 +357,46 @@ pub async fn delete_expired_items(db: &DB) -> () {
             Err(e) => tracing::error!("Error deleting jobs: {}", e.to_string()),
         }
     }
+
+    let deleted_folder_jobs: std::result::Result<Vec<Uuid>, _> = sqlx::query_scalar(
+        "DELETE FROM completed_job USING folder
+        WHERE folder.default_settings->>'retention_secs' IS NOT NULL
+        AND completed_job.workspace_id = folder.workspace_id
+        AND completed_job.script_path LIKE 'f/%'
+        AND split_part(completed_job.script_path, '/', 2) = folder.name
+        AND completed_job.created_at <= now() - ((folder.default_settings->>'retention_secs')::bigint::text || ' s')::interval
+        RETURNING completed_job.id",
+    )
+    .fetch_all(db)
+    .await;
+
+    match deleted_folder_jobs {
+        Ok(deleted_jobs) => {
+            if deleted_jobs.len() > 0 {
+                tracing::info!(
+                    "deleted {} jobs completed past their folder retention period: {:?}",
+                    deleted_jobs.len(),
+                    deleted_jobs,
+                )
+            }
+        }
+        Err(e) => tracing::error!(
+            "Error deleting jobs past folder retention: {}",
+            e.to_string()
+        ),
+    }
+
+    let audit_retention_secs = *AUDIT_RETENTION_SECS.read().await;
+    match delete_expired_audit_logs(db, audit_retention_secs).await {
+        Ok(deleted) => {
+            if deleted > 0 {
+                tracing::info!(
+                    "deleted {deleted} audit logs written AUDIT_RETENTION_SECS {audit_retention_secs} ago"
+                )
+            }
+        }
+        Err(e) => tracing::error!("Error deleting audit logs: {}", e.to_string()),
+    }
 }
 
 pub async fn reload_extra_pip_index_url_setting(db: &DB) {
@@ -314,6 +440,21 @@ pub async fn reload_retention_period_setting(db: &DB) {
     }
 }
 
+pub async fn reload_audit_retention_setting(db: &DB) {
+    if let Err(e) = reload_setting(
+        db,
+        AUDIT_RETENTION_SECS_SETTING,
+        "AUDIT_RETENTION_SECS",
+        0,
+        AUDIT_RETENTION_SECS.clone(),
+        |x| x,
+    )
+    .await
+    {
+        tracing::error!("Error reloading audit retention period: {:?}", e)
+    }
+}
+
 pub async fn reload_request_size(db: &DB) {
     if let Err(e) = reload_setting(
         db,
@@ -483,6 +624,8 @@ pub async fn monitor_db<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
     let expired_items_f = async {
         if server_mode {
             delete_expired_items(&db).await;
+            #[cfg(feature = "s3")]
+            windmill_worker::job_artifacts::delete_expired_artifacts(&db, base_internal_url).await;
         }
     };
 
@@ -501,6 +644,40 @@ pub async fn monitor_db<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
         }
     };
 
+    let export_audit_logs_f = async {
+        if server_mode && !AUDIT_EXPORT_RUNNING.swap(true, Ordering::SeqCst) {
+            let db = db.clone();
+            tokio::spawn(async move {
+                match export_audit_logs(&db).await {
+                    Ok(exported) if exported > 0 => {
+                        tracing::info!("exported {exported} audit logs")
+                    }
+                    Ok(_) => (),
+                    Err(e) => tracing::error!("Error exporting audit logs: {e}"),
+                }
+                AUDIT_EXPORT_RUNNING.store(false, Ordering::SeqCst);
+            });
+        }
+    };
+
+    let expire_access_requests_f = async {
+        if server_mode {
+            match expire_access_requests(db).await {
+                Ok(expired) if expired > 0 => {
+                    tracing::info!("revoked {expired} expired access requests")
+                }
+                Ok(_) => (),
+                Err(e) => tracing::error!("Error revoking expired access requests: {e}"),
+            }
+        }
+    };
+
+    let refresh_oauth_tokens_f = async {
+        if server_mode {
+            refresh_expiring_oauth_tokens(db, rsmq.clone()).await;
+        }
+    };
+
     let expose_queue_metrics_f = async {
         if METRICS_ENABLED.load(std::sync::atomic::Ordering::Relaxed) && server_mode {
             expose_queue_metrics(&db).await;
@@ -510,10 +687,119 @@ pub async fn monitor_db<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
         expired_items_f,
         zombie_jobs_f,
         expose_queue_metrics_f,
-        verify_license_key_f
+        verify_license_key_f,
+        refresh_oauth_tokens_f,
+        export_audit_logs_f,
+        expire_access_requests_f
     );
 }
 
+/// Refreshes the oauth tokens ahead of their expiry, so that the jobs of idle connections do not
+/// have to, and notifies the workspace error handler of the connections that keep failing to
+/// refresh. Failed refreshes are retried with a linear backoff until the connection is broken.
+async fn refresh_expiring_oauth_tokens<R: rsmq_async::RsmqConnection + Send + Sync + Clone>(
+    db: &DB,
+    rsmq: Option<R>,
+) {
+    let accounts = sqlx::query_as::<_, (String, i32, String)>(
+        "SELECT account.workspace_id, account.id, variable.path FROM account
+        JOIN variable ON variable.workspace_id = account.workspace_id
+            AND variable.account = account.id AND variable.is_oauth
+        WHERE account.refresh_token != ''
+        AND account.expires_at <= now() + ($1::bigint::text || ' s')::interval
+        AND account.refresh_failures < $2
+        AND (account.refresh_failed_at IS NULL
+            OR account.refresh_failed_at <= now() - make_interval(mins => account.refresh_failures))",
+    )
+    .bind(*OAUTH_REFRESH_BEFORE_EXPIRY_SECS)
+    .bind(*OAUTH_REFRESH_MAX_FAILURES)
+    .fetch_all(db)
+    .await;
+
+    let accounts = match accounts {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            tracing::error!("Error fetching the oauth tokens to refresh: {e}");
+            return;
+        }
+    };
+
+    for (w_id, id, path) in accounts {
+        if let Err(e) = refresh_oauth_token(db, &w_id, id, &path).await {
+            tracing::warn!("Error refreshing the token of account {id} in {w_id}: {e}");
+        }
+    }
+
+    if let Err(e) = notify_broken_oauth_accounts(db, rsmq).await {
+        tracing::error!("Error notifying the broken oauth connections: {e}");
+    }
+}
+
+async fn refresh_oauth_token(db: &DB, w_id: &str, id: i32, path: &str) -> error::Result<()> {
+    let mut tx = db.begin().await?;
+    // the account may be refreshed by another server or by a job in the meantime
+    let still_expiring = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM account WHERE workspace_id = $1 AND id = $2
+        AND expires_at <= now() + ($3::bigint::text || ' s')::interval
+        FOR UPDATE SKIP LOCKED",
+    )
+    .bind(w_id)
+    .bind(id)
+    .bind(*OAUTH_REFRESH_BEFORE_EXPIRY_SECS)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if still_expiring.is_none() {
+        return Ok(());
+    }
+    _refresh_token(tx, path, w_id, id).await?;
+    tracing::info!("refreshed the token of account {id} in {w_id} ahead of its expiry");
+    Ok(())
+}
+
+async fn notify_broken_oauth_accounts<R: rsmq_async::RsmqConnection + Send + Sync + Clone>(
+    db: &DB,
+    rsmq: Option<R>,
+) -> error::Result<()> {
+    // marked as notified first, so that a connection is notified once even with several servers
+    let broken = sqlx::query_as::<_, (String, i32, String, i32, Option<String>)>(
+        "UPDATE account SET refresh_broken_notified = true
+        WHERE refresh_failures >= $1 AND NOT refresh_broken_notified
+        RETURNING workspace_id, id, client, refresh_failures, refresh_error",
+    )
+    .bind(*OAUTH_REFRESH_MAX_FAILURES)
+    .fetch_all(db)
+    .await?;
+
+    for (w_id, id, client, failures, refresh_error) in broken {
+        tracing::warn!(
+            "oauth connection {client} of account {id} in {w_id} is broken after {failures} failed refreshes"
+        );
+        let paths = sqlx::query_scalar::<_, String>(
+            "SELECT path FROM variable WHERE workspace_id = $1 AND account = $2",
+        )
+        .bind(&w_id)
+        .bind(id)
+        .fetch_all(db)
+        .await?;
+        let extra = HashMap::from([
+            ("account".to_string(), to_raw_value(&id)),
+            ("client".to_string(), to_raw_value(&client)),
+            ("path".to_string(), to_raw_value(&paths.first())),
+            ("variable_paths".to_string(), to_raw_value(&paths)),
+        ]);
+        let error = serde_json::json!({
+            "name": "OAuthRefreshError",
+            "message": format!(
+                "the {client} connection could not be refreshed {failures} times in a row and \
+                 must be reconnected: {}",
+                refresh_error.unwrap_or_default()
+            ),
+        });
+        send_event_error_to_workspace_handler(rsmq.clone(), db, &w_id, extra, error).await?;
+    }
+    Ok(())
+}
+
 pub async fn expose_queue_metrics(db: &Pool<Postgres>) {
     let queue_counts = sqlx::query!(
         "SELECT tag, count(*) as count FROM queue WHERE
@@ -641,11 +927,14 @@ pub async fn reload_base_url_setting(db: &DB) -> error::Result<()> {
 
     let is_secure = base_url.starts_with("https://");
 
-    {
-        let mut l = OAUTH_CLIENTS.write().await;
-        *l = build_oauth_clients(&base_url, oauths)
+    // the oidc providers are discovered before locking the clients
+    let oauth_clients = build_oauth_clients(&base_url, oauths)
+        .await
         .map_err(|e| tracing::error!("Error building oauth clients (is the oauth.json mounted and in correct format? Use '{}' as minimal oauth.json): {}", "{}", e))
         .unwrap();
+    {
+        let mut l = OAUTH_CLIENTS.write().await;
+        *l = oauth_clients;
     }
 
     {
@@ -26,7 +26,7 @@ use sqlx::query;
 use windmill_api_client::types::{EditSchedule, NewSchedule, ScriptArgs};
 
 
-use windmill_common::worker::{WORKER_CONFIG, PriorityTags};
+use windmill_common::worker::{WORKER_CONFIG, PriorityTags, WorkspacedPath};
 use windmill_common::{
     flow_status::{FlowStatus, FlowStatusModule, RestartedFrom},
     flows::{FlowModule, FlowModuleValue, FlowValue, InputTransform},
@@ -930,7 +930,17 @@ async fn in_test_worker<Fut: std::future::Future>(
     inner: Fut,
     port: u16,
 ) -> <Fut as std::future::Future>::Output {
-    let (quit, worker) = spawn_test_worker(db, port);
+    in_test_worker_with(db, inner, port, None).await
+}
+
+/// Same as `in_test_worker` but the worker is dedicated to the given script when set.
+async fn in_test_worker_with<Fut: std::future::Future>(
+    db: &Pool<Postgres>,
+    inner: Fut,
+    port: u16,
+    dedicated_worker: Option<WorkspacedPath>,
+) -> <Fut as std::future::Future>::Output {
+    let (quit, worker) = spawn_test_worker(db, port, dedicated_worker);
     let worker = tokio::time::timeout(std::time::Duration::from_secs(45), worker);
     tokio::pin!(worker);
 
@@ -954,9 +964,16 @@ async fn in_test_worker<Fut: std::future::Future>(
     res
 }
 
+lazy_static::lazy_static! {
+    /// WORKER_CONFIG is shared by all the tests of this binary, a dedicated test worker needs
+    /// it for itself while the other test workers only read the default config
+    static ref WORKER_CONFIG_LOCK: RwLock<()> = RwLock::new(());
+}
+
 fn spawn_test_worker(
     db: &Pool<Postgres>,
     port: u16,
+    dedicated_worker: Option<WorkspacedPath>,
 ) -> (
     tokio::sync::broadcast::Sender<()>,
     tokio::task::JoinHandle<()>,
@@ -977,10 +994,19 @@ fn spawn_test_worker(
     let tx2 = tx.clone();
     let future = async move {
         let base_internal_url = format!("http://localhost:{}", port);
+        let (_shared, _exclusive) = if dedicated_worker.is_some() {
+            (None, Some(WORKER_CONFIG_LOCK.write().await))
+        } else {
+            (Some(WORKER_CONFIG_LOCK.read().await), None)
+        };
         {
         let mut wc = WORKER_CONFIG.write().await;
-        (*wc).worker_tags = windmill_common::worker::DEFAULT_TAGS.clone();
-        (*wc).priority_tags_sorted = vec![PriorityTags { priority: 0, tags: (*wc).worker_tags.clone()} ]
+        (*wc).worker_tags = match dedicated_worker.as_ref() {
+            Some(wp) => vec![format!("{}:{}", wp.workspace_id, wp.path)],
+            None => windmill_common::worker::DEFAULT_TAGS.clone(),
+        };
+        (*wc).priority_tags_sorted = vec![PriorityTags { priority: 0, tags: (*wc).worker_tags.clone()} ];
+        (*wc).dedicated_worker = dedicated_worker;
         }
         windmill_worker::run_worker::<rsmq_async::MultiplexedRsmq>(
             &db,
@@ -1081,6 +1107,7 @@ async fn test_deno_flow(db: Pool<Postgres>) {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -1117,6 +1144,7 @@ async fn test_deno_flow(db: Pool<Postgres>) {
                             retry: None,
                             sleep: None,
                             cache_ttl: None,
+                            cache_key_args: None,
                             mock: None,
                             timeout: None,
                             priority: None,
@@ -1129,6 +1157,7 @@ async fn test_deno_flow(db: Pool<Postgres>) {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -1230,6 +1259,7 @@ async fn test_deno_flow_same_worker(db: Pool<Postgres>) {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -1277,6 +1307,7 @@ async fn test_deno_flow_same_worker(db: Pool<Postgres>) {
                                 retry: None,
                                 sleep: None,
                                 cache_ttl: None,
+                                cache_key_args: None,
                                 mock: None,
                                 timeout: None,
                                 priority: None,
@@ -1310,6 +1341,7 @@ async fn test_deno_flow_same_worker(db: Pool<Postgres>) {
                                 retry: None,
                                 sleep: None,
                                 cache_ttl: None,
+                                cache_key_args: None,
                                 mock: None,
                                 timeout: None,
                                 priority: None,
@@ -1323,6 +1355,7 @@ async fn test_deno_flow_same_worker(db: Pool<Postgres>) {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -1363,6 +1396,7 @@ async fn test_deno_flow_same_worker(db: Pool<Postgres>) {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -1803,6 +1837,218 @@ def main():
     assert_eq!(result, serde_json::json!("test-workspace"));
 }
 
+/// Deploy a dedicated script, then run one job per args through a worker dedicated to it
+#[cfg(feature = "enterprise")]
+async fn run_dedicated_jobs(
+    db: &Pool<Postgres>,
+    port: u16,
+    path: &str,
+    language: ScriptLang,
+    content: &str,
+    lock: &str,
+    args: Vec<serde_json::Map<String, serde_json::Value>>,
+) -> Vec<CompletedJob> {
+    let hash = 4242;
+    sqlx::query(
+        "INSERT INTO script (workspace_id, hash, path, summary, description, content, created_by, language, lock, dedicated_worker, schema)
+        VALUES ('test-workspace', $1, $2, '', '', $3, 'test-user', $4, $5, true, '{}')",
+    )
+    .bind(hash)
+    .bind(path)
+    .bind(content)
+    .bind(language)
+    .bind(lock)
+    .execute(db)
+    .await
+    .unwrap();
+
+    let mut uuids = vec![];
+    for args in args {
+        let job = RunJob {
+            payload: JobPayload::ScriptHash {
+                path: path.to_string(),
+                hash: ScriptHash(hash),
+                concurrent_limit: None,
+                concurrency_time_window_s: None,
+                cache_ttl: None,
+                cache_key_args: None,
+                dedicated_worker: Some(true),
+                language,
+                priority: None,
+            },
+            args,
+        };
+        uuids.push(job.push(db).await);
+    }
+
+    let mut completed = listen_for_completed_jobs(db).await;
+    let dedicated_worker =
+        WorkspacedPath { workspace_id: "test-workspace".to_string(), path: path.to_string() };
+    let uuids2 = uuids.clone();
+    in_test_worker_with(
+        db,
+        async move {
+            let mut remaining = uuids2.len();
+            while remaining > 0 {
+                let uuid = completed.next().await.unwrap();
+                if uuids2.contains(&uuid) {
+                    remaining -= 1;
+                }
+            }
+        },
+        port,
+        Some(dedicated_worker),
+    )
+    .await;
+
+    let mut jobs = vec![];
+    for uuid in uuids {
+        jobs.push(completed_job(uuid, db).await);
+    }
+    jobs
+}
+
+/// Both jobs must have been handled by the same process or connection, identified by `pid`
+#[cfg(feature = "enterprise")]
+fn assert_same_dedicated_process(
+    jobs: &[CompletedJob],
+    pid: impl Fn(&serde_json::Value) -> serde_json::Value,
+) {
+    let results = jobs
+        .iter()
+        .map(|job| {
+            assert!(job.success, "dedicated job failed: {:?}", job.result);
+            job.json_result().unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(results.len(), 2);
+    assert!(!pid(&results[0]).is_null());
+    assert_eq!(pid(&results[0]), pid(&results[1]));
+}
+
+#[cfg(feature = "enterprise")]
+fn dedicated_args(xs: &[i64]) -> Vec<serde_json::Map<String, serde_json::Value>> {
+    xs.iter()
+        .map(|x| serde_json::Map::from_iter([("x".to_string(), json!(x))]))
+        .collect()
+}
+
+#[cfg(feature = "enterprise")]
+#[sqlx::test(fixtures("base"))]
+async fn test_dedicated_go_worker(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    let content = r#"
+package inner
+
+import "os"
+
+func main(x int) (interface{}, error) {
+	return map[string]int{"pid": os.Getpid(), "x": x * 2}, nil
+}
+"#;
+
+    let jobs = run_dedicated_jobs(
+        &db,
+        port,
+        "f/dedicated/go",
+        ScriptLang::Go,
+        content,
+        "module mymod\n\ngo 1.21\n",
+        dedicated_args(&[1, 2]),
+    )
+    .await;
+
+    assert_same_dedicated_process(&jobs, |r| r["pid"].clone());
+    assert_eq!(jobs[0].json_result().unwrap()["x"], json!(2));
+    assert_eq!(jobs[1].json_result().unwrap()["x"], json!(4));
+}
+
+#[cfg(feature = "enterprise")]
+#[sqlx::test(fixtures("base"))]
+async fn test_dedicated_postgresql_worker(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    // the pinned connection is opened against the server the tests run on
+    let url = url::Url::parse(&std::env::var("DATABASE_URL").expect("DATABASE_URL")).unwrap();
+    let database = json!({
+        "host": url.host_str().unwrap_or("localhost"),
+        "port": url.port().unwrap_or(5432),
+        "user": url.username(),
+        "password": url.password().unwrap_or_default(),
+        "dbname": url.path().trim_start_matches('/'),
+        "sslmode": "disable",
+    });
+    let args = dedicated_args(&[1, 2])
+        .into_iter()
+        .map(|mut args| {
+            args.insert("database".to_string(), database.clone());
+            args
+        })
+        .collect();
+
+    let content = "-- $1 x\nSELECT pg_backend_pid() AS pid, $1::int * 2 AS x;";
+    let jobs = run_dedicated_jobs(
+        &db,
+        port,
+        "f/dedicated/postgresql",
+        ScriptLang::Postgresql,
+        content,
+        "",
+        args,
+    )
+    .await;
+
+    assert_same_dedicated_process(&jobs, |r| r[0]["pid"].clone());
+    assert_eq!(jobs[0].json_result().unwrap()[0]["x"], json!(2));
+    assert_eq!(jobs[1].json_result().unwrap()[0]["x"], json!(4));
+}
+
+#[cfg(feature = "enterprise")]
+#[sqlx::test(fixtures("base"))]
+#[ignore = "requires a MySQL server at MYSQL_TEST_URL"]
+async fn test_dedicated_mysql_worker(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    let url = url::Url::parse(&std::env::var("MYSQL_TEST_URL").expect("MYSQL_TEST_URL")).unwrap();
+    let database = json!({
+        "host": url.host_str().unwrap_or("localhost"),
+        "port": url.port().unwrap_or(3306),
+        "user": url.username(),
+        "password": url.password().unwrap_or_default(),
+        "database": url.path().trim_start_matches('/'),
+    });
+    let args = dedicated_args(&[1, 2])
+        .into_iter()
+        .map(|mut args| {
+            args.insert("database".to_string(), database.clone());
+            args
+        })
+        .collect();
+
+    let content = "-- ? x (int)\nSELECT CONNECTION_ID() AS pid, ? * 2 AS x;";
+    let jobs = run_dedicated_jobs(
+        &db,
+        port,
+        "f/dedicated/mysql",
+        ScriptLang::Mysql,
+        content,
+        "",
+        args,
+    )
+    .await;
+
+    assert_same_dedicated_process(&jobs, |r| r[0]["pid"].clone());
+    assert_eq!(jobs[0].json_result().unwrap()[0]["x"], json!(2));
+    assert_eq!(jobs[1].json_result().unwrap()[0]["x"], json!(4));
+}
+
 #[sqlx::test(fixtures("base"))]
 async fn test_empty_loop(db: Pool<Postgres>) {
     initialize_tracing().await;
@@ -2587,6 +2833,7 @@ async fn test_flow_lock_all(db: Pool<Postgres>) {
                     priority: None,
                     dedicated_worker: None,
                     timeout: None,
+                    service_account: None,
                 },
                 draft_only: None,
                 deployment_message: None,
@@ -2869,6 +3116,7 @@ async fn test_script_schedule_handlers(db: Pool<Postgres>) {
         timezone: "UTC".to_string(),
         schedule: format!("{} {} * * * *", then.second(), then.minute()).to_string(),
         ws_error_handler_muted: None,
+        service_account: None,
     };
 
     let _ = client.create_schedule("test-workspace", &schedule).await;
@@ -2929,6 +3177,7 @@ async fn test_script_schedule_handlers(db: Pool<Postgres>) {
                 timezone: "UTC".to_string(),
                 schedule: format!("{} {} * * * *", then.second(), then.minute()).to_string(),
                 ws_error_handler_muted: None,
+                service_account: None,
             },
         )
         .await
@@ -3005,6 +3254,7 @@ async fn test_flow_schedule_handlers(db: Pool<Postgres>) {
         timezone: "UTC".to_string(),
         schedule: format!("{} {} * * * *", then.second(), then.minute()).to_string(),
         ws_error_handler_muted: None,
+        service_account: None,
     };
 
     let _ = client.create_schedule("test-workspace", &schedule).await;
@@ -3066,6 +3316,7 @@ async fn test_flow_schedule_handlers(db: Pool<Postgres>) {
                 timezone: "UTC".to_string(),
                 schedule: format!("{} {} * * * *", then.second(), then.minute()).to_string(),
                 ws_error_handler_muted: None,
+                service_account: None,
             },
         )
         .await
@@ -3124,6 +3375,7 @@ async fn run_deployed_relative_imports(db: &Pool<Postgres>, script_content: Stri
             concurrent_limit: None,
             concurrency_time_window_s: None,
             cache_ttl: None,
+            cache_key_args: None,
             dedicated_worker: None,
             description: "".to_string(),
             draft_only: None,
@@ -3161,6 +3413,7 @@ async fn run_deployed_relative_imports(db: &Pool<Postgres>, script_content: Stri
             concurrent_limit: None,
             concurrency_time_window_s: None,
             cache_ttl: None,
+            cache_key_args: None,
             dedicated_worker: None,
             language,
             priority: None,
@@ -3311,4 +3564,500 @@ def main():
 
     run_deployed_relative_imports(&db, content.clone(), ScriptLang::Python3).await;
     run_preview_relative_imports(&db, content, ScriptLang::Python3).await;
-}
\ No newline at end of file
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_flow_step_cache_key_args(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    let flow: FlowValue = serde_json::from_value(json!({
+        "modules": [{
+            "id": "a",
+            "value": {
+                "type": "rawscript",
+                "language": "deno",
+                "content": "export function main(x, y) { return [x, y]; }",
+                "input_transforms": {
+                    "x": { "type": "javascript", "expr": "flow_input.x" },
+                    "y": { "type": "javascript", "expr": "flow_input.y" },
+                },
+            },
+            "cache_ttl": 600,
+            "cache_key_args": ["x"],
+        }],
+    }))
+    .unwrap();
+    let run = |x: i32, y: i32| {
+        RunJob::from(JobPayload::RawFlow {
+            value: flow.clone(),
+            path: Some("f/cache/flow".to_string()),
+            restarted_from: None,
+        })
+        .arg("x", json!(x))
+        .arg("y", json!(y))
+    };
+
+    let first = run(1, 1).run_until_complete(&db, port).await;
+    // y is not part of the cache key of the step, the result of the first run is reused
+    let second = run(1, 2).run_until_complete(&db, port).await;
+    let third = run(2, 2).run_until_complete(&db, port).await;
+
+    assert_eq!(first.json_result(), Some(json!([1, 1])));
+    assert_eq!(second.json_result(), Some(json!([1, 1])));
+    assert_eq!(third.json_result(), Some(json!([2, 2])));
+
+    let cache_hits = sqlx::query_scalar::<_, bool>(
+        "SELECT cache_hit FROM completed_job WHERE parent_job = ANY($1) ORDER BY created_at",
+    )
+    .bind(vec![first.id, second.id, third.id])
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert_eq!(cache_hits, vec![false, true, false]);
+
+    let hits = sqlx::query_scalar::<_, i32>(
+        "SELECT hits FROM job_result_cache WHERE workspace_id = 'test-workspace' AND path = 'f/cache/flow' ORDER BY created_at",
+    )
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert_eq!(hits, vec![1, 0]);
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_result_cache_api(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    sqlx::query(
+        "INSERT INTO job_result_cache (workspace_id, cache_key, path, result, expire, hits) VALUES
+            ('test-workspace', 'f/cache/script/cache/script_1/a', 'f/cache/script', '1', now() + interval '1 hour', 3),
+            ('test-workspace', 'f/cache/script/cache/script_1/b', 'f/cache/script', '2', now() - interval '1 hour', 0),
+            ('test-workspace', 'f/cache/other/cache/script_2/a', 'f/cache/other', '3', now() + interval '1 hour', 0)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO usr(workspace_id, email, username, is_admin, role) VALUES
+            ('test-workspace', 'dev@windmill.dev', 'dev-user', false, 'Developer')",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO token(token, email, label, super_admin) VALUES
+            ('DEV_TOKEN', 'dev@windmill.dev', 'dev token', false)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+
+    let base = format!("http://localhost:{port}/api/w/test-workspace/result_cache");
+    let client = reqwest::Client::new();
+
+    // expired entries are not listed
+    let entries: serde_json::Value = serde_json::from_str(
+        &client
+            .get(format!("{base}/list?path=f/cache/script"))
+            .bearer_auth("SECRET_TOKEN")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0]["cache_key"],
+        json!("f/cache/script/cache/script_1/a")
+    );
+    assert_eq!(entries[0]["hits"], json!(3));
+
+    // listing the entries of every path is reserved to admins
+    let status = client
+        .get(format!("{base}/list"))
+        .bearer_auth("DEV_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert!(status.is_client_error());
+
+    client
+        .delete(format!("{base}/purge/p/f/cache/script"))
+        .bearer_auth("SECRET_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let remaining = sqlx::query_scalar::<_, String>(
+        "SELECT path FROM job_result_cache WHERE workspace_id = 'test-workspace'",
+    )
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert_eq!(remaining, vec!["f/cache/other".to_string()]);
+
+    client
+        .delete(format!("{base}/purge_all"))
+        .bearer_auth("SECRET_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let remaining = sqlx::query_scalar::<_, i64>(
+        "SELECT count(*) FROM job_result_cache WHERE workspace_id = 'test-workspace'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_workspace_key_rotation(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    let base = format!("http://localhost:{port}/api/w/test-workspace");
+    let client = reqwest::Client::new();
+    let get_value = |path: &'static str| {
+        let request = client
+            .get(format!("{base}/variables/get_value/{path}"))
+            .bearer_auth("SECRET_TOKEN");
+        async move { request.send().await.unwrap() }
+    };
+
+    client
+        .post(format!("{base}/variables/create"))
+        .bearer_auth("SECRET_TOKEN")
+        .json(&json!({
+            "path": "u/test-user/secret",
+            "value": "p4ssw0rd",
+            "is_secret": true,
+            "description": "",
+        }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let encrypted_with_old_key = sqlx::query_scalar::<_, String>(
+        "SELECT value FROM variable WHERE workspace_id = 'test-workspace' AND path = 'u/test-user/secret'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+
+    let rotation: serde_json::Value = client
+        .post(format!("{base}/workspaces/rotate_key"))
+        .bearer_auth("SECRET_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        rotation,
+        json!({
+            "reencrypted_variables": 1,
+            "public_apps": 0,
+            "suspended_flows": 0,
+            "previous_key_expires_in_secs": 3600,
+        })
+    );
+
+    // the secret is re-encrypted with the new key
+    let reencrypted = sqlx::query_scalar::<_, String>(
+        "SELECT value FROM variable WHERE workspace_id = 'test-workspace' AND path = 'u/test-user/secret'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+    assert_ne!(reencrypted, encrypted_with_old_key);
+    let value: String = get_value("u/test-user/secret").await.json().await.unwrap();
+    assert_eq!(value, "p4ssw0rd");
+
+    // a value encrypted with the old key, e.g. concurrently to the rotation, is still decrypted
+    sqlx::query(
+        "INSERT INTO variable (workspace_id, path, value, is_secret, description, extra_perms)
+            VALUES ('test-workspace', 'u/test-user/concurrent', $1, true, '', '{}'::jsonb)",
+    )
+    .bind(&encrypted_with_old_key)
+    .execute(&db)
+    .await
+    .unwrap();
+    let value: String = get_value("u/test-user/concurrent")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(value, "p4ssw0rd");
+
+    // but not once the rotation window has passed
+    sqlx::query(
+        "UPDATE workspace_key SET rotated_at = now() - interval '1 day' WHERE workspace_id = 'test-workspace'",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    assert!(!get_value("u/test-user/concurrent")
+        .await
+        .status()
+        .is_success());
+    let value: String = get_value("u/test-user/secret").await.json().await.unwrap();
+    assert_eq!(value, "p4ssw0rd");
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_access_request_overlapping_approval(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    sqlx::query(
+        "INSERT INTO usr(workspace_id, email, username, is_admin, role) VALUES
+            ('test-workspace', 'dev@windmill.dev', 'dev-user', false, 'Developer')",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO token(token, email, label, super_admin) VALUES
+            ('DEV_TOKEN', 'dev@windmill.dev', 'dev token', false)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO group_(workspace_id, name) VALUES ('test-workspace', 'devs')")
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let base = format!("http://localhost:{port}/api/w/test-workspace/access_requests");
+    let client = reqwest::Client::new();
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let id: i64 = client
+            .post(format!("{base}/create"))
+            .bearer_auth("DEV_TOKEN")
+            .json(&json!({ "kind": "group", "path": "devs", "hours": 1 }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    let approve = |id: i64| {
+        let request = client
+            .post(format!("{base}/approve/{id}"))
+            .bearer_auth("SECRET_TOKEN");
+        async move { request.send().await.unwrap().status() }
+    };
+
+    assert!(approve(ids[0]).await.is_success());
+    // the membership is already granted by the first request
+    assert_eq!(approve(ids[1]).await, reqwest::StatusCode::BAD_REQUEST);
+
+    // once the first request expired, it is reverted for the second to be granted
+    sqlx::query("UPDATE access_request SET expires_at = now() - interval '1 minute' WHERE id = $1")
+        .bind(ids[0])
+        .execute(&db)
+        .await
+        .unwrap();
+    assert!(approve(ids[1]).await.is_success());
+    let statuses = sqlx::query_scalar::<_, String>(
+        "SELECT status::text FROM access_request WHERE id = ANY($1) ORDER BY id",
+    )
+    .bind(&ids)
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert_eq!(statuses, vec!["expired", "approved"]);
+    let members = sqlx::query_scalar::<_, String>(
+        "SELECT usr FROM usr_to_group WHERE workspace_id = 'test-workspace' AND group_ = 'devs'",
+    )
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    assert_eq!(members, vec!["dev-user"]);
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_delete_workspace_with_access_requests(db: Pool<Postgres>) {
+    initialize_tracing().await;
+    let server = ApiServer::start(db.clone()).await;
+    let port = server.addr.port();
+
+    sqlx::query("INSERT INTO group_(workspace_id, name) VALUES ('test-workspace', 'devs')")
+        .execute(&db)
+        .await
+        .unwrap();
+    let client = reqwest::Client::new();
+    client
+        .post(format!(
+            "http://localhost:{port}/api/w/test-workspace/access_requests/create"
+        ))
+        .bearer_auth("SECRET_TOKEN")
+        .json(&json!({ "kind": "group", "path": "devs", "hours": 1 }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    client
+        .delete(format!(
+            "http://localhost:{port}/api/workspaces/delete/test-workspace"
+        ))
+        .bearer_auth("SECRET_TOKEN")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let remaining = sqlx::query_scalar::<_, i64>(
+        "SELECT count(*) FROM access_request WHERE workspace_id = 'test-workspace'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+async fn push_identity_job(
+    db: &Pool<Postgres>,
+    w_id: &str,
+    schedule_path: Option<String>,
+    is_flow_step: bool,
+) -> windmill_common::error::Result<Uuid> {
+    let tx = PushIsolationLevel::IsolatedRoot(db.clone(), None);
+    let (uuid, tx) = windmill_queue::push::<_, rsmq_async::MultiplexedRsmq>(
+        db,
+        tx,
+        w_id,
+        JobPayload::Identity,
+        Json(serde_json::Map::new()),
+        "test-user",
+        "test@windmill.dev",
+        "u/test-user".to_string(),
+        None,
+        schedule_path,
+        None,
+        None,
+        None,
+        is_flow_step,
+        false,
+        None,
+        true,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    tx.commit().await.unwrap();
+    Ok(uuid)
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_usage_hard_quota(db: Pool<Postgres>) {
+    initialize_tracing().await;
+
+    // quotas are cached per workspace, so the test uses a workspace no other test pushes to
+    query("INSERT INTO workspace (id, name, owner) VALUES ('quota-workspace', 'quota-workspace', 'test-user')")
+        .execute(&db)
+        .await
+        .unwrap();
+    query("INSERT INTO workspace_settings (workspace_id, usage_quotas) VALUES ('quota-workspace', $1)")
+        .bind(json!({ "period": "daily", "hard": { "jobs": 2 } }))
+        .execute(&db)
+        .await
+        .unwrap();
+    query(
+        "INSERT INTO job_usage_rollup (workspace_id, day, username, runnable_path, job_count)
+        VALUES ('quota-workspace', current_date, 'test-user', '', 2)",
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    windmill_queue::usage::invalidate_quota_cache("quota-workspace").await;
+
+    let err = push_identity_job(&db, "quota-workspace", None, false)
+        .await
+        .expect_err("the hard quota is reached");
+    assert!(err.to_string().contains("hard usage quota"), "{err}");
+
+    push_identity_job(&db, "quota-workspace", Some("f/quota/schedule".to_string()), false)
+        .await
+        .expect("schedule re-pushes are not blocked");
+    push_identity_job(&db, "quota-workspace", None, true)
+        .await
+        .expect("flow steps are not blocked");
+
+    query("UPDATE workspace_settings SET usage_quotas = $1 WHERE workspace_id = 'quota-workspace'")
+        .bind(json!({ "period": "daily", "soft": { "jobs": 2 } }))
+        .execute(&db)
+        .await
+        .unwrap();
+    windmill_queue::usage::invalidate_quota_cache("quota-workspace").await;
+    push_identity_job(&db, "quota-workspace", None, false)
+        .await
+        .expect("soft quotas are only reported");
+}
+
+#[sqlx::test(fixtures("base"))]
+async fn test_usage_rollup(db: Pool<Postgres>) {
+    initialize_tracing().await;
+
+    let server = ApiServer::start(db.clone()).await;
+
+    let flow: FlowValue = serde_json::from_value(json!({
+        "modules": [
+            { "id": "a", "value": { "type": "identity" } },
+            { "id": "b", "value": { "type": "identity" } },
+        ],
+    }))
+    .unwrap();
+    let job = RunJob::from(JobPayload::RawFlow { value: flow, path: None, restarted_from: None })
+        .run_until_complete(&db, server.addr.port())
+        .await;
+    assert!(job.success);
+    let steps =
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM completed_job WHERE parent_job = $1")
+            .bind(job.id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(steps, 2);
+
+    let (job_count, failed_count) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT SUM(job_count)::BIGINT, SUM(failed_count)::BIGINT FROM job_usage_rollup
+        WHERE workspace_id = 'test-workspace' AND day = current_date AND username = 'test-user'",
+    )
+    .fetch_one(&db)
+    .await
+    .unwrap();
+    // the flow is counted once, not its steps
+    assert_eq!((job_count, failed_count), (1, 0));
+}
@@ -0,0 +1,604 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Just-in-time access: a user requests read or write on an item, or the membership of a group,
+//! for a few hours. Once an owner of the item or an admin approves it, the access is granted as a
+//! granular acl (or a group membership) and reverted when it expires or is revoked.
+
+use crate::{
+    db::{ApiAuthed, DB},
+    granular_acls::{require_can_share, ACL_KINDS},
+    users::notify_invalidated_tokens,
+};
+
+use axum::{
+    extract::{Extension, Path, Query},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, Postgres, Transaction};
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{Error, JsonResult, Result},
+    utils::{paginate, Pagination},
+};
+
+lazy_static::lazy_static! {
+    static ref ACCESS_REQUEST_MAX_HOURS: i32 = std::env::var("ACCESS_REQUEST_MAX_HOURS")
+        .ok()
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(24 * 7);
+}
+
+/// Kind of the requests for the membership of a group
+const GROUP_MEMBERSHIP: &str = "group";
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/list", get(list_access_requests))
+        .route("/create", post(create_access_request))
+        .route("/approve/:id", post(approve_access_request))
+        .route("/deny/:id", post(deny_access_request))
+        .route("/revoke/:id", post(revoke_access_request))
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name = "ACCESS_REQUEST_STATUS", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AccessRequestStatus {
+    Pending,
+    Approved,
+    Denied,
+    Revoked,
+    Expired,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct AccessRequest {
+    pub id: i64,
+    pub workspace_id: String,
+    pub requester: String,
+    pub kind: String,
+    pub path: String,
+    pub write: bool,
+    pub hours: i32,
+    pub reason: Option<String>,
+    pub status: AccessRequestStatus,
+    #[serde(skip)]
+    pub previous_perm: Option<Value>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl AccessRequest {
+    fn resource(&self) -> String {
+        format!("{}/{}", self.kind, self.path)
+    }
+
+    /// Kind of the item whose owners can approve the request
+    fn acl_kind(&self) -> &str {
+        if self.kind == GROUP_MEMBERSHIP {
+            "group_"
+        } else {
+            &self.kind
+        }
+    }
+
+    fn granted_perm(&self) -> bool {
+        granted_perm(self.write, self.previous_perm.as_ref())
+    }
+}
+
+/// Value of the acl of the requester while the request is granted: the access they already had is
+/// never downgraded
+fn granted_perm(write: bool, previous_perm: Option<&Value>) -> bool {
+    write || previous_perm.and_then(|x| x.as_bool()).unwrap_or(false)
+}
+
+fn identifier(kind: &str) -> &'static str {
+    if kind == "group_" || kind == "folder" {
+        "name"
+    } else {
+        "path"
+    }
+}
+
+#[derive(Deserialize)]
+struct ListAccessRequestsQuery {
+    status: Option<AccessRequestStatus>,
+}
+
+/// Admins see all the requests of the workspace, other users the requests they made and the ones
+/// on the items they own
+async fn list_access_requests(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(q): Query<ListAccessRequestsQuery>,
+) -> JsonResult<Vec<AccessRequest>> {
+    let (per_page, offset) = paginate(pagination);
+    let owned_folders = authed
+        .folders
+        .iter()
+        .filter(|(_, _, is_owner)| *is_owner)
+        .map(|(name, _, _)| name.clone())
+        .collect::<Vec<_>>();
+    let groups = authed
+        .groups
+        .iter()
+        .map(|x| format!("g/{x}"))
+        .collect::<Vec<_>>();
+
+    let rows = sqlx::query_as::<_, AccessRequest>(
+        "SELECT * FROM access_request
+        WHERE workspace_id = $1 AND ($2::access_request_status IS NULL OR status = $2)
+            AND ($3 OR requester = $4
+                OR (kind = 'folder' AND path = ANY($5))
+                OR (kind NOT IN ('folder', 'group_', 'group') AND (
+                    (split_part(path, '/', 1) = 'u' AND split_part(path, '/', 2) = $4)
+                    OR (split_part(path, '/', 1) = 'f' AND split_part(path, '/', 2) = ANY($5))))
+                OR (kind IN ('group_', 'group') AND EXISTS(
+                    SELECT 1 FROM group_, jsonb_each_text(group_.extra_perms) perm
+                    WHERE group_.name = access_request.path AND group_.workspace_id = $1
+                        AND perm.value::boolean AND (perm.key = 'u/' || $4 OR perm.key = ANY($6)))))
+        ORDER BY created_at DESC
+        LIMIT $7 OFFSET $8",
+    )
+    .bind(&w_id)
+    .bind(q.status)
+    .bind(authed.is_admin)
+    .bind(&authed.username)
+    .bind(&owned_folders)
+    .bind(&groups)
+    .bind(per_page as i64)
+    .bind(offset as i64)
+    .fetch_all(&db)
+    .await?;
+
+    Ok(Json(rows))
+}
+
+#[derive(Deserialize)]
+struct CreateAccessRequest {
+    kind: String,
+    path: String,
+    write: Option<bool>,
+    hours: i32,
+    reason: Option<String>,
+}
+
+async fn create_access_request(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Json(cr): Json<CreateAccessRequest>,
+) -> JsonResult<i64> {
+    if cr.kind != GROUP_MEMBERSHIP && !ACL_KINDS.contains(&cr.kind.as_str()) {
+        return Err(Error::BadRequest(format!(
+            "Invalid kind {}, expected {GROUP_MEMBERSHIP} or one of {}",
+            cr.kind,
+            ACL_KINDS.join(", ")
+        )));
+    }
+    let write = cr.write.unwrap_or(false);
+    if cr.kind == GROUP_MEMBERSHIP && write {
+        return Err(Error::BadRequest(
+            "Group memberships cannot be requested with write access, request write on the group_ \
+             itself to manage it"
+                .to_string(),
+        ));
+    }
+    if cr.hours < 1 || cr.hours > *ACCESS_REQUEST_MAX_HOURS {
+        return Err(Error::BadRequest(format!(
+            "Access can be requested for 1 to {} hours",
+            *ACCESS_REQUEST_MAX_HOURS
+        )));
+    }
+
+    let table = if cr.kind == GROUP_MEMBERSHIP {
+        "group_"
+    } else {
+        &cr.kind
+    };
+    let exists = sqlx::query_scalar::<_, bool>(&format!(
+        "SELECT EXISTS(SELECT 1 FROM {table} WHERE {} = $1 AND workspace_id = $2)",
+        identifier(table)
+    ))
+    .bind(&cr.path)
+    .bind(&w_id)
+    .fetch_one(&db)
+    .await?;
+    if !exists {
+        return Err(Error::NotFound(format!(
+            "{} {} not found",
+            cr.kind, cr.path
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+    let id = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO access_request (workspace_id, requester, kind, path, write, hours, reason)
+        VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+    )
+    .bind(&w_id)
+    .bind(&authed.username)
+    .bind(&cr.kind)
+    .bind(&cr.path)
+    .bind(write)
+    .bind(cr.hours)
+    .bind(&cr.reason)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let id_str = id.to_string();
+    let hours = cr.hours.to_string();
+    let write = write.to_string();
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "access_requests.create",
+        ActionKind::Create,
+        &w_id,
+        Some(&format!("{}/{}", cr.kind, cr.path)),
+        Some(
+            [
+                ("id", id_str.as_str()),
+                ("write", write.as_str()),
+                ("hours", hours.as_str()),
+            ]
+            .into(),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(id))
+}
+
+async fn get_request_for_update<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+    id: i64,
+) -> Result<AccessRequest> {
+    sqlx::query_as::<_, AccessRequest>(
+        "SELECT * FROM access_request WHERE id = $1 AND workspace_id = $2 FOR UPDATE",
+    )
+    .bind(id)
+    .bind(w_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("Access request {id} not found")))
+}
+
+fn require_status(request: &AccessRequest, status: AccessRequestStatus) -> Result<()> {
+    if request.status != status {
+        return Err(Error::BadRequest(format!(
+            "Access request {} is {:?}",
+            request.id, request.status
+        )));
+    }
+    Ok(())
+}
+
+async fn approve_access_request(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, id)): Path<(String, i64)>,
+) -> Result<String> {
+    let mut tx = db.begin().await?;
+    let mut request = get_request_for_update(&mut tx, &w_id, id).await?;
+    require_status(&request, AccessRequestStatus::Pending)?;
+    if request.requester == authed.username {
+        return Err(Error::NotAuthorized(
+            "Access requests cannot be approved by their requester".to_string(),
+        ));
+    }
+    require_can_share(&authed, &db, &w_id, request.acl_kind(), &request.path).await?;
+    end_overlapping_grant(&mut tx, &request).await?;
+
+    request.previous_perm = grant(&mut tx, &request).await?;
+    let expires_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        "UPDATE access_request SET status = 'approved', decided_by = $1, decided_at = now(),
+            expires_at = now() + make_interval(hours => hours), previous_perm = $2
+        WHERE id = $3 RETURNING expires_at",
+    )
+    .bind(&authed.username)
+    .bind(&request.previous_perm)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let expires_at = expires_at.to_rfc3339();
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "access_requests.approve",
+        ActionKind::PermissionChange,
+        &w_id,
+        Some(&request.resource()),
+        Some(
+            [
+                ("requester", request.requester.as_str()),
+                ("write", &request.write.to_string()),
+                ("expires_at", expires_at.as_str()),
+            ]
+            .into(),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("Approved access request {id} until {expires_at}"))
+}
+
+/// Denies a pending request, or withdraws it when done by its requester
+async fn deny_access_request(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, id)): Path<(String, i64)>,
+) -> Result<String> {
+    let mut tx = db.begin().await?;
+    let request = get_request_for_update(&mut tx, &w_id, id).await?;
+    require_status(&request, AccessRequestStatus::Pending)?;
+    if request.requester != authed.username {
+        require_can_share(&authed, &db, &w_id, request.acl_kind(), &request.path).await?;
+    }
+
+    sqlx::query(
+        "UPDATE access_request SET status = 'denied', decided_by = $1, decided_at = now()
+        WHERE id = $2",
+    )
+    .bind(&authed.username)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "access_requests.deny",
+        ActionKind::Update,
+        &w_id,
+        Some(&request.resource()),
+        Some([("requester", request.requester.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("Denied access request {id}"))
+}
+
+/// Revokes an approved request before it expires
+async fn revoke_access_request(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, id)): Path<(String, i64)>,
+) -> Result<String> {
+    let mut tx = db.begin().await?;
+    let request = get_request_for_update(&mut tx, &w_id, id).await?;
+    require_status(&request, AccessRequestStatus::Approved)?;
+    if request.requester != authed.username {
+        require_can_share(&authed, &db, &w_id, request.acl_kind(), &request.path).await?;
+    }
+
+    end_grant(&mut tx, &request, AccessRequestStatus::Revoked).await?;
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "access_requests.revoke",
+        ActionKind::PermissionChange,
+        &w_id,
+        Some(&request.resource()),
+        Some([("requester", request.requester.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("Revoked access request {id}"))
+}
+
+/// Approving a request while another one on the same item is granted to the requester would
+/// make the first to end revert the access of both, so it is rejected. The granted requests which
+/// already expired are reverted right away instead of waiting for the monitor.
+async fn end_overlapping_grant<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    request: &AccessRequest,
+) -> Result<()> {
+    // locks the item against the concurrent approvals of the other requests on it
+    let kind = request.acl_kind();
+    sqlx::query(&format!(
+        "SELECT 1 FROM {kind} WHERE {} = $1 AND workspace_id = $2 FOR UPDATE",
+        identifier(kind)
+    ))
+    .bind(&request.path)
+    .bind(&request.workspace_id)
+    .execute(&mut **tx)
+    .await?;
+
+    let granted = sqlx::query_as::<_, AccessRequest>(
+        "SELECT * FROM access_request WHERE workspace_id = $1 AND requester = $2 AND kind = $3
+            AND path = $4 AND status = 'approved' AND id != $5
+        FOR UPDATE",
+    )
+    .bind(&request.workspace_id)
+    .bind(&request.requester)
+    .bind(&request.kind)
+    .bind(&request.path)
+    .bind(request.id)
+    .fetch_all(&mut **tx)
+    .await?;
+    for other in granted.iter() {
+        match other.expires_at {
+            Some(expires_at) if expires_at > chrono::Utc::now() => {
+                return Err(Error::BadRequest(format!(
+                    "{} already has access to {} through request {} until {}, revoke it first",
+                    request.requester,
+                    request.resource(),
+                    other.id,
+                    expires_at.to_rfc3339()
+                )));
+            }
+            _ => expire_access_request(tx, other).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Grants the requested access, returning the access the requester had before
+async fn grant<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    request: &AccessRequest,
+) -> Result<Option<Value>> {
+    if request.kind == GROUP_MEMBERSHIP {
+        let inserted = sqlx::query(
+            "INSERT INTO usr_to_group (workspace_id, usr, group_) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(&request.workspace_id)
+        .bind(&request.requester)
+        .bind(&request.path)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+        if inserted > 0 {
+            invalidate_requester_tokens(tx, request).await?;
+        }
+        return Ok(if inserted == 0 {
+            Some(Value::Bool(true))
+        } else {
+            None
+        });
+    }
+
+    let owner = format!("u/{}", request.requester);
+    let kind = &request.kind;
+    let identifier = identifier(kind);
+    let previous_perm = sqlx::query_scalar::<_, Option<Value>>(&format!(
+        "SELECT extra_perms->$1 FROM {kind} WHERE {identifier} = $2 AND workspace_id = $3 LIMIT 1"
+    ))
+    .bind(&owner)
+    .bind(&request.path)
+    .bind(&request.workspace_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("{kind} {} not found", request.path)))?;
+
+    sqlx::query(&format!(
+        "UPDATE {kind} SET extra_perms = jsonb_set(extra_perms, ARRAY[$1], to_jsonb($2::bool), true)
+        WHERE {identifier} = $3 AND workspace_id = $4"
+    ))
+    .bind(&owner)
+    .bind(granted_perm(request.write, previous_perm.as_ref()))
+    .bind(&request.path)
+    .bind(&request.workspace_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(previous_perm)
+}
+
+/// Restores the access the requester had before the request was granted, unless it was changed
+/// since, and closes the request with the given status
+async fn end_grant<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    request: &AccessRequest,
+    status: AccessRequestStatus,
+) -> Result<()> {
+    if request.kind == GROUP_MEMBERSHIP {
+        if request.previous_perm.is_none() {
+            sqlx::query(
+                "DELETE FROM usr_to_group WHERE workspace_id = $1 AND usr = $2 AND group_ = $3",
+            )
+            .bind(&request.workspace_id)
+            .bind(&request.requester)
+            .bind(&request.path)
+            .execute(&mut **tx)
+            .await?;
+            invalidate_requester_tokens(tx, request).await?;
+        }
+    } else {
+        let kind = &request.kind;
+        let identifier = identifier(kind);
+        sqlx::query(&format!(
+            "UPDATE {kind} SET extra_perms = CASE WHEN $1::jsonb IS NULL THEN extra_perms - $2
+                ELSE jsonb_set(extra_perms, ARRAY[$2], $1::jsonb) END
+            WHERE {identifier} = $3 AND workspace_id = $4 AND extra_perms->$2 = to_jsonb($5::bool)"
+        ))
+        .bind(&request.previous_perm)
+        .bind(format!("u/{}", request.requester))
+        .bind(&request.path)
+        .bind(&request.workspace_id)
+        .bind(request.granted_perm())
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    sqlx::query("UPDATE access_request SET status = $1 WHERE id = $2")
+        .bind(status)
+        .bind(request.id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// The groups of a user are cached with their tokens, which are evicted for the changes of their
+/// memberships to apply right away
+async fn invalidate_requester_tokens<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    request: &AccessRequest,
+) -> Result<()> {
+    let tokens = sqlx::query_scalar::<_, String>(
+        "SELECT token FROM token WHERE email = (SELECT email FROM usr
+            WHERE username = $1 AND workspace_id = $2)",
+    )
+    .bind(&request.requester)
+    .bind(&request.workspace_id)
+    .fetch_all(&mut **tx)
+    .await?;
+    notify_invalidated_tokens(tx, &tokens, &[request.workspace_id.clone()]).await
+}
+
+async fn expire_access_request<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    request: &AccessRequest,
+) -> Result<()> {
+    end_grant(tx, request, AccessRequestStatus::Expired).await?;
+    audit_log(
+        &mut **tx,
+        &request.requester,
+        "access_requests.expire",
+        ActionKind::PermissionChange,
+        &request.workspace_id,
+        Some(&request.resource()),
+        Some([("id", request.id.to_string().as_str())].into()),
+    )
+    .await
+}
+
+/// Reverts the accesses whose request expired, returning the number of requests expired
+pub async fn expire_access_requests(db: &DB) -> Result<usize> {
+    let mut tx = db.begin().await?;
+    let requests = sqlx::query_as::<_, AccessRequest>(
+        "SELECT * FROM access_request WHERE status = 'approved' AND expires_at <= now()
+        ORDER BY expires_at LIMIT 100 FOR UPDATE SKIP LOCKED",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for request in requests.iter() {
+        expire_access_request(&mut tx, request).await?;
+    }
+    tx.commit().await?;
+
+    Ok(requests.len())
+}
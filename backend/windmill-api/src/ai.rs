@@ -0,0 +1,996 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    db::{ApiAuthed, DB},
+    secret_providers::get_external_secret,
+    variables::build_decrypt,
+    HTTP_CLIENT,
+};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Extension, Path, Query},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde_json::value::RawValue;
+use sqlx::FromRow;
+use tokio::sync::RwLock;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{to_anyhow, Error, JsonResult},
+    utils::{paginate, Pagination},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub fn workspaced_service() -> Router {
+    let router = Router::new()
+        .route("/proxy/*openai_path", post(proxy))
+        .route("/usage", get(list_usage));
+
+    router
+}
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// required by anthropic, which has no default
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u64 = 4096;
+const AZURE_API_VERSION: &str = "2023-05-15";
+
+/// Responses larger than this are still streamed back to the client but are not inspected for
+/// token usage.
+const MAX_USAGE_BUFFER_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AIProvider {
+    OpenAI,
+    AzureOpenAI,
+    Anthropic,
+    /// Any server implementing the OpenAI API (llama.cpp, vLLM, Ollama, ...)
+    Custom,
+}
+
+impl AIProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AIProvider::OpenAI => "openai",
+            AIProvider::AzureOpenAI => "azure_openai",
+            AIProvider::Anthropic => "anthropic",
+            AIProvider::Custom => "custom",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AIConfig {
+    pub provider: AIProvider,
+    pub resource_path: Option<String>,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub log_requests: bool,
+    #[serde(default)]
+    pub log_responses: bool,
+}
+
+impl AIConfig {
+    /// Copilot settings saved before providers existed only carried an OpenAI resource path
+    fn from_openai_resource_path(resource_path: String) -> Self {
+        Self {
+            provider: AIProvider::OpenAI,
+            resource_path: Some(resource_path),
+            base_url: None,
+            model: None,
+            log_requests: false,
+            log_responses: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AIResource {
+    #[serde(alias = "apiKey")]
+    api_key: Option<String>,
+    organization_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AIClientCredentialsOauthResource {
+    client_id: String,
+    client_secret: String,
+    token_url: String,
+    user: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AIResourceConfig {
+    ClientCredentialsOauthResource(AIClientCredentialsOauthResource),
+    Resource(AIResource),
+}
+
+#[derive(FromRow)]
+struct Variable {
+    value: String,
+    is_secret: bool,
+    secret_provider: Option<String>,
+}
+async fn get_variable_or_self(path: String, db: &DB, w_id: &String) -> Result<String, Error> {
+    if !path.starts_with("$var:") {
+        return Ok(path);
+    }
+    let path = path.strip_prefix("$var:").unwrap().to_string();
+    let mut tx = db.begin().await?;
+    let mut variable = sqlx::query_as::<_, Variable>(
+        "SELECT value, is_secret, secret_provider
+        FROM variable
+        WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(&path)
+    .bind(&w_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(provider) = variable.secret_provider.as_deref() {
+        variable.value = get_external_secret(provider, &w_id, &variable.value).await?;
+    } else if variable.is_secret {
+        let decrypt = build_decrypt(&mut tx, &w_id).await?;
+        variable.value = decrypt.decrypt_base64_to_string(&variable.value)?;
+    }
+    tx.commit().await?;
+    Ok(variable.value)
+}
+
+lazy_static::lazy_static! {
+    pub static ref OPENAI_AZURE_BASE_PATH: Option<String> = std::env::var("OPENAI_AZURE_BASE_PATH").ok();
+}
+
+#[derive(Deserialize)]
+struct AICredentials {
+    access_token: String,
+}
+async fn get_api_key_using_credentials_flow(
+    mut resource: AIClientCredentialsOauthResource,
+    db: &DB,
+    w_id: &String,
+) -> Result<String, Error> {
+    resource.client_id = get_variable_or_self(resource.client_id, &db, &w_id).await?;
+    resource.client_secret = get_variable_or_self(resource.client_secret, &db, &w_id).await?;
+    resource.token_url = get_variable_or_self(resource.token_url, &db, &w_id).await?;
+    let mut params = HashMap::new();
+    params.insert("grant_type", "client_credentials");
+    let response = HTTP_CLIENT
+        .post(resource.token_url)
+        .form(&params)
+        .basic_auth(resource.client_id, Some(resource.client_secret))
+        .send()
+        .await
+        .map_err(|err| {
+            Error::InternalErr(format!(
+                "Failed to get AI credentials using credentials flow: {}",
+                err
+            ))
+        })?;
+    let response = response.json::<AICredentials>().await.map_err(|err| {
+        Error::InternalErr(format!(
+            "Failed to parse AI credentials from credentials flow: {}",
+            err
+        ))
+    })?;
+    Ok(response.access_token)
+}
+
+#[derive(Clone)]
+struct AIRequestConfig {
+    provider: AIProvider,
+    base_url: String,
+    api_key: Option<String>,
+    organization_id: Option<String>,
+    user: Option<String>,
+    model: Option<String>,
+    log_requests: bool,
+    log_responses: bool,
+}
+
+impl AIRequestConfig {
+    fn prepare_request(&self, path: &str, body: Bytes) -> reqwest::RequestBuilder {
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), path);
+        let mut request = HTTP_CLIENT
+            .post(url)
+            .header("content-type", "application/json")
+            .body(body);
+
+        match (self.provider, self.api_key.as_ref()) {
+            (AIProvider::AzureOpenAI, Some(api_key)) => {
+                request = request
+                    .header("api-key", api_key)
+                    .query(&[("api-version", AZURE_API_VERSION)])
+            }
+            (AIProvider::Anthropic, Some(api_key)) => {
+                request = request
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", ANTHROPIC_VERSION)
+            }
+            (_, Some(api_key)) if !api_key.is_empty() => {
+                request = request.header("authorization", format!("Bearer {}", api_key))
+            }
+            _ => {}
+        }
+
+        if let Some(org_id) = self.organization_id.as_ref() {
+            request = request.header("OpenAI-Organization", org_id);
+        }
+
+        request
+    }
+}
+
+struct AIRequestConfigCache {
+    config: AIRequestConfig,
+    expires_at: std::time::Instant,
+}
+
+impl AIRequestConfigCache {
+    fn new(config: AIRequestConfig, expires_at: std::time::Instant) -> Self {
+        Self { config, expires_at }
+    }
+    fn is_expired(&self) -> bool {
+        self.expires_at < std::time::Instant::now()
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref AI_REQUEST_CONFIG_CACHE: Arc<RwLock<HashMap<String, AIRequestConfigCache>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+pub async fn get_workspace_ai_config(db: &DB, w_id: &str) -> Result<Option<AIConfig>, Error> {
+    let ai_config = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT ai_config FROM workspace_settings WHERE workspace_id = $1",
+    )
+    .bind(w_id)
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    if let Some(ai_config) = ai_config {
+        return serde_json::from_value::<AIConfig>(ai_config)
+            .map(Some)
+            .map_err(|e| Error::InternalErr(format!("validating ai config {e}")));
+    }
+
+    let openai_resource_path = sqlx::query_scalar!(
+        "SELECT openai_resource_path FROM workspace_settings WHERE workspace_id = $1",
+        w_id
+    )
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    Ok(openai_resource_path.map(AIConfig::from_openai_resource_path))
+}
+
+async fn get_azure_base_path(db: &DB) -> Result<Option<String>, Error> {
+    let azure_base_path = sqlx::query_scalar!(
+        "SELECT value
+            FROM global_settings
+            WHERE name = 'openai_azure_base_path'",
+    )
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(azure_base_path) = azure_base_path {
+        Ok(Some(
+            serde_json::from_value::<String>(azure_base_path).map_err(|e| {
+                Error::InternalErr(format!("validating openai azure base path {e}"))
+            })?,
+        ))
+    } else {
+        Ok(OPENAI_AZURE_BASE_PATH.clone())
+    }
+}
+
+async fn build_request_config(db: &DB, w_id: &String) -> Result<AIRequestConfig, Error> {
+    let ai_config = get_workspace_ai_config(db, w_id)
+        .await?
+        .ok_or_else(|| Error::InternalErr("AI provider not configured".to_string()))?;
+
+    let mut api_key = None::<String>;
+    let mut organization_id = None::<String>;
+    let mut user = None::<String>;
+
+    if let Some(resource_path) = ai_config.resource_path.as_ref() {
+        let resource = sqlx::query_scalar!(
+            "SELECT value
+            FROM resource
+            WHERE path = $1 AND workspace_id = $2",
+            resource_path,
+            w_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| {
+            Error::InternalErr(format!(
+                "Could not find the AI resource at path {resource_path}, update the resource path in the workspace settings"
+            ))
+        })?
+        .ok_or_else(|| Error::InternalErr("AI resource missing value".to_string()))?;
+
+        let config: AIResourceConfig = serde_json::from_value(resource)
+            .map_err(|e| Error::InternalErr(format!("validating ai resource {e}")))?;
+
+        match config {
+            AIResourceConfig::Resource(resource) => {
+                tracing::debug!("Getting AI key from static resource");
+                api_key = resource.api_key;
+                organization_id = resource.organization_id;
+            }
+            AIResourceConfig::ClientCredentialsOauthResource(resource) => {
+                tracing::debug!("Getting AI key with client credentials flow");
+                user = resource.user.clone();
+                api_key = Some(get_api_key_using_credentials_flow(resource, db, w_id).await?);
+            }
+        };
+    } else if ai_config.provider != AIProvider::Custom {
+        return Err(Error::InternalErr(format!(
+            "A resource is required for the {} AI provider",
+            ai_config.provider.as_str()
+        )));
+    }
+
+    if let Some(key) = api_key {
+        api_key = Some(get_variable_or_self(key, db, w_id).await?);
+    }
+    if let Some(org_id) = organization_id {
+        organization_id = Some(get_variable_or_self(org_id, db, w_id).await?);
+    }
+    if let Some(u) = user {
+        user = Some(get_variable_or_self(u, db, w_id).await?);
+    }
+
+    let (provider, base_url) = match ai_config.provider {
+        AIProvider::OpenAI => match get_azure_base_path(db).await? {
+            // instances configured with an azure base path predate the azure provider
+            Some(azure_base_path) => (AIProvider::AzureOpenAI, azure_base_path),
+            None => (
+                AIProvider::OpenAI,
+                ai_config
+                    .base_url
+                    .clone()
+                    .unwrap_or_else(|| OPENAI_BASE_URL.to_string()),
+            ),
+        },
+        AIProvider::AzureOpenAI => {
+            let base_url = match ai_config.base_url.clone() {
+                Some(base_url) => base_url,
+                None => get_azure_base_path(db).await?.ok_or_else(|| {
+                    Error::BadConfig("Azure OpenAI provider requires a base url".to_string())
+                })?,
+            };
+            (AIProvider::AzureOpenAI, base_url)
+        }
+        AIProvider::Anthropic => (
+            AIProvider::Anthropic,
+            ai_config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| ANTHROPIC_BASE_URL.to_string()),
+        ),
+        AIProvider::Custom => (
+            AIProvider::Custom,
+            ai_config.base_url.clone().ok_or_else(|| {
+                Error::BadConfig("Custom AI provider requires a base url".to_string())
+            })?,
+        ),
+    };
+
+    Ok(AIRequestConfig {
+        provider,
+        base_url,
+        api_key,
+        organization_id,
+        user,
+        model: ai_config.model,
+        log_requests: ai_config.log_requests,
+        log_responses: ai_config.log_responses,
+    })
+}
+
+/// Extracts (prompt tokens, completion tokens) from either a plain JSON response or a
+/// server-sent events stream. Both the OpenAI (`prompt_tokens`/`completion_tokens`) and the
+/// Anthropic (`input_tokens`/`output_tokens`) naming are supported.
+fn extract_token_usage(body: &[u8]) -> Option<(i64, i64)> {
+    fn usage_of(value: &serde_json::Value) -> Option<(i64, i64)> {
+        let usage = value
+            .get("usage")
+            .or_else(|| value.get("message").and_then(|m| m.get("usage")))?;
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|k| usage.get(*k).and_then(|v| v.as_i64()))
+                .unwrap_or(0)
+        };
+        Some((
+            get(&["prompt_tokens", "input_tokens"]),
+            get(&["completion_tokens", "output_tokens"]),
+        ))
+    }
+
+    if let Ok(value) = serde_json::from_slice::<serde_json::Value>(body) {
+        return usage_of(&value);
+    }
+
+    // streamed responses report usage across several events, counters are cumulative
+    let body = std::str::from_utf8(body).ok()?;
+    body.lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data.trim()).ok())
+        .filter_map(|event| usage_of(&event))
+        .reduce(|(p1, c1), (p2, c2)| (p1.max(p2), c1.max(c2)))
+}
+
+async fn record_token_usage(
+    db: &DB,
+    w_id: &str,
+    username: &str,
+    provider: AIProvider,
+    model: &str,
+    (prompt_tokens, completion_tokens): (i64, i64),
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO ai_usage (workspace_id, username, provider, model, prompt_tokens, completion_tokens, requests) \
+         VALUES ($1, $2, $3, $4, $5, $6, 1) \
+         ON CONFLICT (workspace_id, username, provider, model, day) DO UPDATE SET \
+         prompt_tokens = ai_usage.prompt_tokens + EXCLUDED.prompt_tokens, \
+         completion_tokens = ai_usage.completion_tokens + EXCLUDED.completion_tokens, \
+         requests = ai_usage.requests + 1",
+    )
+    .bind(w_id)
+    .bind(username)
+    .bind(provider.as_str())
+    .bind(model)
+    .bind(prompt_tokens)
+    .bind(completion_tokens)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Copilot clients speak the OpenAI chat completions API, which is translated to and from the
+/// messages API of Anthropic
+fn is_anthropic_chat_completion(provider: AIProvider, path: &str) -> bool {
+    provider == AIProvider::Anthropic && path.trim_matches('/') == "chat/completions"
+}
+
+fn message_text(content: Option<&serde_json::Value>) -> String {
+    match content {
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join(""),
+        _ => String::new(),
+    }
+}
+
+/// Returns the body of the Anthropic request and whether its response is streamed
+fn openai_to_anthropic_request(body: &[u8]) -> Result<(Vec<u8>, bool), Error> {
+    let request = serde_json::from_slice::<serde_json::Value>(body)
+        .map_err(|e| Error::BadRequest(format!("Failed to parse request body: {}", e)))?;
+
+    let mut system = vec![];
+    let mut messages: Vec<(&str, String)> = vec![];
+    let openai_messages = request.get("messages").and_then(|m| m.as_array());
+    for message in openai_messages.into_iter().flatten() {
+        let content = message_text(message.get("content"));
+        let role = match message.get("role").and_then(|r| r.as_str()) {
+            Some("system") | Some("developer") => {
+                system.push(content);
+                continue;
+            }
+            Some("assistant") => "assistant",
+            _ => "user",
+        };
+        // anthropic requires the roles to alternate
+        match messages.last_mut() {
+            Some((last_role, last_content)) if *last_role == role => {
+                last_content.push_str("\n\n");
+                last_content.push_str(&content);
+            }
+            _ => messages.push((role, content)),
+        }
+    }
+
+    let stream = request
+        .get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false);
+    let mut anthropic = serde_json::json!({
+        "model": request.get("model"),
+        "messages": messages
+            .into_iter()
+            .map(|(role, content)| serde_json::json!({ "role": role, "content": content }))
+            .collect::<Vec<_>>(),
+        "max_tokens": request
+            .get("max_tokens")
+            .or_else(|| request.get("max_completion_tokens"))
+            .and_then(|m| m.as_u64())
+            .unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+        "stream": stream,
+    });
+    if !system.is_empty() {
+        anthropic["system"] = system.join("\n\n").into();
+    }
+    for key in ["temperature", "top_p"] {
+        if let Some(value) = request.get(key) {
+            anthropic[key] = value.clone();
+        }
+    }
+    match request.get("stop") {
+        Some(serde_json::Value::String(stop)) => {
+            anthropic["stop_sequences"] = vec![stop.clone()].into()
+        }
+        Some(stop @ serde_json::Value::Array(_)) => anthropic["stop_sequences"] = stop.clone(),
+        _ => {}
+    }
+
+    let body = serde_json::to_vec(&anthropic)
+        .map_err(|e| Error::InternalErr(format!("Failed to serialize request body: {}", e)))?;
+    Ok((body, stream))
+}
+
+fn openai_finish_reason(stop_reason: Option<&serde_json::Value>) -> Option<&'static str> {
+    match stop_reason.and_then(|r| r.as_str())? {
+        "max_tokens" => Some("length"),
+        _ => Some("stop"),
+    }
+}
+
+fn anthropic_to_openai_response(body: &[u8]) -> Option<Vec<u8>> {
+    let response = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    let (prompt_tokens, completion_tokens) = extract_token_usage(body).unwrap_or((0, 0));
+    let response = serde_json::json!({
+        "id": response.get("id"),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": response.get("model"),
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": message_text(response.get("content")) },
+            "finish_reason": openai_finish_reason(response.get("stop_reason")),
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        },
+    });
+    serde_json::to_vec(&response).ok()
+}
+
+/// Translates the server-sent events of an Anthropic stream into OpenAI chat completion chunks,
+/// as they arrive
+#[derive(Default)]
+struct AnthropicStreamTranslator {
+    pending: Vec<u8>,
+    id: Option<serde_json::Value>,
+    model: Option<serde_json::Value>,
+}
+
+impl AnthropicStreamTranslator {
+    fn translate(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(bytes);
+        let mut translated = vec![];
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line = self.pending.drain(..=end).collect::<Vec<_>>();
+            let event = std::str::from_utf8(&line)
+                .ok()
+                .and_then(|line| line.trim_end().strip_prefix("data:"))
+                .and_then(|data| serde_json::from_str::<serde_json::Value>(data.trim()).ok());
+            if let Some(event) = event {
+                translated.extend(self.translate_event(&event));
+            }
+        }
+        translated
+    }
+
+    fn translate_event(&mut self, event: &serde_json::Value) -> Vec<u8> {
+        let (delta, finish_reason) = match event.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => {
+                let message = event.get("message");
+                self.id = message.and_then(|m| m.get("id")).cloned();
+                self.model = message.and_then(|m| m.get("model")).cloned();
+                (
+                    serde_json::json!({ "role": "assistant", "content": "" }),
+                    None,
+                )
+            }
+            Some("content_block_delta") => {
+                let text = event
+                    .get("delta")
+                    .and_then(|d| d.get("text"))
+                    .and_then(|t| t.as_str());
+                match text {
+                    Some(text) => (serde_json::json!({ "content": text }), None),
+                    None => return vec![],
+                }
+            }
+            Some("message_delta") => (
+                serde_json::json!({}),
+                openai_finish_reason(event.get("delta").and_then(|d| d.get("stop_reason"))),
+            ),
+            Some("message_stop") => return b"data: [DONE]\n\n".to_vec(),
+            _ => return vec![],
+        };
+        let chunk = serde_json::json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": chrono::Utc::now().timestamp(),
+            "model": self.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        format!("data: {}\n\n", chunk).into_bytes()
+    }
+}
+
+/// Drops the cached config of the workspace, for its new AI settings to apply right away
+pub async fn invalidate_ai_request_config(w_id: &str) {
+    AI_REQUEST_CONFIG_CACHE.write().await.remove(w_id);
+}
+
+#[derive(Deserialize)]
+struct ProxyQueryParams {
+    no_cache: Option<bool>,
+}
+async fn proxy(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, openai_path)): Path<(String, String)>,
+    Query(query_params): Query<ProxyQueryParams>,
+    mut body: Bytes,
+) -> impl IntoResponse {
+    let config = {
+        let mut cache = AI_REQUEST_CONFIG_CACHE.write().await;
+        match cache.get(&w_id) {
+            Some(workspace_cache)
+                if !query_params.no_cache.unwrap_or(false) && !workspace_cache.is_expired() =>
+            {
+                tracing::debug!("Using cached AI config");
+                workspace_cache.config.clone()
+            }
+            _ => {
+                let config = build_request_config(&db, &w_id).await?;
+                let expires_at = std::time::Instant::now() + std::time::Duration::from_secs(60);
+                cache.insert(
+                    w_id.clone(),
+                    AIRequestConfigCache::new(config.clone(), expires_at),
+                );
+                config
+            }
+        }
+    };
+
+    let inject_user = config.user.is_some() && config.provider != AIProvider::Anthropic;
+    let mut model = None::<String>;
+    if inject_user || config.model.is_some() {
+        let mut json_body: HashMap<String, Box<RawValue>> = serde_json::from_slice(&body)
+            .map_err(|e| Error::InternalErr(format!("Failed to parse request body: {}", e)))?;
+
+        if inject_user {
+            tracing::debug!("Adding user to request body");
+            let user_json_string =
+                serde_json::Value::String(config.user.clone().unwrap()).to_string(); // makes sure to escape characters
+
+            json_body.insert(
+                "user".to_string(),
+                RawValue::from_string(user_json_string)
+                    .map_err(|e| Error::InternalErr(format!("Failed to parse user: {}", e)))?,
+            );
+        }
+
+        if let Some(workspace_model) = config.model.as_ref() {
+            tracing::debug!("Using workspace model {workspace_model}");
+            json_body.insert(
+                "model".to_string(),
+                RawValue::from_string(
+                    serde_json::Value::String(workspace_model.clone()).to_string(),
+                )
+                .map_err(|e| Error::InternalErr(format!("Failed to parse model: {}", e)))?,
+            );
+            model = Some(workspace_model.clone());
+        }
+
+        body = serde_json::to_vec(&json_body)
+            .map_err(|e| Error::InternalErr(format!("Failed to reserialize request body: {}", e)))?
+            .into();
+    }
+
+    let anthropic_chat = is_anthropic_chat_completion(config.provider, &openai_path);
+    let (path, streamed) = if anthropic_chat {
+        let (anthropic_body, streamed) = openai_to_anthropic_request(&body)?;
+        body = anthropic_body.into();
+        ("messages".to_string(), streamed)
+    } else {
+        (openai_path.clone(), false)
+    };
+
+    let model = model
+        .or_else(|| {
+            serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|v| v.get("model").and_then(|m| m.as_str()).map(String::from))
+        })
+        .unwrap_or_default();
+
+    if config.log_requests {
+        tracing::info!(
+            workspace_id = %w_id,
+            username = %authed.username,
+            provider = config.provider.as_str(),
+            path = %openai_path,
+            "AI request: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+
+    let response = config
+        .prepare_request(&path, body)
+        .send()
+        .await
+        .map_err(to_anyhow)?;
+
+    let mut tx = db.begin().await?;
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "openai.request",
+        ActionKind::Execute,
+        &w_id,
+        Some(&authed.email),
+        Some(
+            [
+                ("openai_path", &format!("{:?}", openai_path)[..]),
+                ("provider", config.provider.as_str()),
+            ]
+            .into(),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    if response.error_for_status_ref().is_err() {
+        return Err(Error::OpenAIError(
+            response.text().await.unwrap_or("".to_string()),
+        ));
+    }
+
+    let status_code = response.status();
+    let mut headers = response.headers().clone();
+    if anthropic_chat {
+        headers.remove(reqwest::header::CONTENT_LENGTH);
+    }
+    let mut stream = response.bytes_stream();
+    let mut translator = AnthropicStreamTranslator::default();
+
+    // the response is forwarded as it arrives while being accumulated to account for token usage
+    let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<Bytes, reqwest::Error>>();
+    tokio::spawn(async move {
+        let mut buffer: Vec<u8> = vec![];
+        let mut overflowed = false;
+        while let Some(chunk) = stream.next().await {
+            if let Ok(bytes) = chunk.as_ref() {
+                if buffer.len() + bytes.len() > MAX_USAGE_BUFFER_SIZE {
+                    overflowed = true;
+                } else if !overflowed {
+                    buffer.extend_from_slice(bytes);
+                }
+            }
+            let chunk = match chunk {
+                Ok(bytes) if anthropic_chat && streamed => Ok(translator.translate(&bytes).into()),
+                // the whole response is needed to translate it
+                Ok(_) if anthropic_chat => continue,
+                chunk => chunk,
+            };
+            if sender.unbounded_send(chunk).is_err() {
+                tracing::debug!("AI proxy client disconnected before the end of the response");
+                break;
+            }
+        }
+        if anthropic_chat && !streamed {
+            let translated = anthropic_to_openai_response(&buffer).unwrap_or_else(|| {
+                tracing::error!(
+                    "Could not translate the Anthropic response, truncated: {overflowed}"
+                );
+                buffer.clone()
+            });
+            let _ = sender.unbounded_send(Ok(translated.into()));
+        }
+        drop(sender);
+
+        if config.log_responses {
+            tracing::info!(
+                workspace_id = %w_id,
+                username = %authed.username,
+                provider = config.provider.as_str(),
+                truncated = overflowed,
+                "AI response: {}",
+                String::from_utf8_lossy(&buffer)
+            );
+        }
+
+        let usage = if overflowed {
+            None
+        } else {
+            extract_token_usage(&buffer)
+        };
+        if let Err(e) = record_token_usage(
+            &db,
+            &w_id,
+            &authed.username,
+            config.provider,
+            &model,
+            usage.unwrap_or((0, 0)),
+        )
+        .await
+        {
+            tracing::error!("Could not record AI token usage: {e}");
+        }
+    });
+
+    Ok((status_code, headers, StreamBody::new(receiver)))
+}
+
+#[derive(FromRow, Serialize)]
+struct AIUsage {
+    username: String,
+    provider: String,
+    model: String,
+    day: chrono::NaiveDate,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    requests: i64,
+}
+
+#[derive(Deserialize)]
+struct ListUsageQuery {
+    username: Option<String>,
+    after: Option<chrono::NaiveDate>,
+    before: Option<chrono::NaiveDate>,
+}
+
+async fn list_usage(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(lq): Query<ListUsageQuery>,
+) -> JsonResult<Vec<AIUsage>> {
+    let (per_page, offset) = paginate(pagination);
+
+    // non-admins can only see their own consumption
+    let username = if authed.is_admin {
+        lq.username
+    } else {
+        Some(authed.username.clone())
+    };
+
+    let rows = sqlx::query_as::<_, AIUsage>(
+        "SELECT username, provider, model, day, prompt_tokens, completion_tokens, requests \
+         FROM ai_usage \
+         WHERE workspace_id = $1 AND ($2::text IS NULL OR username = $2) \
+         AND ($3::date IS NULL OR day >= $3) AND ($4::date IS NULL OR day <= $4) \
+         ORDER BY day DESC, username LIMIT $5 OFFSET $6",
+    )
+    .bind(&w_id)
+    .bind(username)
+    .bind(lq.after)
+    .bind(lq.before)
+    .bind(per_page as i64)
+    .bind(offset as i64)
+    .fetch_all(&db)
+    .await?;
+
+    Ok(Json(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_usage_from_json_response() {
+        let openai =
+            br#"{"id":"x","usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42}}"#;
+        assert_eq!(extract_token_usage(openai), Some((12, 30)));
+
+        let anthropic = br#"{"id":"x","usage":{"input_tokens":7,"output_tokens":3}}"#;
+        assert_eq!(extract_token_usage(anthropic), Some((7, 3)));
+
+        assert_eq!(extract_token_usage(br#"{"id":"x"}"#), None);
+    }
+
+    #[test]
+    fn token_usage_from_event_stream() {
+        let anthropic = b"event: message_start\n\
+data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n\
+event: content_block_delta\n\
+data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"hi\"}}\n\n\
+event: message_delta\n\
+data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":15}}\n\n";
+        assert_eq!(extract_token_usage(anthropic), Some((25, 15)));
+
+        let openai = b"data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\ndata: [DONE]\n\n";
+        assert_eq!(extract_token_usage(openai), None);
+    }
+
+    #[test]
+    fn anthropic_request_from_openai() {
+        let openai = br#"{"model":"claude-3-haiku","stream":true,"stop":"END","temperature":0.2,
+            "messages":[{"role":"system","content":"be brief"},{"role":"user","content":"hi"},
+            {"role":"user","content":[{"type":"text","text":"again"}]},{"role":"assistant","content":"hello"}]}"#;
+        let (body, streamed) = openai_to_anthropic_request(openai).unwrap();
+        assert!(streamed);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "model": "claude-3-haiku",
+                "system": "be brief",
+                "messages": [
+                    { "role": "user", "content": "hi\n\nagain" },
+                    { "role": "assistant", "content": "hello" },
+                ],
+                "max_tokens": ANTHROPIC_DEFAULT_MAX_TOKENS,
+                "stream": true,
+                "temperature": 0.2,
+                "stop_sequences": ["END"],
+            })
+        );
+    }
+
+    #[test]
+    fn openai_response_from_anthropic() {
+        let anthropic = br#"{"id":"msg_1","model":"claude-3-haiku","stop_reason":"max_tokens",
+            "content":[{"type":"text","text":"hello"}],"usage":{"input_tokens":7,"output_tokens":3}}"#;
+        let response = serde_json::from_slice::<serde_json::Value>(
+            &anthropic_to_openai_response(anthropic).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response["choices"][0]["message"]["content"], "hello");
+        assert_eq!(response["choices"][0]["finish_reason"], "length");
+        assert_eq!(response["usage"]["total_tokens"], 10);
+    }
+
+    #[test]
+    fn openai_chunks_from_anthropic_stream() {
+        let mut translator = AnthropicStreamTranslator::default();
+        let mut translated = translator.translate(
+            b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"model\":\"m\"}}\n\nevent: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",",
+        );
+        // events split across chunks are translated once complete
+        translated.extend(translator.translate(
+            b"\"text\":\"hi\"}}\n\nevent: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"}}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        ));
+        let translated = String::from_utf8(translated).unwrap();
+        let chunks = translated
+            .split("\n\n")
+            .filter_map(|c| c.strip_prefix("data: "))
+            .collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 4);
+        let chunk = |i: usize| serde_json::from_str::<serde_json::Value>(chunks[i]).unwrap();
+        assert_eq!(chunk(0)["id"], "msg_1");
+        assert_eq!(chunk(1)["choices"][0]["delta"]["content"], "hi");
+        assert_eq!(chunk(2)["choices"][0]["finish_reason"], "stop");
+        assert_eq!(chunks[3], "[DONE]");
+    }
+}
@@ -11,7 +11,7 @@ use crate::{
     db::{ApiAuthed, DB},
     deployment_metadata_helpers,
     users::{require_owner_of_path, OptAuthed},
-    variables::build_crypt,
+    variables::{build_crypt, build_decrypt},
     webhook_util::{WebhookMessage, WebhookShared},
     HTTP_CLIENT,
 };
@@ -36,9 +36,11 @@ use windmill_common::{
     db::UserDB,
     error::{to_anyhow, Error, JsonResult, Result},
     jobs::{get_payload_tag_from_prefixed_path, JobPayload, RawCode},
+    service_accounts::get_service_account_identity,
     users::username_to_permissioned_as,
     utils::{
-        http_get_from_hub, not_found_if_none, paginate, query_elems_from_hub, Pagination, StripPath,
+        http_get_from_hub, not_found_if_none, paginate, query_elems_from_hub, require_admin,
+        Pagination, StripPath,
     },
 };
 use windmill_queue::{push, PushArgs, PushIsolationLevel, QueueTransaction};
@@ -160,6 +162,9 @@ pub struct Policy {
     // - rawscript/<sha256>
     pub triggerables: HashMap<String, StaticFields>,
     pub execution_mode: ExecutionMode,
+    /// when set, the app runs on behalf of this service account instead of its publisher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -418,11 +423,11 @@ async fn get_public_app_by_secret(
 ) -> JsonResult<AppWithLastVersion> {
     let mut tx = db.begin().await?;
 
-    let mc = build_crypt(&mut tx, &w_id).await?;
+    let decrypt = build_decrypt(&mut tx, &w_id).await?;
 
-    let decrypted = mc
-        .decrypt_bytes_to_bytes(&(hex::decode(secret)?))
-        .map_err(|e| Error::InternalErr(e.to_string()))?;
+    let decrypted = decrypt.decrypt_bytes_to_bytes(&(hex::decode(secret)?), |b| {
+        str::from_utf8(b).is_ok_and(|s| s.parse::<i64>().is_ok())
+    })?;
     let bytes = str::from_utf8(&decrypted).map_err(to_anyhow)?;
 
     let id: i64 = bytes.parse().map_err(to_anyhow)?;
@@ -513,8 +518,7 @@ async fn create_app(
 ) -> Result<(StatusCode, String)> {
     let mut tx: QueueTransaction<'_, _> = (rsmq, user_db.clone().begin(&authed).await?).into();
 
-    app.policy.on_behalf_of = Some(username_to_permissioned_as(&authed.username));
-    app.policy.on_behalf_of_email = Some(authed.email.clone());
+    set_policy_on_behalf_of(&db, &authed, &w_id, &mut app.policy).await?;
 
     if &app.path == "" {
         return Err(Error::BadRequest("App path cannot be empty".to_string()));
@@ -726,6 +730,27 @@ async fn delete_app(
     Ok(format!("app {} deleted", path))
 }
 
+/// Apps run on behalf of their publisher unless their policy names a service account, which
+/// only admins can set
+async fn set_policy_on_behalf_of(
+    db: &DB,
+    authed: &ApiAuthed,
+    w_id: &str,
+    policy: &mut Policy,
+) -> Result<()> {
+    if let Some(service_account) = policy.service_account.as_ref() {
+        require_admin(authed.is_admin, &authed.username)?;
+        let (permissioned_as, email) =
+            get_service_account_identity(db, w_id, service_account).await?;
+        policy.on_behalf_of = Some(permissioned_as);
+        policy.on_behalf_of_email = Some(email);
+    } else {
+        policy.on_behalf_of = Some(username_to_permissioned_as(&authed.username));
+        policy.on_behalf_of_email = Some(authed.email.clone());
+    }
+    Ok(())
+}
+
 async fn update_app(
     authed: ApiAuthed,
     Extension(db): Extension<DB>,
@@ -741,6 +766,19 @@ async fn update_app(
 
     let mut tx: QueueTransaction<'_, _> = (rsmq, user_db.clone().begin(&authed).await?).into();
 
+    let runs_as_service_account = sqlx::query_scalar::<_, Option<bool>>(
+        "SELECT policy->>'service_account' IS NOT NULL FROM app WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(path)
+    .bind(&w_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .flatten()
+    .unwrap_or(false);
+    if runs_as_service_account {
+        require_admin(authed.is_admin, &authed.username)?;
+    }
+
     let npath = if ns.policy.is_some() || ns.path.is_some() || ns.summary.is_some() {
         let mut sqlb = SqlBuilder::update_table("app");
         sqlb.and_where_eq("path", "?".bind(&path));
@@ -775,8 +813,7 @@ async fn update_app(
         }
 
         if let Some(mut npolicy) = ns.policy {
-            npolicy.on_behalf_of = Some(username_to_permissioned_as(&authed.username));
-            npolicy.on_behalf_of_email = Some(authed.email.clone());
+            set_policy_on_behalf_of(&db, &authed, &w_id, &mut npolicy).await?;
             sqlb.set(
                 "policy",
                 &format!(
@@ -973,6 +1010,7 @@ async fn execute_component(
             triggerables: hm,
             on_behalf_of: None,
             on_behalf_of_email: None,
+            service_account: None,
         }
     } else {
         let policy_o = sqlx::query_scalar!(
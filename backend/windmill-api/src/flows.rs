@@ -37,7 +37,8 @@ use windmill_common::{
     jobs::JobPayload,
     schedule::Schedule,
     scripts::Schema,
-    utils::{http_get_from_hub, not_found_if_none, paginate, Pagination, StripPath},
+    service_accounts::get_service_account_identity,
+    utils::{http_get_from_hub, not_found_if_none, paginate, require_admin, Pagination, StripPath},
 };
 use windmill_queue::PushArgs;
 use windmill_queue::{push, schedule::push_scheduled_job, PushIsolationLevel, QueueTransaction};
@@ -331,6 +332,15 @@ async fn create_flow(
     .execute(&mut tx)
     .await?;
 
+    set_flow_service_account(
+        tx.transaction_mut(),
+        &authed,
+        &w_id,
+        &nf.path,
+        nf.service_account.as_deref(),
+    )
+    .await?;
+
     sqlx::query!(
         "DELETE FROM draft WHERE path = $1 AND workspace_id = $2 AND typ = 'flow'",
         nf.path,
@@ -444,6 +454,40 @@ async fn check_schedule_conflict<'c>(
     Ok(())
 }
 
+/// Only admins can make a flow run as a service account or edit one that does
+async fn set_flow_service_account<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    authed: &ApiAuthed,
+    w_id: &str,
+    path: &str,
+    service_account: Option<&str>,
+) -> Result<()> {
+    let current = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT service_account FROM flow WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(path)
+    .bind(w_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .flatten();
+
+    if current.is_none() && service_account.is_none() {
+        return Ok(());
+    }
+    require_admin(authed.is_admin, &authed.username)?;
+    if let Some(service_account) = service_account {
+        get_service_account_identity(&mut **tx, w_id, service_account).await?;
+    }
+
+    sqlx::query("UPDATE flow SET service_account = $1 WHERE path = $2 AND workspace_id = $3")
+        .bind(service_account)
+        .bind(path)
+        .bind(w_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
 pub async fn require_is_writer(authed: &ApiAuthed, path: &str, w_id: &str, db: DB) -> Result<()> {
     return crate::users::require_is_writer(
         authed,
@@ -512,6 +556,15 @@ async fn update_flow(
     .execute(&mut tx)
     .await?;
 
+    set_flow_service_account(
+        tx.transaction_mut(),
+        &authed,
+        &w_id,
+        &nf.path,
+        nf.service_account.as_deref(),
+    )
+    .await?;
+
     if nf.path != flow_path {
         check_schedule_conflict(tx.transaction_mut(), &w_id, &nf.path).await?;
 
@@ -860,6 +913,7 @@ mod tests {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -886,6 +940,7 @@ mod tests {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -909,6 +964,7 @@ mod tests {
                     retry: None,
                     sleep: None,
                     cache_ttl: None,
+                    cache_key_args: None,
                     mock: None,
                     timeout: None,
                     priority: None,
@@ -931,6 +987,7 @@ mod tests {
                 retry: None,
                 sleep: None,
                 cache_ttl: None,
+                cache_key_args: None,
                 mock: None,
                 timeout: None,
                 priority: None,
@@ -941,6 +998,7 @@ mod tests {
             concurrency_time_window_s: None,
             skip_expr: None,
             cache_ttl: None,
+            cache_key_args: None,
             priority: None,
             early_return: None,
         };
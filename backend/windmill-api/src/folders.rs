@@ -26,6 +26,7 @@ use windmill_audit::{audit_log, ActionKind};
 use windmill_common::{
     db::UserDB,
     error::{self, to_anyhow, JsonResult, Result},
+    folders::FolderDefaults,
     users::username_to_permissioned_as,
     utils::{not_found_if_none, paginate, Pagination},
 };
@@ -40,6 +41,8 @@ pub fn workspaced_service() -> Router {
         .route("/create", post(create_folder))
         .route("/get/:name", get(get_folder))
         .route("/update/:name", post(update_folder))
+        .route("/get_defaults/:name", get(get_folder_defaults))
+        .route("/update_defaults/:name", post(update_folder_defaults))
         .route("/getusage/:name", get(get_folder_usage))
         .route("/delete/:name", delete(delete_folder))
         .route("/addowner/:name", post(add_owner))
@@ -358,6 +361,78 @@ async fn get_folder(
     Ok(Json(folder))
 }
 
+async fn get_folder_defaults(
+    authed: ApiAuthed,
+    Extension(user_db): Extension<UserDB>,
+    Path((w_id, name)): Path<(String, String)>,
+) -> JsonResult<FolderDefaults> {
+    let mut tx = user_db.begin(&authed).await?;
+
+    let defaults = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT default_settings FROM folder WHERE name = $1 AND workspace_id = $2",
+    )
+    .bind(&name)
+    .bind(&w_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let defaults = not_found_if_none(defaults, "Folder", &name)?
+        .map(serde_json::from_value::<FolderDefaults>)
+        .transpose()
+        .map_err(to_anyhow)?
+        .unwrap_or_default();
+
+    tx.commit().await?;
+    Ok(Json(defaults))
+}
+
+async fn update_folder_defaults(
+    authed: ApiAuthed,
+    Extension(user_db): Extension<UserDB>,
+    Extension(webhook): Extension<WebhookShared>,
+    Path((w_id, name)): Path<(String, String)>,
+    Json(defaults): Json<FolderDefaults>,
+) -> Result<String> {
+    require_is_owner(&authed, &name)?;
+
+    if let Some(error_handler) = defaults.error_handler.as_ref() {
+        if !error_handler.starts_with("script/") && !error_handler.starts_with("flow/") {
+            return Err(error::Error::BadRequest(format!(
+                "error handler must start with script/ or flow/ (got {error_handler})"
+            )));
+        }
+    }
+
+    let mut tx = user_db.begin(&authed).await?;
+
+    let updated = sqlx::query_scalar::<_, String>(
+        "UPDATE folder SET default_settings = $1 WHERE name = $2 AND workspace_id = $3 RETURNING name",
+    )
+    .bind(serde_json::to_value(&defaults).map_err(to_anyhow)?)
+    .bind(&name)
+    .bind(&w_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    not_found_if_none(updated, "Folder", &name)?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "folder.update_defaults",
+        ActionKind::Update,
+        &w_id,
+        Some(&name.to_string()),
+        Some([("default_settings", &format!("{:?}", defaults)[..])].into()),
+    )
+    .await?;
+    tx.commit().await?;
+    webhook.send_message(
+        w_id.clone(),
+        WebhookMessage::UpdateFolder { workspace: w_id, name: name.to_owned() },
+    );
+
+    Ok(format!("Updated default settings of folder {}", name))
+}
+
 #[derive(Serialize)]
 struct FolderUsage {
     pub scripts: i64,
@@ -510,7 +585,7 @@ async fn add_owner(
         &mut *tx,
         &authed.username,
         "folder.add_owner",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         &w_id,
         Some(&name.to_string()),
         Some([("owner", owner.as_str())].into()),
@@ -577,7 +652,7 @@ async fn remove_owner(
         &mut *tx,
         &authed.username,
         "folder.remove_owner",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         &w_id,
         Some(&name.to_string()),
         Some([("owner", owner.as_str())].into()),
@@ -16,6 +16,7 @@ use axum::{
 use crate::db::ApiAuthed;
 
 use serde::{Deserialize, Serialize};
+use windmill_audit::{audit_log, ActionKind};
 use windmill_common::{
     db::UserDB,
     error::{Error, JsonResult, Result},
@@ -29,6 +30,31 @@ pub fn workspaced_service() -> Router {
         .route("/remove/*path", post(remove_granular_acl))
 }
 
+/// Kinds of items holding granular acls
+pub const ACL_KINDS: [&str; 9] = [
+    "script", "group_", "resource", "schedule", "variable", "flow", "folder", "app", "raw_app",
+];
+
+/// Only the owners of an item, or admins, can share it
+pub async fn require_can_share(
+    authed: &ApiAuthed,
+    db: &DB,
+    w_id: &str,
+    kind: &str,
+    path: &str,
+) -> Result<()> {
+    if authed.is_admin {
+        return Ok(());
+    }
+    if kind == "folder" {
+        crate::folders::require_is_owner(authed, path)
+    } else if kind == "group_" {
+        crate::groups::require_is_owner(path, &authed.username, &authed.groups, w_id, db).await
+    } else {
+        require_owner_of_path(authed, path)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GranularAcl {
     pub owner: String,
@@ -55,16 +81,7 @@ async fn add_granular_acl(
         "path"
     };
 
-    if !authed.is_admin {
-        if kind == "folder" {
-            crate::folders::require_is_owner(&authed, path)?;
-        } else if kind == "group_" {
-            crate::groups::require_is_owner(path, &authed.username, &authed.groups, &w_id, &db)
-                .await?;
-        } else {
-            require_owner_of_path(&authed, path)?;
-        }
-    }
+    require_can_share(&authed, &db, &w_id, kind, path).await?;
 
     let obj_o = sqlx::query_scalar::<_, serde_json::Value>(&format!(
         "UPDATE {kind} SET extra_perms = jsonb_set(extra_perms, '{{\"{owner}\"}}', to_jsonb($1), \
@@ -77,6 +94,18 @@ async fn add_granular_acl(
     .await?;
 
     let _ = not_found_if_none(obj_o, &kind, &path)?;
+
+    let write = write.unwrap_or(false).to_string();
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "acls.add",
+        ActionKind::PermissionChange,
+        &w_id,
+        Some(&format!("{kind}/{path}")),
+        Some([("owner", owner.as_str()), ("write", write.as_str())].into()),
+    )
+    .await?;
     tx.commit().await?;
 
     Ok("Successfully modified granular acl".to_string())
@@ -95,16 +124,7 @@ async fn remove_granular_acl(
         .split_once('/')
         .ok_or_else(|| Error::BadRequest("Invalid path or kind".to_string()))?;
 
-    if !authed.is_admin {
-        if kind == "folder" {
-            crate::folders::require_is_owner(&authed, path)?;
-        } else if kind == "group_" {
-            crate::groups::require_is_owner(path, &authed.username, &authed.groups, &w_id, &db)
-                .await?;
-        } else {
-            require_owner_of_path(&authed, path)?;
-        }
-    }
+    require_can_share(&authed, &db, &w_id, kind, path).await?;
 
     let mut tx = user_db.begin(&authed).await?;
 
@@ -122,13 +142,24 @@ async fn remove_granular_acl(
         "UPDATE {kind} SET extra_perms = extra_perms - $1 WHERE {identifier} = $2 AND \
          workspace_id = $3 RETURNING extra_perms"
     ))
-    .bind(owner)
+    .bind(&owner)
     .bind(path)
-    .bind(w_id)
+    .bind(&w_id)
     .fetch_optional(&mut *tx)
     .await?;
 
     let _ = not_found_if_none(obj_o, &kind, &path)?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "acls.remove",
+        ActionKind::PermissionChange,
+        &w_id,
+        Some(&format!("{kind}/{path}")),
+        Some([("owner", owner.as_str())].into()),
+    )
+    .await?;
     tx.commit().await?;
 
     Ok("Successfully removed granular acl".to_string())
@@ -484,7 +484,7 @@ async fn add_user(
         &mut *tx,
         &authed.username,
         "group.adduser",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         &w_id,
         Some(&name.to_string()),
         Some([("user", user_username.as_str())].into()),
@@ -522,7 +522,7 @@ async fn add_user_igroup(
         &mut *tx,
         &authed.username,
         "igroup.adduser",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         "global",
         Some(&name.to_string()),
         Some([("email", email.as_str())].into()),
@@ -590,7 +590,7 @@ async fn remove_user_igroup(
         &mut *tx,
         &authed.username,
         "igroup.removeuser",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         "global",
         Some(&name.to_string()),
         Some([("email", email.as_str())].into()),
@@ -629,7 +629,7 @@ async fn remove_user(
         &mut *tx,
         &authed.username,
         "group.removeuser",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         &w_id,
         Some(&name.to_string()),
         Some([("user", user_username.as_str())].into()),
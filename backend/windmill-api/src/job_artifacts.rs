@@ -0,0 +1,105 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use hyper::header;
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+use windmill_common::{
+    db::UserDB,
+    error::{Error, JsonResult, Result},
+    utils::{not_found_if_none, StripPath},
+};
+
+use crate::{
+    db::{ApiAuthed, DB},
+    job_helpers::read_workspace_s3_object,
+    users::Tokened,
+};
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/list/:id", get(list_job_artifacts))
+        .route("/download/:id/*name", get(download_job_artifact))
+}
+
+/// File written by a job in its `./artifacts/` dir
+#[derive(Serialize, FromRow)]
+struct JobArtifact {
+    name: String,
+    size: i64,
+    /// key of the file in the large file storage of the workspace, if it is stored there
+    s3_key: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+async fn list_job_artifacts(
+    Extension(db): Extension<DB>,
+    Path((w_id, id)): Path<(String, Uuid)>,
+) -> JsonResult<Vec<JobArtifact>> {
+    let artifacts = sqlx::query_as::<_, JobArtifact>(
+        "SELECT name, size, s3_key, created_at FROM job_artifact \
+         WHERE workspace_id = $1 AND job_id = $2 ORDER BY name",
+    )
+    .bind(&w_id)
+    .bind(id)
+    .fetch_all(&db)
+    .await?;
+    Ok(Json(artifacts))
+}
+
+async fn download_job_artifact(
+    authed: ApiAuthed,
+    Extension(user_db): Extension<UserDB>,
+    Extension(db): Extension<DB>,
+    Tokened { token }: Tokened,
+    Path((w_id, id, name)): Path<(String, Uuid, StripPath)>,
+) -> Result<impl IntoResponse> {
+    let name = name.to_path();
+    let artifact = sqlx::query_as::<_, (Option<String>, Option<Vec<u8>>)>(
+        "SELECT s3_key, content FROM job_artifact \
+         WHERE workspace_id = $1 AND job_id = $2 AND name = $3",
+    )
+    .bind(&w_id)
+    .bind(id)
+    .bind(name)
+    .fetch_optional(&db)
+    .await?;
+    let content = match not_found_if_none(artifact, "Job artifact", name)? {
+        (_, Some(content)) => content,
+        (Some(s3_key), None) => {
+            read_workspace_s3_object(&authed, &user_db, &db, &token, &w_id, &s3_key).await?
+        }
+        (None, None) => {
+            return Err(Error::InternalErr(format!(
+                "artifact {name} of job {id} has no content"
+            )))
+        }
+    };
+
+    let file_name = name.rsplit('/').next().unwrap_or(name).replace('"', "_");
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            mime_guess::from_path(name)
+                .first_or_octet_stream()
+                .to_string(),
+        ),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{file_name}\""),
+        ),
+    ];
+    Ok((headers, content))
+}
@@ -683,6 +683,42 @@ async fn get_s3_resource<'c>(
     return Ok(Some(s3_resource));
 }
 
+/// Content of an object of the large file storage of the workspace
+pub(crate) async fn read_workspace_s3_object(
+    authed: &ApiAuthed,
+    user_db: &UserDB,
+    db: &DB,
+    token: &str,
+    w_id: &str,
+    file_key: &str,
+) -> error::Result<Vec<u8>> {
+    let s3_resource = get_workspace_s3_resource(authed, user_db, db, token, w_id)
+        .await?
+        .ok_or(error::Error::InternalErr(
+            "No files storage resource defined at the workspace level".to_string(),
+        ))?;
+    let s3_client = build_s3_client(&s3_resource);
+
+    let s3_object = s3_client
+        .get_object()
+        .bucket(&s3_resource.bucket)
+        .key(file_key)
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::warn!("Error fetching file from S3: {:?}", err);
+            error::Error::InternalErr(err.to_string())
+        })?;
+    let payload = s3_object
+        .body
+        .collect()
+        .await
+        .map_err(|err| error::Error::InternalErr(err.to_string()))?
+        .into_bytes()
+        .to_vec();
+    return Ok(payload);
+}
+
 fn build_s3_client(s3_resource_ref: &S3Resource) -> aws_sdk_s3::Client {
     let s3_resource = s3_resource_ref.clone();
 
@@ -17,7 +17,7 @@ use crate::{
     db::DB,
     users::{check_scopes, require_owner_of_path, OptAuthed},
     utils::require_super_admin,
-    variables::get_workspace_key,
+    variables::{get_previous_workspace_key, get_workspace_key},
 };
 use anyhow::Context;
 use axum::{
@@ -46,6 +46,7 @@ use windmill_common::{
     jobs::{script_path_to_payload, CompletedJob, JobKind, JobPayload, QueuedJob, RawCode},
     oauth2::HmacSha256,
     scripts::{ScriptHash, ScriptLang},
+    service_accounts::get_flow_run_as,
     users::username_to_permissioned_as,
     utils::{not_found_if_none, now_from_db, paginate, require_admin, Pagination, StripPath},
 };
@@ -369,7 +370,7 @@ async fn get_job_internal(db: &DB, workspace_id: &str, job_id: Uuid) -> error::R
         id, workspace_id, parent_job, created_by, created_at, duration_ms, success, script_hash, script_path, 
         CASE WHEN args is null or pg_column_size(args) < 2000000 THEN args ELSE '{\"reason\": \"WINDMILL_TOO_BIG\"}'::jsonb END as args, CASE WHEN result is null or pg_column_size(result) < 2000000 THEN result ELSE '\"WINDMILL_TOO_BIG\"'::jsonb END as result, right(logs, 20000000) as logs, deleted, raw_code, canceled, canceled_by, canceled_reason, job_kind, env_id,
         schedule_path, permissioned_as, flow_status, raw_flow, is_flow_step, language, started_at, is_skipped,
-        raw_lock, email, visible_to_owner, mem_peak, tag, priority
+        raw_lock, email, visible_to_owner, mem_peak, tag, priority, cache_hit
         FROM completed_job WHERE id = $1 AND workspace_id = $2")
             .bind(job_id)
             .bind(workspace_id)
@@ -456,6 +457,7 @@ pub struct ListableCompletedJob {
     pub tag: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub priority: Option<i16>,
+    pub cache_hit: bool,
 }
 
 #[derive(Deserialize, Clone)]
@@ -783,6 +785,7 @@ async fn list_jobs(
                 "null as concurrent_limit",
                 "null as concurrency_time_window_s",
                 "priority",
+                "cache_hit",
             ],
         ))
     } else {
@@ -844,6 +847,7 @@ async fn list_jobs(
                 "concurrent_limit",
                 "concurrency_time_window_s",
                 "priority",
+                "false as cache_hit",
             ],
         );
 
@@ -892,6 +896,33 @@ pub async fn resume_suspended_flow_as_owner(
     Ok(StatusCode::CREATED)
 }
 
+/// Verifies the signature of a resume url, which may have been signed with the key the workspace
+/// had before its last rotation
+async fn verify_resume_signature(
+    tx: &mut Transaction<'_, Postgres>,
+    w_id: &str,
+    job_id: Uuid,
+    resume_id: u32,
+    approver: Option<&str>,
+    secret: &str,
+) -> error::Result<()> {
+    let signature = hex::decode(secret)?;
+    let mut keys = vec![get_workspace_key(w_id, tx).await?];
+    keys.extend(get_previous_workspace_key(w_id, tx).await?);
+    for key in keys {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).map_err(to_anyhow)?;
+        mac.update(job_id.as_bytes());
+        mac.update(resume_id.to_be_bytes().as_ref());
+        if let Some(approver) = approver {
+            mac.update(approver.as_bytes());
+        }
+        if mac.verify_slice(signature.as_ref()).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(anyhow::anyhow!("Invalid signature").into())
+}
+
 pub async fn resume_suspended_job(
     authed: Option<ApiAuthed>,
     Extension(db): Extension<DB>,
@@ -901,15 +932,15 @@ pub async fn resume_suspended_job(
 ) -> error::Result<StatusCode> {
     let value = value.unwrap_or(serde_json::Value::Null);
     let mut tx = db.begin().await?;
-    let key = get_workspace_key(&w_id, &mut tx).await?;
-    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).map_err(to_anyhow)?;
-    mac.update(job_id.as_bytes());
-    mac.update(resume_id.to_be_bytes().as_ref());
-    if let Some(approver) = approver.approver.clone() {
-        mac.update(approver.as_bytes());
-    }
-    mac.verify_slice(hex::decode(secret)?.as_ref())
-        .map_err(|_| anyhow::anyhow!("Invalid signature"))?;
+    verify_resume_signature(
+        &mut tx,
+        &w_id,
+        job_id,
+        resume_id,
+        approver.approver.as_deref(),
+        &secret,
+    )
+    .await?;
     let parent_flow_info = get_suspended_parent_flow_info(job_id, &mut tx).await?;
     let parent_flow = get_job_internal(&db, w_id.as_str(), parent_flow_info.id).await?;
     let flow_status = parent_flow
@@ -1074,15 +1105,15 @@ pub async fn cancel_suspended_job(
     Query(approver): Query<QueryApprover>,
 ) -> error::Result<String> {
     let mut tx = db.begin().await?;
-    let key = get_workspace_key(&w_id, &mut tx).await?;
-    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).map_err(to_anyhow)?;
-    mac.update(job.as_bytes());
-    mac.update(resume_id.to_be_bytes().as_ref());
-    if let Some(approver) = approver.approver.clone() {
-        mac.update(approver.as_bytes());
-    }
-    mac.verify_slice(hex::decode(secret)?.as_ref())
-        .map_err(|_| anyhow::anyhow!("Invalid signature"))?;
+    verify_resume_signature(
+        &mut tx,
+        &w_id,
+        job,
+        resume_id,
+        approver.approver.as_deref(),
+        &secret,
+    )
+    .await?;
 
     let whom = approver.approver.unwrap_or_else(|| "unknown".to_string());
     let parent_flow_id = get_suspended_parent_flow_info(job, &mut tx).await?.id;
@@ -1143,15 +1174,15 @@ pub async fn get_suspended_job_flow(
     Query(approver): Query<QueryApprover>,
 ) -> error::Result<Response> {
     let mut tx = db.begin().await?;
-    let key = get_workspace_key(&w_id, &mut tx).await?;
-    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).map_err(to_anyhow)?;
-    mac.update(job.as_bytes());
-    mac.update(resume_id.to_be_bytes().as_ref());
-    if let Some(approver) = approver.approver {
-        mac.update(approver.as_bytes());
-    }
-    mac.verify_slice(hex::decode(secret)?.as_ref())
-        .map_err(|_| anyhow::anyhow!("Invalid signature"))?;
+    verify_resume_signature(
+        &mut tx,
+        &w_id,
+        job,
+        resume_id,
+        approver.approver.as_deref(),
+        &secret,
+    )
+    .await?;
 
     let flow_id = sqlx::query_scalar!(
         r#"
@@ -1403,6 +1434,7 @@ struct UnifiedJob {
     concurrent_limit: Option<i32>,
     concurrency_time_window_s: Option<i32>,
     priority: Option<i16>,
+    cache_hit: bool,
 }
 
 impl<'a> From<UnifiedJob> for Job {
@@ -1440,6 +1472,7 @@ impl<'a> From<UnifiedJob> for Job {
                 mem_peak: uj.mem_peak,
                 tag: uj.tag,
                 priority: uj.priority,
+                cache_hit: uj.cache_hit,
             }),
             "QueuedJob" => Job::QueuedJob(QueuedJob {
                 workspace_id: uj.workspace_id,
@@ -1692,6 +1725,14 @@ pub async fn run_flow_by_path(
     .unwrap_or_else(|| (None, None));
 
     check_tag_available_for_workspace(&w_id, &tag).await?;
+    let (permissioned_as, email) = get_flow_run_as(&db, &w_id, flow_path)
+        .await?
+        .unwrap_or_else(|| {
+            (
+                username_to_permissioned_as(&authed.username),
+                authed.email.clone(),
+            )
+        });
     let scheduled_for = run_query.get_scheduled_for(&db).await?;
     let tx = PushIsolationLevel::Isolated(user_db.clone(), authed.clone().into(), rsmq);
     let (uuid, tx) = push(
@@ -1701,8 +1742,8 @@ pub async fn run_flow_by_path(
         JobPayload::Flow { path: flow_path.to_string(), dedicated_worker },
         args,
         &authed.username,
-        &authed.email,
-        username_to_permissioned_as(&authed.username),
+        &email,
+        permissioned_as,
         scheduled_for,
         None,
         run_query.parent_job,
@@ -2363,6 +2404,14 @@ async fn run_wait_result_flow_by_path_internal(
     .unwrap_or_else(|| (None, None, None));
 
     check_tag_available_for_workspace(&w_id, &tag).await?;
+    let (permissioned_as, email) = get_flow_run_as(&db, &w_id, flow_path)
+        .await?
+        .unwrap_or_else(|| {
+            (
+                username_to_permissioned_as(&authed.username),
+                authed.email.clone(),
+            )
+        });
     let tx = PushIsolationLevel::Isolated(user_db.clone(), authed.clone().into(), rsmq);
 
     let (uuid, tx) = push(
@@ -2372,8 +2421,8 @@ async fn run_wait_result_flow_by_path_internal(
         JobPayload::Flow { path: flow_path.to_string(), dedicated_worker },
         args,
         &authed.username,
-        &authed.email,
-        username_to_permissioned_as(&authed.username),
+        &email,
+        permissioned_as,
         scheduled_for,
         None,
         run_query.parent_job,
@@ -2943,6 +2992,7 @@ async fn list_completed_jobs(
             "mem_peak",
             "tag",
             "priority",
+            "cache_hit",
             "'CompletedJob' as type",
         ],
     )
@@ -2960,7 +3010,7 @@ async fn get_completed_job<'a>(
     let job_o = sqlx::query("SELECT id, workspace_id, parent_job, created_by, created_at, duration_ms, success, script_hash, script_path, 
     CASE WHEN args is null or pg_column_size(args) < 2000000 THEN args ELSE '\"WINDMILL_TOO_BIG\"'::jsonb END as args, CASE WHEN result is null or pg_column_size(result) < 2000000 THEN result ELSE '\"WINDMILL_TOO_BIG\"'::jsonb END as result, right(logs, 20000000) as logs, deleted, raw_code, canceled, canceled_by, canceled_reason, job_kind, env_id,
     schedule_path, permissioned_as, flow_status, raw_flow, is_flow_step, language, started_at, is_skipped,
-    raw_lock, email, visible_to_owner, mem_peak, tag, priority FROM completed_job WHERE id = $1 AND workspace_id = $2")
+    raw_lock, email, visible_to_owner, mem_peak, tag, priority, cache_hit FROM completed_job WHERE id = $1 AND workspace_id = $2")
         .bind(id)
         .bind(w_id)
         .fetch_optional(&db)
@@ -44,6 +44,8 @@ use windmill_common::BASE_URL;
 
 use windmill_common::error::AppError;
 
+pub mod access_requests;
+mod ai;
 mod apps;
 mod audit;
 mod capture;
@@ -60,19 +62,29 @@ mod granular_acls;
 mod groups;
 mod inputs;
 mod integration;
+mod job_artifacts;
 pub mod job_helpers;
 pub mod jobs;
+pub mod mfa;
+mod network_policies;
 pub mod oauth2;
-mod openai;
+pub mod oidc;
+mod package_mirror;
 mod raw_apps;
 mod resources;
+mod result_cache;
 mod saml;
 mod schedule;
 mod scim;
+mod scim_filter;
+mod scopes;
 mod scripts;
+mod secret_providers;
+mod service_accounts;
 mod settings;
 mod static_assets;
 mod tracing_init;
+mod usage;
 mod users;
 mod utils;
 mod variables;
@@ -106,7 +118,8 @@ lazy_static::lazy_static! {
     pub static ref OAUTH_CLIENTS: Arc<RwLock<AllClients>> = Arc::new(RwLock::new(AllClients {
         logins: HashMap::new(),
         connects: HashMap::new(),
-        slack: None
+        slack: None,
+        oidc: HashMap::new()
     }));
 }
 
@@ -135,6 +148,11 @@ pub async fn run_server(
         db.clone(),
         std::env::var("SUPERADMIN_SECRET").ok(),
     ));
+    {
+        let auth_cache = auth_cache.clone();
+        let rx = rx.resubscribe();
+        tokio::spawn(async move { auth_cache.listen_invalidations(rx).await });
+    }
     let argon2 = Arc::new(Argon2::default());
 
     let middleware_stack = ServiceBuilder::new()
@@ -184,7 +202,9 @@ pub async fn run_server(
                     "/w/:workspace_id",
                     Router::new()
                         // Reordered alphabetically
+                        .nest("/access_requests", access_requests::workspaced_service())
                         .nest("/acls", granular_acls::workspaced_service())
+                        .nest("/ai", ai::workspaced_service())
                         .nest("/apps", apps::workspaced_service())
                         .nest("/audit", audit::workspaced_service())
                         .nest("/capture", capture::workspaced_service())
@@ -198,14 +218,22 @@ pub async fn run_server(
                         .nest("/folders", folders::workspaced_service())
                         .nest("/groups", groups::workspaced_service())
                         .nest("/inputs", inputs::workspaced_service())
+                        .nest("/job_artifacts", job_artifacts::workspaced_service())
                         .nest("/job_helpers", job_helpers::workspaced_service())
                         .nest("/jobs", jobs::workspaced_service())
+                        .nest("/network_policies", network_policies::workspaced_service())
                         .nest("/oauth", oauth2::workspaced_service())
-                        .nest("/openai", openai::workspaced_service())
+                        .nest("/openai", ai::workspaced_service())
                         .nest("/raw_apps", raw_apps::workspaced_service())
                         .nest("/resources", resources::workspaced_service())
+                        .nest("/result_cache", result_cache::workspaced_service())
                         .nest("/schedules", schedule::workspaced_service())
                         .nest("/scripts", scripts::workspaced_service())
+                        .nest(
+                            "/service_accounts",
+                            service_accounts::workspaced_service(),
+                        )
+                        .nest("/usage", usage::workspaced_service())
                         .nest(
                             "/users",
                             users::workspaced_service().layer(Extension(argon2.clone())),
@@ -221,6 +249,7 @@ pub async fn run_server(
                 .nest("/settings", settings::global_service())
                 .nest("/workers", workers::global_service())
                 .nest("/configs", configs::global_service())
+                .nest("/package_mirror", package_mirror::global_service())
                 .nest("/scripts", scripts::global_service())
                 .nest("/integrations", integration::global_service())
                 .nest("/groups", groups::global_service())
@@ -243,6 +272,7 @@ pub async fn run_server(
                     scim::global_service().route_layer(axum::middleware::from_fn(has_scim_token)),
                 )
                 .nest("/scripts_u", scripts::global_unauthed_service())
+                .nest("/mirror", package_mirror::registry_service())
                 .nest(
                     "/w/:workspace_id/apps_u",
                     apps::unauthed_service()
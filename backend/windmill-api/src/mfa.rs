@@ -0,0 +1,546 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Multi-factor authentication of the password logins, with time-based one-time passwords
+//! (RFC 6238: HMAC-SHA1, 6 digits, 30 seconds steps) and single-use recovery codes.
+//!
+//! Enrolling stores a pending secret and recovery codes, which are only enabled once a first
+//! code of the secret is verified. Sensitive operations require a step-up: a code verified on the
+//! session less than `MFA_STEP_UP_SECS` ago.
+
+use std::sync::Arc;
+
+use argon2::Argon2;
+use axum::{
+    extract::Extension,
+    routing::{get, post},
+    Json, Router,
+};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{Error, JsonResult, Result},
+    utils::rd_string,
+};
+
+use crate::{
+    db::{ApiAuthed, DB},
+    users::{check_password, Tokened},
+};
+
+lazy_static::lazy_static! {
+    pub static ref REQUIRE_MFA: Arc<RwLock<RequireMfa>> = Arc::new(RwLock::new(
+        std::env::var("REQUIRE_MFA")
+            .ok()
+            .and_then(|x| RequireMfa::parse(&x))
+            .unwrap_or(RequireMfa::None)
+    ));
+    static ref MFA_STEP_UP_SECS: i64 = std::env::var("MFA_STEP_UP_SECS")
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .unwrap_or(300);
+}
+
+const ISSUER: &str = "Windmill";
+const DIGITS: u32 = 6;
+const STEP_SECS: i64 = 30;
+// codes of the previous and next steps are accepted, for the clocks drifting apart
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+const MAX_FAILURES: i32 = 5;
+const LOCKOUT_SECS: i64 = 15 * 60;
+
+/// Password users required to use MFA, set by the `require_mfa` instance setting
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequireMfa {
+    None,
+    SuperAdmins,
+    All,
+}
+
+impl RequireMfa {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(RequireMfa::None),
+            "super_admins" => Some(RequireMfa::SuperAdmins),
+            "all" => Some(RequireMfa::All),
+            _ => None,
+        }
+    }
+}
+
+pub async fn is_mfa_required(super_admin: bool) -> bool {
+    match *REQUIRE_MFA.read().await {
+        RequireMfa::None => false,
+        RequireMfa::SuperAdmins => super_admin,
+        RequireMfa::All => true,
+    }
+}
+
+pub fn global_service() -> Router {
+    Router::new()
+        .route("/status", get(get_mfa_status))
+        .route("/enroll", post(enroll))
+        .route("/enable", post(enable))
+        .route("/disable", post(disable))
+        .route("/recovery_codes", post(regenerate_recovery_codes))
+        .route("/step_up", post(step_up))
+}
+
+fn totp(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // dynamic truncation of RFC 4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    code % 10u32.pow(DIGITS)
+}
+
+/// Returns the step of the one-time password matching the code, if any is after `last_step`
+fn verify_totp(secret: &str, code: &str, now_secs: i64, last_step: Option<i64>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let step = now_secs.div_euclid(STEP_SECS);
+    (step - ALLOWED_DRIFT_STEPS..=step + ALLOWED_DRIFT_STEPS)
+        .filter(|s| last_step.map_or(true, |last| *s > last))
+        .find(|s| totp(&secret, *s) == code)
+}
+
+fn generate_secret() -> String {
+    BASE32_NOPAD.encode(&rand::random::<[u8; 20]>())
+}
+
+fn otpauth_url(email: &str, secret: &str) -> String {
+    let label = urlencoding::encode(&format!("{ISSUER}:{email}")).into_owned();
+    format!(
+        "otpauth://totp/{label}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}"
+    )
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = rd_string(10).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+async fn store_recovery_codes<'c>(
+    tx: &mut sqlx::Transaction<'c, sqlx::Postgres>,
+    email: &str,
+) -> Result<Vec<String>> {
+    let codes = generate_recovery_codes();
+    sqlx::query("DELETE FROM mfa_recovery_code WHERE email = $1")
+        .bind(email)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO mfa_recovery_code (email, code_hash) SELECT $1, unnest($2::varchar[])",
+    )
+    .bind(email)
+    .bind(
+        codes
+            .iter()
+            .map(|x| hash_recovery_code(x))
+            .collect::<Vec<_>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(codes)
+}
+
+#[derive(Serialize)]
+pub struct MfaEnrollment {
+    secret: String,
+    otpauth_url: String,
+    recovery_codes: Vec<String>,
+}
+
+/// Starts the enrollment of a user without MFA, which is enabled once a first code is verified
+pub async fn start_enrollment(db: &DB, email: &str) -> Result<MfaEnrollment> {
+    let mut tx = db.begin().await?;
+    let secret = generate_secret();
+    let updated = sqlx::query(
+        "UPDATE password SET mfa_pending_secret = $1 WHERE email = $2 AND login_type = 'password'
+        AND mfa_enabled = false",
+    )
+    .bind(&secret)
+    .bind(email)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(Error::BadRequest(
+            "MFA can only be enrolled by password users who have not enabled it yet".to_string(),
+        ));
+    }
+    let recovery_codes = store_recovery_codes(&mut tx, email).await?;
+    tx.commit().await?;
+
+    Ok(MfaEnrollment { otpauth_url: otpauth_url(email, &secret), secret, recovery_codes })
+}
+
+/// Verifies a code of the user, a one-time password or, once MFA is enabled, a recovery code
+/// which is then consumed. With `pending`, the code is verified against the secret being
+/// enrolled. Each one-time password is only accepted once, and the verification is locked for a
+/// while after too many invalid codes.
+pub async fn verify_mfa_code(db: &DB, email: &str, code: &str, pending: bool) -> Result<()> {
+    let user = sqlx::query_as::<_, (Option<String>, Option<String>, Option<i64>, bool)>(
+        "SELECT mfa_secret, mfa_pending_secret, mfa_last_step,
+            COALESCE(mfa_failures >= $2
+                AND mfa_failed_at > now() - ($3::bigint::text || ' s')::interval, false)
+        FROM password WHERE email = $1",
+    )
+    .bind(email)
+    .bind(MAX_FAILURES)
+    .bind(LOCKOUT_SECS)
+    .fetch_optional(db)
+    .await?;
+    let Some((secret, pending_secret, last_step, locked)) = user else {
+        return Err(Error::NotAuthorized("Invalid MFA code".to_string()));
+    };
+    if locked {
+        return Err(Error::NotAuthorized(
+            "Too many invalid MFA codes, retry in a few minutes".to_string(),
+        ));
+    }
+    let secret = if pending { pending_secret } else { secret }
+        .ok_or_else(|| Error::BadRequest("MFA is not enrolled".to_string()))?;
+
+    let now = chrono::Utc::now().timestamp();
+    if let Some(step) = verify_totp(&secret, code, now, last_step) {
+        // the step is only recorded if no concurrent request verified the same code first
+        let accepted = sqlx::query(
+            "UPDATE password SET mfa_last_step = $2, mfa_failures = 0
+            WHERE email = $1 AND (mfa_last_step IS NULL OR mfa_last_step < $2)",
+        )
+        .bind(email)
+        .bind(step)
+        .execute(db)
+        .await?
+        .rows_affected();
+        if accepted > 0 {
+            return Ok(());
+        }
+    } else if !pending {
+        let consumed = sqlx::query(
+            "DELETE FROM mfa_recovery_code WHERE email = $1 AND code_hash = $2
+            AND EXISTS(SELECT 1 FROM password WHERE email = $1 AND mfa_enabled)",
+        )
+        .bind(email)
+        .bind(hash_recovery_code(code))
+        .execute(db)
+        .await?
+        .rows_affected();
+        if consumed > 0 {
+            sqlx::query("UPDATE password SET mfa_failures = 0 WHERE email = $1")
+                .bind(email)
+                .execute(db)
+                .await?;
+            return Ok(());
+        }
+    }
+
+    // failures older than the lockout window do not count anymore
+    sqlx::query(
+        "UPDATE password SET mfa_failures = CASE
+                WHEN mfa_failed_at > now() - ($2::bigint::text || ' s')::interval
+                THEN mfa_failures + 1 ELSE 1 END,
+            mfa_failed_at = now()
+        WHERE email = $1",
+    )
+    .bind(email)
+    .bind(LOCKOUT_SECS)
+    .execute(db)
+    .await?;
+    Err(Error::NotAuthorized("Invalid MFA code".to_string()))
+}
+
+/// Enables MFA with the secret being enrolled, whose first code was just verified
+pub async fn enable_mfa<'c>(
+    tx: &mut sqlx::Transaction<'c, sqlx::Postgres>,
+    email: &str,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE password SET mfa_enabled = true, mfa_secret = mfa_pending_secret,
+            mfa_pending_secret = NULL
+        WHERE email = $1 AND mfa_pending_secret IS NOT NULL",
+    )
+    .bind(email)
+    .execute(&mut **tx)
+    .await?;
+    audit_log(
+        &mut **tx,
+        email,
+        "users.mfa.enable",
+        ActionKind::Update,
+        "global",
+        Some(email),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sensitive operations of users with MFA require a code verified on their session recently
+pub async fn require_mfa_step_up(db: &DB, email: &str, token: &str) -> Result<()> {
+    let stepped_up = sqlx::query_scalar::<_, bool>(
+        "SELECT NOT COALESCE((SELECT mfa_enabled FROM password WHERE email = $1), false)
+            OR COALESCE((SELECT mfa_verified_at > now() - ($3::bigint::text || ' s')::interval
+                FROM token WHERE token = $2), false)",
+    )
+    .bind(email)
+    .bind(token)
+    .bind(*MFA_STEP_UP_SECS)
+    .fetch_one(db)
+    .await?;
+    if stepped_up {
+        Ok(())
+    } else {
+        Err(Error::NotAuthorized(
+            "MFA step-up required: verify a code with /users/mfa/step_up first".to_string(),
+        ))
+    }
+}
+
+#[derive(Serialize)]
+struct MfaStatus {
+    enabled: bool,
+    required: bool,
+    recovery_codes_left: i64,
+}
+
+async fn get_mfa_status(authed: ApiAuthed, Extension(db): Extension<DB>) -> JsonResult<MfaStatus> {
+    let (enabled, super_admin, recovery_codes_left) = sqlx::query_as::<_, (bool, bool, i64)>(
+        "SELECT mfa_enabled, super_admin,
+            (SELECT count(*) FROM mfa_recovery_code WHERE email = $1)
+        FROM password WHERE email = $1",
+    )
+    .bind(&authed.email)
+    .fetch_optional(&db)
+    .await?
+    .unwrap_or((false, false, 0));
+
+    Ok(Json(MfaStatus {
+        enabled,
+        required: is_mfa_required(super_admin).await,
+        recovery_codes_left: if enabled { recovery_codes_left } else { 0 },
+    }))
+}
+
+async fn enroll(authed: ApiAuthed, Extension(db): Extension<DB>) -> JsonResult<MfaEnrollment> {
+    Ok(Json(start_enrollment(&db, &authed.email).await?))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordEnrollment {
+    email: String,
+    password: String,
+}
+
+/// Enrollment for the password users who must enable MFA before their first login with it
+pub async fn enroll_with_password(
+    Extension(db): Extension<DB>,
+    Extension(argon2): Extension<Arc<Argon2<'_>>>,
+    Json(pe): Json<PasswordEnrollment>,
+) -> JsonResult<MfaEnrollment> {
+    let user = check_password(&db, &argon2, &pe.email, &pe.password).await?;
+    Ok(Json(start_enrollment(&db, &user.email).await?))
+}
+
+#[derive(Deserialize)]
+struct MfaCode {
+    code: String,
+}
+
+async fn enable(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Tokened { token }: Tokened,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> Result<String> {
+    verify_mfa_code(&db, &authed.email, &code, true).await?;
+    let mut tx = db.begin().await?;
+    enable_mfa(&mut tx, &authed.email).await?;
+    mark_stepped_up(&mut tx, &token).await?;
+    tx.commit().await?;
+    Ok("MFA enabled".to_string())
+}
+
+async fn disable(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> Result<String> {
+    let super_admin =
+        sqlx::query_scalar::<_, bool>("SELECT super_admin FROM password WHERE email = $1")
+            .bind(&authed.email)
+            .fetch_optional(&db)
+            .await?
+            .unwrap_or(false);
+    if is_mfa_required(super_admin).await {
+        return Err(Error::BadRequest(
+            "MFA is required by the instance settings and cannot be disabled".to_string(),
+        ));
+    }
+    verify_mfa_code(&db, &authed.email, &code, false).await?;
+
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "UPDATE password SET mfa_enabled = false, mfa_secret = NULL, mfa_pending_secret = NULL,
+            mfa_last_step = NULL
+        WHERE email = $1",
+    )
+    .bind(&authed.email)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM mfa_recovery_code WHERE email = $1")
+        .bind(&authed.email)
+        .execute(&mut *tx)
+        .await?;
+    audit_log(
+        &mut *tx,
+        &authed.email,
+        "users.mfa.disable",
+        ActionKind::Update,
+        "global",
+        Some(&authed.email),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok("MFA disabled".to_string())
+}
+
+async fn regenerate_recovery_codes(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> JsonResult<Vec<String>> {
+    verify_mfa_code(&db, &authed.email, &code, false).await?;
+    let mut tx = db.begin().await?;
+    let codes = store_recovery_codes(&mut tx, &authed.email).await?;
+    audit_log(
+        &mut *tx,
+        &authed.email,
+        "users.mfa.recovery_codes",
+        ActionKind::Update,
+        "global",
+        Some(&authed.email),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Json(codes))
+}
+
+pub async fn mark_stepped_up<'c>(
+    tx: &mut sqlx::Transaction<'c, sqlx::Postgres>,
+    token: &str,
+) -> Result<()> {
+    sqlx::query("UPDATE token SET mfa_verified_at = now() WHERE token = $1")
+        .bind(token)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+async fn step_up(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Tokened { token }: Tokened,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> Result<String> {
+    verify_mfa_code(&db, &authed.email, &code, false).await?;
+    let mut tx = db.begin().await?;
+    mark_stepped_up(&mut tx, &token).await?;
+    tx.commit().await?;
+    Ok(format!(
+        "MFA verified for the next {} seconds",
+        *MFA_STEP_UP_SECS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, truncated to 6 digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_totp_rfc_vectors() {
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(totp(RFC_SECRET, time / STEP_SECS), code);
+        }
+    }
+
+    #[test]
+    fn test_verify_totp() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1111111109 / STEP_SECS;
+        assert_eq!(verify_totp(&secret, "081804", 1111111109, None), Some(step));
+        // previous step, within the allowed drift
+        assert_eq!(
+            verify_totp(&secret, "081804", 1111111109 + STEP_SECS, None),
+            Some(step)
+        );
+        assert_eq!(
+            verify_totp(&secret, "081804", 1111111109 + 2 * STEP_SECS, None),
+            None
+        );
+        // replayed code
+        assert_eq!(verify_totp(&secret, "081804", 1111111109, Some(step)), None);
+        assert_eq!(verify_totp(&secret, "81804", 1111111109, None), None);
+        assert_eq!(verify_totp(&secret, "abcdef", 1111111109, None), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        let code = &codes[0];
+        assert_eq!(
+            hash_recovery_code(code),
+            hash_recovery_code(&format!(" {} ", code.to_uppercase().replace('-', "")))
+        );
+    }
+}
@@ -0,0 +1,150 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use axum::{
+    extract::{Extension, Path},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{Error, JsonResult, Result},
+    network_policy::{get_network_policy, parse_allowlist, NetworkMode, NetworkPolicy},
+    utils::{require_admin, StripPath},
+};
+
+use crate::db::{ApiAuthed, DB};
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/list", get(list_network_policies))
+        .route("/get/*path", get(get_policy))
+        .route("/set", post(set_network_policy))
+        .route("/delete/*path", delete(delete_network_policy))
+}
+
+#[derive(Deserialize)]
+struct SetNetworkPolicy {
+    path: String,
+    mode: NetworkMode,
+    #[serde(default)]
+    allowlist: Vec<String>,
+}
+
+async fn list_network_policies(
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> JsonResult<Vec<NetworkPolicy>> {
+    let policies = sqlx::query_as::<_, NetworkPolicy>(
+        "SELECT path, mode, allowlist, edited_by, edited_at FROM network_policy \
+         WHERE workspace_id = $1 ORDER BY path",
+    )
+    .bind(&w_id)
+    .fetch_all(&db)
+    .await?;
+    Ok(Json(policies))
+}
+
+/// Policy applying to a script path, which may be the one of its folder
+async fn get_policy(
+    Extension(db): Extension<DB>,
+    Path((w_id, path)): Path<(String, StripPath)>,
+) -> JsonResult<Option<NetworkPolicy>> {
+    Ok(Json(get_network_policy(&db, &w_id, path.to_path()).await?))
+}
+
+async fn set_network_policy(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Json(np): Json<SetNetworkPolicy>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let path = np.path.trim_matches('/');
+    let depth = path.split('/').filter(|x| !x.is_empty()).count();
+    if !((path.starts_with("f/") && depth >= 2) || (path.starts_with("u/") && depth >= 3)) {
+        return Err(Error::BadRequest(format!(
+            "{path} is neither a script path nor a folder path of the form f/<folder>"
+        )));
+    }
+    parse_allowlist(&np.allowlist)?;
+    if np.mode != NetworkMode::Allowlist && !np.allowlist.is_empty() {
+        return Err(Error::BadRequest(
+            "an allowlist can only be set with the allowlist mode".to_string(),
+        ));
+    }
+
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "INSERT INTO network_policy (workspace_id, path, mode, allowlist, edited_by) \
+         VALUES ($1, $2, $3, $4, $5) ON CONFLICT (workspace_id, path) DO UPDATE \
+         SET mode = EXCLUDED.mode, allowlist = EXCLUDED.allowlist, \
+         edited_by = EXCLUDED.edited_by, edited_at = now()",
+    )
+    .bind(&w_id)
+    .bind(path)
+    .bind(np.mode)
+    .bind(&np.allowlist)
+    .bind(&authed.username)
+    .execute(&mut *tx)
+    .await?;
+
+    let mode = format!("{:?}", np.mode).to_lowercase();
+    let allowlist = np.allowlist.join(",");
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "network_policies.set",
+        ActionKind::Update,
+        &w_id,
+        Some(path),
+        Some([("mode", mode.as_str()), ("allowlist", allowlist.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("network policy of {path} set to {mode}"))
+}
+
+async fn delete_network_policy(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, path)): Path<(String, StripPath)>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+    let path = path.to_path();
+
+    let mut tx = db.begin().await?;
+    let deleted = sqlx::query("DELETE FROM network_policy WHERE workspace_id = $1 AND path = $2")
+        .bind(&w_id)
+        .bind(path)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(Error::NotFound(format!(
+            "network policy of {path} not found"
+        )));
+    }
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "network_policies.delete",
+        ActionKind::Delete,
+        &w_id,
+        Some(path),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("network policy of {path} deleted"))
+}
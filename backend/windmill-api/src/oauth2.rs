@@ -40,6 +40,9 @@ use windmill_common::users::username_to_permissioned_as;
 use windmill_common::utils::{not_found_if_none, now_from_db};
 
 use crate::db::ApiAuthed;
+use crate::oidc::{
+    discover_oidc_provider, oidc_scopes, OidcConfig, OidcProvider, OidcTokenResponse,
+};
 use crate::saml::SamlSsoLogin;
 use crate::users::{login_externally, LoginUserInfo};
 use crate::webhook_util::{InstanceEvent, WebhookShared};
@@ -112,6 +115,7 @@ pub struct OAuthClient {
     allowed_domains: Option<Vec<String>>,
     connect_config: Option<OAuthConfig>,
     login_config: Option<OAuthConfig>,
+    oidc: Option<OidcConfig>,
 }
 
 #[derive(Debug)]
@@ -119,9 +123,10 @@ pub struct AllClients {
     pub logins: BasicClientsMap,
     pub connects: BasicClientsMap,
     pub slack: Option<OClient>,
+    pub oidc: HashMap<String, OidcProvider>,
 }
 
-pub fn build_oauth_clients(
+pub async fn build_oauth_clients(
     base_url: &str,
     oauths_from_config: Option<HashMap<String, OAuthClient>>,
 ) -> anyhow::Result<AllClients> {
@@ -132,7 +137,7 @@ pub fn build_oauth_clients(
         "../../oauth_login.json"
     ))?;
 
-    let oauths = if let Some(oauths) = oauths_from_config {
+    let mut oauths = if let Some(oauths) = oauths_from_config {
         oauths
     } else {
         let path = "./oauth.json";
@@ -151,6 +156,7 @@ pub fn build_oauth_clients(
                 logins: HashMap::new(),
                 connects: HashMap::new(),
                 slack: None,
+                oidc: HashMap::new(),
             });
         };
 
@@ -160,6 +166,7 @@ pub fn build_oauth_clients(
                 logins: HashMap::new(),
                 connects: HashMap::new(),
                 slack: None,
+                oidc: HashMap::new(),
             });
         };
         match serde_json::from_str::<HashMap<String, OAuthClient>>(&content) {
@@ -175,6 +182,27 @@ pub fn build_oauth_clients(
 
     tracing::info!("OAuth loaded clients: {}", oauths.keys().join(", "));
 
+    let mut oidc = HashMap::new();
+    for (name, client) in oauths.iter_mut() {
+        if let Some(config) = client.oidc.as_ref() {
+            match discover_oidc_provider(&client.id, config).await {
+                Ok((discovery, provider)) => {
+                    client.login_config = Some(OAuthConfig {
+                        auth_url: discovery.authorization_endpoint,
+                        token_url: discovery.token_endpoint,
+                        userinfo_url: discovery.userinfo_endpoint,
+                        scopes: Some(oidc_scopes(config)),
+                        extra_params: None,
+                        extra_params_callback: None,
+                        req_body_auth: None,
+                    });
+                    oidc.insert(name.clone(), provider);
+                }
+                Err(e) => tracing::error!("Error discovering the oidc provider of {name}: {e}"),
+            }
+        }
+    }
+
     let logins = login_configs
         .into_iter()
         .filter_map(|x| oauths.get(&x.0).map(|c| (x.0, (c, x.1))))
@@ -284,7 +312,7 @@ pub fn build_oauth_clients(
             .ok()
         })
         .flatten();
-    let all_clients = AllClients { logins, connects, slack };
+    let all_clients = AllClients { logins, connects, slack, oidc };
     tracing::debug!("Final oauth config: {all_clients:#?}");
     Ok(all_clients)
 }
@@ -548,14 +576,21 @@ async fn disconnect_slack(
 }
 
 async fn login(Path(client_name): Path<String>, cookies: Cookies) -> error::Result<Redirect> {
-    let clients = &OAUTH_CLIENTS.read().await.logins;
+    let clients = OAUTH_CLIENTS.read().await;
+    let is_secure = *IS_SECURE.read().await;
+    let extra_params = if clients.oidc.contains_key(&client_name) {
+        let nonce = set_nonce_cookie(&cookies, is_secure);
+        Some(HashMap::from([("nonce".to_string(), nonce)]))
+    } else {
+        None
+    };
     oauth_redirect(
-        clients,
+        &clients.logins,
         client_name,
         cookies,
         None,
-        None,
-        IS_SECURE.read().await.clone(),
+        extra_params,
+        is_secure,
     )
 }
 
@@ -602,12 +637,14 @@ pub async fn _refresh_token<'c>(
     let token = _exchange_token(client, &account.refresh_token).await;
 
     if let Err(token_err) = token {
-        sqlx::query!(
-            "UPDATE account SET refresh_error = $1 WHERE workspace_id = $2 AND id = $3",
-            token_err.alt(),
-            w_id,
-            id,
+        // consecutive failures, tracked to detect the broken connections
+        sqlx::query(
+            "UPDATE account SET refresh_error = $1, refresh_failures = refresh_failures + 1, \
+             refresh_failed_at = now() WHERE workspace_id = $2 AND id = $3",
         )
+        .bind(token_err.alt())
+        .bind(w_id)
+        .bind(id)
         .execute(&mut *tx)
         .await?;
         tx.commit().await?;
@@ -627,17 +664,20 @@ pub async fn _refresh_token<'c>(
                 .try_into()
                 .unwrap(),
         );
-    sqlx::query!(
-        "UPDATE account SET refresh_token = $1, expires_at = $2 WHERE workspace_id = $3 AND id = \
-         $4",
+    sqlx::query(
+        "UPDATE account SET refresh_token = $1, expires_at = $2, refresh_error = NULL, \
+         refresh_failures = 0, refresh_failed_at = NULL, refresh_broken_notified = false \
+         WHERE workspace_id = $3 AND id = $4",
+    )
+    .bind(
         token
             .refresh_token
             .map(|x| x.to_string())
             .unwrap_or(account.refresh_token),
-        expires_at,
-        w_id,
-        id
     )
+    .bind(expires_at)
+    .bind(w_id)
+    .bind(id)
     .execute(&mut *tx)
     .await?;
 
@@ -957,13 +997,29 @@ async fn login_callback(
     Extension(webhook): Extension<WebhookShared>,
     Json(callback): Json<OAuthCallback>,
 ) -> error::Result<String> {
-    let client_w_config = {
-        let clients = OAUTH_CLIENTS.read().await.logins.clone();
-        clients
-            .get(&client_name)
-            .ok_or_else(|| error::Error::BadRequest("invalid client".to_string()))?
-            .clone()
+    let (client_w_config, oidc_provider) = {
+        let clients = OAUTH_CLIENTS.read().await;
+        (
+            clients
+                .logins
+                .get(&client_name)
+                .ok_or_else(|| error::Error::BadRequest("invalid client".to_string()))?
+                .clone(),
+            clients.oidc.get(&client_name).cloned(),
+        )
     };
+    if let Some(provider) = oidc_provider {
+        return oidc_login_callback(
+            client_name,
+            client_w_config,
+            provider,
+            callback,
+            cookies,
+            db,
+            webhook,
+        )
+        .await;
+    }
     let client = client_w_config.client.to_owned();
     let token_res =
         exchange_code::<TokenResponse>(callback, &cookies, client, &HTTP_CLIENT, None).await;
@@ -1030,6 +1086,65 @@ async fn login_callback(
     }
 }
 
+async fn oidc_login_callback(
+    client_name: String,
+    client_w_config: ClientWithScopes,
+    provider: OidcProvider,
+    callback: OAuthCallback,
+    cookies: Cookies,
+    db: DB,
+    webhook: WebhookShared,
+) -> error::Result<String> {
+    let nonce = cookies
+        .get(OIDC_NONCE_COOKIE)
+        .map(|x| x.value().to_string())
+        .unwrap_or_default();
+    let mut nonce_cookie = Cookie::new(OIDC_NONCE_COOKIE, "");
+    nonce_cookie.set_path("/");
+    cookies.remove(nonce_cookie);
+
+    let token = exchange_code::<OidcTokenResponse>(
+        callback,
+        &cookies,
+        client_w_config.client.to_owned(),
+        &HTTP_CLIENT,
+        None,
+    )
+    .await?;
+    let claims = provider.validate_id_token(&token.id_token, &nonce).await?;
+    let email = provider.email(&claims)?;
+
+    if let Some(domains) = &client_w_config.allowed_domains {
+        if !domains.iter().any(|d| email.ends_with(d)) {
+            return Err(error::Error::BadRequest(format!(
+                "domain is not in the list of allowed domains: {email}, allowed: {domains:#?}",
+            )));
+        }
+    }
+
+    let user = LoginUserInfo {
+        email: Some(email.clone()),
+        name: provider.name(&claims),
+        company: None,
+        displayName: None,
+    };
+    // the session must not be created with the groups and roles of a previous login
+    provider.apply_claim_mappings(&db, &email, &claims).await?;
+    login_externally(
+        db.clone(),
+        &email,
+        client_name,
+        cookies,
+        Some(token.access_token.to_string()),
+        Some(user),
+    )
+    .await?;
+
+    webhook.send_instance_event(InstanceEvent::UserSignupOAuth { email: email.clone() });
+
+    Ok("Successfully logged in".to_string())
+}
+
 async fn exchange_code<T: DeserializeOwned>(
     callback: OAuthCallback,
     cookies: &Cookies,
@@ -1166,6 +1281,20 @@ fn set_cookie(state: &State, cookies: Cookies, is_secure: bool) {
     cookies.add(cookie);
 }
 
+const OIDC_NONCE_COOKIE: &str = "oidc_nonce";
+
+/// Sets the nonce the ID token of an OIDC login must contain, returning it
+fn set_nonce_cookie(cookies: &Cookies, is_secure: bool) -> String {
+    let nonce = State::new_random().to_base64();
+    let mut cookie = Cookie::new(OIDC_NONCE_COOKIE, nonce.clone());
+    cookie.set_secure(is_secure);
+    cookie.set_same_site(Some(cookie::SameSite::Lax));
+    cookie.set_http_only(true);
+    cookie.set_path("/");
+    cookies.add(cookie);
+    nonce
+}
+
 #[derive(Clone, Debug)]
 pub struct SlackVerifier {
     mac: HmacSha256,
@@ -0,0 +1,602 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Generic OpenID Connect login providers, set by an `oidc` config on a client of the oauth
+//! settings. The endpoints and signing keys of the provider are fetched from its discovery document
+//! when the clients are built, and the ID token of each login is validated against them.
+//!
+//! The claim mappings of a provider are applied on each login and are authoritative for the
+//! instance groups and workspaces they name: the user is added to the instance groups whose
+//! mappings match and removed from the others, gets the highest role matched in each workspace,
+//! and is removed from the workspaces where no mapping matches.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Mutex, RwLock};
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::error::{Error, Result};
+
+use crate::{db::DB, HTTP_CLIENT};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const DEFAULT_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// anyone can send a token with an unknown key id, so the keys are fetched again at most this often
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// issuer url, or the full url of its discovery document
+    discovery_url: String,
+    scopes: Option<Vec<String>>,
+    email_claim: Option<String>,
+    claim_mappings: Option<Vec<ClaimMapping>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClaimMapping {
+    /// name of the claim, or dot-separated path for nested claims (e.g. `realm_access.roles`)
+    claim: String,
+    /// value the claim must be equal to, or contain if it is an array
+    value: String,
+    instance_group: Option<String>,
+    workspace_id: Option<String>,
+    role: Option<WorkspaceRole>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    Operator,
+    Developer,
+    Admin,
+}
+
+impl WorkspaceRole {
+    fn is_admin_and_operator(&self) -> (bool, bool) {
+        match self {
+            WorkspaceRole::Operator => (false, true),
+            WorkspaceRole::Developer => (false, false),
+            WorkspaceRole::Admin => (true, false),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            WorkspaceRole::Operator => "operator",
+            WorkspaceRole::Developer => "developer",
+            WorkspaceRole::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    jwks_uri: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcProvider {
+    client_id: String,
+    issuer: String,
+    jwks_uri: String,
+    email_claim: String,
+    claim_mappings: Vec<ClaimMapping>,
+    jwks: Arc<RwLock<JwkSet>>,
+    jwks_fetched_at: Arc<Mutex<Instant>>,
+}
+
+#[derive(Deserialize)]
+pub struct OidcTokenResponse {
+    pub access_token: oauth2::AccessToken,
+    pub id_token: String,
+}
+
+fn discovery_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if url.ends_with(DISCOVERY_PATH) {
+        url.to_string()
+    } else {
+        format!("{url}{DISCOVERY_PATH}")
+    }
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    HTTP_CLIENT
+        .get(url)
+        .timeout(HTTP_TIMEOUT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| Error::BadConfig(format!("fetching {url}: {e}")))?
+        .json::<T>()
+        .await
+        .map_err(|e| Error::BadConfig(format!("decoding {url}: {e}")))
+}
+
+/// Fetches the discovery document and the signing keys of the provider of a client
+pub async fn discover_oidc_provider(
+    client_id: &str,
+    config: &OidcConfig,
+) -> Result<(OidcDiscovery, OidcProvider)> {
+    let discovery = fetch_json::<OidcDiscovery>(&discovery_url(&config.discovery_url)).await?;
+    let jwks = fetch_json::<JwkSet>(&discovery.jwks_uri).await?;
+    let provider = OidcProvider {
+        client_id: client_id.to_string(),
+        issuer: discovery.issuer.clone(),
+        jwks_uri: discovery.jwks_uri.clone(),
+        email_claim: config
+            .email_claim
+            .clone()
+            .unwrap_or_else(|| "email".to_string()),
+        claim_mappings: config.claim_mappings.clone().unwrap_or_default(),
+        jwks: Arc::new(RwLock::new(jwks)),
+        jwks_fetched_at: Arc::new(Mutex::new(Instant::now())),
+    };
+    Ok((discovery, provider))
+}
+
+/// Scopes requested from the provider, always including `openid`
+pub fn oidc_scopes(config: &OidcConfig) -> Vec<String> {
+    let mut scopes = config
+        .scopes
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SCOPES.iter().map(|x| x.to_string()).collect());
+    if !scopes.iter().any(|x| x == "openid") {
+        scopes.insert(0, "openid".to_string());
+    }
+    scopes
+}
+
+impl OidcProvider {
+    /// Finds the key the token was signed with, fetching the keys again if the provider rotated them
+    /// and they were not fetched in the last `JWKS_REFETCH_INTERVAL`
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        fn find(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+            match kid {
+                Some(kid) => jwks.find(kid).cloned(),
+                None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+                None => None,
+            }
+        }
+
+        let mut jwk = find(&*self.jwks.read().await, kid);
+        if jwk.is_none() {
+            let mut fetched_at = self.jwks_fetched_at.lock().await;
+            // the keys may have been fetched while waiting for the lock
+            jwk = find(&*self.jwks.read().await, kid);
+            if jwk.is_none() && fetched_at.elapsed() >= JWKS_REFETCH_INTERVAL {
+                *fetched_at = Instant::now();
+                let jwks = fetch_json::<JwkSet>(&self.jwks_uri).await?;
+                jwk = find(&jwks, kid);
+                *self.jwks.write().await = jwks;
+            }
+        }
+        let jwk = jwk.ok_or_else(|| {
+            Error::BadRequest(format!(
+                "no key {} found to verify the id token",
+                kid.unwrap_or_default()
+            ))
+        })?;
+        DecodingKey::from_jwk(&jwk)
+            .map_err(|e| Error::BadConfig(format!("invalid key in the oidc provider jwks: {e}")))
+    }
+
+    /// Verifies the signature, issuer, audience, expiry and nonce of an ID token and returns its claims
+    pub async fn validate_id_token(&self, id_token: &str, nonce: &str) -> Result<Value> {
+        let header = decode_header(id_token)
+            .map_err(|e| Error::BadRequest(format!("invalid id token: {e}")))?;
+        // the tokens must be signed with the keys of the provider, not with the client secret
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(Error::BadRequest(format!(
+                "unsupported id token algorithm {:?}",
+                header.alg
+            )));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+        let claims = decode::<Value>(id_token, &key, &validation)
+            .map_err(|e| Error::BadRequest(format!("invalid id token: {e}")))?
+            .claims;
+
+        if nonce.is_empty() || claims.get("nonce").and_then(|x| x.as_str()) != Some(nonce) {
+            return Err(Error::BadRequest(
+                "the nonce of the id token did not match".to_string(),
+            ));
+        }
+        if let Some(azp) = claims.get("azp").and_then(|x| x.as_str()) {
+            if azp != self.client_id {
+                return Err(Error::BadRequest(format!(
+                    "the id token was issued to another client: {azp}"
+                )));
+            }
+        }
+        Ok(claims)
+    }
+
+    pub fn email(&self, claims: &Value) -> Result<String> {
+        if claims.get("email_verified").and_then(|x| x.as_bool()) == Some(false) {
+            return Err(Error::BadRequest(
+                "the email address of the user is not verified".to_string(),
+            ));
+        }
+        claim_values(claims, &self.email_claim)
+            .into_iter()
+            .next()
+            .map(|x| x.to_lowercase())
+            .ok_or_else(|| {
+                Error::BadRequest(format!(
+                    "claim {} not found in the id token",
+                    self.email_claim
+                ))
+            })
+    }
+
+    pub fn name(&self, claims: &Value) -> Option<String> {
+        ["name", "preferred_username"]
+            .iter()
+            .find_map(|x| claim_values(claims, x).into_iter().next())
+    }
+
+    /// Syncs the instance groups and workspace roles of the user with the claim mappings
+    pub async fn apply_claim_mappings(&self, db: &DB, email: &str, claims: &Value) -> Result<()> {
+        if self.claim_mappings.is_empty() {
+            return Ok(());
+        }
+        let (groups, roles) = resolve_mappings(&self.claim_mappings, claims);
+
+        let mut tx = db.begin().await?;
+        let mut groups_added = vec![];
+        let mut groups_removed = vec![];
+        for (group, matched) in groups {
+            if matched {
+                sqlx::query("INSERT INTO instance_group (name) VALUES ($1) ON CONFLICT DO NOTHING")
+                    .bind(&group)
+                    .execute(&mut *tx)
+                    .await?;
+                let added = sqlx::query(
+                    "INSERT INTO email_to_igroup (email, igroup) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                )
+                .bind(email)
+                .bind(&group)
+                .execute(&mut *tx)
+                .await?;
+                if added.rows_affected() > 0 {
+                    groups_added.push(group);
+                }
+            } else {
+                let removed =
+                    sqlx::query("DELETE FROM email_to_igroup WHERE email = $1 AND igroup = $2")
+                        .bind(email)
+                        .bind(&group)
+                        .execute(&mut *tx)
+                        .await?;
+                if removed.rows_affected() > 0 {
+                    groups_removed.push(group);
+                }
+            }
+        }
+
+        let mut workspaces_changed = vec![];
+        for (w_id, role) in roles {
+            let workspace_exists = sqlx::query_scalar::<_, bool>(
+                "SELECT EXISTS(SELECT 1 FROM workspace WHERE id = $1 AND deleted = false)",
+            )
+            .bind(&w_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if !workspace_exists {
+                tracing::warn!("workspace {w_id} of an oidc claim mapping does not exist");
+                continue;
+            }
+            let usr = sqlx::query_as::<_, (String, bool, bool)>(
+                "SELECT username, is_admin, operator FROM usr WHERE workspace_id = $1 AND email = $2",
+            )
+            .bind(&w_id)
+            .bind(email)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            match (role, usr) {
+                (Some(role), Some((_, was_admin, was_operator))) => {
+                    let (is_admin, operator) = role.is_admin_and_operator();
+                    if (is_admin, operator) != (was_admin, was_operator) {
+                        sqlx::query(
+                            "UPDATE usr SET is_admin = $3, operator = $4
+                            WHERE workspace_id = $1 AND email = $2",
+                        )
+                        .bind(&w_id)
+                        .bind(email)
+                        .bind(is_admin)
+                        .bind(operator)
+                        .execute(&mut *tx)
+                        .await?;
+                        workspaces_changed.push(format!("{w_id}:{}", role.as_str()));
+                    }
+                }
+                // the user picks a username when accepting the invite, as for the other invites
+                (Some(role), None) => {
+                    let (is_admin, operator) = role.is_admin_and_operator();
+                    sqlx::query(
+                        "INSERT INTO workspace_invite (workspace_id, email, is_admin, operator)
+                        VALUES ($1, $2, $3, $4) ON CONFLICT (workspace_id, email)
+                        DO UPDATE SET is_admin = $3, operator = $4",
+                    )
+                    .bind(&w_id)
+                    .bind(email)
+                    .bind(is_admin)
+                    .bind(operator)
+                    .execute(&mut *tx)
+                    .await?;
+                    workspaces_changed.push(format!("{w_id}:invited_{}", role.as_str()));
+                }
+                (None, usr) => {
+                    let uninvited = sqlx::query(
+                        "DELETE FROM workspace_invite WHERE workspace_id = $1 AND email = $2",
+                    )
+                    .bind(&w_id)
+                    .bind(email)
+                    .execute(&mut *tx)
+                    .await?;
+                    if let Some((username, ..)) = usr {
+                        sqlx::query(
+                            "DELETE FROM usr_to_group WHERE workspace_id = $1 AND usr = $2",
+                        )
+                        .bind(&w_id)
+                        .bind(&username)
+                        .execute(&mut *tx)
+                        .await?;
+                        sqlx::query("DELETE FROM usr WHERE workspace_id = $1 AND email = $2")
+                            .bind(&w_id)
+                            .bind(email)
+                            .execute(&mut *tx)
+                            .await?;
+                        workspaces_changed.push(format!("{w_id}:removed"));
+                    } else if uninvited.rows_affected() > 0 {
+                        workspaces_changed.push(format!("{w_id}:uninvited"));
+                    }
+                }
+            }
+        }
+
+        if !groups_added.is_empty() || !groups_removed.is_empty() || !workspaces_changed.is_empty()
+        {
+            let groups_added = groups_added.join(",");
+            let groups_removed = groups_removed.join(",");
+            let workspaces_changed = workspaces_changed.join(",");
+            audit_log(
+                &mut *tx,
+                email,
+                "oauth.claim_mappings",
+                ActionKind::PermissionChange,
+                "global",
+                Some(email),
+                Some(
+                    [
+                        ("groups_added", &groups_added[..]),
+                        ("groups_removed", &groups_removed[..]),
+                        ("workspaces", &workspaces_changed[..]),
+                    ]
+                    .into(),
+                ),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Values of a claim as strings, looked up by its full name first so that claims named after urls
+/// keep working, then as a dot-separated path
+fn claim_values(claims: &Value, path: &str) -> Vec<String> {
+    let value = claims.get(path).or_else(|| {
+        path.split('.')
+            .try_fold(claims, |value, segment| value.get(segment))
+    });
+    fn to_string(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+    match value {
+        Some(Value::Array(values)) => values.iter().filter_map(to_string).collect(),
+        Some(value) => to_string(value).into_iter().collect(),
+        None => vec![],
+    }
+}
+
+/// Whether each instance group named by the mappings is matched, and the highest role matched in
+/// each workspace named by the mappings
+fn resolve_mappings(
+    mappings: &[ClaimMapping],
+    claims: &Value,
+) -> (
+    BTreeMap<String, bool>,
+    BTreeMap<String, Option<WorkspaceRole>>,
+) {
+    let mut groups = BTreeMap::new();
+    let mut roles = BTreeMap::new();
+    for mapping in mappings {
+        let matched = claim_values(claims, &mapping.claim).contains(&mapping.value);
+        if let Some(group) = &mapping.instance_group {
+            *groups.entry(group.clone()).or_insert(false) |= matched;
+        }
+        if let Some(w_id) = &mapping.workspace_id {
+            let role = roles.entry(w_id.clone()).or_insert(None);
+            if matched {
+                *role = (*role).max(Some(mapping.role.unwrap_or(WorkspaceRole::Developer)));
+            }
+        }
+    }
+    (groups, roles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mapping(
+        claim: &str,
+        value: &str,
+        instance_group: Option<&str>,
+        workspace_id: Option<&str>,
+        role: Option<WorkspaceRole>,
+    ) -> ClaimMapping {
+        ClaimMapping {
+            claim: claim.to_string(),
+            value: value.to_string(),
+            instance_group: instance_group.map(|x| x.to_string()),
+            workspace_id: workspace_id.map(|x| x.to_string()),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_discovery_url() {
+        for url in [
+            "https://idp.example.com/realms/windmill",
+            "https://idp.example.com/realms/windmill/",
+            "https://idp.example.com/realms/windmill/.well-known/openid-configuration",
+        ] {
+            assert_eq!(
+                discovery_url(url),
+                "https://idp.example.com/realms/windmill/.well-known/openid-configuration"
+            );
+        }
+    }
+
+    #[test]
+    fn test_claim_values() {
+        let claims = json!({
+            "email": "alice@example.com",
+            "groups": ["/admins", "/dev"],
+            "realm_access": { "roles": ["offline_access", "windmill-admin"] },
+            "https://example.com/team": "data",
+            "level": 3,
+        });
+        assert_eq!(claim_values(&claims, "email"), vec!["alice@example.com"]);
+        assert_eq!(claim_values(&claims, "groups"), vec!["/admins", "/dev"]);
+        assert_eq!(
+            claim_values(&claims, "realm_access.roles"),
+            vec!["offline_access", "windmill-admin"]
+        );
+        assert_eq!(
+            claim_values(&claims, "https://example.com/team"),
+            vec!["data"]
+        );
+        assert_eq!(claim_values(&claims, "level"), vec!["3"]);
+        assert!(claim_values(&claims, "realm_access.missing").is_empty());
+        assert!(claim_values(&claims, "realm_access").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_mappings() {
+        let mappings = vec![
+            mapping("groups", "/admins", Some("admins"), None, None),
+            mapping("groups", "/ops", Some("ops"), None, None),
+            mapping(
+                "groups",
+                "/dev",
+                None,
+                Some("prod"),
+                Some(WorkspaceRole::Operator),
+            ),
+            mapping(
+                "realm_access.roles",
+                "windmill-admin",
+                None,
+                Some("prod"),
+                Some(WorkspaceRole::Admin),
+            ),
+            mapping("groups", "/dev", None, Some("staging"), None),
+            mapping(
+                "groups",
+                "/ops",
+                None,
+                Some("infra"),
+                Some(WorkspaceRole::Admin),
+            ),
+        ];
+        let claims = json!({
+            "groups": ["/admins", "/dev"],
+            "realm_access": { "roles": ["windmill-admin"] },
+        });
+        let (groups, roles) = resolve_mappings(&mappings, &claims);
+        assert_eq!(
+            groups,
+            BTreeMap::from([("admins".to_string(), true), ("ops".to_string(), false)])
+        );
+        assert_eq!(
+            roles,
+            BTreeMap::from([
+                ("infra".to_string(), None),
+                ("prod".to_string(), Some(WorkspaceRole::Admin)),
+                ("staging".to_string(), Some(WorkspaceRole::Developer)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_oidc_scopes() {
+        let mut config: OidcConfig =
+            serde_json::from_value(json!({ "discovery_url": "https://idp.example.com" })).unwrap();
+        assert_eq!(oidc_scopes(&config), vec!["openid", "email", "profile"]);
+        config.scopes = Some(vec!["email".to_string(), "groups".to_string()]);
+        assert_eq!(oidc_scopes(&config), vec!["openid", "email", "groups"]);
+    }
+
+    #[tokio::test]
+    async fn test_jwks_refetch_interval() {
+        let provider = OidcProvider {
+            client_id: "windmill".to_string(),
+            issuer: "https://idp.example.com".to_string(),
+            // nothing listens there, so each refetch fails
+            jwks_uri: "http://127.0.0.1:1/jwks".to_string(),
+            email_claim: "email".to_string(),
+            claim_mappings: vec![],
+            jwks: Arc::new(RwLock::new(JwkSet { keys: vec![] })),
+            jwks_fetched_at: Arc::new(Mutex::new(Instant::now() - JWKS_REFETCH_INTERVAL)),
+        };
+        assert!(matches!(
+            provider.decoding_key(Some("unknown")).await,
+            Err(Error::BadConfig(_))
+        ));
+        // the keys were just fetched, the unknown key is rejected without fetching them again
+        assert!(matches!(
+            provider.decoding_key(Some("unknown")).await,
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::collections::HashMap;
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Extension, Path, Query},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use hyper::header;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tempfile::TempDir;
+use tokio::{fs::File, io::AsyncReadExt};
+use tokio_util::io::ReaderStream;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{to_anyhow, Error, JsonResult, Result},
+    global_settings::PACKAGE_MIRROR_SECRET_SETTING,
+    package_mirror::{insert_artifact, normalize_pip_name, GO, MIRROR_KINDS, NPM, PIP},
+    utils::{paginate, Pagination, StripPath},
+};
+
+use crate::{
+    db::{ApiAuthed, DB},
+    utils::require_super_admin,
+};
+
+const METADATA_SUFFIX: &str = ".metadata.json";
+
+pub fn global_service() -> Router {
+    Router::new()
+        .route("/list", get(list_artifacts))
+        .route("/export", get(export_artifacts))
+        .route("/import", post(import_artifacts))
+        .route("/delete/:kind/*path", delete(delete_artifact))
+}
+
+/// Registry protocols served to workers in offline mode, authenticated by the mirror secret
+pub fn registry_service() -> Router {
+    Router::new()
+        .route("/:secret/pip/simple/:name/", get(pip_simple_index))
+        .route("/:secret/pip/files/*filename", get(pip_file))
+        .route("/:secret/npm/*path", get(npm_registry))
+        .route("/:secret/go/*path", get(go_proxy))
+}
+
+#[derive(FromRow, Serialize)]
+struct MirrorArtifact {
+    kind: String,
+    path: String,
+    name: String,
+    version: String,
+    sha256: String,
+    size: i64,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+struct ListArtifactsQuery {
+    kind: Option<String>,
+    name: Option<String>,
+}
+
+async fn list_artifacts(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Query(pagination): Query<Pagination>,
+    Query(lq): Query<ListArtifactsQuery>,
+) -> JsonResult<Vec<MirrorArtifact>> {
+    require_super_admin(&db, &authed.email).await?;
+    let (per_page, offset) = paginate(pagination);
+
+    let rows = sqlx::query_as::<_, MirrorArtifact>(
+        "SELECT kind, path, name, version, sha256, size, created_at FROM package_mirror \
+         WHERE ($1::text IS NULL OR kind = $1) AND ($2::text IS NULL OR name = $2) \
+         ORDER BY kind, name, created_at DESC LIMIT $3 OFFSET $4",
+    )
+    .bind(&lq.kind)
+    .bind(&lq.name)
+    .bind(per_page as i64)
+    .bind(offset as i64)
+    .fetch_all(&db)
+    .await?;
+    Ok(Json(rows))
+}
+
+fn tar_header(size: usize) -> tokio_tar::Header {
+    let mut header = tokio_tar::Header::new_gnu();
+    header.set_size(size as u64);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mode(0o644);
+    header.set_cksum();
+    header
+}
+
+async fn export_artifacts(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+) -> Result<([(header::HeaderName, String); 2], impl IntoResponse)> {
+    require_super_admin(&db, &authed.email).await?;
+
+    let tmp_dir = TempDir::new_in("/tmp/windmill/")?;
+    let name = "windmill-package-mirror.tar";
+    let file_path = tmp_dir.path().join(name);
+    let mut archive = tokio_tar::Builder::new(File::create(&file_path).await?);
+
+    let keys = sqlx::query_as::<_, (String, String)>(
+        "SELECT kind, path FROM package_mirror ORDER BY kind, path",
+    )
+    .fetch_all(&db)
+    .await?;
+    // artifacts are loaded one at a time to not hold the whole mirror in memory
+    for (kind, path) in keys {
+        let artifact = sqlx::query_as::<_, (Vec<u8>, Option<serde_json::Value>)>(
+            "SELECT content, metadata FROM package_mirror WHERE kind = $1 AND path = $2",
+        )
+        .bind(&kind)
+        .bind(&path)
+        .fetch_optional(&db)
+        .await?;
+        if let Some((content, metadata)) = artifact {
+            let entry = format!("{kind}/{path}");
+            archive
+                .append_data(&mut tar_header(content.len()), &entry, content.as_slice())
+                .await?;
+            if let Some(metadata) = metadata {
+                let metadata = serde_json::to_vec(&metadata).map_err(to_anyhow)?;
+                archive
+                    .append_data(
+                        &mut tar_header(metadata.len()),
+                        format!("{entry}{METADATA_SUFFIX}"),
+                        metadata.as_slice(),
+                    )
+                    .await?;
+            }
+        }
+    }
+    archive.into_inner().await?.sync_all().await?;
+
+    let file = File::open(&file_path).await?;
+    let body = StreamBody::new(ReaderStream::new(file));
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/x-tar".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}\""),
+        ),
+    ];
+    Ok((headers, body))
+}
+
+async fn import_artifacts(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    body: Bytes,
+) -> Result<String> {
+    require_super_admin(&db, &authed.email).await?;
+
+    let mut archive = tokio_tar::Archive::new(body.as_ref());
+    let mut entries = archive.entries()?;
+    let mut artifacts = vec![];
+    let mut metadatas = HashMap::new();
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path()?.to_string_lossy().to_string();
+        let mut content = vec![];
+        entry.read_to_end(&mut content).await?;
+        if let Some(artifact) = entry_path.strip_suffix(METADATA_SUFFIX) {
+            let metadata =
+                serde_json::from_slice::<serde_json::Value>(&content).map_err(to_anyhow)?;
+            metadatas.insert(artifact.to_string(), metadata);
+        } else {
+            artifacts.push((entry_path, content));
+        }
+    }
+
+    let mut imported = 0;
+    for (entry_path, content) in artifacts {
+        let (kind, path) = entry_path
+            .split_once('/')
+            .filter(|(kind, _)| MIRROR_KINDS.contains(kind))
+            .ok_or_else(|| {
+                Error::BadRequest(format!("{entry_path} is not a package mirror artifact"))
+            })?;
+        let metadata = metadatas.remove(&entry_path);
+        if insert_artifact(&db, kind, path, &content, metadata).await? {
+            imported += 1;
+        }
+    }
+
+    let mut tx = db.begin().await?;
+    let imported_count = imported.to_string();
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "package_mirror.import",
+        ActionKind::Create,
+        "global",
+        None,
+        Some([("imported", imported_count.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!(
+        "imported {imported} artifacts in the package mirror"
+    ))
+}
+
+async fn delete_artifact(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((kind, path)): Path<(String, StripPath)>,
+) -> Result<String> {
+    require_super_admin(&db, &authed.email).await?;
+    let path = path.to_path();
+
+    let mut tx = db.begin().await?;
+    let deleted = sqlx::query("DELETE FROM package_mirror WHERE kind = $1 AND path = $2")
+        .bind(&kind)
+        .bind(path)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(Error::NotFound(format!(
+            "{kind} artifact {path} not found in the package mirror"
+        )));
+    }
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "package_mirror.delete",
+        ActionKind::Delete,
+        "global",
+        Some(&format!("{kind}/{path}")),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("deleted {kind} artifact {path}"))
+}
+
+async fn check_mirror_secret(db: &DB, secret: &str) -> Result<()> {
+    let expected = sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT value FROM global_settings WHERE name = $1",
+    )
+    .bind(PACKAGE_MIRROR_SECRET_SETTING)
+    .fetch_optional(db)
+    .await?;
+    match expected {
+        Some(serde_json::Value::String(expected)) if expected == secret => Ok(()),
+        _ => Err(Error::NotAuthorized(
+            "Invalid package mirror secret".to_string(),
+        )),
+    }
+}
+
+async fn get_artifact_content(db: &DB, kind: &str, path: &str) -> Result<Vec<u8>> {
+    sqlx::query_scalar::<_, Vec<u8>>(
+        "SELECT content FROM package_mirror WHERE kind = $1 AND path = $2",
+    )
+    .bind(kind)
+    .bind(path)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("{kind} artifact {path} not found")))
+}
+
+fn binary_response(content_type: &'static str, content: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, content_type)], content).into_response()
+}
+
+/// PEP 503 simple repository page of a distribution
+async fn pip_simple_index(
+    Extension(db): Extension<DB>,
+    Path((secret, name)): Path<(String, String)>,
+) -> Result<Response> {
+    check_mirror_secret(&db, &secret).await?;
+    let name = normalize_pip_name(&name);
+
+    let files = sqlx::query_as::<_, (String, String)>(
+        "SELECT path, sha256 FROM package_mirror WHERE kind = $1 AND name = $2 ORDER BY path",
+    )
+    .bind(PIP)
+    .bind(&name)
+    .fetch_all(&db)
+    .await?;
+    if files.is_empty() {
+        return Err(Error::NotFound(format!(
+            "{name} not found in the package mirror"
+        )));
+    }
+
+    let links = files
+        .iter()
+        .map(|(path, sha256)| {
+            format!("<a href=\"../../files/{path}#sha256={sha256}\">{path}</a><br/>")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let page = format!(
+        "<!DOCTYPE html>\n<html><body>\n<h1>Links for {name}</h1>\n{links}\n</body></html>\n"
+    );
+    Ok(([(header::CONTENT_TYPE, "text/html")], page).into_response())
+}
+
+async fn pip_file(
+    Extension(db): Extension<DB>,
+    Path((secret, filename)): Path<(String, StripPath)>,
+) -> Result<Response> {
+    check_mirror_secret(&db, &secret).await?;
+    let content = get_artifact_content(&db, PIP, filename.to_path()).await?;
+    Ok(binary_response("application/octet-stream", content))
+}
+
+/// Base url of the mirror as seen by the client, used for the tarball urls of npm packuments
+fn request_base_url(headers: &HeaderMap, secret: &str) -> String {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("localhost");
+    let proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("http");
+    format!("{proto}://{host}/api/mirror/{secret}")
+}
+
+async fn npm_registry(
+    Extension(db): Extension<DB>,
+    headers: HeaderMap,
+    Path((secret, path)): Path<(String, StripPath)>,
+) -> Result<Response> {
+    check_mirror_secret(&db, &secret).await?;
+    let path = path.to_path().replace("%2f", "/").replace("%2F", "/");
+
+    if path.contains("/-/") {
+        let content = get_artifact_content(&db, NPM, &path).await?;
+        return Ok(binary_response("application/octet-stream", content));
+    }
+
+    let versions = sqlx::query_as::<_, (String, String, Option<serde_json::Value>)>(
+        "SELECT path, version, metadata FROM package_mirror WHERE kind = $1 AND name = $2 \
+         ORDER BY created_at",
+    )
+    .bind(NPM)
+    .bind(&path)
+    .fetch_all(&db)
+    .await?;
+    if versions.is_empty() {
+        return Err(Error::NotFound(format!(
+            "{path} not found in the package mirror"
+        )));
+    }
+
+    let base_url = request_base_url(&headers, &secret);
+    let mut latest = None;
+    let mut manifests = serde_json::Map::new();
+    for (artifact_path, version, metadata) in versions {
+        let mut manifest = metadata
+            .filter(|x| x.is_object())
+            .unwrap_or_else(|| serde_json::json!({ "name": path, "version": version }));
+        let tarball = format!("{base_url}/npm/{artifact_path}");
+        match manifest.get_mut("dist").and_then(|x| x.as_object_mut()) {
+            Some(dist) => {
+                dist.insert("tarball".to_string(), serde_json::Value::String(tarball));
+            }
+            None => {
+                manifest["dist"] = serde_json::json!({ "tarball": tarball });
+            }
+        }
+        latest = Some(version.clone());
+        manifests.insert(version, manifest);
+    }
+
+    Ok(Json(serde_json::json!({
+        "name": path,
+        "dist-tags": { "latest": latest },
+        "versions": manifests,
+    }))
+    .into_response())
+}
+
+/// GOPROXY protocol, `@latest` is not served as offline resolution must go through a go.sum
+async fn go_proxy(
+    Extension(db): Extension<DB>,
+    Path((secret, path)): Path<(String, StripPath)>,
+) -> Result<Response> {
+    check_mirror_secret(&db, &secret).await?;
+    let path = path.to_path();
+
+    if let Some(module) = path.strip_suffix("/@v/list") {
+        let versions = sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT version FROM package_mirror WHERE kind = $1 AND name = $2 \
+             AND path LIKE '%.mod' ORDER BY version",
+        )
+        .bind(GO)
+        .bind(module)
+        .fetch_all(&db)
+        .await?;
+        let mut list = versions.join("\n");
+        list.push('\n');
+        return Ok(([(header::CONTENT_TYPE, "text/plain")], list).into_response());
+    }
+
+    let content = get_artifact_content(&db, GO, path).await?;
+    let content_type = if path.ends_with(".zip") {
+        "application/zip"
+    } else if path.ends_with(".info") {
+        "application/json"
+    } else {
+        "text/plain"
+    };
+    Ok(binary_response(content_type, content))
+}
@@ -20,6 +20,7 @@ use axum::{
 };
 use bytes::Bytes;
 use hyper::{header, StatusCode};
+use jsonschema::{error::ValidationErrorKind, JSONSchema};
 use serde::{Deserialize, Serialize};
 use serde_json::{value::RawValue, Value};
 use sql_builder::{bind::Bind, SqlBuilder};
@@ -57,6 +58,7 @@ pub fn workspaced_service() -> Router {
         .route("/type/update/:name", post(update_resource_type))
         .route("/type/delete/:name", delete(delete_resource_type))
         .route("/type/create", post(create_resource_type))
+        .route("/type/validate/:name", get(validate_resources_of_type))
 }
 
 pub fn public_service() -> Router {
@@ -130,6 +132,12 @@ pub struct ListResourceQuery {
     resource_type_exclude: Option<String>,
 }
 
+#[derive(Serialize)]
+pub struct InvalidResource {
+    path: String,
+    errors: Vec<String>,
+}
+
 #[derive(Serialize, FromRow)]
 pub struct NamePath {
     name: String,
@@ -461,7 +469,7 @@ pub async fn transform_json_value<'c>(
         Value::String(y) if y.starts_with("$var:") => {
             let path = y.strip_prefix("$var:").unwrap();
             let tx: Transaction<'_, Postgres> = user_db.clone().begin(authed).await?;
-            let v = crate::variables::get_value_internal(tx, db, workspace, path, &authed.username)
+            let v = crate::variables::get_value_internal(tx, db, workspace, path, authed, *job_id)
                 .await?;
             Ok(Value::String(v))
         }
@@ -570,6 +578,150 @@ async fn check_path_conflict<'c>(
     return Ok(());
 }
 
+/// Schema of the resource type, the one of the workspace taking precedence over the global one
+async fn fetch_resource_type_schema<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+    resource_type: &str,
+) -> Result<Option<Option<Value>>> {
+    let schema = sqlx::query_scalar::<_, Option<Value>>(
+        "SELECT schema FROM resource_type WHERE name = $1 \
+         AND (workspace_id = $2 OR workspace_id = 'admins') \
+         ORDER BY workspace_id = 'admins' LIMIT 1",
+    )
+    .bind(resource_type)
+    .bind(w_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(schema)
+}
+
+async fn get_resource_type_schema<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+    resource_type: &str,
+) -> Result<Option<JSONSchema>> {
+    let schema = fetch_resource_type_schema(tx, w_id, resource_type)
+        .await?
+        .flatten();
+    let Some(schema) = schema else {
+        return Ok(None);
+    };
+    match compile_resource_type_schema(&schema) {
+        Ok(schema) => Ok(Some(schema)),
+        Err(e) => {
+            tracing::warn!("resource type {resource_type} of {w_id} has an invalid schema: {e}");
+            Ok(None)
+        }
+    }
+}
+
+fn compile_resource_type_schema(schema: &Value) -> Result<JSONSchema> {
+    JSONSchema::compile(schema)
+        .map_err(|e| Error::BadRequest(format!("invalid resource type schema: {e}")))
+}
+
+fn collect_references(value: &Value, pointer: String, references: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(s) if s.starts_with("$var:") || s.starts_with("$res:") => {
+            references.push((pointer, s.clone()))
+        }
+        // like transform_json_value, only objects are traversed
+        Value::Object(m) => {
+            for (k, v) in m {
+                let key = k.replace('~', "~0").replace('/', "~1");
+                collect_references(v, format!("{pointer}/{key}"), references);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Errors of a resource value against the schema of its resource type, once its `$var:` and
+/// `$res:` references are resolved like they are at runtime. Secrets are not decrypted, so only
+/// their type, a string, is checked.
+async fn resource_value_errors<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+    schema: &JSONSchema,
+    value: &Value,
+) -> Result<Vec<String>> {
+    let mut references = vec![];
+    collect_references(value, String::new(), &mut references);
+
+    let mut value = value.clone();
+    let mut errors = vec![];
+    let mut secrets = vec![];
+    for (pointer, reference) in references {
+        let resolved = if let Some(path) = reference.strip_prefix("$var:") {
+            sqlx::query_as::<_, (String, bool)>(
+                "SELECT value, is_secret FROM variable WHERE path = $1 AND workspace_id = $2",
+            )
+            .bind(path)
+            .bind(w_id)
+            .fetch_optional(&mut **tx)
+            .await?
+            .map(|(v, is_secret)| {
+                if is_secret {
+                    secrets.push(pointer.clone());
+                    Value::String(reference.clone())
+                } else {
+                    Value::String(v)
+                }
+            })
+        } else {
+            let path = reference.strip_prefix("$res:").unwrap_or_default();
+            sqlx::query_scalar::<_, Option<Value>>(
+                "SELECT value FROM resource WHERE path = $1 AND workspace_id = $2",
+            )
+            .bind(path)
+            .bind(w_id)
+            .fetch_optional(&mut **tx)
+            .await?
+            .map(|v| v.unwrap_or(Value::Null))
+        };
+        match (resolved, value.pointer_mut(&pointer)) {
+            (Some(resolved), Some(v)) => *v = resolved,
+            _ => errors.push(format!("{pointer}: {reference} not found")),
+        }
+    }
+
+    if let Err(schema_errors) = schema.validate(&value) {
+        errors.extend(
+            schema_errors
+                .filter(|e| {
+                    matches!(e.kind, ValidationErrorKind::Type { .. })
+                        || !secrets.contains(&e.instance_path.to_string())
+                })
+                .map(|e| match e.instance_path.to_string() {
+                    pointer if pointer.is_empty() => e.to_string(),
+                    pointer => format!("{pointer}: {e}"),
+                }),
+        );
+    }
+    Ok(errors)
+}
+
+async fn check_resource_value<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+    path: &str,
+    resource_type: &str,
+    value: &Value,
+) -> Result<()> {
+    let Some(schema) = get_resource_type_schema(tx, w_id, resource_type).await? else {
+        return Ok(());
+    };
+    let errors = resource_value_errors(tx, w_id, &schema, value).await?;
+    if !errors.is_empty() {
+        return Err(Error::BadRequest(format!(
+            "value of resource {path} does not match resource type {resource_type}: {}",
+            errors.join(", ")
+        )));
+    }
+    Ok(())
+}
+
 #[derive(Deserialize)]
 struct CreateResourceQuery {
     update_if_exists: Option<bool>,
@@ -593,6 +745,15 @@ async fn create_resource(
     }
 
     let res_value = resource.value.unwrap_or_default();
+    let value = serde_json::from_str::<Value>(res_value.get()).unwrap_or(Value::Null);
+    check_resource_value(
+        &mut tx,
+        &w_id,
+        &resource.path,
+        &resource.resource_type,
+        &value,
+    )
+    .await?;
     let raw_json = sqlx::types::Json(res_value.as_ref());
 
     sqlx::query!(
@@ -693,7 +854,7 @@ async fn update_resource(
     if let Some(npath) = &ns.path {
         sqlb.set_str("path", npath);
     }
-    if let Some(nvalue) = ns.value {
+    if let Some(nvalue) = ns.value.as_ref() {
         sqlb.set_str("value", nvalue.to_string());
     }
     if let Some(ndesc) = ns.description {
@@ -722,6 +883,20 @@ async fn update_resource(
         }
     }
 
+    if let Some(nvalue) = ns.value.as_ref() {
+        let value = serde_json::from_str::<Value>(nvalue.get()).unwrap_or(Value::Null);
+        let resource_type = sqlx::query_scalar::<_, String>(
+            "SELECT resource_type FROM resource WHERE path = $1 AND workspace_id = $2",
+        )
+        .bind(path)
+        .bind(&w_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(resource_type) = resource_type {
+            check_resource_value(&mut tx, &w_id, path, &resource_type, &value).await?;
+        }
+    }
+
     let sql = sqlb.sql().map_err(|e| Error::InternalErr(e.to_string()))?;
     let npath_o: Option<String> = sqlx::query_scalar(&sql).fetch_optional(&mut *tx).await?;
 
@@ -766,6 +941,18 @@ async fn update_resource_value(
     let path = path.to_path();
     let mut tx = user_db.begin(&authed).await?;
 
+    let resource_type = sqlx::query_scalar::<_, String>(
+        "SELECT resource_type FROM resource WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(path)
+    .bind(&w_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(resource_type) = resource_type {
+        let value = nv.value.clone().unwrap_or(Value::Null);
+        check_resource_value(&mut tx, &w_id, path, &resource_type, &value).await?;
+    }
+
     sqlx::query!(
         "UPDATE resource SET value = $1 WHERE path = $2 AND workspace_id = $3",
         nv.value,
@@ -872,6 +1059,10 @@ async fn create_resource_type(
     Path(w_id): Path<String>,
     Json(resource_type): Json<CreateResourceType>,
 ) -> Result<(StatusCode, String)> {
+    if let Some(schema) = resource_type.schema.as_ref() {
+        compile_resource_type_schema(schema)?;
+    }
+
     let mut tx = user_db.begin(&authed).await?;
 
     check_rt_path_conflict(&mut tx, &w_id, &resource_type.name).await?;
@@ -981,6 +1172,7 @@ async fn update_resource_type(
     sqlb.and_where_eq("name", "?".bind(&name));
     sqlb.and_where_eq("workspace_id", "?".bind(&w_id));
     if let Some(nschema) = ns.schema {
+        compile_resource_type_schema(&nschema)?;
         sqlb.set_str("schema", nschema);
     }
     if let Some(ndesc) = ns.description {
@@ -1008,3 +1200,42 @@ async fn update_resource_type(
 
     Ok(format!("resource_type {} updated", name))
 }
+
+/// Resources of the type, among the ones visible to the user, whose value does not match its
+/// schema, typically after it changed
+async fn validate_resources_of_type(
+    authed: ApiAuthed,
+    Extension(user_db): Extension<UserDB>,
+    Path((w_id, name)): Path<(String, String)>,
+) -> JsonResult<Vec<InvalidResource>> {
+    let mut tx = user_db.begin(&authed).await?;
+
+    let schema = fetch_resource_type_schema(&mut tx, &w_id, &name).await?;
+    let schema = not_found_if_none(schema, "ResourceType", &name)?;
+    let Some(schema) = schema else {
+        tx.commit().await?;
+        return Ok(Json(vec![]));
+    };
+    let schema = compile_resource_type_schema(&schema)?;
+
+    let resources = sqlx::query_as::<_, (String, Option<Value>)>(
+        "SELECT path, value FROM resource WHERE resource_type = $1 AND workspace_id = $2 \
+         ORDER BY path",
+    )
+    .bind(&name)
+    .bind(&w_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut invalid = vec![];
+    for (path, value) in resources {
+        let value = value.unwrap_or(Value::Null);
+        let errors = resource_value_errors(&mut tx, &w_id, &schema, &value).await?;
+        if !errors.is_empty() {
+            invalid.push(InvalidResource { path, errors });
+        }
+    }
+    tx.commit().await?;
+
+    Ok(Json(invalid))
+}
@@ -0,0 +1,171 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use axum::{
+    extract::{Extension, Path, Query},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    db::UserDB,
+    error::{Error, JsonResult, Result},
+    utils::{paginate, require_admin, Pagination, StripPath},
+};
+
+use crate::db::{ApiAuthed, DB};
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/list", get(list_cache_entries))
+        .route("/purge/p/*path", delete(purge_cache_entries))
+        .route("/purge_all", delete(purge_all_cache_entries))
+}
+
+#[derive(FromRow, Serialize)]
+struct CacheEntry {
+    path: String,
+    cache_key: String,
+    job_id: Option<Uuid>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    expire: chrono::DateTime<chrono::Utc>,
+    hits: i32,
+    last_hit_at: Option<chrono::DateTime<chrono::Utc>>,
+    result_size: i32,
+}
+
+#[derive(Deserialize)]
+struct ListCacheQuery {
+    path: Option<String>,
+}
+
+// cached results can only be seen or purged by those who can see the script or flow they are
+// the results of, cache entries of all paths are reserved to admins
+async fn check_path_visible(
+    authed: &ApiAuthed,
+    user_db: UserDB,
+    w_id: &str,
+    path: &str,
+) -> Result<()> {
+    if authed.is_admin {
+        return Ok(());
+    }
+    let mut tx = user_db.begin(authed).await?;
+    let visible = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM script WHERE path = $1 AND workspace_id = $2) \
+         OR EXISTS(SELECT 1 FROM flow WHERE path = $1 AND workspace_id = $2)",
+    )
+    .bind(path)
+    .bind(w_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    if visible {
+        Ok(())
+    } else {
+        Err(Error::NotFound(format!("script or flow {path} not found")))
+    }
+}
+
+async fn list_cache_entries(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Extension(user_db): Extension<UserDB>,
+    Path(w_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(lq): Query<ListCacheQuery>,
+) -> JsonResult<Vec<CacheEntry>> {
+    if let Some(path) = lq.path.as_ref() {
+        check_path_visible(&authed, user_db, &w_id, path).await?;
+    } else {
+        require_admin(authed.is_admin, &authed.username)?;
+    }
+    let (per_page, offset) = paginate(pagination);
+
+    let entries = sqlx::query_as::<_, CacheEntry>(
+        "SELECT path, cache_key, job_id, created_at, expire, hits, last_hit_at, \
+         pg_column_size(result) AS result_size FROM job_result_cache \
+         WHERE workspace_id = $1 AND ($2::text IS NULL OR path = $2) AND expire > now() \
+         ORDER BY path, created_at DESC LIMIT $3 OFFSET $4",
+    )
+    .bind(&w_id)
+    .bind(&lq.path)
+    .bind(per_page as i64)
+    .bind(offset as i64)
+    .fetch_all(&db)
+    .await?;
+
+    Ok(Json(entries))
+}
+
+async fn purge_cache_entries(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Extension(user_db): Extension<UserDB>,
+    Path((w_id, path)): Path<(String, StripPath)>,
+) -> Result<String> {
+    let path = path.to_path();
+    check_path_visible(&authed, user_db, &w_id, path).await?;
+
+    let mut tx = db.begin().await?;
+    let purged = sqlx::query("DELETE FROM job_result_cache WHERE workspace_id = $1 AND path = $2")
+        .bind(&w_id)
+        .bind(path)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let purged_count = purged.to_string();
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "result_cache.purge",
+        ActionKind::Delete,
+        &w_id,
+        Some(path),
+        Some([("purged", purged_count.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("purged {purged} cached results of {path}"))
+}
+
+async fn purge_all_cache_entries(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+    let purged = sqlx::query("DELETE FROM job_result_cache WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let purged_count = purged.to_string();
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "result_cache.purge_all",
+        ActionKind::Delete,
+        &w_id,
+        None,
+        Some([("purged", purged_count.as_str())].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("purged {purged} cached results"))
+}
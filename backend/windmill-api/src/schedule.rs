@@ -27,7 +27,8 @@ use windmill_common::{
     db::UserDB,
     error::{Error, JsonResult, Result},
     schedule::Schedule,
-    utils::{not_found_if_none, paginate, Pagination, StripPath},
+    service_accounts::get_service_account_identity,
+    utils::{not_found_if_none, paginate, require_admin, Pagination, StripPath},
 };
 use windmill_queue::{self, schedule::push_scheduled_job, QueueTransaction};
 
@@ -66,6 +67,7 @@ pub struct NewSchedule {
     pub on_recovery_times: Option<i32>,
     pub on_recovery_extra_args: Option<serde_json::Value>,
     pub ws_error_handler_muted: Option<bool>,
+    pub service_account: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -174,6 +176,15 @@ async fn create_schedule(
     .await
     .map_err(|e| Error::InternalErr(format!("inserting schedule in {w_id}: {e}")))?;
 
+    set_schedule_service_account(
+        tx.transaction_mut(),
+        &authed,
+        &w_id,
+        &ns.path,
+        ns.service_account.as_deref(),
+    )
+    .await?;
+
     audit_log(
         &mut tx,
         &authed.username,
@@ -240,6 +251,15 @@ async fn edit_schedule(
     .await
     .map_err(|e| Error::InternalErr(format!("updating schedule in {w_id}: {e}")))?;
 
+    set_schedule_service_account(
+        tx.transaction_mut(),
+        &authed,
+        &w_id,
+        path,
+        es.service_account.as_deref(),
+    )
+    .await?;
+
     audit_log(
         &mut tx,
         &authed.username,
@@ -670,6 +690,41 @@ pub struct EditSchedule {
     pub on_recovery_times: Option<i32>,
     pub on_recovery_extra_args: Option<serde_json::Value>,
     pub ws_error_handler_muted: Option<bool>,
+    pub service_account: Option<String>,
+}
+
+/// Only admins can make a schedule run as a service account or edit one that does
+async fn set_schedule_service_account(
+    tx: &mut Transaction<'_, Postgres>,
+    authed: &ApiAuthed,
+    w_id: &str,
+    path: &str,
+    service_account: Option<&str>,
+) -> Result<()> {
+    let current = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT service_account FROM schedule WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(path)
+    .bind(w_id)
+    .fetch_optional(&mut **tx)
+    .await?
+    .flatten();
+
+    if current.is_none() && service_account.is_none() {
+        return Ok(());
+    }
+    require_admin(authed.is_admin, &authed.username)?;
+    if let Some(service_account) = service_account {
+        get_service_account_identity(&mut **tx, w_id, service_account).await?;
+    }
+
+    sqlx::query("UPDATE schedule SET service_account = $1 WHERE path = $2 AND workspace_id = $3")
+        .bind(service_account)
+        .bind(path)
+        .bind(w_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
 }
 
 pub async fn clear_schedule<'c>(
@@ -20,16 +20,29 @@ use hyper::{header, http::HeaderValue, Request, StatusCode};
 use mime_guess::mime;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sql_builder::SqlBuilder;
-use windmill_common::error::{Error, Result};
+use windmill_common::error::Result;
 
 #[cfg(feature = "enterprise")]
 use axum::{extract::Path, Json};
+#[cfg(feature = "enterprise")]
+use sql_builder::SqlBuilder;
+#[cfg(feature = "enterprise")]
+use std::sync::Arc;
+#[cfg(feature = "enterprise")]
+use windmill_audit::{audit_log, ActionKind};
+#[cfg(feature = "enterprise")]
+use windmill_common::error::Error;
 
 #[cfg(feature = "enterprise")]
 use windmill_common::utils::not_found_if_none;
 
 use crate::db::DB;
+use crate::scim_filter::{parse_filter, Attribute, AttributeKind, SqlBind};
+#[cfg(feature = "enterprise")]
+use crate::{
+    schedule::clear_schedule,
+    users::{notify_invalidated_tokens, AuthCache},
+};
 
 lazy_static::lazy_static! {
     static ref SCIM_TOKEN: Option<String> = std::env::var("SCIM_TOKEN")
@@ -40,6 +53,13 @@ lazy_static::lazy_static! {
 pub fn global_service() -> Router {
     Router::new()
         .route("/Users", get(get_users).post(create_user))
+        .route(
+            "/Users/:id",
+            get(get_user)
+                .put(replace_user)
+                .patch(patch_user)
+                .delete(delete_user),
+        )
         .route("/Groups", get(get_groups).post(create_group))
         .route(
             "/Groups/:id",
@@ -118,6 +138,40 @@ struct User {
     id: String,
     userName: String,
     active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    displayName: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    email: String,
+    name: Option<String>,
+    disabled: bool,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        User {
+            id: row.email.clone(),
+            userName: row.email,
+            active: !row.disabled,
+            displayName: row.name,
+        }
+    }
+}
+
+/// Columns of the user attributes, the users being identified by their email
+fn user_attribute(path: &str) -> Option<Attribute> {
+    match path {
+        "id" | "username" | "emails" | "emails.value" => {
+            Some(Attribute { sql: "email", kind: AttributeKind::String })
+        }
+        "displayname" | "name.formatted" => {
+            Some(Attribute { sql: "name", kind: AttributeKind::String })
+        }
+        "active" => Some(Attribute { sql: "(NOT disabled)", kind: AttributeKind::Boolean }),
+        _ => None,
+    }
 }
 pub fn resource_response<S>(schema: &str, resources: Vec<S>) -> JsonScim<serde_json::Value>
 where
@@ -143,28 +197,33 @@ pub async fn get_users(
     Extension(db): Extension<DB>,
     Query(query): Query<ScimQuery>,
 ) -> Result<JsonScim<serde_json::Value>> {
-    let mut sqlb = SqlBuilder::select_from("usr")
-        .fields(&["email"])
-        .limit(query.count.unwrap_or(100000))
-        .offset(query.startIndex.map(|x| x - 1).unwrap_or(0))
-        .clone();
-
     tracing::info!("SCIM filter: {:?}", query.filter);
 
-    if let Some(filter) = query.filter {
-        let filter = filter
-            .replace("userName", "email")
-            .replace("eq", "=")
-            .replace("\"", "'");
-        sqlb.and_where(&filter);
+    let mut binds = vec![];
+    let condition = match query.filter {
+        Some(filter) => parse_filter(&filter)?.to_sql(&user_attribute, 0, &mut binds)?,
+        None => "true".to_string(),
+    };
+    let sql = format!(
+        "SELECT email, name, disabled FROM password WHERE {condition} ORDER BY email LIMIT ${} \
+         OFFSET ${}",
+        binds.len() + 1,
+        binds.len() + 2
+    );
+    let mut users_query = sqlx::query_as::<_, UserRow>(&sql);
+    for bind in binds {
+        users_query = match bind {
+            SqlBind::Text(s) => users_query.bind(s),
+            SqlBind::Bool(b) => users_query.bind(b),
+        };
     }
-
-    let sql = sqlb.sql().map_err(|e| Error::InternalErr(e.to_string()))?;
-    let users = sqlx::query_scalar(&sql)
+    let users: Vec<User> = users_query
+        .bind(query.count.unwrap_or(100000) as i64)
+        .bind(query.startIndex.unwrap_or(1).saturating_sub(1) as i64)
         .fetch_all(&db)
         .await?
         .into_iter()
-        .map(|x: String| User { id: x.clone(), userName: x, active: true })
+        .map(User::from)
         .collect();
     tracing::info!("SCIM users: {:?}", users);
     Ok(resource_response(
@@ -173,27 +232,359 @@ pub async fn get_users(
     ))
 }
 
+#[cfg(feature = "enterprise")]
+fn user_response(user: User) -> JsonScim<serde_json::Value> {
+    let mut json = json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+        "emails": [{"value": user.userName, "primary": true}],
+        "meta": {
+            "resourceType": "User"
+        }
+    });
+    if let (Some(json), Ok(serde_json::Value::Object(user))) =
+        (json.as_object_mut(), serde_json::to_value(user))
+    {
+        json.extend(user);
+    }
+    JsonScim(json)
+}
+
+#[cfg(feature = "enterprise")]
+async fn fetch_user<'c, E: sqlx::Executor<'c, Database = sqlx::Postgres>>(
+    db: E,
+    id: &str,
+) -> Result<User> {
+    let user =
+        sqlx::query_as::<_, UserRow>("SELECT email, name, disabled FROM password WHERE email = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await?;
+    Ok(not_found_if_none(user, "User", id)?.into())
+}
+
+#[cfg(feature = "enterprise")]
+#[derive(Deserialize, Debug)]
+pub struct ScimName {
+    formatted: Option<String>,
+}
+
 #[cfg(feature = "enterprise")]
 #[derive(Deserialize, Debug)]
 pub struct CreateUser {
     userName: String,
+    active: Option<bool>,
+    displayName: Option<String>,
+    name: Option<ScimName>,
+}
+
+#[cfg(feature = "enterprise")]
+impl CreateUser {
+    fn display_name(&self) -> Option<String> {
+        self.displayName
+            .clone()
+            .or_else(|| self.name.as_ref().and_then(|x| x.formatted.clone()))
+    }
 }
+
 #[cfg(feature = "enterprise")]
 pub async fn create_user(
     Extension(db): Extension<DB>,
     Json(body): Json<CreateUser>,
 ) -> Result<JsonScim<serde_json::Value>> {
     tracing::info!("SCIM creating user: {:?}", body);
-    sqlx::query!(
-        "INSERT INTO password (email, login_type, verified) VALUES ($1, 'saml', true) ON CONFLICT DO NOTHING",
-        body.userName,
-    ).execute(&db).await?;
-    Ok(JsonScim(json!({
-        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
-        "id": body.userName,
-        "userName": body.userName,
-        "active": true
-    })))
+    sqlx::query(
+        "INSERT INTO password (email, login_type, verified, name, disabled) \
+         VALUES ($1, 'saml', true, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(&body.userName)
+    .bind(body.display_name())
+    .bind(!body.active.unwrap_or(true))
+    .execute(&db)
+    .await?;
+    Ok(user_response(fetch_user(&db, &body.userName).await?))
+}
+
+#[cfg(feature = "enterprise")]
+pub async fn get_user(
+    Extension(db): Extension<DB>,
+    Path(id): Path<String>,
+) -> Result<JsonScim<serde_json::Value>> {
+    Ok(user_response(fetch_user(&db, &id).await?))
+}
+
+#[cfg(feature = "enterprise")]
+pub async fn replace_user(
+    Extension(db): Extension<DB>,
+    Extension(cache): Extension<Arc<AuthCache>>,
+    Path(id): Path<String>,
+    Json(body): Json<CreateUser>,
+) -> Result<JsonScim<serde_json::Value>> {
+    tracing::info!("SCIM replacing user {id}: {:?}", body);
+    if body.userName != id {
+        return Err(Error::BadRequest(format!(
+            "the userName of {id} cannot be changed to {}",
+            body.userName
+        )));
+    }
+    let mut tx = db.begin().await?;
+    fetch_user(&mut *tx, &id).await?;
+    sqlx::query("UPDATE password SET name = $1 WHERE email = $2")
+        .bind(body.display_name())
+        .bind(&id)
+        .execute(&mut *tx)
+        .await?;
+    let revoked = set_user_active(&mut tx, &id, body.active.unwrap_or(true)).await?;
+    let workspaces = user_workspaces(&mut tx, &id).await?;
+    notify_invalidated_tokens(&mut tx, &revoked, &workspaces).await?;
+    tx.commit().await?;
+    invalidate_tokens(&cache, revoked, &workspaces).await;
+    Ok(user_response(fetch_user(&db, &id).await?))
+}
+
+#[cfg(feature = "enterprise")]
+#[derive(Deserialize, Debug)]
+pub struct PatchUser {
+    pub Operations: Vec<Operation>,
+}
+
+#[cfg(feature = "enterprise")]
+#[derive(Default)]
+struct UserChanges {
+    active: Option<bool>,
+    name: Option<Option<String>>,
+}
+
+#[cfg(feature = "enterprise")]
+impl UserChanges {
+    fn apply(&mut self, path: &str, value: Option<&serde_json::Value>) -> Result<()> {
+        let path = path.to_lowercase();
+        let path = path
+            .strip_prefix("urn:ietf:params:scim:schemas:core:2.0:user:")
+            .unwrap_or(&path);
+        match (path, value) {
+            // some identity providers send the booleans as strings
+            ("active", Some(serde_json::Value::Bool(b))) => self.active = Some(*b),
+            ("active", Some(serde_json::Value::String(b))) => {
+                self.active = Some(
+                    b.to_lowercase()
+                        .parse::<bool>()
+                        .map_err(|_| Error::BadRequest(format!("invalid value of active: {b}")))?,
+                )
+            }
+            ("displayname" | "name.formatted", Some(serde_json::Value::String(name))) => {
+                self.name = Some(Some(name.clone()))
+            }
+            ("displayname" | "name.formatted", None | Some(serde_json::Value::Null)) => {
+                self.name = Some(None)
+            }
+            ("name", Some(serde_json::Value::Object(name))) => {
+                if let Some(formatted) = name.get("formatted") {
+                    self.apply("name.formatted", Some(formatted))?;
+                }
+            }
+            ("active" | "name", _) => {
+                return Err(Error::BadRequest(format!(
+                    "invalid value of {path}: {value:?}"
+                )))
+            }
+            _ => tracing::info!("SCIM ignoring unsupported user attribute {path}"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "enterprise")]
+pub async fn patch_user(
+    Extension(db): Extension<DB>,
+    Extension(cache): Extension<Arc<AuthCache>>,
+    Path(id): Path<String>,
+    Json(body): Json<PatchUser>,
+) -> Result<JsonScim<serde_json::Value>> {
+    tracing::info!("SCIM patching user {id}: {:?}", body);
+    let mut changes = UserChanges::default();
+    for operation in body.Operations.iter() {
+        let value = match operation.op.to_lowercase().as_str() {
+            "add" | "replace" => operation.value.as_ref(),
+            "remove" => None,
+            op => return Err(Error::BadRequest(format!("unknown patch operation {op}"))),
+        };
+        match (operation.path.as_ref(), value) {
+            (Some(path), value) => changes.apply(path, value)?,
+            // without a path, the value holds the attributes to set
+            (None, Some(serde_json::Value::Object(attributes))) => {
+                for (path, value) in attributes {
+                    changes.apply(path, Some(value))?;
+                }
+            }
+            (None, _) => {
+                return Err(Error::BadRequest(
+                    "patch operations without a path must have an object value".to_string(),
+                ))
+            }
+        }
+    }
+
+    let mut tx = db.begin().await?;
+    fetch_user(&mut *tx, &id).await?;
+    if let Some(name) = changes.name {
+        sqlx::query("UPDATE password SET name = $1 WHERE email = $2")
+            .bind(name)
+            .bind(&id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let revoked = match changes.active {
+        Some(active) => set_user_active(&mut tx, &id, active).await?,
+        None => vec![],
+    };
+    let workspaces = user_workspaces(&mut tx, &id).await?;
+    notify_invalidated_tokens(&mut tx, &revoked, &workspaces).await?;
+    tx.commit().await?;
+    invalidate_tokens(&cache, revoked, &workspaces).await;
+    Ok(user_response(fetch_user(&db, &id).await?))
+}
+
+/// Deprovisions the user: deactivates them, then removes them from the instance and from their
+/// workspaces. The items they own in the workspaces are kept.
+#[cfg(feature = "enterprise")]
+pub async fn delete_user(
+    Extension(db): Extension<DB>,
+    Extension(cache): Extension<Arc<AuthCache>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    tracing::info!("SCIM deleting user {id}");
+    let mut tx = db.begin().await?;
+    fetch_user(&mut *tx, &id).await?;
+    let revoked = set_user_active(&mut tx, &id, false).await?;
+    let workspaces = user_workspaces(&mut tx, &id).await?;
+
+    sqlx::query(
+        "DELETE FROM usr_to_group USING usr WHERE usr.email = $1 \
+         AND usr_to_group.workspace_id = usr.workspace_id AND usr_to_group.usr = usr.username",
+    )
+    .bind(&id)
+    .execute(&mut *tx)
+    .await?;
+    for query in [
+        "DELETE FROM usr WHERE email = $1",
+        "DELETE FROM workspace_invite WHERE email = $1",
+        "DELETE FROM email_to_igroup WHERE email = $1",
+        "DELETE FROM password WHERE email = $1",
+    ] {
+        sqlx::query(query).bind(&id).execute(&mut *tx).await?;
+    }
+    audit_log(
+        &mut *tx,
+        "scim",
+        "users.delete",
+        ActionKind::Delete,
+        "global",
+        Some(&id),
+        None,
+    )
+    .await?;
+    notify_invalidated_tokens(&mut tx, &revoked, &workspaces).await?;
+    tx.commit().await?;
+    invalidate_tokens(&cache, revoked, &workspaces).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Activates or deactivates the user, returning the tokens revoked. Deactivating revokes the
+/// tokens of the user, prevents them from logging in and disables their schedules, which are not
+/// enabled back on reactivation.
+#[cfg(feature = "enterprise")]
+async fn set_user_active(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+    active: bool,
+) -> Result<Vec<String>> {
+    let was_active = sqlx::query_scalar::<_, bool>(
+        "SELECT NOT disabled FROM password WHERE email = $1 FOR UPDATE",
+    )
+    .bind(email)
+    .fetch_one(&mut **tx)
+    .await?;
+    if was_active == active {
+        return Ok(vec![]);
+    }
+    sqlx::query("UPDATE password SET disabled = $1 WHERE email = $2")
+        .bind(!active)
+        .bind(email)
+        .execute(&mut **tx)
+        .await?;
+    if active {
+        audit_log(
+            &mut **tx,
+            "scim",
+            "users.activate",
+            ActionKind::Update,
+            "global",
+            Some(email),
+            None,
+        )
+        .await?;
+        return Ok(vec![]);
+    }
+
+    let revoked =
+        sqlx::query_scalar::<_, String>("DELETE FROM token WHERE email = $1 RETURNING token")
+            .bind(email)
+            .fetch_all(&mut **tx)
+            .await?;
+    let schedules = sqlx::query_as::<_, (String, String)>(
+        "UPDATE schedule SET enabled = false WHERE email = $1 AND enabled \
+         RETURNING workspace_id, path",
+    )
+    .bind(email)
+    .fetch_all(&mut **tx)
+    .await?;
+    for (w_id, path) in schedules.iter() {
+        clear_schedule(tx, path, w_id).await?;
+    }
+    let revoked_tokens = revoked.len().to_string();
+    let disabled_schedules = schedules.len().to_string();
+    audit_log(
+        &mut **tx,
+        "scim",
+        "users.deactivate",
+        ActionKind::Update,
+        "global",
+        Some(email),
+        Some(
+            [
+                ("revoked_tokens", revoked_tokens.as_str()),
+                ("disabled_schedules", disabled_schedules.as_str()),
+            ]
+            .into(),
+        ),
+    )
+    .await?;
+    Ok(revoked)
+}
+
+#[cfg(feature = "enterprise")]
+async fn user_workspaces(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+) -> Result<Vec<String>> {
+    Ok(
+        sqlx::query_scalar::<_, String>("SELECT workspace_id FROM usr WHERE email = $1")
+            .bind(email)
+            .fetch_all(&mut **tx)
+            .await?,
+    )
+}
+
+/// Evicts the revoked tokens from the authentication cache of this server, where they are cached
+/// per workspace
+#[cfg(feature = "enterprise")]
+async fn invalidate_tokens(cache: &AuthCache, revoked: Vec<String>, workspaces: &[String]) {
+    for token in revoked {
+        cache.invalidate("", token.clone()).await;
+        for w_id in workspaces {
+            cache.invalidate(w_id, token.clone()).await;
+        }
+    }
 }
 
 #[cfg(feature = "enterprise")]
@@ -212,7 +603,7 @@ pub async fn get_groups(
         .fetch_all(&db)
         .await?
         .into_iter()
-        .map(|x: String| User { id: x.clone(), userName: x, active: true })
+        .map(|x: String| User { id: x.clone(), userName: x, active: true, displayName: None })
         .collect();
     Ok(resource_response(
         "urn:ietf:params:scim:api:messages:2.0:ListResponse",
@@ -325,7 +716,7 @@ pub async fn create_group(
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Operation {
     pub op: String,
-    pub path: String,
+    pub path: Option<String>,
     pub value: Option<serde_json::Value>,
 }
 
@@ -0,0 +1,425 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Filters of the SCIM list queries (RFC 7644 section 3.4.2.2), parsed into an expression tree
+//! and compiled to a parameterized SQL condition over the columns the attributes map to.
+
+use serde_json::Value;
+use windmill_common::error::{Error, Result};
+
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    Compare { attr: String, op: CompareOp, value: Value },
+    Present(String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AttributeKind {
+    /// compared case-insensitively, as the string attributes of SCIM are not case exact by default
+    String,
+    Boolean,
+}
+
+/// Column an attribute path is stored in
+#[derive(Debug, Clone, Copy)]
+pub struct Attribute {
+    pub sql: &'static str,
+    pub kind: AttributeKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SqlBind {
+    Text(String),
+    Bool(bool),
+}
+
+pub fn parse_filter(filter: &str) -> Result<Filter> {
+    let tokens = tokenize(filter)?;
+    let mut parser = Parser { tokens, pos: 0, prefix: None, depth: 0 };
+    let filter = parser.parse_or()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(invalid_filter(format!("unexpected {token:?}")));
+    }
+    Ok(filter)
+}
+
+impl Filter {
+    /// Compiles the filter to a SQL condition, pushing the values to bind in order. Its
+    /// placeholders are numbered after the `bind_offset` placeholders of the rest of the query.
+    /// `attribute` maps the lowercased attribute paths, stripped of their schema, to their column.
+    pub fn to_sql(
+        &self,
+        attribute: &impl Fn(&str) -> Option<Attribute>,
+        bind_offset: usize,
+        binds: &mut Vec<SqlBind>,
+    ) -> Result<String> {
+        match self {
+            Filter::And(a, b) => Ok(format!(
+                "({} AND {})",
+                a.to_sql(attribute, bind_offset, binds)?,
+                b.to_sql(attribute, bind_offset, binds)?
+            )),
+            Filter::Or(a, b) => Ok(format!(
+                "({} OR {})",
+                a.to_sql(attribute, bind_offset, binds)?,
+                b.to_sql(attribute, bind_offset, binds)?
+            )),
+            Filter::Not(a) => Ok(format!(
+                "(NOT {})",
+                a.to_sql(attribute, bind_offset, binds)?
+            )),
+            Filter::Present(attr) => {
+                let Attribute { sql, kind } = get_attribute(attribute, attr)?;
+                Ok(match kind {
+                    AttributeKind::String => format!("({sql} IS NOT NULL AND {sql} != '')"),
+                    AttributeKind::Boolean => format!("({sql} IS NOT NULL)"),
+                })
+            }
+            Filter::Compare { attr, op, value } => {
+                let Attribute { sql, kind } = get_attribute(attribute, attr)?;
+                let placeholder = |binds: &Vec<SqlBind>| format!("${}", bind_offset + binds.len());
+                match (kind, op, value) {
+                    (_, CompareOp::Eq, Value::Null) => Ok(format!("({sql} IS NULL)")),
+                    (_, CompareOp::Ne, Value::Null) => Ok(format!("({sql} IS NOT NULL)")),
+                    (AttributeKind::Boolean, CompareOp::Eq | CompareOp::Ne, Value::Bool(b)) => {
+                        binds.push(SqlBind::Bool(*b));
+                        let op = if *op == CompareOp::Eq { "=" } else { "!=" };
+                        Ok(format!("({sql} {op} {})", placeholder(binds)))
+                    }
+                    (AttributeKind::String, op, Value::String(s)) => {
+                        let (op, s) = match op {
+                            CompareOp::Co => ("LIKE", format!("%{}%", escape_like(s))),
+                            CompareOp::Sw => ("LIKE", format!("{}%", escape_like(s))),
+                            CompareOp::Ew => ("LIKE", format!("%{}", escape_like(s))),
+                            CompareOp::Eq => ("=", s.clone()),
+                            CompareOp::Ne => ("IS DISTINCT FROM", s.clone()),
+                            CompareOp::Gt => (">", s.clone()),
+                            CompareOp::Ge => (">=", s.clone()),
+                            CompareOp::Lt => ("<", s.clone()),
+                            CompareOp::Le => ("<=", s.clone()),
+                        };
+                        binds.push(SqlBind::Text(s));
+                        Ok(format!("(lower({sql}) {op} lower({}))", placeholder(binds)))
+                    }
+                    _ => Err(invalid_filter(format!(
+                        "{op:?} {value} is not a valid comparison for the attribute {attr}"
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+fn get_attribute(attribute: &impl Fn(&str) -> Option<Attribute>, attr: &str) -> Result<Attribute> {
+    attribute(attr).ok_or_else(|| invalid_filter(format!("unsupported attribute {attr}")))
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn invalid_filter(message: String) -> Error {
+    Error::BadRequest(format!("invalid SCIM filter: {message}"))
+}
+
+/// Lowercased attribute path, stripped of the schema of the core resources
+fn normalize_attr_path(path: &str) -> String {
+    let path = path.to_lowercase();
+    for schema in [
+        "urn:ietf:params:scim:schemas:core:2.0:user:",
+        "urn:ietf:params:scim:schemas:core:2.0:group:",
+    ] {
+        if let Some(path) = path.strip_prefix(schema) {
+            return path.to_string();
+        }
+    }
+    path
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Word(String),
+    Str(String),
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = filter.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            '"' => {
+                // strings are JSON strings, escapes included
+                let mut end = None;
+                let mut escaped = false;
+                while let Some((j, c)) = chars.next() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = Some(j);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or_else(|| invalid_filter("unterminated string".to_string()))?;
+                let s = serde_json::from_str::<String>(&filter[i..=end])
+                    .map_err(|e| invalid_filter(format!("invalid string: {e}")))?;
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"') {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(filter[i..end].to_string()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // attribute path of the value path being parsed, e.g. `emails` in `emails[value eq "x"]`
+    prefix: Option<String>,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if *token == expected => Ok(()),
+            token => Err(invalid_filter(format!(
+                "expected {expected:?}, found {token:?}"
+            ))),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Filter> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(invalid_filter("too deeply nested".to_string()));
+        }
+        let mut filter = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_unary()?;
+        while self.peek_keyword("and") {
+            self.pos += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter> {
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            self.expect(Token::LParen)?;
+            let filter = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            return Ok(Filter::Not(Box::new(filter)));
+        }
+        if self.tokens.get(self.pos) == Some(&Token::LParen) {
+            self.pos += 1;
+            let filter = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            return Ok(filter);
+        }
+        self.parse_attr_expr()
+    }
+
+    fn parse_attr_expr(&mut self) -> Result<Filter> {
+        let path = match self.next() {
+            Some(Token::Word(path)) => normalize_attr_path(path),
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected an attribute, found {token:?}"
+                )))
+            }
+        };
+        let attr = match self.prefix.as_ref() {
+            Some(prefix) => format!("{prefix}.{path}"),
+            None => path,
+        };
+
+        if self.tokens.get(self.pos) == Some(&Token::LBracket) {
+            if self.prefix.is_some() {
+                return Err(invalid_filter("nested value paths".to_string()));
+            }
+            self.pos += 1;
+            self.prefix = Some(attr);
+            let filter = self.parse_or()?;
+            self.prefix = None;
+            self.expect(Token::RBracket)?;
+            return Ok(filter);
+        }
+
+        let op = match self.next() {
+            Some(Token::Word(op)) => op.to_lowercase(),
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected an operator after {attr}, found {token:?}"
+                )))
+            }
+        };
+        let op = match op.as_str() {
+            "pr" => return Ok(Filter::Present(attr)),
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            op => return Err(invalid_filter(format!("unknown operator {op}"))),
+        };
+        let value = match self.next() {
+            Some(Token::Str(s)) => Value::String(s.clone()),
+            Some(Token::Word(w)) => match w.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                w => serde_json::from_str::<serde_json::Number>(w)
+                    .map(Value::Number)
+                    .map_err(|_| invalid_filter(format!("invalid value {w}")))?,
+            },
+            token => {
+                return Err(invalid_filter(format!(
+                    "expected a value after {attr} {op:?}, found {token:?}"
+                )))
+            }
+        };
+        Ok(Filter::Compare { attr, op, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(path: &str) -> Option<Attribute> {
+        match path {
+            "username" | "emails.value" => {
+                Some(Attribute { sql: "email", kind: AttributeKind::String })
+            }
+            "active" => Some(Attribute { sql: "(NOT disabled)", kind: AttributeKind::Boolean }),
+            _ => None,
+        }
+    }
+
+    fn compile(filter: &str) -> Result<(String, Vec<SqlBind>)> {
+        let mut binds = vec![];
+        let sql = parse_filter(filter)?.to_sql(&attribute, 0, &mut binds)?;
+        Ok((sql, binds))
+    }
+
+    #[test]
+    fn test_compile_comparisons() {
+        let (sql, binds) = compile(r#"userName eq "Bob@Example.com""#).unwrap();
+        assert_eq!(sql, "(lower(email) = lower($1))");
+        assert_eq!(binds, vec![SqlBind::Text("Bob@Example.com".to_string())]);
+
+        let (sql, binds) = compile(r#"userName co "a_b%" and active eq true"#).unwrap();
+        assert_eq!(
+            sql,
+            "((lower(email) LIKE lower($1)) AND ((NOT disabled) = $2))"
+        );
+        assert_eq!(
+            binds,
+            vec![SqlBind::Text("%a\\_b\\%%".to_string()), SqlBind::Bool(true)]
+        );
+    }
+
+    #[test]
+    fn test_compile_precedence_and_paths() {
+        let (sql, _) = compile(
+            r#"urn:ietf:params:scim:schemas:core:2.0:User:userName sw "a" or userName ew "b" and not (active eq false)"#,
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "((lower(email) LIKE lower($1)) OR ((lower(email) LIKE lower($2)) AND (NOT ((NOT disabled) = $3))))"
+        );
+
+        let (sql, _) = compile(r#"emails[value eq "a\"b"] and userName pr"#).unwrap();
+        assert_eq!(
+            sql,
+            "((lower(email) = lower($1)) AND (email IS NOT NULL AND email != ''))"
+        );
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        for filter in [
+            r#"userName eq"#,
+            r#"userName eq "a"#,
+            r#"userName eq "a")"#,
+            r#"userName like "a""#,
+            r#"password eq "a""#,
+            r#"active co "a""#,
+            r#"userName eq "a"; DROP TABLE usr"#,
+            r#"(userName eq "a""#,
+        ] {
+            assert!(compile(filter).is_err(), "{filter}");
+        }
+    }
+}
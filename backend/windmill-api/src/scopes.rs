@@ -0,0 +1,400 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Token scopes follow the grammar `<domain>:<action>[:<path glob>]`, e.g. `scripts:read`,
+//! `jobs:run:f/team/*` or `variables:read:u/bot/*`. The domain is the workspaced router the
+//! request targets (`/api/w/:workspace/<domain>/...`) or `*` for all of them. `write` implies
+//! `read`. A scope restricted to a path glob only grants access to routes that target a single
+//! item whose path matches, listing routes are never granted by it.
+//!
+//! Scopes of the previous form (`run:script/<path>`, `listjobs`, ...) are still accepted and only
+//! give access to the jobs routes, where they are checked by `check_scopes`.
+
+use std::fmt;
+
+use axum::http::Method;
+
+const SCOPE_DOMAINS: &[&str] = &[
+    "access_requests",
+    "acls",
+    "ai",
+    "apps",
+    "audit",
+    "capture",
+    "embeddings",
+    "drafts",
+    "favorites",
+    "flows",
+    "folders",
+    "groups",
+    "inputs",
+    "job_artifacts",
+    "job_helpers",
+    "jobs",
+    "network_policies",
+    "oauth",
+    "raw_apps",
+    "resources",
+    "result_cache",
+    "schedules",
+    "scripts",
+    "service_accounts",
+    "usage",
+    "users",
+    "variables",
+    "workspaces",
+];
+
+const LEGACY_SCOPES: &[&str] = &[
+    "listjobs",
+    "resumeflow",
+    "deletejob",
+    "runscript",
+    "runflow",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScopeAction {
+    Read,
+    Write,
+    Run,
+}
+
+impl ScopeAction {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(ScopeAction::Read),
+            "write" => Some(ScopeAction::Write),
+            "run" => Some(ScopeAction::Run),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScopeAction::Read => "read",
+            ScopeAction::Write => "write",
+            ScopeAction::Run => "run",
+        }
+    }
+
+    fn grants(&self, required: ScopeAction) -> bool {
+        *self == required || (*self == ScopeAction::Write && required == ScopeAction::Read)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub domain: String,
+    pub action: ScopeAction,
+    pub path: Option<String>,
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(3, ':');
+        let domain = parts.next().unwrap_or_default();
+        let action = parts.next().unwrap_or_default();
+        let path = parts.next();
+
+        if domain != "*" && !SCOPE_DOMAINS.contains(&domain) {
+            return Err(format!(
+                "unknown scope domain `{domain}` in `{s}`, expected one of *, {}",
+                SCOPE_DOMAINS.join(", ")
+            ));
+        }
+        let action = ScopeAction::parse(action).ok_or_else(|| {
+            format!("invalid scope action `{action}` in `{s}`, expected read, write or run")
+        })?;
+        if action == ScopeAction::Run && domain != "jobs" && domain != "*" {
+            return Err(format!("the run action only applies to jobs in `{s}`"));
+        }
+        if let Some(path) = path {
+            if !(path.starts_with("u/") || path.starts_with("f/")) {
+                return Err(format!(
+                    "scope path `{path}` in `{s}` must start with u/ or f/"
+                ));
+            }
+        }
+
+        Ok(Scope { domain: domain.to_string(), action, path: path.map(|p| p.to_string()) })
+    }
+
+    fn grants(&self, required: &ScopeRequirement) -> bool {
+        if self.domain != "*" && self.domain != required.domain {
+            return false;
+        }
+        if !self.action.grants(required.action) {
+            return false;
+        }
+        match (&self.path, &required.path) {
+            (None, _) => true,
+            (Some(pattern), Some(path)) => glob_match(pattern, path),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// What a request needs to be granted by at least one of the scopes of its token
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeRequirement {
+    pub domain: String,
+    pub action: ScopeAction,
+    pub path: Option<String>,
+}
+
+impl fmt::Display for ScopeRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.domain, self.action.as_str())?;
+        if let Some(path) = &self.path {
+            write!(f, ":{path}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ScopeRequirement {
+    /// `path_vec` is the request path split on `/`, i.e. `["", "api", "w", <workspace>, <domain>, ..]`
+    pub fn from_request(method: &Method, path_vec: &[&str]) -> Option<Self> {
+        if path_vec.len() < 5 || path_vec[1] != "api" || path_vec[2] != "w" {
+            return None;
+        }
+        let domain = match path_vec[4] {
+            "openai" => "ai",
+            domain => domain,
+        };
+        let rest = &path_vec[5..];
+        let first = rest.first().copied().unwrap_or_default();
+
+        if domain == "jobs" {
+            let is_run =
+                first.starts_with("run") || first == "restart" || first == "add_batch_jobs";
+            let action = if is_run || (first == "flow" && rest.get(1) == Some(&"resume")) {
+                ScopeAction::Run
+            } else {
+                action_of_method(method)
+            };
+            let path = if first.starts_with("run") && matches!(rest.get(1), Some(&"p" | &"f")) {
+                join_item_path(&rest[2..])
+            } else {
+                None
+            };
+            return Some(ScopeRequirement { domain: domain.to_string(), action, path });
+        }
+
+        Some(ScopeRequirement {
+            domain: domain.to_string(),
+            action: action_of_method(method),
+            path: item_path_of(rest),
+        })
+    }
+
+    /// Maps the scopes historically checked by the jobs handlers to the current grammar
+    pub fn from_legacy(required: &str) -> Option<Self> {
+        let (action, path) = match required {
+            "listjobs" => (ScopeAction::Read, None),
+            "deletejob" => (ScopeAction::Write, None),
+            "resumeflow" | "runscript" | "runflow" => (ScopeAction::Run, None),
+            _ => {
+                let path = required
+                    .strip_prefix("run:script/")
+                    .or_else(|| required.strip_prefix("run:flow/"))?;
+                (ScopeAction::Run, Some(path.to_string()))
+            }
+        };
+        Some(ScopeRequirement { domain: "jobs".to_string(), action, path })
+    }
+}
+
+fn action_of_method(method: &Method) -> ScopeAction {
+    if method == Method::GET || method == Method::HEAD {
+        ScopeAction::Read
+    } else {
+        ScopeAction::Write
+    }
+}
+
+fn join_item_path(segments: &[&str]) -> Option<String> {
+    if segments.len() >= 3 && matches!(segments[0], "u" | "f") {
+        Some(segments.join("/"))
+    } else {
+        None
+    }
+}
+
+/// Routes targeting a single item end with its path, e.g. `get/p/f/team/script`
+fn item_path_of(rest: &[&str]) -> Option<String> {
+    rest.iter()
+        .position(|s| *s == "u" || *s == "f")
+        .and_then(|i| join_item_path(&rest[i..]))
+}
+
+pub fn is_legacy_scope(scope: &str) -> bool {
+    LEGACY_SCOPES.contains(&scope)
+        || scope.starts_with("run:script/")
+        || scope.starts_with("run:flow/")
+}
+
+pub fn validate_scopes(scopes: &[String]) -> Result<(), String> {
+    for scope in scopes {
+        if !is_legacy_scope(scope) {
+            Scope::parse(scope)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn scopes_grant(scopes: &[String], required: &ScopeRequirement) -> bool {
+    scopes
+        .iter()
+        .filter_map(|s| Scope::parse(s).ok())
+        .any(|s| s.grants(required))
+}
+
+/// Checks a scoped token against the route it is used on
+pub fn check_route_scopes(
+    scopes: &[String],
+    method: &Method,
+    path_vec: &[&str],
+) -> Result<(), String> {
+    let required = match ScopeRequirement::from_request(method, path_vec) {
+        Some(required) => required,
+        None => {
+            return Err(format!(
+                "Unauthorized scoped token: {scopes:?} only gives access to workspace routes"
+            ))
+        }
+    };
+
+    // tokens with legacy scopes are checked by the jobs handlers themselves
+    if required.domain == "jobs" && scopes.iter().any(|s| is_legacy_scope(s)) {
+        return Ok(());
+    }
+
+    if scopes_grant(scopes, &required) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unauthorized scoped token: {scopes:?} does not grant {required}"
+        ))
+    }
+}
+
+/// `*` matches any sequence of characters, including `/`
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    if parts.is_empty() {
+        return rest.is_empty();
+    }
+    let (last, middle) = parts.split_last().unwrap();
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(method: Method, path: &str) -> ScopeRequirement {
+        let path_vec: Vec<&str> = path.split('/').collect();
+        ScopeRequirement::from_request(&method, &path_vec).unwrap()
+    }
+
+    #[test]
+    fn test_parse_scope() {
+        assert_eq!(
+            Scope::parse("jobs:run:f/team/*").unwrap(),
+            Scope {
+                domain: "jobs".to_string(),
+                action: ScopeAction::Run,
+                path: Some("f/team/*".to_string())
+            }
+        );
+        assert!(Scope::parse("scripts:read").is_ok());
+        assert!(Scope::parse("access_requests:write").is_ok());
+        assert!(Scope::parse("*:read").is_ok());
+        assert!(Scope::parse("scripts:run").is_err());
+        assert!(Scope::parse("unknown:read").is_err());
+        assert!(Scope::parse("variables:read:bot/*").is_err());
+        assert!(
+            validate_scopes(&["run:script/u/bot/a".to_string(), "listjobs".to_string()]).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_request_requirement() {
+        assert_eq!(
+            req(Method::POST, "/api/w/demo/jobs/run/f/f/team/flow"),
+            ScopeRequirement {
+                domain: "jobs".to_string(),
+                action: ScopeAction::Run,
+                path: Some("f/team/flow".to_string())
+            }
+        );
+        assert_eq!(
+            req(Method::GET, "/api/w/demo/scripts/get/p/f/u/script").path,
+            Some("f/u/script".to_string())
+        );
+        assert_eq!(req(Method::GET, "/api/w/demo/variables/list").path, None);
+        assert_eq!(
+            req(Method::POST, "/api/w/demo/resources/update/u/bot/db").action,
+            ScopeAction::Write
+        );
+    }
+
+    #[test]
+    fn test_scopes_grant() {
+        let scopes = vec![
+            "variables:read:u/bot/*".to_string(),
+            "scripts:write".to_string(),
+        ];
+        assert!(scopes_grant(
+            &scopes,
+            &req(Method::GET, "/api/w/demo/variables/get/u/bot/key")
+        ));
+        assert!(!scopes_grant(
+            &scopes,
+            &req(Method::GET, "/api/w/demo/variables/get/u/admin/key")
+        ));
+        assert!(!scopes_grant(
+            &scopes,
+            &req(Method::GET, "/api/w/demo/variables/list")
+        ));
+        assert!(scopes_grant(
+            &scopes,
+            &req(Method::GET, "/api/w/demo/scripts/list")
+        ));
+        assert!(!scopes_grant(
+            &scopes,
+            &req(Method::GET, "/api/w/demo/flows/list")
+        ));
+        let scopes = vec!["jobs:run:f/team/*".to_string()];
+        assert!(scopes_grant(
+            &scopes,
+            &ScopeRequirement::from_legacy("run:flow/f/team/flow").unwrap()
+        ));
+        assert!(!scopes_grant(
+            &scopes,
+            &ScopeRequirement::from_legacy("listjobs").unwrap()
+        ));
+        assert!(glob_match("f/team/*", "f/team/sub/script"));
+        assert!(!glob_match("f/team/*", "f/other/script"));
+        assert!(glob_match("u/*/job", "u/bot/job"));
+    }
+}
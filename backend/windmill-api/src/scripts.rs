@@ -77,6 +77,8 @@ pub struct ScriptWDraft {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_ttl: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_key_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub dedicated_worker: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ws_error_handler_muted: Option<bool>,
@@ -451,7 +453,9 @@ async fn create_script(
     let lock = if !(ns.language == ScriptLang::Python3
         || ns.language == ScriptLang::Go
         || ns.language == ScriptLang::Bun
-        || ns.language == ScriptLang::Deno)
+        || ns.language == ScriptLang::Deno
+        || ns.language == ScriptLang::Powershell
+        || ns.language == ScriptLang::Bash)
     {
         Some(String::new())
     } else {
@@ -463,6 +467,15 @@ async fn create_script(
 
     let needs_lock_gen = lock.is_none();
 
+    let output_schema = match ns.language {
+        ScriptLang::Bash | ScriptLang::Powershell => {
+            windmill_parser_bash::parse_output_schema(&ns.content)
+                .map_err(|e| Error::BadRequest(format!("invalid output schema: {e}")))?
+                .map(|x| x.to_json_schema())
+        }
+        _ => None,
+    };
+
     let envs = ns.envs.as_ref().map(|x| x.as_slice());
     let envs = if ns.envs.is_none() || ns.envs.as_ref().unwrap().is_empty() {
         None
@@ -505,6 +518,24 @@ async fn create_script(
     .execute(&mut tx)
     .await?;
 
+    if let Some(cache_key_args) = ns.cache_key_args.as_ref() {
+        sqlx::query("UPDATE script SET cache_key_args = $1 WHERE hash = $2 AND workspace_id = $3")
+            .bind(cache_key_args)
+            .bind(&hash.0)
+            .bind(&w_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    if let Some(output_schema) = output_schema.as_ref() {
+        sqlx::query("UPDATE script SET output_schema = $1 WHERE hash = $2 AND workspace_id = $3")
+            .bind(output_schema)
+            .bind(&hash.0)
+            .bind(&w_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
     if let Some(p_path) = parent_hashes_and_perms.as_ref().map(|x| x.p_path.clone()) {
         sqlx::query!(
             "DELETE FROM draft WHERE path = $1 AND workspace_id = $2 AND typ = 'script'",
@@ -719,7 +750,7 @@ async fn get_script_by_path_w_draft(
     let mut tx = user_db.begin(&authed).await?;
 
     let script_o = sqlx::query_as::<_, ScriptWDraft>(
-        "SELECT hash, script.path, summary, description, content, language, kind, tag, schema, draft_only, envs, concurrent_limit, concurrency_time_window_s, cache_ttl, ws_error_handler_muted, draft.value as draft, dedicated_worker, priority, restart_unless_cancelled, delete_after_use FROM script LEFT JOIN draft ON 
+        "SELECT hash, script.path, summary, description, content, language, kind, tag, schema, draft_only, envs, concurrent_limit, concurrency_time_window_s, cache_ttl, cache_key_args, ws_error_handler_muted, draft.value as draft, dedicated_worker, priority, restart_unless_cancelled, delete_after_use FROM script LEFT JOIN draft ON 
          script.path = draft.path AND script.workspace_id = draft.workspace_id AND draft.typ = 'script'
          WHERE script.path = $1 AND script.workspace_id = $2 \
          AND script.created_at = (SELECT max(created_at) FROM script WHERE path = $1 AND \
@@ -0,0 +1,267 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Providers of the secret variables that are not stored in the database. Such variables store
+//! the path of the secret in their provider instead of its encrypted value, and the secret is
+//! read from the provider each time the variable is. Paths are relative to a root specific to the
+//! workspace, so that a workspace cannot reference the secrets of another.
+
+use serde_json::Value;
+use windmill_common::error::{Error, Result};
+
+use crate::HTTP_CLIENT;
+
+lazy_static::lazy_static! {
+    static ref VAULT_ADDR: Option<String> = std::env::var("VAULT_ADDR").ok();
+    static ref VAULT_TOKEN: Option<String> = std::env::var("VAULT_TOKEN").ok();
+    static ref VAULT_NAMESPACE: Option<String> = std::env::var("VAULT_NAMESPACE").ok();
+    static ref VAULT_KV_MOUNT: String =
+        std::env::var("VAULT_KV_MOUNT").unwrap_or_else(|_| "secret".to_string());
+    static ref VAULT_PATH_PREFIX: String = std::env::var("VAULT_PATH_PREFIX")
+        .unwrap_or_else(|_| "windmill/{workspace}".to_string());
+
+    static ref SECRET_FILES_DIR: Option<String> = std::env::var("SECRET_FILES_DIR").ok();
+}
+
+/// Key of the vault secret read when the path does not specify one
+const DEFAULT_VAULT_KEY: &str = "value";
+
+#[async_trait::async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Reads the secret at `path`, relative to the root of the workspace in the provider
+    async fn get_secret(&self, w_id: &str, path: &str) -> Result<String>;
+}
+
+pub fn get_secret_provider(name: &str) -> Result<Box<dyn SecretProvider>> {
+    match name {
+        "vault" => match (VAULT_ADDR.as_ref(), VAULT_TOKEN.as_ref()) {
+            (Some(addr), Some(token)) => Ok(Box::new(VaultKv2 {
+                addr: addr.trim_end_matches('/').to_string(),
+                token: token.clone(),
+            })),
+            _ => Err(Error::BadRequest(
+                "the vault secret provider requires VAULT_ADDR and VAULT_TOKEN to be set"
+                    .to_string(),
+            )),
+        },
+        "file" => match SECRET_FILES_DIR.as_ref() {
+            Some(root) => Ok(Box::new(FileSecrets { root: root.clone() })),
+            None => Err(Error::BadRequest(
+                "the file secret provider requires SECRET_FILES_DIR to be set".to_string(),
+            )),
+        },
+        _ => Err(Error::BadRequest(format!(
+            "unknown secret provider {name}, expected vault or file"
+        ))),
+    }
+}
+
+pub async fn get_external_secret(provider: &str, w_id: &str, path: &str) -> Result<String> {
+    check_secret_path(path)?;
+    get_secret_provider(provider)?.get_secret(w_id, path).await
+}
+
+fn check_secret_path(path: &str) -> Result<()> {
+    if path.is_empty()
+        || path.starts_with('/')
+        || path
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+    {
+        return Err(Error::BadRequest(format!(
+            "invalid secret path {path}, it must be relative to the root of the workspace"
+        )));
+    }
+    Ok(())
+}
+
+/// Secrets of a KV version 2 secrets engine of HashiCorp Vault. Paths are of the form
+/// `<path>#<key>`, the key defaulting to `value`.
+struct VaultKv2 {
+    addr: String,
+    token: String,
+}
+
+#[async_trait::async_trait]
+impl SecretProvider for VaultKv2 {
+    async fn get_secret(&self, w_id: &str, path: &str) -> Result<String> {
+        let (secret_path, key) = path.split_once('#').unwrap_or((path, DEFAULT_VAULT_KEY));
+        let prefix = VAULT_PATH_PREFIX.replace("{workspace}", w_id);
+        let url = vault_kv2_url(&self.addr, &VAULT_KV_MOUNT, &prefix, secret_path)?;
+
+        let mut request = HTTP_CLIENT.get(url).header("X-Vault-Token", &self.token);
+        if let Some(namespace) = VAULT_NAMESPACE.as_ref() {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Error::InternalErr(format!("could not reach vault: {e}")))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(format!(
+                "secret {secret_path} not found in vault"
+            )));
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|e| Error::InternalErr(format!("could not read {secret_path} in vault: {e}")))?
+            .json::<Value>()
+            .await
+            .map_err(|e| Error::InternalErr(format!("invalid response of vault: {e}")))?;
+
+        match response.pointer("/data/data").and_then(|x| x.get(key)) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(v) => Ok(v.to_string()),
+            None => Err(Error::NotFound(format!(
+                "key {key} not found in the vault secret {secret_path}"
+            ))),
+        }
+    }
+}
+
+/// Url of the secret at `secret_path` under `prefix` in the KV2 engine mounted at `mount`. Each
+/// segment is percent-encoded, so that a path cannot escape its prefix or add a query.
+fn vault_kv2_url(addr: &str, mount: &str, prefix: &str, secret_path: &str) -> Result<reqwest::Url> {
+    let mut url = reqwest::Url::parse(addr)
+        .map_err(|e| Error::InternalErr(format!("invalid VAULT_ADDR {addr}: {e}")))?;
+    let segments = mount
+        .split('/')
+        .chain(["data"])
+        .chain(prefix.split('/'))
+        .filter(|x| !x.is_empty())
+        .chain(secret_path.split('/'))
+        .collect::<Vec<_>>();
+    if let Some(segment) = segments.iter().find(|x| **x == "." || **x == "..") {
+        return Err(Error::BadRequest(format!(
+            "invalid segment {segment} in the vault path of {secret_path}"
+        )));
+    }
+    url.path_segments_mut()
+        .map_err(|_| Error::InternalErr(format!("invalid VAULT_ADDR {addr}")))?
+        .pop_if_empty()
+        .push("v1")
+        .extend(segments);
+    Ok(url)
+}
+
+/// Secrets mounted as files, e.g. kubernetes or docker secrets, under a directory per workspace.
+/// A trailing newline is not part of the secret.
+struct FileSecrets {
+    root: String,
+}
+
+#[async_trait::async_trait]
+impl SecretProvider for FileSecrets {
+    async fn get_secret(&self, w_id: &str, path: &str) -> Result<String> {
+        let workspace_root = format!("{}/{w_id}", self.root.trim_end_matches('/'));
+        let not_found = || Error::NotFound(format!("secret file {path} not found"));
+        let workspace_root = tokio::fs::canonicalize(&workspace_root)
+            .await
+            .map_err(|_| not_found())?;
+        // secret mounts are usually made of symlinks, which must not lead out of the workspace
+        let file = tokio::fs::canonicalize(workspace_root.join(path))
+            .await
+            .map_err(|_| not_found())?;
+        if !file.starts_with(&workspace_root) {
+            return Err(Error::BadRequest(format!(
+                "secret file {path} is outside of the secrets of the workspace"
+            )));
+        }
+        let content = tokio::fs::read_to_string(&file)
+            .await
+            .map_err(|e| Error::InternalErr(format!("could not read secret file {path}: {e}")))?;
+        let content = content.strip_suffix('\n').unwrap_or(&content);
+        Ok(content.strip_suffix('\r').unwrap_or(content).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_secret_path() {
+        for path in ["db/password", "password", "db/creds#user", "a.b/c..d"] {
+            assert!(check_secret_path(path).is_ok(), "{path} should be valid");
+        }
+        for path in [
+            "",
+            "/etc/passwd",
+            "../other/secret",
+            "db/../../other",
+            "./db",
+            "db//password",
+            "db/",
+        ] {
+            assert!(check_secret_path(path).is_err(), "{path} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_vault_kv2_url() {
+        let url =
+            |addr, prefix, path| vault_kv2_url(addr, "secret", prefix, path).map(|x| x.to_string());
+        assert_eq!(
+            url("https://vault:8200", "windmill/ws", "db/password").unwrap(),
+            "https://vault:8200/v1/secret/data/windmill/ws/db/password"
+        );
+        assert_eq!(
+            url("https://host/vault/", "/windmill/ws/", "db").unwrap(),
+            "https://host/vault/v1/secret/data/windmill/ws/db"
+        );
+        assert_eq!(
+            url("https://vault", "windmill/ws", "a?b/c%2F..").unwrap(),
+            "https://vault/v1/secret/data/windmill/ws/a%3Fb/c%252F.."
+        );
+        assert!(matches!(
+            url("https://vault", "windmill/..", "db"),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            url("https://vault", "windmill/ws", "db/../../other"),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_file_secrets_stay_in_workspace() {
+        let root = tempfile::tempdir().unwrap();
+        let ws = root.path().join("ws");
+        std::fs::create_dir_all(ws.join("db")).unwrap();
+        std::fs::create_dir_all(root.path().join("other")).unwrap();
+        std::fs::write(ws.join("db/password"), "s3cret\n").unwrap();
+        std::fs::write(root.path().join("other/password"), "not yours").unwrap();
+        std::os::unix::fs::symlink(root.path().join("other/password"), ws.join("leak")).unwrap();
+        std::os::unix::fs::symlink(ws.join("db/password"), ws.join("alias")).unwrap();
+
+        let provider = FileSecrets { root: root.path().to_string_lossy().to_string() };
+
+        assert_eq!(
+            provider.get_secret("ws", "db/password").await.unwrap(),
+            "s3cret"
+        );
+        // symlinks are followed as long as they stay in the secrets of the workspace
+        assert_eq!(provider.get_secret("ws", "alias").await.unwrap(), "s3cret");
+        assert!(matches!(
+            provider.get_secret("ws", "leak").await,
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            provider.get_secret("ws", "../other/password").await,
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            provider.get_secret("ws", "db/missing").await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            provider.get_secret("missing", "db/password").await,
+            Err(Error::NotFound(_))
+        ));
+    }
+}
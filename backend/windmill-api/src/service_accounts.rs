@@ -0,0 +1,449 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use axum::{
+    extract::{Extension, Path},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{Error, JsonResult, Result},
+    service_accounts::service_account_email,
+    users::username_to_permissioned_as,
+    utils::{not_found_if_none, rd_string, require_admin},
+};
+
+use crate::{
+    db::{ApiAuthed, DB},
+    scopes::validate_scopes,
+    users::{NewToken, TruncatedToken},
+};
+
+lazy_static! {
+    // the reserved email of a service account must fit in usr.email (50 chars)
+    static ref SERVICE_ACCOUNT_NAME: Regex = Regex::new(r"^[a-zA-Z0-9_-]{1,25}$").unwrap();
+}
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/list", get(list_service_accounts))
+        .route("/get/:name", get(get_service_account))
+        .route("/create", post(create_service_account))
+        .route("/update/:name", post(update_service_account))
+        .route("/delete/:name", delete(delete_service_account))
+        .route("/tokens/list/:name", get(list_service_account_tokens))
+        .route("/tokens/create/:name", post(create_service_account_token))
+        .route(
+            "/tokens/delete/:name/:token_prefix",
+            delete(delete_service_account_token),
+        )
+}
+
+#[derive(FromRow, Serialize)]
+struct ServiceAccount {
+    name: String,
+    description: String,
+    created_by: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    disabled: bool,
+    groups: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ServiceAccountWithUsage {
+    #[serde(flatten)]
+    service_account: ServiceAccount,
+    flows: Vec<String>,
+    schedules: Vec<String>,
+    apps: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct NewServiceAccount {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EditServiceAccount {
+    description: Option<String>,
+    disabled: Option<bool>,
+}
+
+const SELECT_SERVICE_ACCOUNT: &str = "SELECT service_account.name, service_account.description, \
+     service_account.created_by, service_account.created_at, usr.disabled, \
+     COALESCE(ARRAY(SELECT group_ FROM usr_to_group WHERE usr_to_group.workspace_id = usr.workspace_id \
+     AND usr_to_group.usr = usr.username ORDER BY group_), ARRAY[]::VARCHAR[]) AS groups \
+     FROM service_account JOIN usr ON usr.workspace_id = service_account.workspace_id \
+     AND usr.username = service_account.name";
+
+async fn list_service_accounts(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> JsonResult<Vec<ServiceAccount>> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let rows = sqlx::query_as::<_, ServiceAccount>(&format!(
+        "{SELECT_SERVICE_ACCOUNT} WHERE service_account.workspace_id = $1 ORDER BY service_account.name"
+    ))
+    .bind(&w_id)
+    .fetch_all(&db)
+    .await?;
+    Ok(Json(rows))
+}
+
+async fn get_service_account(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name)): Path<(String, String)>,
+) -> JsonResult<ServiceAccountWithUsage> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let service_account = sqlx::query_as::<_, ServiceAccount>(&format!(
+        "{SELECT_SERVICE_ACCOUNT} WHERE service_account.workspace_id = $1 AND service_account.name = $2"
+    ))
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_optional(&db)
+    .await?;
+    let service_account = not_found_if_none(service_account, "Service account", &name)?;
+
+    let flows = sqlx::query_scalar::<_, String>(
+        "SELECT path FROM flow WHERE workspace_id = $1 AND service_account = $2 ORDER BY path",
+    )
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_all(&db)
+    .await?;
+    let schedules = sqlx::query_scalar::<_, String>(
+        "SELECT path FROM schedule WHERE workspace_id = $1 AND service_account = $2 ORDER BY path",
+    )
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_all(&db)
+    .await?;
+    let apps = sqlx::query_scalar::<_, String>(
+        "SELECT path FROM app WHERE workspace_id = $1 AND policy->>'service_account' = $2 ORDER BY path",
+    )
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_all(&db)
+    .await?;
+
+    Ok(Json(ServiceAccountWithUsage {
+        service_account,
+        flows,
+        schedules,
+        apps,
+    }))
+}
+
+async fn create_service_account(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Json(nsa): Json<NewServiceAccount>,
+) -> Result<(StatusCode, String)> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    if !SERVICE_ACCOUNT_NAME.is_match(&nsa.name) {
+        return Err(Error::BadRequest(format!(
+            "invalid service account name {}: only up to 25 alphanumeric characters, _ and - are allowed",
+            nsa.name
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM usr WHERE workspace_id = $1 AND username = $2)",
+    )
+    .bind(&w_id)
+    .bind(&nsa.name)
+    .fetch_one(&mut *tx)
+    .await?;
+    if exists {
+        return Err(Error::BadRequest(format!(
+            "a user or service account named {} already exists",
+            nsa.name
+        )));
+    }
+
+    sqlx::query(
+        "INSERT INTO usr (workspace_id, username, email, is_admin, operator) \
+         VALUES ($1, $2, $3, false, false)",
+    )
+    .bind(&w_id)
+    .bind(&nsa.name)
+    .bind(service_account_email(&nsa.name))
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO service_account (workspace_id, name, description, created_by) \
+         VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&w_id)
+    .bind(&nsa.name)
+    .bind(nsa.description.unwrap_or_default())
+    .bind(&authed.username)
+    .execute(&mut *tx)
+    .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "service_accounts.create",
+        ActionKind::Create,
+        &w_id,
+        Some(&nsa.name),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::CREATED,
+        format!("Created service account {}", nsa.name),
+    ))
+}
+
+async fn update_service_account(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name)): Path<(String, String)>,
+    Json(esa): Json<EditServiceAccount>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+
+    let updated = sqlx::query_scalar::<_, String>(
+        "UPDATE service_account SET description = COALESCE($1, description) \
+         WHERE workspace_id = $2 AND name = $3 RETURNING name",
+    )
+    .bind(&esa.description)
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_optional(&mut *tx)
+    .await?;
+    not_found_if_none(updated, "Service account", &name)?;
+
+    if let Some(disabled) = esa.disabled {
+        sqlx::query("UPDATE usr SET disabled = $1 WHERE workspace_id = $2 AND username = $3")
+            .bind(disabled)
+            .bind(&w_id)
+            .bind(&name)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "service_accounts.update",
+        ActionKind::Update,
+        &w_id,
+        Some(&name),
+        esa.disabled
+            .map(|d| [("disabled", if d { "true" } else { "false" })].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("Updated service account {name}"))
+}
+
+async fn delete_service_account(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name)): Path<(String, String)>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+
+    let in_use = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT path FROM flow WHERE workspace_id = $1 AND service_account = $2 \
+         UNION ALL SELECT path FROM schedule WHERE workspace_id = $1 AND service_account = $2 \
+         UNION ALL SELECT path FROM app WHERE workspace_id = $1 AND policy->>'service_account' = $2 \
+         LIMIT 1",
+    )
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_optional(&mut *tx)
+    .await?
+    .flatten();
+    if let Some(path) = in_use {
+        return Err(Error::BadRequest(format!(
+            "service account {name} is still used by {path}, make it run as someone else first"
+        )));
+    }
+
+    let deleted = sqlx::query_scalar::<_, String>(
+        "DELETE FROM service_account WHERE workspace_id = $1 AND name = $2 RETURNING name",
+    )
+    .bind(&w_id)
+    .bind(&name)
+    .fetch_optional(&mut *tx)
+    .await?;
+    not_found_if_none(deleted, "Service account", &name)?;
+
+    sqlx::query("DELETE FROM token WHERE workspace_id = $1 AND owner = $2")
+        .bind(&w_id)
+        .bind(username_to_permissioned_as(&name))
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM usr_to_group WHERE workspace_id = $1 AND usr = $2")
+        .bind(&w_id)
+        .bind(&name)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM usr WHERE workspace_id = $1 AND username = $2")
+        .bind(&w_id)
+        .bind(&name)
+        .execute(&mut *tx)
+        .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "service_accounts.delete",
+        ActionKind::Delete,
+        &w_id,
+        Some(&name),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!("Deleted service account {name}"))
+}
+
+async fn require_service_account(db: &DB, w_id: &str, name: &str) -> Result<()> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM service_account WHERE workspace_id = $1 AND name = $2)",
+    )
+    .bind(w_id)
+    .bind(name)
+    .fetch_one(db)
+    .await?;
+    if !exists {
+        return Err(Error::NotFound(format!("Service account {name} not found")));
+    }
+    Ok(())
+}
+
+async fn list_service_account_tokens(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name)): Path<(String, String)>,
+) -> JsonResult<Vec<TruncatedToken>> {
+    require_admin(authed.is_admin, &authed.username)?;
+    require_service_account(&db, &w_id, &name).await?;
+
+    let rows = sqlx::query_as::<_, TruncatedToken>(
+        "SELECT label, concat(substring(token for 10)) as token_prefix, expiration, created_at, \
+         last_used_at, scopes FROM token WHERE workspace_id = $1 AND owner = $2 \
+         AND label IS DISTINCT FROM 'ephemeral-script' ORDER BY created_at DESC",
+    )
+    .bind(&w_id)
+    .bind(username_to_permissioned_as(&name))
+    .fetch_all(&db)
+    .await?;
+    Ok(Json(rows))
+}
+
+async fn create_service_account_token(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name)): Path<(String, String)>,
+    Json(new_token): Json<NewToken>,
+) -> Result<(StatusCode, String)> {
+    require_admin(authed.is_admin, &authed.username)?;
+    require_service_account(&db, &w_id, &name).await?;
+    if let Some(scopes) = new_token.scopes.as_ref() {
+        validate_scopes(scopes).map_err(Error::BadRequest)?;
+    }
+
+    let token = rd_string(30);
+    let mut tx = db.begin().await?;
+
+    // tokens of a service account are bound to its workspace
+    sqlx::query(
+        "INSERT INTO token (workspace_id, token, owner, email, label, expiration, super_admin, scopes) \
+         VALUES ($1, $2, $3, $4, $5, $6, false, $7)",
+    )
+    .bind(&w_id)
+    .bind(&token)
+    .bind(username_to_permissioned_as(&name))
+    .bind(service_account_email(&name))
+    .bind(&new_token.label)
+    .bind(new_token.expiration)
+    .bind(&new_token.scopes)
+    .execute(&mut *tx)
+    .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "service_accounts.token.create",
+        ActionKind::TokenCreation,
+        &w_id,
+        Some(&name),
+        Some([("token", &token[0..10])].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, token))
+}
+
+async fn delete_service_account_token(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path((w_id, name, token_prefix)): Path<(String, String, String)>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+    let tokens_deleted: Vec<String> = sqlx::query_scalar(
+        "DELETE FROM token WHERE workspace_id = $1 AND owner = $2 \
+         AND token LIKE concat($3::text, '%') \
+         RETURNING concat(substring(token for 10), '*****')",
+    )
+    .bind(&w_id)
+    .bind(username_to_permissioned_as(&name))
+    .bind(&token_prefix)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "service_accounts.token.delete",
+        ActionKind::Delete,
+        &w_id,
+        Some(&name),
+        Some([("tokens", &tokens_deleted.join(",")[..])].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(format!(
+        "deleted {} tokens of service account {name}",
+        tokens_deleted.len()
+    ))
+}
@@ -0,0 +1,243 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use hyper::header;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use windmill_audit::{audit_log, ActionKind};
+use windmill_common::{
+    error::{Error, JsonResult, Result},
+    usage::{UsagePeriod, UsageQuotas, WorkspaceUsage},
+    utils::require_admin,
+};
+use windmill_queue::usage::{get_usage_quotas, get_workspace_usage, invalidate_quota_cache};
+
+use crate::db::{ApiAuthed, DB};
+
+pub fn workspaced_service() -> Router {
+    Router::new()
+        .route("/report", get(get_usage_report))
+        .route("/report/csv", get(export_usage_report))
+        .route("/quotas", get(get_quotas).post(edit_quotas))
+        .route("/quotas/remove", post(remove_quotas))
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum UsageGroupBy {
+    #[default]
+    Workspace,
+    User,
+    Script,
+}
+
+#[derive(Deserialize)]
+struct UsageReportQuery {
+    #[serde(default)]
+    period: UsagePeriod,
+    #[serde(default)]
+    group_by: UsageGroupBy,
+    username: Option<String>,
+    after: Option<chrono::NaiveDate>,
+    before: Option<chrono::NaiveDate>,
+}
+
+#[derive(FromRow, Serialize)]
+struct UsageReportRow {
+    period_start: chrono::NaiveDate,
+    username: Option<String>,
+    runnable_path: Option<String>,
+    job_count: i64,
+    failed_count: i64,
+    execution_seconds: i64,
+    mem_peak: i32,
+}
+
+async fn fetch_usage_report(
+    authed: &ApiAuthed,
+    db: &DB,
+    w_id: &str,
+    rq: UsageReportQuery,
+) -> Result<Vec<UsageReportRow>> {
+    // non-admins can only see their own consumption
+    let username = if authed.is_admin {
+        rq.username
+    } else {
+        Some(authed.username.clone())
+    };
+
+    let (username_col, path_col) = match rq.group_by {
+        UsageGroupBy::Workspace => ("NULL::VARCHAR", "NULL::VARCHAR"),
+        UsageGroupBy::User => ("username", "NULL::VARCHAR"),
+        UsageGroupBy::Script => ("username", "runnable_path"),
+    };
+
+    let sql = format!(
+        "SELECT date_trunc($2, day)::DATE AS period_start, {username_col} AS username, \
+         {path_col} AS runnable_path, SUM(job_count)::BIGINT AS job_count, \
+         SUM(failed_count)::BIGINT AS failed_count, \
+         (SUM(execution_ms)::BIGINT / 1000) AS execution_seconds, MAX(mem_peak) AS mem_peak \
+         FROM job_usage_rollup \
+         WHERE workspace_id = $1 AND ($3::DATE IS NULL OR day >= $3) \
+         AND ($4::DATE IS NULL OR day <= $4) AND ($5::VARCHAR IS NULL OR username = $5) \
+         GROUP BY 1, 2, 3 ORDER BY 1 DESC, 2, 3"
+    );
+
+    let rows = sqlx::query_as::<_, UsageReportRow>(&sql)
+        .bind(w_id)
+        .bind(rq.period.as_trunc_unit())
+        .bind(rq.after)
+        .bind(rq.before)
+        .bind(username)
+        .fetch_all(db)
+        .await?;
+
+    Ok(rows)
+}
+
+async fn get_usage_report(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Query(rq): Query<UsageReportQuery>,
+) -> JsonResult<Vec<UsageReportRow>> {
+    let rows = fetch_usage_report(&authed, &db, &w_id, rq).await?;
+    Ok(Json(rows))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+async fn export_usage_report(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Query(rq): Query<UsageReportQuery>,
+) -> Result<impl IntoResponse> {
+    let period = rq.period;
+    let rows = fetch_usage_report(&authed, &db, &w_id, rq).await?;
+
+    let mut csv = String::from(
+        "period_start,username,runnable_path,job_count,failed_count,execution_seconds,mem_peak\n",
+    );
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            row.period_start,
+            csv_field(row.username.as_deref().unwrap_or("")),
+            csv_field(row.runnable_path.as_deref().unwrap_or("")),
+            row.job_count,
+            row.failed_count,
+            row.execution_seconds,
+            row.mem_peak
+        ));
+    }
+
+    let headers = [
+        (header::CONTENT_TYPE, "text/csv".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{w_id}_{}_usage.csv\"",
+                period.as_trunc_unit()
+            ),
+        ),
+    ];
+    Ok((headers, csv))
+}
+
+#[derive(Serialize)]
+struct QuotasStatus {
+    quotas: Option<UsageQuotas>,
+    usage: WorkspaceUsage,
+    soft_exceeded: Option<String>,
+    hard_exceeded: Option<String>,
+}
+
+async fn get_quotas(
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> JsonResult<QuotasStatus> {
+    let quotas = get_usage_quotas(&db, &w_id).await?;
+    let period = quotas.as_ref().map(|q| q.period).unwrap_or_default();
+    let usage = get_workspace_usage(&db, &w_id, period).await?;
+
+    Ok(Json(QuotasStatus {
+        soft_exceeded: quotas.as_ref().and_then(|q| q.soft.exceeded(&usage)),
+        hard_exceeded: quotas.as_ref().and_then(|q| q.hard.exceeded(&usage)),
+        quotas,
+        usage,
+    }))
+}
+
+async fn set_quotas(
+    authed: ApiAuthed,
+    db: &DB,
+    w_id: &str,
+    quotas: Option<UsageQuotas>,
+) -> Result<()> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let serialized = quotas
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(|e| Error::InternalErr(e.to_string()))?;
+
+    let mut tx = db.begin().await?;
+    sqlx::query("UPDATE workspace_settings SET usage_quotas = $1 WHERE workspace_id = $2")
+        .bind(serialized)
+        .bind(w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "workspaces.edit_usage_quotas",
+        ActionKind::Update,
+        w_id,
+        Some(&authed.email),
+        Some([("usage_quotas", &format!("{:?}", quotas)[..])].into()),
+    )
+    .await?;
+    tx.commit().await?;
+
+    invalidate_quota_cache(w_id).await;
+    Ok(())
+}
+
+async fn edit_quotas(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Json(quotas): Json<UsageQuotas>,
+) -> Result<String> {
+    set_quotas(authed, &db, &w_id, Some(quotas)).await?;
+    Ok(format!("Edit usage quotas for workspace {}", &w_id))
+}
+
+async fn remove_quotas(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> Result<String> {
+    set_quotas(authed, &db, &w_id, None).await?;
+    Ok(format!("Removed usage quotas for workspace {}", &w_id))
+}
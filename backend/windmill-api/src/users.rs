@@ -15,6 +15,10 @@ use crate::db::ApiAuthed;
 use crate::{
     db::DB,
     folders::get_folders_for_user,
+    mfa::{
+        self, enable_mfa, is_mfa_required, mark_stepped_up, require_mfa_step_up, verify_mfa_code,
+    },
+    scopes::{check_route_scopes, scopes_grant, validate_scopes, ScopeRequirement},
     utils::require_super_admin,
     webhook_util::{InstanceEvent, WebhookShared},
     workspaces::invite_user_to_all_auto_invite_worspaces,
@@ -45,6 +49,7 @@ use windmill_audit::{audit_log, ActionKind};
 #[cfg(feature = "enterprise")]
 use windmill_common::ee::{get_license_plan, LicensePlan};
 use windmill_common::oauth2::REQUIRE_PREEXISTING_USER_FOR_OAUTH;
+use windmill_common::service_accounts::is_service_account_email;
 use windmill_common::users::truncate_token;
 use windmill_common::worker::{CLOUD_HOSTED, SERVER_CONFIG};
 use windmill_common::{
@@ -97,6 +102,7 @@ pub fn global_service() -> Router {
             post(update_tutorial_progress).get(get_tutorial_progress),
         )
         .route("/leave_instance", post(leave_instance))
+        .nest("/mfa", mfa::global_service())
     // .route("/list_invite_codes", get(list_invite_codes))
     // .route("/create_invite_code", post(create_invite_code))
     // .route("/signup", post(signup))
@@ -107,10 +113,38 @@ pub fn global_service() -> Router {
 pub fn make_unauthed_service() -> Router {
     Router::new()
         .route("/login", post(login))
+        .route("/mfa/enroll", post(mfa::enroll_with_password))
         .route("/logout", post(logout))
         .route("/logout", get(logout))
 }
 
+/// Channel on which the servers are notified of the tokens to evict from their `AuthCache`
+const TOKEN_INVALIDATION_CHANNEL: &str = "notify_token_invalidation";
+
+/// Notifies every server to evict the tokens from their authentication cache, for the given
+/// workspaces and out of any workspace, once the transaction commits
+pub async fn notify_invalidated_tokens(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    tokens: &[String],
+    workspaces: &[String],
+) -> Result<()> {
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let mut workspaces = workspaces.to_vec();
+    workspaces.push("".to_string());
+    sqlx::query(
+        "SELECT pg_notify($1, w_id || ':' || token) FROM unnest($2::text[]) token, \
+         unnest($3::text[]) w_id",
+    )
+    .bind(TOKEN_INVALIDATION_CHANNEL)
+    .bind(tokens)
+    .bind(&workspaces)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct ExpiringAuthCache {
     pub authed: ApiAuthed,
@@ -131,6 +165,33 @@ impl AuthCache {
         self.cache.remove(&(w_id.to_string(), token));
     }
 
+    /// Evicts the tokens other servers notify as invalidated, until the server is shut down
+    pub async fn listen_invalidations(&self, mut rx: tokio::sync::broadcast::Receiver<()>) {
+        loop {
+            tokio::select! {
+                r = self.recv_invalidations() => {
+                    if let Err(e) = r {
+                        tracing::error!(error = %e, "Could not listen to the invalidated tokens, retrying in 5 seconds");
+                    }
+                }
+                _ = rx.recv() => return,
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn recv_invalidations(&self) -> std::result::Result<(), sqlx::Error> {
+        let mut listener = sqlx::postgres::PgListener::connect_with(&self.db).await?;
+        listener.listen(TOKEN_INVALIDATION_CHANNEL).await?;
+        loop {
+            let notification = listener.recv().await?;
+            // tokens are notified as `<w_id>:<token>`, workspace ids have no `:`
+            if let Some((w_id, token)) = notification.payload().split_once(':') {
+                self.invalidate(w_id, token.to_string()).await;
+            }
+        }
+    }
+
     pub async fn get_authed(&self, w_id: Option<String>, token: &str) -> Option<ApiAuthed> {
         let key = (
             w_id.as_ref().unwrap_or(&"".to_string()).to_string(),
@@ -144,9 +205,9 @@ impl AuthCache {
                 Some(authed)
             }
             _ => {
-                let user_o = sqlx::query_as::<_, (Option<String>, Option<String>, bool, Option<Vec<String>>, Option<chrono::DateTime<chrono::Utc>>)>(
+                let user_o = sqlx::query_as::<_, (Option<String>, Option<String>, bool, Option<Vec<String>>, Option<chrono::DateTime<chrono::Utc>>, Option<String>)>(
                     "UPDATE token SET last_used_at = now() WHERE token = $1 AND (expiration > NOW() \
-                     OR expiration IS NULL) RETURNING owner, email, super_admin, scopes, expiration",
+                     OR expiration IS NULL) RETURNING owner, email, super_admin, scopes, expiration, workspace_id",
                 )
                 .bind(token)
                 .fetch_optional(&self.db)
@@ -157,7 +218,16 @@ impl AuthCache {
                 if let Some(user) = user_o {
                     let authed_o = {
                         match user {
-                            (Some(owner), Some(email), super_admin, _, _) if w_id.is_some() => {
+                            // tokens of service accounts are bound to their workspace
+                            (_, Some(email), _, _, _, token_w_id)
+                                if is_service_account_email(&email)
+                                    && token_w_id.as_ref() != w_id.as_ref() =>
+                            {
+                                None
+                            }
+                            (Some(owner), Some(email), super_admin, scopes, _, _)
+                                if w_id.is_some() =>
+                            {
                                 if let Some((prefix, name)) = owner.split_once('/') {
                                     if prefix == "u" {
                                         let (is_admin, is_operator) = if super_admin {
@@ -199,7 +269,7 @@ impl AuthCache {
                                             is_operator,
                                             groups,
                                             folders,
-                                            scopes: None,
+                                            scopes,
                                         })
                                     } else {
                                         let groups = vec![name.to_string()];
@@ -236,7 +306,7 @@ impl AuthCache {
                                     })
                                 }
                             }
-                            (_, Some(email), super_admin, scopes, _) => {
+                            (_, Some(email), super_admin, scopes, _, _) => {
                                 if w_id.is_some() {
                                     let row_o = sqlx::query_as::<_, (String, bool, bool)>(
                                         "SELECT username, is_admin, operator FROM usr where email = $1 AND \
@@ -477,14 +547,11 @@ where
                     Extension::<Arc<AuthCache>>::from_request_parts(parts, state).await
                 {
                     if let Some(authed) = cache.get_authed(workspace_id.clone(), &token).await {
-                        parts.extensions.insert(authed.clone());
-                        if authed.scopes.is_some() && (path_vec.len() < 3 || path_vec[4] != "jobs")
-                        {
-                            return Err((
-                                StatusCode::UNAUTHORIZED,
-                                format!("Unauthorized scoped token: {:?}", authed.scopes),
-                            ));
+                        if let Some(scopes) = authed.scopes.as_ref() {
+                            check_route_scopes(scopes, &parts.method, &path_vec)
+                                .map_err(|e| (StatusCode::UNAUTHORIZED, e))?;
                         }
+                        parts.extensions.insert(authed.clone());
                         Span::current().record("username", &authed.username.as_str());
                         Span::current().record("email", &authed.email);
 
@@ -506,7 +573,9 @@ where
 {
     if let Some(scopes) = &authed.scopes {
         let req = &required();
-        if !scopes.contains(req) {
+        let granted = scopes.contains(req)
+            || ScopeRequirement::from_legacy(req).is_some_and(|r| scopes_grant(scopes, &r));
+        if !granted {
             return Err(Error::BadRequest(format!("missing required scope: {req}")));
         }
     }
@@ -677,6 +746,7 @@ pub struct NewToken {
 pub struct Login {
     pub email: String,
     pub password: String,
+    pub mfa_code: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -1489,7 +1559,7 @@ async fn update_workspace_user(
         &mut *tx,
         &username,
         "users.update",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         &w_id,
         Some(&username_to_update),
         None,
@@ -1522,7 +1592,7 @@ async fn update_user(
         &mut *tx,
         &email,
         "users.update",
-        ActionKind::Update,
+        ActionKind::PermissionChange,
         "global",
         Some(&email_to_update),
         None,
@@ -1933,23 +2003,32 @@ pub fn hash_password(argon2: Arc<Argon2>, password: String) -> Result<String> {
 //     Ok(tx)
 // }
 
-async fn login(
-    cookies: Cookies,
-    Extension(db): Extension<DB>,
-    Extension(argon2): Extension<Arc<Argon2<'_>>>,
-    Json(Login { email, password }): Json<Login>,
-) -> Result<String> {
-    let mut tx = db.begin().await?;
+pub struct PasswordUser {
+    pub email: String,
+    pub super_admin: bool,
+    first_time_user: bool,
+    mfa_enabled: bool,
+    mfa_pending: bool,
+}
+
+/// Checks the credentials of a password user
+pub async fn check_password(
+    db: &DB,
+    argon2: &Argon2<'_>,
+    email: &str,
+    password: &str,
+) -> Result<PasswordUser> {
     let email = email.to_lowercase();
-    let email_w_h: Option<(String, String, bool, bool)> = sqlx::query_as(
-        "SELECT email, password_hash, super_admin, first_time_user FROM password WHERE email = $1 AND login_type = \
-         'password'",
+    let user: Option<(String, String, bool, bool, bool, bool)> = sqlx::query_as(
+        "SELECT email, password_hash, super_admin, first_time_user, mfa_enabled,
+            mfa_pending_secret IS NOT NULL
+        FROM password WHERE email = $1 AND login_type = 'password'",
     )
     .bind(&email)
-    .fetch_optional(&mut *tx)
+    .fetch_optional(db)
     .await?;
 
-    if let Some((email, hash, super_admin, first_time_user)) = email_w_h {
+    if let Some((email, hash, super_admin, first_time_user, mfa_enabled, mfa_pending)) = user {
         let parsed_hash =
             PasswordHash::new(&hash).map_err(|e| Error::InternalErr(e.to_string()))?;
         if argon2
@@ -1958,33 +2037,83 @@ async fn login(
         {
             Err(Error::BadRequest("Invalid login".to_string()))
         } else {
-            if first_time_user {
-                sqlx::query_scalar!(
-                    "UPDATE password SET first_time_user = false WHERE email = $1",
-                    &email
-                )
-                .execute(&mut *tx)
-                .await?;
-                let mut c = Cookie::new("first_time", "1");
-                if let Some(domain) = COOKIE_DOMAIN.as_ref() {
-                    c.set_domain(domain);
-                }
-                c.set_secure(false);
-                c.set_expires(time::OffsetDateTime::now_utc() + time::Duration::minutes(15));
-                c.set_http_only(false);
-                c.set_path("/");
+            Ok(PasswordUser { email, super_admin, first_time_user, mfa_enabled, mfa_pending })
+        }
+    } else {
+        Err(Error::BadRequest("Invalid login".to_string()))
+    }
+}
 
-                cookies.add(c);
+async fn login(
+    cookies: Cookies,
+    Extension(db): Extension<DB>,
+    Extension(argon2): Extension<Arc<Argon2<'_>>>,
+    Json(Login { email, password, mfa_code }): Json<Login>,
+) -> Result<String> {
+    let PasswordUser { email, super_admin, first_time_user, mfa_enabled, mfa_pending } =
+        check_password(&db, &argon2, &email, &password).await?;
+
+    // users required to use MFA who have not enabled it yet log in with the first code of their
+    // enrollment
+    let enrolling = !mfa_enabled && is_mfa_required(super_admin).await;
+    if mfa_enabled || enrolling {
+        let code = match mfa_code {
+            Some(code) if mfa_enabled || mfa_pending => code,
+            _ if mfa_enabled => {
+                return Err(Error::NotAuthorized("MFA code required".to_string()));
             }
+            _ => {
+                return Err(Error::NotAuthorized(
+                    "MFA enrollment required: enroll with /auth/mfa/enroll and log in with the \
+                     first code"
+                        .to_string(),
+                ));
+            }
+        };
+        verify_mfa_code(&db, &email, &code, enrolling).await?;
+    }
 
-            let token = create_session_token(&email, super_admin, &mut tx, cookies).await?;
+    let mut tx = db.begin().await?;
+    if enrolling {
+        enable_mfa(&mut tx, &email).await?;
+    }
 
-            tx.commit().await?;
-            Ok(token)
+    if first_time_user {
+        sqlx::query_scalar!(
+            "UPDATE password SET first_time_user = false WHERE email = $1",
+            &email
+        )
+        .execute(&mut *tx)
+        .await?;
+        let mut c = Cookie::new("first_time", "1");
+        if let Some(domain) = COOKIE_DOMAIN.as_ref() {
+            c.set_domain(domain);
         }
-    } else {
-        Err(Error::BadRequest("Invalid login".to_string()))
+        c.set_secure(false);
+        c.set_expires(time::OffsetDateTime::now_utc() + time::Duration::minutes(15));
+        c.set_http_only(false);
+        c.set_path("/");
+
+        cookies.add(c);
     }
+
+    let token = create_session_token(&email, super_admin, &mut tx, cookies).await?;
+    if mfa_enabled || enrolling {
+        mark_stepped_up(&mut tx, &token).await?;
+    }
+
+    audit_log(
+        &mut *tx,
+        &email,
+        "users.login",
+        ActionKind::Login,
+        "global",
+        Some(&truncate_token(&token)),
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(token)
 }
 
 async fn refresh_token(
@@ -2014,6 +2143,15 @@ pub async fn create_session_token<'c>(
     tx: &mut sqlx::Transaction<'c, sqlx::Postgres>,
     cookies: Cookies,
 ) -> Result<String> {
+    // users deactivated through SCIM cannot log in anymore
+    let disabled = sqlx::query_scalar::<_, bool>("SELECT disabled FROM password WHERE email = $1")
+        .bind(email)
+        .fetch_optional(&mut **tx)
+        .await?
+        .unwrap_or(false);
+    if disabled {
+        return Err(Error::NotAuthorized(format!("user {email} is deactivated")));
+    }
     let token = rd_string(30);
     sqlx::query!(
         "INSERT INTO token
@@ -2045,8 +2183,18 @@ pub async fn create_session_token<'c>(
 async fn create_token(
     Extension(db): Extension<DB>,
     ApiAuthed { email, .. }: ApiAuthed,
+    Tokened { token: session_token }: Tokened,
     Json(new_token): Json<NewToken>,
 ) -> Result<(StatusCode, String)> {
+    if is_service_account_email(&email) {
+        return Err(Error::BadRequest(
+            "tokens of service accounts are managed by the workspace admins".to_string(),
+        ));
+    }
+    require_mfa_step_up(&db, &email, &session_token).await?;
+    if let Some(scopes) = new_token.scopes.as_ref() {
+        validate_scopes(scopes).map_err(Error::BadRequest)?;
+    }
     let token = rd_string(30);
     let mut tx = db.begin().await?;
 
@@ -2069,14 +2217,17 @@ async fn create_token(
     .execute(&mut *tx)
     .await?;
 
+    let scopes = new_token.scopes.as_ref().map(|x| x.join(","));
     audit_log(
         &mut *tx,
         &email,
         "users.token.create",
-        ActionKind::Delete,
+        ActionKind::TokenCreation,
         &"global",
         Some(&token[0..10]),
-        None,
+        scopes
+            .as_ref()
+            .map(|scopes| [("scopes", &scopes[..])].into()),
     )
     .instrument(tracing::info_span!("token", email = &email))
     .await?;
@@ -2087,10 +2238,12 @@ async fn create_token(
 async fn impersonate(
     Extension(db): Extension<DB>,
     ApiAuthed { email, username, .. }: ApiAuthed,
+    Tokened { token: session_token }: Tokened,
     Json(new_token): Json<NewToken>,
 ) -> Result<(StatusCode, String)> {
     let token = rd_string(30);
     require_super_admin(&db, &email).await?;
+    require_mfa_step_up(&db, &email, &session_token).await?;
 
     if new_token.impersonate_email.is_none() {
         return Err(Error::BadRequest(
@@ -2404,7 +2557,7 @@ pub async fn login_externally(
                 &mut *tx,
                 &email,
                 "oauth.login",
-                ActionKind::Create,
+                ActionKind::Login,
                 "global",
                 Some(&truncate_token(&token.unwrap())[..]),
                 None,
@@ -2415,7 +2568,7 @@ pub async fn login_externally(
                 &mut *tx,
                 &email,
                 "oauth.login",
-                ActionKind::Create,
+                ActionKind::Login,
                 "global",
                 None,
                 None,
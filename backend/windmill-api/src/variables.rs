@@ -9,6 +9,7 @@
 use crate::{
     db::{ApiAuthed, DB},
     oauth2::_refresh_token,
+    secret_providers::{get_external_secret, get_secret_provider},
     users::{maybe_refresh_folders, require_owner_of_path},
     webhook_util::{WebhookMessage, WebhookShared},
 };
@@ -24,17 +25,25 @@ use windmill_audit::{audit_log, ActionKind};
 use windmill_common::{
     db::UserDB,
     error::{Error, JsonResult, Result},
-    utils::{not_found_if_none, StripPath},
+    utils::{not_found_if_none, rd_string, require_admin, StripPath},
     variables::{get_reserved_variables, ContextualVariable, CreateVariable, ListableVariable},
 };
 
+use std::collections::HashMap;
+
 use lazy_static::lazy_static;
-use magic_crypt::{MagicCrypt256, MagicCryptTrait};
-use serde::Deserialize;
-use sqlx::{Postgres, Transaction};
+use magic_crypt::{MagicCrypt256, MagicCryptError, MagicCryptTrait};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use uuid::Uuid;
 
 lazy_static! {
     pub static ref SECRET_SALT: Option<String> = std::env::var("SECRET_SALT").ok();
+    /// How long the key of a workspace keeps being accepted once rotated
+    static ref KEY_ROTATION_WINDOW_SECS: i64 = std::env::var("KEY_ROTATION_WINDOW_SECS")
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .unwrap_or(3600);
 }
 
 pub fn workspaced_service() -> Router {
@@ -43,6 +52,7 @@ pub fn workspaced_service() -> Router {
         .route("/list_contextual", get(list_contextual_variables))
         .route("/get/*path", get(get_variable))
         .route("/get_value/*path", get(get_value))
+        .route("/secret_access", get(list_secret_access))
         .route("/exists/*path", get(exists_variable))
         .route("/update/*path", post(update_variable))
         .route("/delete/*path", delete(delete_variable))
@@ -85,7 +95,7 @@ async fn list_variables(
          is_secret, variable.description, variable.extra_perms, account, is_oauth, (now() > account.expires_at) as is_expired,
          account.refresh_error,
          resource.path IS NOT NULL as is_linked,
-         account.refresh_token != '' as is_refreshed
+         account.refresh_token != '' as is_refreshed, variable.secret_provider
          from variable
          LEFT JOIN account ON variable.account = account.id AND account.workspace_id = $1
          LEFT JOIN resource ON resource.path = variable.path AND resource.workspace_id = $1
@@ -140,29 +150,24 @@ async fn get_variable(
 
     let r = if variable.is_secret {
         if decrypt_secret {
-            audit_log(
-                &mut *tx,
-                &authed.username,
-                "variables.decrypt_secret",
-                ActionKind::Execute,
-                &w_id,
-                Some(&variable.path),
-                None,
-            )
-            .await?;
+            audit_secret_read(&mut tx, &db, &authed, &w_id, &variable.path, None).await?;
         }
         let value = variable.value.unwrap_or_else(|| "".to_string());
         ListableVariable {
             value: if variable.is_expired.unwrap_or(false) && variable.account.is_some() {
                 Some(_refresh_token(tx, &variable.path, &w_id, variable.account.unwrap()).await?)
+            } else if let Some(provider) = variable.secret_provider.as_deref() {
+                if decrypt_secret {
+                    tx.commit().await?;
+                    Some(get_external_secret(provider, &w_id, &value).await?)
+                } else {
+                    None
+                }
             } else if !value.is_empty() && decrypt_secret {
-                let mc = build_crypt(&mut tx, &w_id).await?;
+                let decrypt = build_decrypt(&mut tx, &w_id).await?;
                 tx.commit().await?;
 
-                Some(
-                    mc.decrypt_base64_to_string(value)
-                        .map_err(|e| Error::InternalErr(e.to_string()))?,
-                )
+                Some(decrypt.decrypt_base64_to_string(&value)?)
             } else {
                 None
             },
@@ -175,31 +180,49 @@ async fn get_variable(
     Ok(Json(r))
 }
 
+#[derive(Deserialize)]
+struct GetValueQuery {
+    job_id: Option<Uuid>,
+}
+
 async fn get_value(
     authed: ApiAuthed,
     Extension(user_db): Extension<UserDB>,
     Extension(db): Extension<DB>,
-
+    Query(q): Query<GetValueQuery>,
     Path((w_id, path)): Path<(String, StripPath)>,
 ) -> JsonResult<String> {
     let path = path.to_path();
     let tx = user_db.begin(&authed).await?;
-    return get_value_internal(tx, &db, &w_id, &path, &authed.username)
+    return get_value_internal(tx, &db, &w_id, &path, &authed, q.job_id)
         .await
         .map(Json);
 }
 
+#[derive(FromRow)]
+struct VariableValue {
+    value: String,
+    account: Option<i32>,
+    is_expired: Option<bool>,
+    is_secret: bool,
+    path: String,
+    secret_provider: Option<String>,
+}
+
 pub async fn get_value_internal<'c>(
     mut tx: Transaction<'c, Postgres>,
     db: &DB,
     w_id: &str,
     path: &str,
-    username: &str,
+    authed: &ApiAuthed,
+    job_id: Option<Uuid>,
 ) -> Result<String> {
-    let variable_o = sqlx::query!(
-        "SELECT value, account, (now() > account.expires_at) as is_expired, is_secret, path from variable
-        LEFT JOIN account ON variable.account = account.id WHERE variable.path = $1 AND variable.workspace_id = $2", path, w_id
+    let variable_o = sqlx::query_as::<_, VariableValue>(
+        "SELECT value, account, (now() > account.expires_at) as is_expired, is_secret, path, secret_provider from variable
+        LEFT JOIN account ON variable.account = account.id WHERE variable.path = $1 AND variable.workspace_id = $2",
     )
+    .bind(path)
+    .bind(w_id)
     .fetch_optional(&mut *tx)
     .await?;
 
@@ -211,25 +234,18 @@ pub async fn get_value_internal<'c>(
     };
 
     let r = if variable.is_secret {
-        audit_log(
-            &mut *tx,
-            username,
-            "variables.decrypt_secret",
-            ActionKind::Execute,
-            &w_id,
-            Some(&variable.path),
-            None,
-        )
-        .await?;
+        audit_secret_read(&mut tx, db, authed, w_id, &variable.path, job_id).await?;
         let value = variable.value;
         if variable.is_expired.unwrap_or(false) && variable.account.is_some() {
             _refresh_token(tx, &variable.path, &w_id, variable.account.unwrap()).await?
+        } else if let Some(provider) = variable.secret_provider {
+            tx.commit().await?;
+            get_external_secret(&provider, w_id, &value).await?
         } else if !value.is_empty() {
-            let mc = build_crypt(&mut tx, &w_id).await?;
+            let decrypt = build_decrypt(&mut tx, &w_id).await?;
             tx.commit().await?;
 
-            mc.decrypt_base64_to_string(value)
-                .map_err(|e| Error::InternalErr(e.to_string()))?
+            decrypt.decrypt_base64_to_string(&value)?
         } else {
             "".to_string()
         }
@@ -240,6 +256,116 @@ pub async fn get_value_internal<'c>(
     Ok(r)
 }
 
+/// Records the decryption of a secret. When it is resolved for a job, the job and the script and
+/// flow it runs are recorded too, as long as the job runs as the caller.
+async fn audit_secret_read<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    db: &DB,
+    authed: &ApiAuthed,
+    w_id: &str,
+    path: &str,
+    job_id: Option<Uuid>,
+) -> Result<()> {
+    let job = if let Some(job_id) = job_id {
+        sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            "SELECT queue.script_path, flow.script_path FROM queue
+            LEFT JOIN queue flow ON flow.id = queue.parent_job AND flow.workspace_id = $2
+            WHERE queue.id = $1 AND queue.workspace_id = $2 AND queue.email = $3",
+        )
+        .bind(job_id)
+        .bind(w_id)
+        .bind(&authed.email)
+        .fetch_optional(db)
+        .await?
+        .map(|(script_path, flow_path)| (job_id.to_string(), script_path, flow_path))
+    } else {
+        None
+    };
+
+    let mut parameters = HashMap::new();
+    if let Some((job_id, script_path, flow_path)) = job.as_ref() {
+        parameters.insert("job_id", job_id.as_str());
+        if let Some(script_path) = script_path {
+            parameters.insert("script_path", script_path.as_str());
+        }
+        if let Some(flow_path) = flow_path {
+            parameters.insert("flow_path", flow_path.as_str());
+        }
+    }
+
+    audit_log(
+        &mut **tx,
+        &authed.username,
+        "variables.decrypt_secret",
+        ActionKind::SecretRead,
+        w_id,
+        Some(path),
+        Some(parameters).filter(|x| !x.is_empty()),
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct SecretAccessQuery {
+    path: Option<String>,
+    after: Option<chrono::DateTime<chrono::Utc>>,
+    before: Option<chrono::DateTime<chrono::Utc>>,
+    period: Option<String>,
+}
+
+#[derive(Serialize, FromRow)]
+struct SecretAccess {
+    path: String,
+    period: chrono::DateTime<chrono::Utc>,
+    username: String,
+    script_path: Option<String>,
+    flow_path: Option<String>,
+    access_count: i64,
+    first_access: chrono::DateTime<chrono::Utc>,
+    last_access: chrono::DateTime<chrono::Utc>,
+}
+
+/// Who decrypted each secret of the workspace, and from which scripts and flows, per period
+/// (the scripts and flows being audit log parameters, they are only recorded on enterprise edition)
+async fn list_secret_access(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Query(q): Query<SecretAccessQuery>,
+) -> JsonResult<Vec<SecretAccess>> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let period = q.period.as_deref().unwrap_or("day");
+    if !["hour", "day", "week", "month"].contains(&period) {
+        return Err(Error::BadRequest(format!(
+            "invalid period {period}, expected hour, day, week or month"
+        )));
+    }
+
+    let rows = sqlx::query_as::<_, SecretAccess>(
+        "SELECT resource AS path, date_trunc($5, timestamp) AS period, username,
+            parameters->>'script_path' AS script_path, parameters->>'flow_path' AS flow_path,
+            count(*) AS access_count, min(timestamp) AS first_access, max(timestamp) AS last_access
+        FROM audit
+        WHERE workspace_id = $1 AND operation = 'variables.decrypt_secret'
+            AND ($2::text IS NULL OR resource = $2)
+            AND ($3::timestamptz IS NULL OR timestamp > $3)
+            AND ($4::timestamptz IS NULL OR timestamp < $4)
+        GROUP BY 1, 2, 3, 4, 5
+        ORDER BY path, period DESC, access_count DESC",
+    )
+    .bind(&w_id)
+    .bind(&q.path)
+    .bind(q.after)
+    .bind(q.before)
+    .bind(period)
+    .fetch_all(&db)
+    .await?;
+
+    Ok(Json(rows))
+}
+
 async fn explain_variable_perm_error(
     path: &str,
     w_id: &str,
@@ -333,7 +459,19 @@ async fn create_variable(
     let mut tx = user_db.begin(&authed).await?;
 
     check_path_conflict(&mut tx, &w_id, &variable.path).await?;
-    let value = if variable.is_secret && !already_encrypted.unwrap_or(false) {
+    if let Some(provider) = variable.secret_provider.as_deref() {
+        check_external_secret(
+            &authed,
+            &w_id,
+            provider,
+            variable.is_secret,
+            &variable.value,
+        )
+        .await?;
+    }
+    let value = if variable.secret_provider.is_some() {
+        variable.value
+    } else if variable.is_secret && !already_encrypted.unwrap_or(false) {
         let mc = build_crypt(&mut tx, &w_id).await?;
         encrypt(&mc, &variable.value)
     } else {
@@ -354,6 +492,16 @@ async fn create_variable(
     )
     .execute(&mut *tx)
     .await?;
+    if variable.secret_provider.is_some() {
+        sqlx::query(
+            "UPDATE variable SET secret_provider = $1 WHERE path = $2 AND workspace_id = $3",
+        )
+        .bind(&variable.secret_provider)
+        .bind(&variable.path)
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+    }
 
     audit_log(
         &mut *tx,
@@ -444,6 +592,8 @@ struct EditVariable {
     value: Option<String>,
     is_secret: Option<bool>,
     description: Option<String>,
+    /// an empty string stores the secret back in the database
+    secret_provider: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -475,6 +625,30 @@ async fn update_variable(
         sqlb.set_str("path", npath);
     }
     let ns_value_is_none = ns.value.is_none();
+    let old_secret_provider = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT secret_provider FROM variable WHERE path = $1 AND workspace_id = $2",
+    )
+    .bind(&path)
+    .bind(&w_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .flatten();
+    let secret_provider = match ns.secret_provider.as_deref() {
+        Some("") => None,
+        Some(provider) => Some(provider.to_string()),
+        None => old_secret_provider.clone(),
+    };
+    if secret_provider != old_secret_provider {
+        if ns_value_is_none {
+            return Err(Error::BadRequest(
+                "cannot change secret_provider without updating value too".to_string(),
+            ));
+        }
+        match secret_provider.as_ref() {
+            Some(provider) => sqlb.set_str("secret_provider", provider),
+            None => sqlb.set("secret_provider", "NULL"),
+        };
+    }
     if let Some(nvalue) = ns.value {
         let is_secret = if ns.is_secret.is_some() {
             ns.is_secret.unwrap()
@@ -489,7 +663,10 @@ async fn update_variable(
             .unwrap_or(false)
         };
 
-        let value = if is_secret && !already_encrypted.unwrap_or(false) {
+        let value = if let Some(provider) = secret_provider.as_deref() {
+            check_external_secret(&authed, &w_id, provider, is_secret, &nvalue).await?;
+            nvalue
+        } else if is_secret && !already_encrypted.unwrap_or(false) {
             let mc = build_crypt(&mut tx, &w_id).await?;
             encrypt(&mc, &nvalue)
         } else {
@@ -584,6 +761,26 @@ async fn update_variable(
     Ok(format!("variable {} updated (npath: {:?})", path, npath))
 }
 
+/// Only admins can reference external secrets, which are not subject to the permissions of
+/// the variables. The secret is read once to report a wrong path early.
+async fn check_external_secret(
+    authed: &ApiAuthed,
+    w_id: &str,
+    provider: &str,
+    is_secret: bool,
+    path: &str,
+) -> Result<()> {
+    require_admin(authed.is_admin, &authed.username)?;
+    if !is_secret {
+        return Err(Error::BadRequest(
+            "only secret variables can be stored in a secret provider".to_string(),
+        ));
+    }
+    get_secret_provider(provider)?;
+    get_external_secret(provider, w_id, path).await?;
+    Ok(())
+}
+
 fn replace_path(v: serde_json::Value, path: &str, npath: &str) -> Value {
     match v {
         Value::Object(v) => Value::Object(
@@ -601,17 +798,93 @@ fn replace_path(v: serde_json::Value, path: &str, npath: &str) -> Value {
     }
 }
 
+/// Crypt to encrypt the values written in `db`. The key is locked until the end of the
+/// transaction, so that a rotation waits for the values being encrypted with the key it replaces
+/// instead of leaving them behind once the previous key expires.
 pub async fn build_crypt<'c>(
     db: &mut Transaction<'c, Postgres>,
     w_id: &str,
 ) -> Result<MagicCrypt256> {
-    let key = get_workspace_key(w_id, db).await?;
+    let key = sqlx::query_scalar::<_, String>(
+        "SELECT key FROM workspace_key WHERE workspace_id = $1 AND kind = 'cloud' FOR SHARE",
+    )
+    .bind(w_id)
+    .fetch_one(&mut **db)
+    .await
+    .map_err(|e| Error::InternalErr(format!("fetching workspace key: {e}")))?;
+    Ok(crypt_from_key(key))
+}
+
+fn crypt_from_key(key: String) -> MagicCrypt256 {
     let crypt_key = if let Some(ref salt) = SECRET_SALT.as_ref() {
         format!("{}{}", key, salt)
     } else {
         key
     };
-    Ok(magic_crypt::new_magic_crypt!(crypt_key, 256))
+    magic_crypt::new_magic_crypt!(crypt_key, 256)
+}
+
+/// Decrypts the values encrypted with the key of the workspace, falling back to its previous key
+/// for the values encrypted with it, e.g. while the key was being rotated
+pub struct WorkspaceDecrypt {
+    crypts: Vec<MagicCrypt256>,
+}
+
+impl WorkspaceDecrypt {
+    pub fn decrypt_base64_to_string(&self, value: &str) -> Result<String> {
+        let decrypted = self.decrypt(|mc| mc.decrypt_base64_to_bytes(value), is_plausible_text)?;
+        String::from_utf8(decrypted).map_err(|e| Error::InternalErr(e.to_string()))
+    }
+
+    /// `verify` checks the plaintext decrypted with the previous key
+    pub fn decrypt_bytes_to_bytes(
+        &self,
+        value: &[u8],
+        verify: impl Fn(&[u8]) -> bool,
+    ) -> Result<Vec<u8>> {
+        self.decrypt(|mc| mc.decrypt_bytes_to_bytes(value), verify)
+    }
+
+    fn decrypt(
+        &self,
+        decrypt: impl Fn(&MagicCrypt256) -> std::result::Result<Vec<u8>, MagicCryptError>,
+        verify: impl Fn(&[u8]) -> bool,
+    ) -> Result<Vec<u8>> {
+        let mut error = None;
+        for (i, mc) in self.crypts.iter().enumerate() {
+            match decrypt(mc) {
+                // a wrong key still gives a valid padding once in a while, so what the previous
+                // key decrypts is only accepted if it looks like a plaintext
+                Ok(decrypted) if i == 0 || verify(&decrypted) => return Ok(decrypted),
+                Ok(_) => {
+                    error = error.or(Some(
+                        "invalid plaintext decrypted with the previous key".to_string(),
+                    ))
+                }
+                Err(e) => error = error.or(Some(e.to_string())),
+            }
+        }
+        Err(Error::InternalErr(
+            error.unwrap_or_else(|| "no key to decrypt with".to_string()),
+        ))
+    }
+}
+
+/// Secrets are text, garbage decrypted with a wrong key rarely is
+fn is_plausible_text(value: &[u8]) -> bool {
+    std::str::from_utf8(value)
+        .is_ok_and(|s| !s.chars().any(|c| c.is_control() && !c.is_whitespace()))
+}
+
+pub async fn build_decrypt<'c>(
+    db: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+) -> Result<WorkspaceDecrypt> {
+    let mut crypts = vec![crypt_from_key(get_workspace_key(w_id, db).await?)];
+    if let Some(previous_key) = get_previous_workspace_key(w_id, db).await? {
+        crypts.push(crypt_from_key(previous_key));
+    }
+    Ok(WorkspaceDecrypt { crypts })
 }
 
 pub async fn get_workspace_key<'c>(
@@ -628,6 +901,164 @@ pub async fn get_workspace_key<'c>(
     Ok(key)
 }
 
+/// Key the workspace had before its last rotation, still accepted to decrypt and to verify
+/// signatures for `KEY_ROTATION_WINDOW_SECS` after the rotation
+pub async fn get_previous_workspace_key<'c>(
+    w_id: &str,
+    db: &mut Transaction<'c, Postgres>,
+) -> Result<Option<String>> {
+    let key = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT previous_key FROM workspace_key WHERE workspace_id = $1 AND kind = 'cloud' \
+         AND rotated_at > now() - ($2::bigint::text || ' s')::interval",
+    )
+    .bind(w_id)
+    .bind(*KEY_ROTATION_WINDOW_SECS)
+    .fetch_optional(&mut **db)
+    .await?
+    .flatten();
+    Ok(key)
+}
+
+#[derive(Serialize)]
+pub struct KeyRotation {
+    pub reencrypted_variables: usize,
+    /// public apps whose secret urls were made with the previous key
+    pub public_apps: i64,
+    /// suspended flows whose resume urls were signed with the previous key
+    pub suspended_flows: i64,
+    pub previous_key_expires_in_secs: i64,
+}
+
+/// Replaces the key of the workspace by a new one and re-encrypts its secret variables with it.
+/// Resources hold their secrets as `$var:` references, so they have nothing to re-encrypt. The
+/// previous key is kept to verify the signatures made with it until `KEY_ROTATION_WINDOW_SECS`
+/// have passed, after which the urls of public apps and the resume urls of suspended flows
+/// handed out before the rotation stop working, so their count is returned.
+pub async fn rotate_workspace_key<'c>(
+    tx: &mut Transaction<'c, Postgres>,
+    w_id: &str,
+) -> Result<KeyRotation> {
+    // locks the key against concurrent rotations
+    sqlx::query(
+        "SELECT key FROM workspace_key WHERE workspace_id = $1 AND kind = 'cloud' FOR UPDATE",
+    )
+    .bind(w_id)
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(|e| Error::InternalErr(format!("fetching workspace key: {e}")))?;
+    let decrypt = build_decrypt(tx, w_id).await?;
+    let new_key = rd_string(64);
+    let new_mc = crypt_from_key(new_key.clone());
+
+    let secrets = sqlx::query_as::<_, (String, String)>(
+        "SELECT path, value FROM variable WHERE workspace_id = $1 AND is_secret \
+         AND secret_provider IS NULL AND value != '' FOR UPDATE",
+    )
+    .bind(w_id)
+    .fetch_all(&mut **tx)
+    .await?;
+    for (path, value) in secrets.iter() {
+        let decrypted = decrypt.decrypt_base64_to_string(value).map_err(|e| {
+            Error::InternalErr(format!("could not decrypt the secret variable {path}: {e}"))
+        })?;
+        sqlx::query("UPDATE variable SET value = $1 WHERE workspace_id = $2 AND path = $3")
+            .bind(encrypt(&new_mc, &decrypted))
+            .bind(w_id)
+            .bind(path)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    sqlx::query(
+        "UPDATE workspace_key SET previous_key = key, key = $1, rotated_at = now() \
+         WHERE workspace_id = $2 AND kind = 'cloud'",
+    )
+    .bind(new_key)
+    .bind(w_id)
+    .execute(&mut **tx)
+    .await?;
+
+    let public_apps = sqlx::query_scalar::<_, i64>(
+        "SELECT count(*) FROM app WHERE workspace_id = $1 \
+         AND policy->>'execution_mode' = 'anonymous'",
+    )
+    .bind(w_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    let suspended_flows = sqlx::query_scalar::<_, i64>(
+        "SELECT count(*) FROM queue WHERE workspace_id = $1 AND suspend_until IS NOT NULL",
+    )
+    .bind(w_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(KeyRotation {
+        reencrypted_variables: secrets.len(),
+        public_apps,
+        suspended_flows,
+        previous_key_expires_in_secs: *KEY_ROTATION_WINDOW_SECS,
+    })
+}
+
 pub fn encrypt(mc: &MagicCrypt256, value: &str) -> String {
     mc.encrypt_str_to_base64(value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decrypt_with(keys: &[&str]) -> WorkspaceDecrypt {
+        WorkspaceDecrypt { crypts: keys.iter().map(|k| crypt_from_key(k.to_string())).collect() }
+    }
+
+    #[test]
+    fn test_decrypt_falls_back_to_previous_key() {
+        let decrypt = decrypt_with(&["new", "old"]);
+        let current = encrypt(&crypt_from_key("new".to_string()), "secret\nvalue");
+        let previous = encrypt(&crypt_from_key("old".to_string()), "secret\nvalue");
+        let other = encrypt(&crypt_from_key("other".to_string()), "secret\nvalue");
+
+        assert_eq!(
+            decrypt.decrypt_base64_to_string(&current).unwrap(),
+            "secret\nvalue"
+        );
+        assert_eq!(
+            decrypt.decrypt_base64_to_string(&previous).unwrap(),
+            "secret\nvalue"
+        );
+        assert!(decrypt.decrypt_base64_to_string(&other).is_err());
+        // once the window has passed, the previous key is not used anymore
+        assert!(decrypt_with(&["new"])
+            .decrypt_base64_to_string(&previous)
+            .is_err());
+    }
+
+    #[test]
+    fn test_decrypt_verifies_previous_key_plaintext() {
+        let decrypt = decrypt_with(&["new", "old"]);
+        let current = crypt_from_key("new".to_string()).encrypt_bytes_to_bytes(b"42");
+        let previous = crypt_from_key("old".to_string()).encrypt_bytes_to_bytes(b"42");
+
+        assert_eq!(
+            decrypt.decrypt_bytes_to_bytes(&current, |_| false).unwrap(),
+            b"42"
+        );
+        assert_eq!(
+            decrypt.decrypt_bytes_to_bytes(&previous, |_| true).unwrap(),
+            b"42"
+        );
+        assert!(decrypt
+            .decrypt_bytes_to_bytes(&previous, |_| false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_is_plausible_text() {
+        assert!(is_plausible_text(b"p4ssw0rd"));
+        assert!(is_plausible_text(
+            "-----BEGIN KEY-----\r\n\tkey é\n".as_bytes()
+        ));
+        assert!(!is_plausible_text(b"\x00\x13abc"));
+        assert!(!is_plausible_text(b"\xff\xfe"));
+    }
+}
@@ -12,13 +12,14 @@ use std::str::FromStr;
 use crate::BASE_URL;
 use crate::db::ApiAuthed;
 use crate::{
+    ai::{get_workspace_ai_config, invalidate_ai_request_config, AIConfig},
     apps::AppWithLastVersion,
     db::DB,
     folders::Folder,
     resources::{Resource, ResourceType},
     users::{WorkspaceInvite, VALID_USERNAME, send_email_if_possible},
     utils::require_super_admin,
-    variables::build_crypt,
+    variables::{build_decrypt, rotate_workspace_key, KeyRotation},
     webhook_util::{InstanceEvent, WebhookShared}
 };
 #[cfg(feature = "enterprise")]
@@ -32,7 +33,6 @@ use axum::{
     Json, Router,
 };
 use chrono::Utc;
-use magic_crypt::MagicCryptTrait;
 #[cfg(feature = "enterprise")]
 use stripe::CustomerId;
 #[cfg(feature = "enterprise")]
@@ -82,9 +82,12 @@ pub fn workspaced_service() -> Router {
         .route("/premium_info", get(premium_info))
         .route("/edit_copilot_config", post(edit_copilot_config))
         .route("/get_copilot_info", get(get_copilot_info) )
+        .route("/edit_ai_config", post(edit_ai_config))
+        .route("/get_ai_config", get(get_ai_config))
         .route("/edit_error_handler", post(edit_error_handler))
         .route("/edit_large_file_storage_config", post(edit_large_file_storage_config))
         .route("/edit_git_sync_config", post(edit_git_sync_config))
+        .route("/rotate_key", post(rotate_key))
         .route("/leave", post(leave_workspace));
 
     #[cfg(feature = "enterprise")]
@@ -210,6 +213,12 @@ struct EditCopilotConfig {
     code_completion_enabled: bool,
 }
 
+#[derive(Deserialize)]
+struct EditAIConfig {
+    ai_config: Option<AIConfig>,
+    code_completion_enabled: bool,
+}
+
 #[derive(Deserialize)]
 struct EditLargeFileStorageConfig {
     large_file_storage: Option<LargeFileStorage>,
@@ -684,6 +693,44 @@ async fn run_slack_message_test_job(
     }))
 }
 
+async fn rotate_key(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> JsonResult<KeyRotation> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+    let rotation = rotate_workspace_key(&mut tx, &w_id).await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "workspaces.rotate_key",
+        ActionKind::Update,
+        &w_id,
+        None,
+        Some(
+            [
+                (
+                    "reencrypted_variables",
+                    rotation.reencrypted_variables.to_string().as_str(),
+                ),
+                ("public_apps", rotation.public_apps.to_string().as_str()),
+                (
+                    "suspended_flows",
+                    rotation.suspended_flows.to_string().as_str(),
+                ),
+            ]
+            .into(),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(rotation))
+}
+
 #[cfg(feature = "enterprise")]
 async fn edit_deploy_to(
     authed: ApiAuthed,
@@ -877,24 +924,16 @@ async fn edit_copilot_config(
 
     let mut tx = db.begin().await?;
 
-    if let Some(openai_resource_path) = &eo.openai_resource_path {
-        sqlx::query!(
-            "UPDATE workspace_settings SET openai_resource_path = $1, code_completion_enabled = $2 WHERE workspace_id = $3",
-            openai_resource_path,
-            eo.code_completion_enabled,
-            &w_id
-        )
-        .execute(&mut *tx)
-        .await?;
-    } else {
-        sqlx::query!(
-            "UPDATE workspace_settings SET openai_resource_path = NULL, code_completion_enabled = $1 WHERE workspace_id = $2",
-            eo.code_completion_enabled,
-            &w_id,
-        )
-        .execute(&mut *tx)
-        .await?;
-    }
+    // the copilot settings only know about the openai proxy and replace any ai config, which
+    // would otherwise take precedence over them
+    sqlx::query(
+        "UPDATE workspace_settings SET openai_resource_path = $1, code_completion_enabled = $2, ai_config = NULL WHERE workspace_id = $3",
+    )
+    .bind(&eo.openai_resource_path)
+    .bind(eo.code_completion_enabled)
+    .bind(&w_id)
+    .execute(&mut *tx)
+    .await?;
     audit_log(
         &mut *tx,
         &authed.username,
@@ -906,6 +945,7 @@ async fn edit_copilot_config(
     )
     .await?;
     tx.commit().await?;
+    invalidate_ai_request_config(&w_id).await;
 
     Ok(format!("Edit copilot config for workspace {}", &w_id))
 }
@@ -928,15 +968,74 @@ async fn get_copilot_info(
     .fetch_one(&mut *tx)
     .await
     .map_err(|e| Error::InternalErr(format!("getting openai_resource_path and code_completion_enabled: {e}")))?;
+    let exists_ai_config = sqlx::query_scalar::<_, bool>(
+        "SELECT ai_config IS NOT NULL FROM workspace_settings WHERE workspace_id = $1",
+    )
+    .bind(&w_id)
+    .fetch_one(&mut *tx)
+    .await?;
     tx.commit().await?;
 
 
     Ok(Json(CopilotInfo {
-        exists_openai_resource_path: record.openai_resource_path.is_some(),
+        exists_openai_resource_path: record.openai_resource_path.is_some() || exists_ai_config,
         code_completion_enabled: record.code_completion_enabled,
     }))
 }
 
+async fn edit_ai_config(
+    authed: ApiAuthed,
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+    Json(eo): Json<EditAIConfig>,
+) -> Result<String> {
+    require_admin(authed.is_admin, &authed.username)?;
+
+    let mut tx = db.begin().await?;
+
+    let serialized_config = eo
+        .ai_config
+        .as_ref()
+        .map(|c| serde_json::to_value::<&AIConfig>(c))
+        .transpose()
+        .map_err(|err| Error::InternalErr(err.to_string()))?;
+
+    // openai_resource_path is kept in sync for clients that only know about the openai proxy
+    let openai_resource_path = eo.ai_config.as_ref().and_then(|c| c.resource_path.clone());
+    sqlx::query(
+        "UPDATE workspace_settings SET ai_config = $1, openai_resource_path = $2, code_completion_enabled = $3 WHERE workspace_id = $4",
+    )
+    .bind(serialized_config)
+    .bind(openai_resource_path)
+    .bind(eo.code_completion_enabled)
+    .bind(&w_id)
+    .execute(&mut *tx)
+    .await?;
+
+    audit_log(
+        &mut *tx,
+        &authed.username,
+        "workspaces.edit_ai_config",
+        ActionKind::Update,
+        &w_id,
+        Some(&authed.email),
+        Some([("ai_config", &format!("{:?}", eo.ai_config)[..]), ("code_completion_enabled", &format!("{:?}", eo.code_completion_enabled)[..])].into()),
+    )
+    .await?;
+    tx.commit().await?;
+    invalidate_ai_request_config(&w_id).await;
+
+    Ok(format!("Edit ai config for workspace {}", &w_id))
+}
+
+async fn get_ai_config(
+    Extension(db): Extension<DB>,
+    Path(w_id): Path<String>,
+) -> JsonResult<Option<AIConfig>> {
+    let ai_config = get_workspace_ai_config(&db, &w_id).await?;
+    Ok(Json(ai_config))
+}
+
 async fn edit_large_file_storage_config(
     authed: ApiAuthed,
     Extension(db): Extension<DB>,
@@ -1478,6 +1577,11 @@ async fn delete_workspace(
         .execute(&mut *tx)
         .await?;
 
+    sqlx::query("DELETE FROM service_account WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
     sqlx::query!("DELETE FROM usr WHERE workspace_id = $1", &w_id)
         .execute(&mut *tx)
         .await?;
@@ -1509,6 +1613,36 @@ async fn delete_workspace(
         .execute(&mut *tx)
         .await?;
 
+    sqlx::query("DELETE FROM ai_usage WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM job_usage_rollup WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM job_result_cache WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM job_artifact WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM network_policy WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM access_request WHERE workspace_id = $1")
+        .bind(&w_id)
+        .execute(&mut *tx)
+        .await?;
+
     sqlx::query!(
         "DELETE FROM workspace_settings WHERE workspace_id = $1",
         &w_id
@@ -1741,6 +1875,8 @@ struct ScriptMetadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     cache_ttl: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    cache_key_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     dedicated_worker: Option<bool>,
     #[serde(skip_serializing_if = "is_none_or_false")]
     ws_error_handler_muted: Option<bool>,
@@ -1955,6 +2091,7 @@ async fn tarball_workspace(
                 concurrent_limit: script.concurrent_limit,
                 concurrency_time_window_s: script.concurrency_time_window_s,
                 cache_ttl: script.cache_ttl,
+                cache_key_args: script.cache_key_args,
                 dedicated_worker: script.dedicated_worker,
                 ws_error_handler_muted: script.ws_error_handler_muted,
                 priority: script.priority,
@@ -2036,17 +2173,16 @@ async fn tarball_workspace(
         .fetch_all(&db)
         .await?;
 
-        let mc = build_crypt(&mut db.begin().await?, &w_id).await?;
+        let decrypt = build_decrypt(&mut db.begin().await?, &w_id).await?;
 
         for mut var in variables {
+            // secrets of an external provider are exported as the path they reference
             if plain_secret.or(plain_secrets).unwrap_or(false)
                 && var.value.is_some()
                 && var.is_secret
+                && var.secret_provider.is_none()
             {
-                var.value = Some(
-                    mc.decrypt_base64_to_string(var.value.unwrap())
-                        .map_err(|e| Error::InternalErr(e.to_string()))?,
-                );
+                var.value = Some(decrypt.decrypt_base64_to_string(&var.value.unwrap())?);
             }
             let var_str = &to_string_without_metadata(&var, false).unwrap();
             archive
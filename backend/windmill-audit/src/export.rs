@@ -0,0 +1,252 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Streaming of the audit logs to an external sink, set by `AUDIT_LOG_SINK`:
+//! - `syslog://host:port`: RFC 5424 messages over TCP, octet-counted (RFC 6587)
+//! - `http(s)://...`: batches POSTed as JSON arrays, with `AUDIT_LOG_SINK_TOKEN` as bearer token
+//! - `file:///path`: appended as JSON lines
+//!
+//! Delivery is at least once: the id of the last exported log is stored as the cursor of the sink
+//! once a batch is acknowledged, and a batch that fails is sent again. The logs are only exported
+//! `AUDIT_LOG_EXPORT_DELAY_SECS` after they are written, for the transactions writing logs with
+//! lower ids to commit first.
+
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use sqlx::{Pool, Postgres};
+use tokio::io::AsyncWriteExt;
+use windmill_common::error::{Error, Result};
+
+use crate::AuditLog;
+
+lazy_static::lazy_static! {
+    pub static ref AUDIT_LOG_SINK: Option<AuditSink> = std::env::var("AUDIT_LOG_SINK")
+        .ok()
+        .and_then(|x| match AuditSink::parse(&x) {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                tracing::error!("Invalid AUDIT_LOG_SINK, audit logs will not be exported: {e}");
+                None
+            }
+        });
+    static ref AUDIT_LOG_SINK_TOKEN: Option<String> = std::env::var("AUDIT_LOG_SINK_TOKEN").ok();
+    static ref AUDIT_LOG_EXPORT_DELAY_SECS: i64 = std::env::var("AUDIT_LOG_EXPORT_DELAY_SECS")
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .unwrap_or(60);
+
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::ClientBuilder::new()
+        .user_agent("windmill/beta")
+        .timeout(SEND_TIMEOUT)
+        .build()
+        .unwrap();
+}
+
+const BATCH_SIZE: i64 = 500;
+const MAX_BATCHES_PER_RUN: usize = 20;
+/// The cursor of the sink stays locked while a batch is sent, so sending cannot hang
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+// facility log audit (13), severity informational (6)
+const SYSLOG_PRIORITY: u8 = 13 * 8 + 6;
+
+pub enum AuditSink {
+    Syslog(String),
+    Http(reqwest::Url),
+    File(String),
+}
+
+impl AuditSink {
+    fn parse(sink: &str) -> Result<Self> {
+        let url = reqwest::Url::parse(sink)
+            .map_err(|e| Error::BadConfig(format!("invalid audit log sink {sink}: {e}")))?;
+        match url.scheme() {
+            "syslog" => match (url.host_str(), url.port()) {
+                (Some(host), Some(port)) => Ok(AuditSink::Syslog(format!("{host}:{port}"))),
+                _ => Err(Error::BadConfig(
+                    "the syslog audit log sink must be of the form syslog://host:port".to_string(),
+                )),
+            },
+            "http" | "https" => Ok(AuditSink::Http(url)),
+            "file" => Ok(AuditSink::File(url.path().to_string())),
+            scheme => Err(Error::BadConfig(format!(
+                "unsupported audit log sink {scheme}, expected syslog, http(s) or file"
+            ))),
+        }
+    }
+
+    /// Name the cursor of the sink is stored under, without the credentials its url may hold
+    fn name(&self) -> String {
+        match self {
+            AuditSink::Syslog(addr) => format!("syslog://{addr}"),
+            AuditSink::Http(url) => {
+                format!(
+                    "{}://{}{}",
+                    url.scheme(),
+                    url.host_str().unwrap_or_default(),
+                    url.port().map(|x| format!(":{x}")).unwrap_or_default()
+                ) + url.path()
+            }
+            AuditSink::File(path) => format!("file://{path}"),
+        }
+    }
+
+    async fn send(&self, logs: &[AuditLog]) -> Result<()> {
+        match self {
+            AuditSink::Syslog(addr) => {
+                let mut stream = tokio::net::TcpStream::connect(addr).await?;
+                for log in logs {
+                    let message = syslog_message(log)?;
+                    stream
+                        .write_all(format!("{} {message}", message.len()).as_bytes())
+                        .await?;
+                }
+                stream.flush().await?;
+                stream.shutdown().await?;
+            }
+            AuditSink::Http(url) => {
+                let mut request = HTTP_CLIENT.post(url.clone()).json(logs);
+                if let Some(token) = AUDIT_LOG_SINK_TOKEN.as_ref() {
+                    request = request.bearer_auth(token);
+                }
+                request
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| Error::InternalErr(format!("sending audit logs: {e}")))?;
+            }
+            AuditSink::File(path) => {
+                let mut lines = String::new();
+                for log in logs {
+                    lines.push_str(&to_json(log)?);
+                    lines.push('\n');
+                }
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(lines.as_bytes()).await?;
+                file.sync_data().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_json(log: &AuditLog) -> Result<String> {
+    serde_json::to_string(log)
+        .map_err(|e| Error::InternalErr(format!("serializing audit log {}: {e}", log.id)))
+}
+
+fn syslog_message(log: &AuditLog) -> Result<String> {
+    // the message id is made of at most 32 printable ascii characters
+    let msg_id = log
+        .operation
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(32)
+        .collect::<String>();
+    Ok(format!(
+        "<{SYSLOG_PRIORITY}>1 {} - windmill - {msg_id} - {}",
+        log.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        to_json(log)?
+    ))
+}
+
+/// Exports the audit logs written since the cursor of the sink, if any, returning the number of
+/// logs exported
+pub async fn export_audit_logs(db: &Pool<Postgres>) -> Result<usize> {
+    let Some(sink) = AUDIT_LOG_SINK.as_ref() else {
+        return Ok(0);
+    };
+    let mut exported = 0;
+    for _ in 0..MAX_BATCHES_PER_RUN {
+        match export_batch(db, sink).await? {
+            Some(n) => {
+                exported += n;
+                if (n as i64) < BATCH_SIZE {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    Ok(exported)
+}
+
+/// Exports the next batch of logs, or returns None if another server is exporting them
+async fn export_batch(db: &Pool<Postgres>, sink: &AuditSink) -> Result<Option<usize>> {
+    let name = sink.name();
+    let mut tx = db.begin().await?;
+    sqlx::query("INSERT INTO audit_export_cursor (sink) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(&name)
+        .execute(&mut *tx)
+        .await?;
+    let last_id = sqlx::query_scalar::<_, i32>(
+        "SELECT last_id FROM audit_export_cursor WHERE sink = $1 FOR UPDATE SKIP LOCKED",
+    )
+    .bind(&name)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(last_id) = last_id else {
+        return Ok(None);
+    };
+
+    let logs = sqlx::query_as::<_, AuditLog>(
+        "SELECT * FROM audit WHERE id > $1 AND timestamp <= now() - ($2::bigint::text || ' s')::interval
+        ORDER BY id LIMIT $3",
+    )
+    .bind(last_id)
+    .bind(*AUDIT_LOG_EXPORT_DELAY_SECS)
+    .bind(BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await?;
+    let Some(new_last_id) = logs.last().map(|x| x.id) else {
+        tx.commit().await?;
+        return Ok(Some(0));
+    };
+
+    tokio::time::timeout(SEND_TIMEOUT, sink.send(&logs))
+        .await
+        .map_err(|_| Error::InternalErr(format!("sending audit logs to {name} timed out")))??;
+    sqlx::query("UPDATE audit_export_cursor SET last_id = $1, exported_at = now() WHERE sink = $2")
+        .bind(new_last_id)
+        .bind(&name)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(logs.len()))
+}
+
+/// Deletes the audit logs older than the retention period, keeping the ones not yet exported to
+/// the sink if there is one. A retention period of 0 keeps the logs forever.
+pub async fn delete_expired_audit_logs(db: &Pool<Postgres>, retention_secs: i64) -> Result<u64> {
+    if retention_secs <= 0 {
+        return Ok(0);
+    }
+    let deleted = if let Some(sink) = AUDIT_LOG_SINK.as_ref() {
+        sqlx::query(
+            "DELETE FROM audit WHERE timestamp <= now() - ($1::bigint::text || ' s')::interval
+            AND id <= COALESCE((SELECT last_id FROM audit_export_cursor WHERE sink = $2), 0)",
+        )
+        .bind(retention_secs)
+        .bind(sink.name())
+        .execute(db)
+        .await?
+    } else {
+        sqlx::query(
+            "DELETE FROM audit WHERE timestamp <= now() - ($1::bigint::text || ' s')::interval",
+        )
+        .bind(retention_secs)
+        .execute(db)
+        .await?
+    };
+    Ok(deleted.rows_affected())
+}
@@ -6,6 +6,8 @@
  * LICENSE-AGPL for a copy of the license.
  */
 
+pub mod export;
+
 use sql_builder::prelude::*;
 
 use std::collections::HashMap;
@@ -23,12 +25,16 @@ use sql_builder::SqlBuilder;
 use sqlx::{FromRow, Postgres, Transaction};
 
 #[derive(sqlx::Type, Serialize, Deserialize, Debug)]
-#[sqlx(type_name = "ACTION_KIND", rename_all = "lowercase")]
+#[sqlx(type_name = "ACTION_KIND", rename_all = "snake_case")]
 pub enum ActionKind {
     Create,
     Update,
     Delete,
     Execute,
+    Login,
+    TokenCreation,
+    PermissionChange,
+    SecretRead,
 }
 
 #[derive(FromRow, Serialize, Deserialize)]
@@ -43,6 +43,8 @@ pub struct Flow {
     pub ws_error_handler_muted: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -78,6 +80,8 @@ pub struct NewFlow {
     pub dedicated_worker: Option<bool>,
     pub timeout: Option<i32>,
     pub deployment_message: Option<String>,
+    /// run the flow as this service account instead of the user triggering it
+    pub service_account: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -98,6 +102,9 @@ pub struct FlowValue {
     pub skip_expr: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_ttl: Option<u32>,
+    /// Only these args are part of the cache key, all of them when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_key_args: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub early_return: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -215,6 +222,9 @@ pub struct FlowModule {
     pub sleep: Option<InputTransform>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_ttl: Option<u32>,
+    /// Only these args are part of the cache key, all of them when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_key_args: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -369,6 +379,7 @@ pub fn add_virtual_items_if_necessary(modules: &mut Vec<FlowModule>) {
             sleep: None,
             suspend: None,
             cache_ttl: None,
+            cache_key_args: None,
             timeout: None,
             priority: None,
             delete_after_use: None,
@@ -0,0 +1,81 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Settings inherited by every script and flow under `f/<folder>/` unless they set their own
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FolderDefaults {
+    pub tag: Option<String>,
+    pub timeout: Option<i32>,
+    /// prefixed path of the error handler, e.g. `script/f/folder/handler` or `flow/f/folder/handler`.
+    /// It replaces the workspace error handler for jobs of the folder
+    pub error_handler: Option<String>,
+    pub error_handler_extra_args: Option<serde_json::Value>,
+    pub ws_error_handler_muted: Option<bool>,
+    pub retention_secs: Option<i64>,
+    pub concurrent_limit: Option<i32>,
+    pub concurrency_time_window_s: Option<i32>,
+}
+
+pub fn folder_name_of_path(path: &str) -> Option<&str> {
+    path.strip_prefix("f/")
+        .and_then(|p| p.split('/').next())
+        .filter(|name| !name.is_empty())
+}
+
+fn parse_folder_defaults(value: Option<serde_json::Value>) -> Result<Option<FolderDefaults>> {
+    value
+        .map(serde_json::from_value::<FolderDefaults>)
+        .transpose()
+        .map_err(|e| Error::InternalErr(format!("invalid folder default settings: {e}")))
+}
+
+pub async fn get_folder_defaults<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    w_id: &str,
+    path: &str,
+) -> Result<Option<FolderDefaults>> {
+    let name = match folder_name_of_path(path) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    let value = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT default_settings FROM folder WHERE workspace_id = $1 AND name = $2",
+    )
+    .bind(w_id)
+    .bind(name)
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    parse_folder_defaults(value)
+}
+
+pub async fn get_folder_defaults_for_script_hash<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    w_id: &str,
+    hash: i64,
+) -> Result<Option<FolderDefaults>> {
+    let value = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT folder.default_settings FROM script JOIN folder \
+         ON folder.workspace_id = script.workspace_id AND script.path LIKE 'f/%' \
+         AND split_part(script.path, '/', 2) = folder.name \
+         WHERE script.hash = $1 AND script.workspace_id = $2",
+    )
+    .bind(hash)
+    .bind(w_id)
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    parse_folder_defaults(value)
+}
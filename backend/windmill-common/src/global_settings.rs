@@ -3,6 +3,7 @@ pub const CUSTOM_TAGS_SETTING: &str = "custom_tags";
 pub const BASE_URL_SETTING: &str = "base_url";
 pub const OAUTH_SETTING: &str = "oauths";
 pub const RETENTION_PERIOD_SECS_SETTING: &str = "retention_period_secs";
+pub const AUDIT_RETENTION_SECS_SETTING: &str = "audit_retention_secs";
 pub const REQUEST_SIZE_LIMIT_SETTING: &str = "request_size_limit_mb";
 pub const LICENSE_KEY_SETTING: &str = "license_key";
 pub const NPM_CONFIG_REGISTRY_SETTING: &str = "npm_config_registry";
@@ -13,8 +14,12 @@ pub const EXPOSE_METRICS_SETTING: &str = "expose_metrics";
 pub const EXPOSE_DEBUG_METRICS_SETTING: &str = "expose_debug_metrics";
 pub const KEEP_JOB_DIR_SETTING: &str = "keep_job_dir";
 pub const REQUIRE_PREEXISTING_USER_FOR_OAUTH_SETTING: &str = "require_preexisting_user_for_oauth";
+pub const REQUIRE_MFA_SETTING: &str = "require_mfa";
+pub const OFFLINE_MODE_SETTING: &str = "offline_mode";
+pub const MIRROR_CAPTURE_SETTING: &str = "mirror_capture";
+pub const PACKAGE_MIRROR_SECRET_SETTING: &str = "package_mirror_secret";
 
-pub const ENV_SETTINGS: [&str; 54] = [
+pub const ENV_SETTINGS: [&str; 61] = [
     "DISABLE_NSJAIL",
     "MODE",
     "NUM_WORKERS",
@@ -30,6 +35,8 @@ pub const ENV_SETTINGS: [&str; 54] = [
     "PORT",
     "KEEP_JOB_DIR",
     "S3_CACHE_BUCKET",
+    "GLOBAL_CACHE_BACKEND",
+    "GLOBAL_CACHE_PATH",
     "TAR_CACHE_RATE",
     "COOKIE_DOMAIN",
     "PYTHON_PATH",
@@ -56,6 +63,9 @@ pub const ENV_SETTINGS: [&str; 54] = [
     "CLOUD_HOSTED",
     "GLOBAL_CACHE_INTERVAL",
     "JOB_RETENTION_SECS",
+    "AUDIT_RETENTION_SECS",
+    "AUDIT_LOG_SINK",
+    "AUDIT_LOG_EXPORT_DELAY_SECS",
     "WAIT_RESULT_FAST_POLL_DURATION_SECS",
     "WAIT_RESULT_SLOW_POLL_INTERVAL_MS",
     "WAIT_RESULT_FAST_POLL_INTERVAL_MS",
@@ -69,4 +79,6 @@ pub const ENV_SETTINGS: [&str; 54] = [
     "GLOBAL_ERROR_HANDLER_PATH_IN_ADMINS_WORKSPACE",
     "MAX_WAIT_FOR_SIGTERM",
     "WORKER_GROUP",
+    "REQUIRE_MFA",
+    "MFA_STEP_UP_SECS",
 ];
@@ -9,6 +9,7 @@ use crate::{
     error::{self, Error},
     flow_status::{FlowStatus, RestartedFrom},
     flows::FlowValue,
+    folders::get_folder_defaults_for_script_hash,
     get_latest_deployed_hash_for_path,
     scripts::{ScriptHash, ScriptLang},
 };
@@ -234,6 +235,7 @@ pub struct CompletedJob {
     pub tag: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub priority: Option<i16>,
+    pub cache_hit: bool,
 }
 
 impl CompletedJob {
@@ -398,10 +400,15 @@ pub async fn script_hash_to_tag_and_limits<'c>(
             "querying getting tag for hash {script_hash}: {e}"
         ))
     })?;
+    let folder_defaults = get_folder_defaults_for_script_hash(&mut **db, w_id, script_hash.0)
+        .await?
+        .unwrap_or_default();
     Ok((
-        script.tag,
-        script.concurrent_limit,
-        script.concurrency_time_window_s,
+        script.tag.or(folder_defaults.tag),
+        script.concurrent_limit.or(folder_defaults.concurrent_limit),
+        script
+            .concurrency_time_window_s
+            .or(folder_defaults.concurrency_time_window_s),
         script.cache_ttl,
         script.language,
         script.dedicated_worker,
@@ -22,14 +22,19 @@ pub mod error;
 pub mod external_ip;
 pub mod flow_status;
 pub mod flows;
+pub mod folders;
 pub mod global_settings;
 pub mod jobs;
 pub mod more_serde;
+pub mod network_policy;
 pub mod oauth2;
+pub mod package_mirror;
 pub mod schedule;
 pub mod scripts;
 pub mod server;
+pub mod service_accounts;
 pub mod stats;
+pub mod usage;
 pub mod users;
 pub mod utils;
 pub mod variables;
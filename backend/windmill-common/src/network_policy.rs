@@ -0,0 +1,182 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    DB,
+};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[cfg_attr(
+    feature = "sqlx",
+    sqlx(type_name = "NETWORK_MODE", rename_all = "lowercase")
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// no network access at all
+    None,
+    /// egress only to the hosts and CIDRs of the allowlist, through the egress proxy of the worker
+    Allowlist,
+    /// unrestricted network access, the default when no policy applies
+    Full,
+}
+
+/// Network policy of a script path, or of all the scripts of a folder when its path is `f/<folder>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct NetworkPolicy {
+    pub path: String,
+    pub mode: NetworkMode,
+    pub allowlist: Vec<String>,
+    pub edited_by: String,
+    pub edited_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AllowEntry {
+    /// exact hostname or `*.domain` wildcard, matched case-insensitively
+    Host(String),
+    Cidr(IpAddr, u8),
+}
+
+impl AllowEntry {
+    pub fn parse(entry: &str) -> Result<Self> {
+        let entry = entry.trim().to_lowercase();
+        if let Some((addr, prefix)) = entry.split_once('/') {
+            let addr = addr
+                .parse::<IpAddr>()
+                .map_err(|_| Error::BadRequest(format!("invalid CIDR address in `{entry}`")))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| Error::BadRequest(format!("invalid CIDR prefix in `{entry}`")))?;
+            return Ok(AllowEntry::Cidr(addr, prefix));
+        }
+        if let Ok(addr) = entry.parse::<IpAddr>() {
+            let prefix = if addr.is_ipv4() { 32 } else { 128 };
+            return Ok(AllowEntry::Cidr(addr, prefix));
+        }
+        let host = entry.strip_prefix("*.").unwrap_or(&entry);
+        if host.is_empty()
+            || !host
+                .split('.')
+                .all(|l| !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        {
+            return Err(Error::BadRequest(format!(
+                "`{entry}` is neither a hostname, a `*.domain` wildcard nor a CIDR"
+            )));
+        }
+        Ok(AllowEntry::Host(entry))
+    }
+
+    pub fn matches_host(&self, host: &str) -> bool {
+        match self {
+            AllowEntry::Host(pattern) => {
+                let host = host.trim_end_matches('.').to_lowercase();
+                match pattern.strip_prefix("*.") {
+                    Some(domain) => host
+                        .strip_suffix(domain)
+                        .is_some_and(|sub| sub.ends_with('.')),
+                    None => *pattern == host,
+                }
+            }
+            AllowEntry::Cidr(..) => false,
+        }
+    }
+
+    pub fn matches_ip(&self, ip: &IpAddr) -> bool {
+        match (self, ip) {
+            (AllowEntry::Cidr(IpAddr::V4(net), prefix), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(*ip) & mask
+            }
+            (AllowEntry::Cidr(IpAddr::V6(net), prefix), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for AllowEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllowEntry::Host(host) => write!(f, "{host}"),
+            AllowEntry::Cidr(addr, prefix) => write!(f, "{addr}/{prefix}"),
+        }
+    }
+}
+
+pub fn parse_allowlist(allowlist: &[String]) -> Result<Vec<AllowEntry>> {
+    allowlist.iter().map(|x| AllowEntry::parse(x)).collect()
+}
+
+/// Folder path a script path inherits its network policy from, if any
+fn folder_policy_path(path: &str) -> Option<String> {
+    let mut parts = path.split('/');
+    match (parts.next(), parts.next()) {
+        (Some("f"), Some(folder)) if !folder.is_empty() => Some(format!("f/{folder}")),
+        _ => None,
+    }
+}
+
+/// Policy of the script path itself, or else of its folder
+pub async fn get_network_policy(db: &DB, w_id: &str, path: &str) -> Result<Option<NetworkPolicy>> {
+    let folder_path = folder_policy_path(path);
+    let policy = sqlx::query_as::<_, NetworkPolicy>(
+        "SELECT path, mode, allowlist, edited_by, edited_at FROM network_policy \
+         WHERE workspace_id = $1 AND (path = $2 OR path = $3) \
+         ORDER BY path = $2 DESC LIMIT 1",
+    )
+    .bind(w_id)
+    .bind(path)
+    .bind(folder_path)
+    .fetch_optional(db)
+    .await?;
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_entries() {
+        let wildcard = AllowEntry::parse("*.Example.com").unwrap();
+        assert!(wildcard.matches_host("api.example.com"));
+        assert!(!wildcard.matches_host("example.com"));
+        assert!(!wildcard.matches_host("badexample.com"));
+
+        let host = AllowEntry::parse("pypi.org").unwrap();
+        assert!(host.matches_host("PyPI.org."));
+        assert!(!host.matches_host("files.pypi.org"));
+
+        let cidr = AllowEntry::parse("10.1.0.0/16").unwrap();
+        assert!(cidr.matches_ip(&"10.1.200.3".parse().unwrap()));
+        assert!(!cidr.matches_ip(&"10.2.0.1".parse().unwrap()));
+        assert!(AllowEntry::parse("0.0.0.0/0")
+            .unwrap()
+            .matches_ip(&"1.2.3.4".parse().unwrap()));
+        assert!(AllowEntry::parse("fd00::/8")
+            .unwrap()
+            .matches_ip(&"fd12::1".parse().unwrap()));
+
+        assert!(AllowEntry::parse("10.0.0.0/33").is_err());
+        assert!(AllowEntry::parse("https://example.com").is_err());
+        assert_eq!(folder_policy_path("f/etl/load"), Some("f/etl".to_string()));
+        assert_eq!(folder_policy_path("u/admin/load"), None);
+    }
+}
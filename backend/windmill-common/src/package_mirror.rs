@@ -0,0 +1,174 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{Error, Result},
+    global_settings::PACKAGE_MIRROR_SECRET_SETTING,
+    utils::rd_string,
+    DB,
+};
+
+/// Artifacts of the package mirror are keyed by kind and by their path in the registry protocol
+/// of that kind:
+/// - pip: `<wheel or sdist filename>`
+/// - npm: `<package name>/-/<tarball filename>`
+/// - go: `<escaped module path>/@v/<version>.<info|mod|zip>`
+pub const PIP: &str = "pip";
+pub const NPM: &str = "npm";
+pub const GO: &str = "go";
+
+pub const MIRROR_KINDS: [&str; 3] = [PIP, NPM, GO];
+
+/// PEP 503 normalized name of a python distribution
+pub fn normalize_pip_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut last_sep = false;
+    for c in name.chars() {
+        if c == '-' || c == '_' || c == '.' {
+            if !last_sep {
+                normalized.push('-');
+            }
+            last_sep = true;
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+            last_sep = false;
+        }
+    }
+    normalized
+}
+
+/// Package name and version an artifact path refers to
+pub fn artifact_name_version(kind: &str, path: &str) -> Option<(String, String)> {
+    match kind {
+        PIP => {
+            if let Some(stem) = path.strip_suffix(".whl") {
+                let mut parts = stem.split('-');
+                let name = parts.next()?;
+                let version = parts.next()?;
+                Some((normalize_pip_name(name), version.to_string()))
+            } else {
+                let stem = path
+                    .strip_suffix(".tar.gz")
+                    .or_else(|| path.strip_suffix(".zip"))?;
+                let (name, version) = stem.rsplit_once('-')?;
+                Some((normalize_pip_name(name), version.to_string()))
+            }
+        }
+        NPM => {
+            let (name, file) = path.split_once("/-/")?;
+            let basename = name.rsplit('/').next()?;
+            let version = file
+                .strip_prefix(&format!("{basename}-"))?
+                .strip_suffix(".tgz")?;
+            Some((name.to_string(), version.to_string()))
+        }
+        GO => {
+            let (module, file) = path.split_once("/@v/")?;
+            let (version, _ext) = file.rsplit_once('.')?;
+            Some((module.to_string(), version.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Store an artifact in the mirror, artifacts already present are kept as is
+pub async fn insert_artifact(
+    db: &DB,
+    kind: &str,
+    path: &str,
+    content: &[u8],
+    metadata: Option<serde_json::Value>,
+) -> Result<bool> {
+    let (name, version) = artifact_name_version(kind, path)
+        .ok_or_else(|| Error::BadRequest(format!("{path} is not a valid {kind} artifact path")))?;
+    let sha256 = format!("{:x}", Sha256::digest(content));
+
+    let inserted = sqlx::query(
+        "INSERT INTO package_mirror (kind, path, name, version, sha256, size, content, metadata) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (kind, path) DO NOTHING",
+    )
+    .bind(kind)
+    .bind(path)
+    .bind(&name)
+    .bind(&version)
+    .bind(&sha256)
+    .bind(content.len() as i64)
+    .bind(content)
+    .bind(metadata.map(sqlx::types::Json))
+    .execute(db)
+    .await?
+    .rows_affected();
+
+    Ok(inserted > 0)
+}
+
+pub async fn artifact_exists(db: &DB, kind: &str, path: &str) -> Result<bool> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM package_mirror WHERE kind = $1 AND path = $2)",
+    )
+    .bind(kind)
+    .bind(path)
+    .fetch_one(db)
+    .await?;
+    Ok(exists)
+}
+
+/// Secret workers authenticate to the package mirror with, created on first use
+pub async fn get_or_create_mirror_secret(db: &DB) -> Result<String> {
+    sqlx::query(
+        "INSERT INTO global_settings (name, value) VALUES ($1, to_jsonb($2::text)) \
+         ON CONFLICT (name) DO NOTHING",
+    )
+    .bind(PACKAGE_MIRROR_SECRET_SETTING)
+    .bind(rd_string(32))
+    .execute(db)
+    .await?;
+
+    let secret = sqlx::query_scalar::<_, serde_json::Value>(
+        "SELECT value FROM global_settings WHERE name = $1",
+    )
+    .bind(PACKAGE_MIRROR_SECRET_SETTING)
+    .fetch_one(db)
+    .await?;
+
+    secret
+        .as_str()
+        .map(|x| x.to_string())
+        .ok_or_else(|| Error::InternalErr("package mirror secret is not a string".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_name_version() {
+        assert_eq!(
+            artifact_name_version(PIP, "Typing_Extensions-4.8.0-py3-none-any.whl"),
+            Some(("typing-extensions".to_string(), "4.8.0".to_string()))
+        );
+        assert_eq!(
+            artifact_name_version(PIP, "wmill-1.230.0.tar.gz"),
+            Some(("wmill".to_string(), "1.230.0".to_string()))
+        );
+        assert_eq!(
+            artifact_name_version(NPM, "@types/node/-/node-20.10.4.tgz"),
+            Some(("@types/node".to_string(), "20.10.4".to_string()))
+        );
+        assert_eq!(
+            artifact_name_version(GO, "github.com/!burnt!sushi/toml/@v/v1.3.2.zip"),
+            Some((
+                "github.com/!burnt!sushi/toml".to_string(),
+                "v1.3.2".to_string()
+            ))
+        );
+        assert_eq!(artifact_name_version(NPM, "lodash"), None);
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use crate::{
+    error::{Error, Result},
+    users::username_to_permissioned_as,
+};
+
+/// Service accounts are workspace users that cannot log in. They get a reserved email on a
+/// domain that can never belong to a real user.
+pub const SERVICE_ACCOUNT_EMAIL_DOMAIN: &str = "service-account.invalid";
+
+pub fn service_account_email(name: &str) -> String {
+    format!("{name}@{SERVICE_ACCOUNT_EMAIL_DOMAIN}")
+}
+
+pub fn is_service_account_email(email: &str) -> bool {
+    email
+        .rsplit_once('@')
+        .is_some_and(|(_, domain)| domain == SERVICE_ACCOUNT_EMAIL_DOMAIN)
+}
+
+fn run_as_identity(
+    name: String,
+    email: Option<String>,
+    disabled: Option<bool>,
+) -> Result<(String, String)> {
+    match (email, disabled) {
+        (Some(email), Some(false)) => Ok((username_to_permissioned_as(&name), email)),
+        (Some(_), _) => Err(Error::BadRequest(format!(
+            "service account {name} is disabled"
+        ))),
+        (None, _) => Err(Error::NotFound(format!(
+            "service account {name} does not exist"
+        ))),
+    }
+}
+
+/// Returns the permissioned_as and email jobs run as the service account must use
+pub async fn get_service_account_identity<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    w_id: &str,
+    name: &str,
+) -> Result<(String, String)> {
+    let row = sqlx::query_as::<_, (String, bool)>(
+        "SELECT usr.email, usr.disabled FROM service_account JOIN usr \
+         ON usr.workspace_id = service_account.workspace_id AND usr.username = service_account.name \
+         WHERE service_account.workspace_id = $1 AND service_account.name = $2",
+    )
+    .bind(w_id)
+    .bind(name)
+    .fetch_optional(db)
+    .await?;
+
+    let (email, disabled) = row.map(|(e, d)| (Some(e), Some(d))).unwrap_or((None, None));
+    run_as_identity(name.to_string(), email, disabled)
+}
+
+async fn get_run_as<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    table: &str,
+    w_id: &str,
+    path: &str,
+) -> Result<Option<(String, String)>> {
+    let row = sqlx::query_as::<_, (String, Option<String>, Option<bool>)>(&format!(
+        "SELECT {table}.service_account, usr.email, usr.disabled FROM {table} \
+         LEFT JOIN service_account ON service_account.workspace_id = {table}.workspace_id \
+         AND service_account.name = {table}.service_account \
+         LEFT JOIN usr ON usr.workspace_id = service_account.workspace_id \
+         AND usr.username = service_account.name \
+         WHERE {table}.workspace_id = $1 AND {table}.path = $2 \
+         AND {table}.service_account IS NOT NULL"
+    ))
+    .bind(w_id)
+    .bind(path)
+    .fetch_optional(db)
+    .await?;
+
+    row.map(|(name, email, disabled)| run_as_identity(name, email, disabled))
+        .transpose()
+}
+
+/// The identity a flow runs as when it was set to run as a service account
+pub async fn get_flow_run_as<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    w_id: &str,
+    path: &str,
+) -> Result<Option<(String, String)>> {
+    get_run_as(db, "flow", w_id, path).await
+}
+
+/// The identity a schedule runs as when it was set to run as a service account
+pub async fn get_schedule_run_as<'c, E: sqlx::PgExecutor<'c>>(
+    db: E,
+    w_id: &str,
+    path: &str,
+) -> Result<Option<(String, String)>> {
+    get_run_as(db, "schedule", w_id, path).await
+}
@@ -0,0 +1,71 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UsagePeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl UsagePeriod {
+    /// Unit understood by postgres `date_trunc`
+    pub fn as_trunc_unit(&self) -> &'static str {
+        match self {
+            UsagePeriod::Daily => "day",
+            UsagePeriod::Monthly => "month",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UsageLimits {
+    pub execution_seconds: Option<i64>,
+    pub jobs: Option<i64>,
+}
+
+impl UsageLimits {
+    pub fn exceeded(&self, usage: &WorkspaceUsage) -> Option<String> {
+        if let Some(limit) = self.execution_seconds {
+            if usage.execution_seconds >= limit {
+                return Some(format!(
+                    "{} execution seconds used out of {limit}",
+                    usage.execution_seconds
+                ));
+            }
+        }
+        if let Some(limit) = self.jobs {
+            if usage.jobs >= limit {
+                return Some(format!("{} jobs run out of {limit}", usage.jobs));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UsageQuotas {
+    #[serde(default)]
+    pub period: UsagePeriod,
+    /// Exceeding a soft limit is only reported
+    #[serde(default)]
+    pub soft: UsageLimits,
+    /// Exceeding a hard limit prevents new jobs from being pushed
+    #[serde(default)]
+    pub hard: UsageLimits,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WorkspaceUsage {
+    pub execution_seconds: i64,
+    pub jobs: i64,
+}
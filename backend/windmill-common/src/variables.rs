@@ -34,6 +34,7 @@ pub struct ListableVariable {
     pub is_refreshed: Option<bool>,
     pub refresh_error: Option<String>,
     pub is_linked: Option<bool>,
+    pub secret_provider: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -49,16 +50,20 @@ pub struct ExportableListableVariable {
     pub account: Option<i32>,
     pub is_oauth: Option<bool>,
     pub is_expired: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_provider: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct CreateVariable {
     pub path: String,
+    /// For the secrets of an external secret provider, the path of the secret in the provider
     pub value: String,
     pub is_secret: bool,
     pub description: String,
     pub account: Option<i32>,
     pub is_oauth: Option<bool>,
+    pub secret_provider: Option<String>,
 }
 
 pub async fn get_reserved_variables(
@@ -49,6 +49,7 @@ use windmill_common::{
         Iterator, JobResult, RestartedFrom, RetryStatus, MAX_RETRY_ATTEMPTS, MAX_RETRY_INTERVAL,
     },
     flows::{add_virtual_items_if_necessary, FlowModuleValue, FlowValue},
+    folders::get_folder_defaults,
     jobs::{
         get_payload_tag_from_prefixed_path, CompletedJob, JobKind, JobPayload, QueuedJob, RawCode,
     },
@@ -65,6 +66,7 @@ use windmill_common::worker::CLOUD_HOSTED;
 
 use crate::{
     schedule::{get_schedule_opt, push_scheduled_job},
+    usage::{check_usage_quotas, is_quota_exempt, record_job_usage},
     QueueTransaction,
 };
 
@@ -487,6 +489,11 @@ pub async fn add_completed_job<
         tracing::debug!("decremented concurrency counter");
     }
 
+    // recorded with the completion so that a retried completion is not counted twice
+    record_job_usage(&mut tx, queued_job, success, _duration, mem_peak)
+        .await
+        .map_err(|e| Error::InternalErr(format!("Could not record usage of job {job_id}: {e}")))?;
+
     tx.commit().await?;
     tracing::info!(
         "inserted completed job: {} (success: {success})",
@@ -787,6 +794,23 @@ pub async fn send_error_to_workspace_handler<
         return Ok(());
     }
 
+    let folder_defaults = match queued_job.script_path.as_ref() {
+        Some(path) if matches!(queued_job.job_kind, JobKind::Script | JobKind::Flow) => {
+            get_folder_defaults(db, w_id, path).await?
+        }
+        _ => None,
+    }
+    .unwrap_or_default();
+
+    // a folder error handler replaces the workspace one for the jobs of the folder
+    let (error_handler, error_handler_extra_args) = match folder_defaults.error_handler {
+        Some(folder_error_handler) => (
+            Some(folder_error_handler),
+            folder_defaults.error_handler_extra_args,
+        ),
+        None => (error_handler, error_handler_extra_args),
+    };
+
     if let Some(error_handler) = error_handler {
         let ws_error_handler_muted: Option<bool> = match queued_job.job_kind {
             JobKind::Script => {
@@ -810,7 +834,8 @@ pub async fn send_error_to_workspace_handler<
             _ => None,
         };
 
-        let muted = ws_error_handler_muted.unwrap_or(false);
+        let muted = ws_error_handler_muted.unwrap_or(false)
+            || folder_defaults.ws_error_handler_muted.unwrap_or(false);
         if !muted {
             tracing::info!("workspace error handled for job {}", &queued_job.id);
             run_error_handler(
@@ -829,6 +854,69 @@ pub async fn send_error_to_workspace_handler<
     Ok(())
 }
 
+/// Sends to the workspace error handler an error that did not happen in a job, e.g. a broken
+/// resource connection. The handler receives the same arguments as for the errors of jobs, with
+/// `extra` in place of the arguments describing the job.
+pub async fn send_event_error_to_workspace_handler<R: rsmq_async::RsmqConnection + Clone + Send>(
+    rsmq: Option<R>,
+    db: &Pool<Postgres>,
+    w_id: &str,
+    mut extra: HashMap<String, Box<RawValue>>,
+    error: serde_json::Value,
+) -> Result<Option<Uuid>, Error> {
+    let (error_handler, error_handler_extra_args) =
+        sqlx::query_as::<_, (Option<String>, Option<serde_json::Value>)>(
+            "SELECT error_handler, error_handler_extra_args FROM workspace_settings \
+             WHERE workspace_id = $1",
+        )
+        .bind(w_id)
+        .fetch_optional(db)
+        .await?
+        .unwrap_or((None, None));
+    let Some(error_handler) = error_handler else {
+        return Ok(None);
+    };
+
+    let (job_payload, tag) = get_payload_tag_from_prefixed_path(&error_handler, db, w_id).await?;
+    extra.insert("workspace_id".to_string(), to_raw_value(&w_id));
+    if let Some(serde_json::Value::Object(args_m)) = error_handler_extra_args {
+        for (k, v) in args_m {
+            extra.insert(k, to_raw_value(&v));
+        }
+    }
+    let result = json!({ "error": error });
+
+    let tx = PushIsolationLevel::IsolatedRoot(db.clone(), rsmq);
+    let (uuid, tx) = push(
+        &db,
+        tx,
+        w_id,
+        job_payload,
+        PushArgs { extra, args: Json(&result) },
+        ERROR_HANDLER_USERNAME,
+        ERROR_HANDLER_USER_EMAIL,
+        ERROR_HANDLER_USER_GROUP.to_string(),
+        None,
+        None,
+        None,
+        None,
+        None,
+        false,
+        false,
+        None,
+        true,
+        tag,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+
+    tracing::info!("Sent error to workspace error handler of {w_id} under uuid {uuid}");
+    Ok(Some(uuid))
+}
+
 #[instrument(level = "trace", skip_all)]
 pub async fn handle_maybe_scheduled_job<'c, R: rsmq_async::RsmqConnection + Clone + Send + 'c>(
     mut tx: QueueTransaction<'c, R>,
@@ -2605,6 +2693,33 @@ pub async fn push<'c, T: Serialize + Send + Sync, R: rsmq_async::RsmqConnection
         ),
     };
 
+    // schedules are pushed again when their previous job completes and failing that disables
+    // them, and the handlers must keep reporting failures once a quota is reached
+    let is_schedule_or_handler = schedule_path.is_some()
+        || [
+            ERROR_HANDLER_USER_EMAIL,
+            SUPERADMIN_SECRET_EMAIL,
+            SUPERADMIN_NOTIFICATION_EMAIL,
+        ]
+        .contains(&email);
+    if !is_quota_exempt(&job_kind, is_flow_step, is_schedule_or_handler) {
+        check_usage_quotas(_db, workspace_id).await?;
+    }
+
+    // settings left unset on the script or flow are inherited from its folder
+    let folder_defaults = match (&job_kind, script_path.as_ref()) {
+        (JobKind::Script | JobKind::Preview | JobKind::Flow | JobKind::FlowPreview, Some(path)) => {
+            get_folder_defaults(_db, workspace_id, path).await?
+        }
+        _ => None,
+    }
+    .unwrap_or_default();
+    tag = tag.filter(|t| !t.is_empty()).or(folder_defaults.tag);
+    let custom_timeout = custom_timeout.or(folder_defaults.timeout);
+    let concurrent_limit = concurrent_limit.or(folder_defaults.concurrent_limit);
+    let concurrency_time_window_s =
+        concurrency_time_window_s.or(folder_defaults.concurrency_time_window_s);
+
     let final_priority: Option<i16>;
     #[cfg(not(feature = "enterprise"))]
     {
@@ -9,6 +9,7 @@
 mod jobs;
 mod queue_transaction;
 pub mod schedule;
+pub mod usage;
 
 pub use jobs::*;
 pub use queue_transaction::*;
@@ -17,6 +17,7 @@ use windmill_common::DB;
 use windmill_common::{
     error::{self, Result},
     schedule::Schedule,
+    service_accounts::{get_flow_run_as, get_schedule_run_as},
     users::username_to_permissioned_as,
     utils::{now_from_db, StripPath},
 };
@@ -71,6 +72,18 @@ pub async fn push_scheduled_job<'c, R: rsmq_async::RsmqConnection + Send + 'c>(
         }
     }
 
+    // the schedule service account takes precedence over the one of the flow it runs
+    let mut run_as = get_schedule_run_as(&mut tx, &schedule.workspace_id, &schedule.path).await?;
+    if run_as.is_none() && schedule.is_flow {
+        run_as = get_flow_run_as(&mut tx, &schedule.workspace_id, &schedule.script_path).await?;
+    }
+    let (permissioned_as, email) = run_as.unwrap_or_else(|| {
+        (
+            username_to_permissioned_as(&schedule.edited_by),
+            schedule.email.clone(),
+        )
+    });
+
     let (payload, tag) = if schedule.is_flow {
         let r = sqlx::query!(
             "SELECT tag, dedicated_worker from flow WHERE path = $1 and workspace_id = $2",
@@ -132,8 +145,8 @@ pub async fn push_scheduled_job<'c, R: rsmq_async::RsmqConnection + Send + 'c>(
         payload,
         args,
         &schedule_to_user(&schedule.path),
-        &schedule.email,
-        username_to_permissioned_as(&schedule.edited_by),
+        &email,
+        permissioned_as,
         Some(next),
         Some(schedule.path.clone()),
         None,
@@ -0,0 +1,217 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use sqlx::{Pool, Postgres};
+use tokio::sync::RwLock;
+use windmill_common::{
+    error::{self, Error},
+    jobs::{JobKind, QueuedJob},
+    usage::{UsagePeriod, UsageQuotas, WorkspaceUsage},
+};
+
+/// Quotas and the usage they are compared to are cached so that pushing jobs does not require
+/// aggregating the rollup table every time.
+const QUOTA_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(10);
+
+struct QuotaCacheEntry {
+    quotas: Option<UsageQuotas>,
+    usage: WorkspaceUsage,
+    expires_at: std::time::Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref QUOTA_CACHE: Arc<RwLock<HashMap<String, QuotaCacheEntry>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+pub async fn get_usage_quotas(
+    db: &Pool<Postgres>,
+    w_id: &str,
+) -> error::Result<Option<UsageQuotas>> {
+    let quotas = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT usage_quotas FROM workspace_settings WHERE workspace_id = $1",
+    )
+    .bind(w_id)
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    quotas
+        .map(serde_json::from_value::<UsageQuotas>)
+        .transpose()
+        .map_err(|e| Error::InternalErr(format!("invalid usage quotas for {w_id}: {e}")))
+}
+
+pub async fn get_workspace_usage(
+    db: &Pool<Postgres>,
+    w_id: &str,
+    period: UsagePeriod,
+) -> error::Result<WorkspaceUsage> {
+    let usage = sqlx::query_as::<_, WorkspaceUsage>(
+        "SELECT (COALESCE(SUM(execution_ms), 0)::BIGINT / 1000) AS execution_seconds, \
+         COALESCE(SUM(job_count), 0)::BIGINT AS jobs \
+         FROM job_usage_rollup \
+         WHERE workspace_id = $1 AND day >= date_trunc($2, now())::DATE",
+    )
+    .bind(w_id)
+    .bind(period.as_trunc_unit())
+    .fetch_one(db)
+    .await?;
+    Ok(usage)
+}
+
+pub async fn invalidate_quota_cache(w_id: &str) {
+    QUOTA_CACHE.write().await.remove(w_id);
+}
+
+/// Jobs never blocked by the hard quotas: flow steps and dependency jobs so that already started
+/// flows and deployments can complete, schedule re-pushes since failing them disables the schedule
+/// for good, and the jobs of the error, recovery and notification handlers so that failures are
+/// still reported.
+pub fn is_quota_exempt(
+    job_kind: &JobKind,
+    is_flow_step: bool,
+    is_schedule_or_handler: bool,
+) -> bool {
+    is_flow_step
+        || is_schedule_or_handler
+        || matches!(
+            job_kind,
+            JobKind::Dependencies
+                | JobKind::FlowDependencies
+                | JobKind::AppDependencies
+                | JobKind::DeploymentCallback
+        )
+}
+
+/// Fails if the workspace went over one of its hard quotas for the current period
+pub async fn check_usage_quotas(db: &Pool<Postgres>, w_id: &str) -> error::Result<()> {
+    let cached = QUOTA_CACHE
+        .read()
+        .await
+        .get(w_id)
+        .filter(|e| e.expires_at > std::time::Instant::now())
+        .map(|e| (e.quotas.clone(), e.usage.clone()));
+
+    let (quotas, usage) = match cached {
+        Some(cached) => cached,
+        None => {
+            let quotas = get_usage_quotas(db, w_id).await?;
+            let usage = match quotas.as_ref() {
+                Some(q) => get_workspace_usage(db, w_id, q.period).await?,
+                None => WorkspaceUsage::default(),
+            };
+            QUOTA_CACHE.write().await.insert(
+                w_id.to_string(),
+                QuotaCacheEntry {
+                    quotas: quotas.clone(),
+                    usage: usage.clone(),
+                    expires_at: std::time::Instant::now() + QUOTA_CACHE_TTL,
+                },
+            );
+            (quotas, usage)
+        }
+    };
+
+    let quotas = if let Some(quotas) = quotas {
+        quotas
+    } else {
+        return Ok(());
+    };
+
+    if let Some(reason) = quotas.hard.exceeded(&usage) {
+        return Err(Error::BadRequest(format!(
+            "Workspace {w_id} exceeded its {:?} hard usage quota ({reason}). Contact a workspace admin to raise it.",
+            quotas.period
+        )));
+    }
+    if let Some(reason) = quotas.soft.exceeded(&usage) {
+        tracing::warn!(
+            workspace_id = %w_id,
+            "Workspace exceeded its {:?} soft usage quota: {reason}",
+            quotas.period
+        );
+    }
+
+    Ok(())
+}
+
+/// Flows are only counted as jobs: the execution time and memory of their steps is already
+/// accounted for when each step completes. Steps are not counted as jobs, a flow run counts as a
+/// single job as it does for the quotas.
+pub async fn record_job_usage<'c, E: sqlx::Executor<'c, Database = Postgres>>(
+    executor: E,
+    queued_job: &QueuedJob,
+    success: bool,
+    duration_ms: i64,
+    mem_peak: i32,
+) -> error::Result<()> {
+    let is_flow = matches!(queued_job.job_kind, JobKind::Flow | JobKind::FlowPreview);
+    let (duration_ms, mem_peak) = if is_flow {
+        (0, 0)
+    } else {
+        (duration_ms.max(0), mem_peak.max(0))
+    };
+    let job_count = if queued_job.is_flow_step { 0i64 } else { 1 };
+    let failed_count = if success { 0i64 } else { job_count };
+
+    sqlx::query(
+        "INSERT INTO job_usage_rollup (workspace_id, day, username, runnable_path, job_count, failed_count, execution_ms, mem_peak) \
+         VALUES ($1, current_date, $2, $3, $4, $5, $6, $7) \
+         ON CONFLICT (workspace_id, day, username, runnable_path) DO UPDATE SET \
+         job_count = job_usage_rollup.job_count + EXCLUDED.job_count, \
+         failed_count = job_usage_rollup.failed_count + EXCLUDED.failed_count, \
+         execution_ms = job_usage_rollup.execution_ms + EXCLUDED.execution_ms, \
+         mem_peak = GREATEST(job_usage_rollup.mem_peak, EXCLUDED.mem_peak)",
+    )
+    .bind(&queued_job.workspace_id)
+    .bind(&queued_job.created_by)
+    .bind(queued_job.script_path.as_deref().unwrap_or(""))
+    .bind(job_count)
+    .bind(failed_count)
+    .bind(duration_ms)
+    .bind(mem_peak)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windmill_common::usage::UsageLimits;
+
+    #[test]
+    fn test_quota_exempt_jobs() {
+        assert!(!is_quota_exempt(&JobKind::Script, false, false));
+        assert!(!is_quota_exempt(&JobKind::Flow, false, false));
+        assert!(is_quota_exempt(&JobKind::Script, true, false));
+        assert!(is_quota_exempt(&JobKind::Flow, false, true));
+        assert!(is_quota_exempt(&JobKind::Dependencies, false, false));
+        assert!(is_quota_exempt(&JobKind::DeploymentCallback, false, false));
+    }
+
+    #[test]
+    fn test_usage_limits_exceeded() {
+        let limits = UsageLimits { execution_seconds: Some(60), jobs: Some(10) };
+        assert!(limits
+            .exceeded(&WorkspaceUsage { execution_seconds: 59, jobs: 9 })
+            .is_none());
+        assert!(limits
+            .exceeded(&WorkspaceUsage { execution_seconds: 60, jobs: 0 })
+            .is_some());
+        assert!(limits
+            .exceeded(&WorkspaceUsage { execution_seconds: 0, jobs: 10 })
+            .is_some());
+        assert!(UsageLimits::default()
+            .exceeded(&WorkspaceUsage { execution_seconds: i64::MAX, jobs: i64::MAX })
+            .is_none());
+    }
+}
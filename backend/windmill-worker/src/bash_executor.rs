@@ -5,7 +5,9 @@ use regex::Regex;
 use serde_json::{json, value::RawValue};
 use sqlx::types::Json;
 use tokio::process::Command;
-use windmill_common::{error::Error, jobs::QueuedJob, worker::to_raw_value};
+use uuid::Uuid;
+use windmill_common::{error::Error, jobs::QueuedJob, utils::calculate_hash, worker::to_raw_value};
+use windmill_parser_bash::{OutputField, OutputFormat, OutputSchema, OutputType};
 use windmill_queue::CanceledBy;
 
 const BIN_BASH: &str = "/bin/bash";
@@ -16,13 +18,170 @@ use crate::{
         build_args_map, get_reserved_variables, handle_child, read_file, read_file_content,
         set_logs, start_child_process, write_file,
     },
-    AuthedClientBackgroundTask, DISABLE_NSJAIL, DISABLE_NUSER, HOME_ENV, NSJAIL_PATH, PATH_ENV,
-    TZ_ENV,
+    network_policy::JobNetwork,
+    AuthedClientBackgroundTask, BASH_CACHE_DIR, DISABLE_NSJAIL, DISABLE_NUSER, HOME_ENV,
+    NSJAIL_PATH, PATH_ENV, POWERSHELL_CACHE_DIR, TZ_ENV,
 };
 
 lazy_static::lazy_static! {
 
     pub static ref ANSI_ESCAPE_RE: Regex = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+
+    static ref POWERSHELL_PATH: String = std::env::var("POWERSHELL_PATH").unwrap_or_else(|_| "/usr/bin/pwsh".to_string());
+    static ref NIX_BIN_PATH: String = std::env::var("NIX_BIN_PATH").unwrap_or_else(|_| "nix".to_string());
+    // pin the nixpkgs revision bash requirements are resolved against, the registry one otherwise
+    static ref NIXPKGS_REV: Option<String> = std::env::var("NIXPKGS_REV").ok();
+
+    static ref RE_DEPENDENCY_PART: Regex = Regex::new(r"^[\w.+\-]+$").unwrap();
+}
+
+/// Resolve the `# requirements:` header of a bash script against a pinned nixpkgs revision. The
+/// lock is the `nixpkgs:<rev>` line followed by one `<tool>==<version>` line per requirement
+pub async fn resolve_bash_requirements(
+    job_id: &Uuid,
+    code: &str,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    worker_name: &str,
+    w_id: &str,
+) -> Result<String, Error> {
+    let requirements = windmill_parser_bash::parse_bash_requirements(code);
+    if requirements.is_empty() {
+        return Ok(String::new());
+    }
+    let nix = NIX_BIN_PATH.as_str();
+    let tools = requirements.iter().map(|r| r.name.as_str()).join(" ");
+    write_file(
+        job_dir,
+        "resolve.sh",
+        &format!(
+            r#"set -e
+nix() {{ "{nix}" --extra-experimental-features "nix-command flakes" "$@"; }}
+rev="{}"
+if [ -z "$rev" ]; then
+    rev=$(nix eval --impure --raw --expr '(builtins.getFlake "nixpkgs").rev')
+fi
+echo "nixpkgs:$rev" > lock.txt
+for tool in {tools}; do
+    echo "$tool==$(nix eval --raw "nixpkgs/$rev#$tool.version")" >> lock.txt
+done
+"#,
+            NIXPKGS_REV.clone().unwrap_or_default()
+        ),
+    )
+    .await?;
+    run_dependency_command(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        job_dir,
+        worker_name,
+        w_id,
+        BIN_BASH,
+        vec!["resolve.sh"],
+        "bash requirements resolve",
+    )
+    .await?;
+
+    let lock = read_file_content(&format!("{job_dir}/lock.txt")).await?;
+    let lock = lock
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .join("\n");
+    for requirement in requirements.iter() {
+        if let Some(version) = requirement.version.as_ref() {
+            let resolved = lock
+                .lines()
+                .find_map(|x| x.strip_prefix(&format!("{}==", requirement.name)))
+                .unwrap_or_default();
+            if resolved != version {
+                return Err(Error::ExecutionErr(format!(
+                    "{}=={version} is not available in the pinned nixpkgs, it provides version {resolved}",
+                    requirement.name
+                )));
+            }
+        }
+    }
+    Ok(lock)
+}
+
+/// Build the tools of a bash lock into a nix profile shared by all the scripts with the same lock
+/// and return its directory
+async fn prepare_bash_requirements(
+    job_id: &Uuid,
+    lock: &str,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    worker_name: &str,
+    w_id: &str,
+) -> Result<Option<String>, Error> {
+    let mut lines = lock.lines().map(|x| x.trim()).filter(|x| !x.is_empty());
+    let rev = match lines.next() {
+        Some(first) => first.strip_prefix("nixpkgs:").ok_or_else(|| {
+            Error::ExecutionErr(format!(
+                "Invalid bash lock, expected nixpkgs:<rev>, got {first}"
+            ))
+        })?,
+        None => return Ok(None),
+    };
+    let mut installables = vec![];
+    for line in lines {
+        let tool = line.split_once("==").map(|(tool, _)| tool).unwrap_or(line);
+        if !RE_DEPENDENCY_PART.is_match(tool) || !RE_DEPENDENCY_PART.is_match(rev) {
+            return Err(Error::ExecutionErr(format!(
+                "Invalid bash lock line: {line}"
+            )));
+        }
+        installables.push(format!("nixpkgs/{rev}#{tool}"));
+    }
+
+    let env_dir = format!("{BASH_CACHE_DIR}/{}", calculate_hash(lock));
+    if tokio::fs::metadata(format!("{env_dir}/profile"))
+        .await
+        .is_err()
+    {
+        logs.push_str("\n\n--- BASH REQUIREMENTS INSTALL ---\n");
+        set_logs(logs, job_id, db).await;
+        tokio::fs::create_dir_all(&env_dir).await?;
+        let profile = format!("{env_dir}/profile");
+        let mut args = vec![
+            "--extra-experimental-features",
+            "nix-command flakes",
+            "profile",
+            "install",
+            "--profile",
+            &profile,
+        ];
+        args.extend(installables.iter().map(|x| x.as_str()));
+        if let Err(e) = run_dependency_command(
+            job_id,
+            db,
+            logs,
+            mem_peak,
+            canceled_by,
+            job_dir,
+            worker_name,
+            w_id,
+            NIX_BIN_PATH.as_str(),
+            args,
+            "bash requirements install",
+        )
+        .await
+        {
+            let _ = tokio::fs::remove_dir_all(&env_dir).await;
+            return Err(e);
+        }
+    }
+    Ok(Some(env_dir))
 }
 
 #[tracing::instrument(level = "trace", skip_all)]
@@ -34,20 +193,61 @@ pub async fn handle_bash_job(
     db: &sqlx::Pool<sqlx::Postgres>,
     client: &AuthedClientBackgroundTask,
     content: &str,
+    requirements_o: Option<String>,
     job_dir: &str,
     shared_mount: &str,
+    network: &JobNetwork,
     base_internal_url: &str,
     worker_name: &str,
     envs: HashMap<String, String>,
 ) -> Result<Box<RawValue>, Error> {
-    logs.push_str("\n\n--- BASH CODE EXECUTION ---\n");
-    set_logs(logs, &job.id, db).await;
-    write_file(
+    let lock = match requirements_o {
+        Some(lock) if !lock.is_empty() => lock,
+        _ => {
+            resolve_bash_requirements(
+                &job.id,
+                content,
+                logs,
+                mem_peak,
+                canceled_by,
+                job_dir,
+                db,
+                worker_name,
+                &job.workspace_id,
+            )
+            .await?
+        }
+    };
+    let requirements_env = prepare_bash_requirements(
+        &job.id,
+        &lock,
+        logs,
+        mem_peak,
+        canceled_by,
         job_dir,
-        "main.sh",
-        &format!("set -e\n{content}\necho \"\"\nsleep 0.02"),
+        db,
+        worker_name,
+        &job.workspace_id,
     )
     .await?;
+    let path_env = requirements_env
+        .as_ref()
+        .map(|env_dir| format!("{env_dir}/profile/bin:{}", *PATH_ENV))
+        .unwrap_or_else(|| PATH_ENV.to_string());
+
+    let output_schema = windmill_parser_bash::parse_output_schema(content)?;
+
+    logs.push_str("\n\n--- BASH CODE EXECUTION ---\n");
+    set_logs(logs, &job.id, db).await;
+    let main = if output_schema.is_some() {
+        // stdout is still streamed to the logs, and kept aside to be parsed as the result
+        format!(
+            "set -e\nset -o pipefail\n{{\n{content}\n}} | tee result.stdout\necho \"\"\nsleep 0.02"
+        )
+    } else {
+        format!("set -e\n{content}\necho \"\"\nsleep 0.02")
+    };
+    write_file(job_dir, "main.sh", &main).await?;
     let token = client.get_token().await;
     let mut reserved_variables = get_reserved_variables(job, &token, db).await?;
     reserved_variables.insert("RUST_LOG".to_string(), "info".to_string());
@@ -71,6 +271,7 @@ pub async fn handle_bash_job(
     let args = args_owned.iter().map(|s| &s[..]).collect::<Vec<&str>>();
     let _ = write_file(job_dir, "result.json", "").await?;
     let _ = write_file(job_dir, "result.out", "").await?;
+    let _ = write_file(job_dir, "result.stdout", "").await?;
 
     let child = if !*DISABLE_NSJAIL {
         let _ = write_file(
@@ -79,19 +280,51 @@ pub async fn handle_bash_job(
             &NSJAIL_CONFIG_RUN_BASH_CONTENT
                 .replace("{JOB_DIR}", job_dir)
                 .replace("{CLONE_NEWUSER}", &(!*DISABLE_NUSER).to_string())
-                .replace("{SHARED_MOUNT}", shared_mount),
+                .replace("{CLONE_NEWNET}", &network.clone_newnet().to_string())
+                .replace("{SHARED_MOUNT}", shared_mount)
+                .replace("{NETWORK_MOUNTS}", &network.nsjail_mounts())
+                .replace(
+                    "{SHARED_DEPENDENCIES}",
+                    &requirements_env
+                        .as_ref()
+                        .map(|env_dir| {
+                            format!(
+                                r#"
+mount {{
+    src: "/nix/store"
+    dst: "/nix/store"
+    is_bind: true
+    rw: false
+}}
+
+mount {{
+    src: "{env_dir}"
+    dst: "{env_dir}"
+    is_bind: true
+    rw: false
+}}
+"#
+                            )
+                        })
+                        .unwrap_or_default(),
+                ),
         )
         .await?;
-        let mut cmd_args = vec!["--config", "run.config.proto", "--", "/bin/bash", "main.sh"];
+        let mut cmd_args = vec!["/bin/bash", "main.sh"];
         cmd_args.extend(args);
         let mut nsjail_cmd = Command::new(NSJAIL_PATH.as_str());
         nsjail_cmd
             .current_dir(job_dir)
             .env_clear()
             .envs(reserved_variables)
-            .env("PATH", PATH_ENV.as_str())
-            .env("BASE_INTERNAL_URL", base_internal_url)
-            .args(cmd_args)
+            .envs(network.proxy_envs())
+            .env("PATH", path_env.as_str())
+            .env(
+                "BASE_INTERNAL_URL",
+                network.base_internal_url(base_internal_url),
+            )
+            .args(["--config", "run.config.proto", "--"])
+            .args(network.nsjail_command(&cmd_args))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
         start_child_process(nsjail_cmd, NSJAIL_PATH.as_str()).await?
@@ -104,7 +337,7 @@ pub async fn handle_bash_job(
             .env_clear()
             .envs(envs)
             .envs(reserved_variables)
-            .env("PATH", PATH_ENV.as_str())
+            .env("PATH", path_env.as_str())
             .env("BASE_INTERNAL_URL", base_internal_url)
             .env("HOME", HOME_ENV.as_str())
             .args(cmd_args)
@@ -128,6 +361,10 @@ pub async fn handle_bash_job(
     )
     .await?;
 
+    if let Some(output_schema) = output_schema.as_ref() {
+        return read_typed_result(job_dir, output_schema).await;
+    }
+
     let result_json_path = format!("{job_dir}/result.json");
     if let Ok(metadata) = tokio::fs::metadata(&result_json_path).await {
         if metadata.len() > 0 {
@@ -159,6 +396,191 @@ fn raw_to_string(x: &str) -> String {
         _ => String::new(),
     }
 }
+fn powershell_module_specs<'a>(
+    modules: impl Iterator<Item = (&'a str, Option<&'a str>)>,
+) -> String {
+    modules
+        .map(|(name, version)| {
+            format!(
+                "@{{ Name = '{name}'; Version = {} }}",
+                version
+                    .map(|v| format!("'{v}'"))
+                    .unwrap_or_else(|| "$null".to_string())
+            )
+        })
+        .join(", ")
+}
+
+async fn run_dependency_command(
+    job_id: &Uuid,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    worker_name: &str,
+    w_id: &str,
+    executable: &str,
+    args: Vec<&str>,
+    child_name: &str,
+) -> Result<(), Error> {
+    let mut cmd = Command::new(executable);
+    cmd.current_dir(job_dir)
+        .env_clear()
+        .env("PATH", PATH_ENV.as_str())
+        .env("HOME", HOME_ENV.as_str())
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = start_child_process(cmd, executable).await?;
+    handle_child(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        child,
+        false,
+        worker_name,
+        w_id,
+        child_name,
+        None,
+        false,
+    )
+    .await
+}
+
+/// Resolve the gallery modules imported by a powershell script to the lines `<module>==<version>`
+/// of its lock, transitive dependencies included
+pub async fn resolve_powershell_modules(
+    job_id: &Uuid,
+    code: &str,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    worker_name: &str,
+    w_id: &str,
+) -> Result<String, Error> {
+    let modules = windmill_parser_bash::parse_powershell_imports(code)?;
+    if modules.is_empty() {
+        return Ok(String::new());
+    }
+    let specs = powershell_module_specs(
+        modules
+            .iter()
+            .map(|m| (m.name.as_str(), m.version.as_deref())),
+    );
+    write_file(
+        job_dir,
+        "resolve.ps1",
+        &format!(
+            r#"$ErrorActionPreference = 'Stop'
+$ProgressPreference = 'SilentlyContinue'
+$locked = foreach ($m in @({specs})) {{
+    $found = if ($m.Version) {{
+        Find-Module -Name $m.Name -RequiredVersion $m.Version -Repository PSGallery -IncludeDependencies
+    }} else {{
+        Find-Module -Name $m.Name -Repository PSGallery -IncludeDependencies
+    }}
+    $found | ForEach-Object {{ "$($_.Name)==$($_.Version)" }}
+}}
+$locked | Sort-Object -Unique | Set-Content -Path lock.txt
+"#
+        ),
+    )
+    .await?;
+    run_dependency_command(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        job_dir,
+        worker_name,
+        w_id,
+        POWERSHELL_PATH.as_str(),
+        vec!["-NoProfile", "-NonInteractive", "-File", "resolve.ps1"],
+        "powershell resolve",
+    )
+    .await?;
+
+    let lock = read_file_content(&format!("{job_dir}/lock.txt")).await?;
+    Ok(lock
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .join("\n"))
+}
+
+/// Save the locked modules missing from the shared powershell cache, which is laid out like a
+/// PSModulePath entry: `<cache>/<module>/<version>`
+async fn install_powershell_modules(
+    job_id: &Uuid,
+    lock: &str,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    worker_name: &str,
+    w_id: &str,
+) -> Result<(), Error> {
+    let mut missing = vec![];
+    for line in lock.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let (name, version) = line
+            .split_once("==")
+            .ok_or_else(|| Error::ExecutionErr(format!("Invalid powershell lock line: {line}")))?;
+        if !RE_DEPENDENCY_PART.is_match(name) || !RE_DEPENDENCY_PART.is_match(version) {
+            return Err(Error::ExecutionErr(format!(
+                "Invalid powershell lock line: {line}"
+            )));
+        }
+        if tokio::fs::metadata(format!("{POWERSHELL_CACHE_DIR}/{name}/{version}"))
+            .await
+            .is_err()
+        {
+            missing.push((name, Some(version)));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    logs.push_str("\n\n--- POWERSHELL MODULES INSTALL ---\n");
+    set_logs(logs, job_id, db).await;
+    let specs = powershell_module_specs(missing.into_iter());
+    write_file(
+        job_dir,
+        "install.ps1",
+        &format!(
+            r#"$ErrorActionPreference = 'Stop'
+$ProgressPreference = 'SilentlyContinue'
+foreach ($m in @({specs})) {{
+    Write-Output "saving $($m.Name) $($m.Version)"
+    Save-Module -Name $m.Name -RequiredVersion $m.Version -Path '{POWERSHELL_CACHE_DIR}' -Repository PSGallery -Force
+}}
+"#
+        ),
+    )
+    .await?;
+    run_dependency_command(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        job_dir,
+        worker_name,
+        w_id,
+        POWERSHELL_PATH.as_str(),
+        vec!["-NoProfile", "-NonInteractive", "-File", "install.ps1"],
+        "powershell install",
+    )
+    .await
+}
+
 #[tracing::instrument(level = "trace", skip_all)]
 pub async fn handle_powershell_job(
     logs: &mut String,
@@ -168,12 +590,46 @@ pub async fn handle_powershell_job(
     db: &sqlx::Pool<sqlx::Postgres>,
     client: &AuthedClientBackgroundTask,
     content: &str,
+    requirements_o: Option<String>,
     job_dir: &str,
     shared_mount: &str,
+    network: &JobNetwork,
     base_internal_url: &str,
     worker_name: &str,
     envs: HashMap<String, String>,
 ) -> Result<Box<RawValue>, Error> {
+    let lock = match requirements_o {
+        Some(lock) if !lock.is_empty() => lock,
+        _ => {
+            resolve_powershell_modules(
+                &job.id,
+                content,
+                logs,
+                mem_peak,
+                canceled_by,
+                job_dir,
+                db,
+                worker_name,
+                &job.workspace_id,
+            )
+            .await?
+        }
+    };
+    install_powershell_modules(
+        &job.id,
+        &lock,
+        logs,
+        mem_peak,
+        canceled_by,
+        job_dir,
+        db,
+        worker_name,
+        &job.workspace_id,
+    )
+    .await?;
+
+    let output_schema = windmill_parser_bash::parse_output_schema(content)?;
+
     logs.push_str("\n\n--- POWERSHELL CODE EXECUTION ---\n");
     set_logs(logs, &job.id, db).await;
     let pwsh_args = {
@@ -206,13 +662,19 @@ pub async fn handle_powershell_job(
         .replace('$', r"\$") // escape powershell variables
         .replace("`", r"\`"); // escape powershell backticks
 
-    write_file(job_dir, "main.sh", &format!("set -e\ncat > script.ps1 << EOF\n{content}\nEOF\npwsh -File script.ps1 {pwsh_args}\necho \"\"\nsleep 0.02")).await?;
+    let run_pwsh = if output_schema.is_some() {
+        format!("set -o pipefail\npwsh -File script.ps1 {pwsh_args} | tee result.stdout")
+    } else {
+        format!("pwsh -File script.ps1 {pwsh_args}")
+    };
+    write_file(job_dir, "main.sh", &format!("set -e\ncat > script.ps1 << EOF\n{content}\nEOF\nexport PSModulePath=\"{POWERSHELL_CACHE_DIR}:$PSModulePath\"\n{run_pwsh}\necho \"\"\nsleep 0.02")).await?;
     let token = client.get_token().await;
     let mut reserved_variables = get_reserved_variables(job, &token, db).await?;
     reserved_variables.insert("RUST_LOG".to_string(), "info".to_string());
 
     let _ = write_file(job_dir, "result.json", "").await?;
     let _ = write_file(job_dir, "result.out", "").await?;
+    let _ = write_file(job_dir, "result.stdout", "").await?;
 
     let child = if !*DISABLE_NSJAIL {
         let _ = write_file(
@@ -221,18 +683,37 @@ pub async fn handle_powershell_job(
             &NSJAIL_CONFIG_RUN_BASH_CONTENT
                 .replace("{JOB_DIR}", job_dir)
                 .replace("{CLONE_NEWUSER}", &(!*DISABLE_NUSER).to_string())
-                .replace("{SHARED_MOUNT}", shared_mount),
+                .replace("{CLONE_NEWNET}", &network.clone_newnet().to_string())
+                .replace("{SHARED_MOUNT}", shared_mount)
+                .replace("{NETWORK_MOUNTS}", &network.nsjail_mounts())
+                .replace(
+                    "{SHARED_DEPENDENCIES}",
+                    &format!(
+                        r#"
+mount {{
+    src: "{POWERSHELL_CACHE_DIR}"
+    dst: "{POWERSHELL_CACHE_DIR}"
+    is_bind: true
+    rw: false
+}}
+"#
+                    ),
+                ),
         )
         .await?;
-        let cmd_args = vec!["--config", "run.config.proto", "--", "/bin/bash", "main.sh"];
         Command::new(NSJAIL_PATH.as_str())
             .current_dir(job_dir)
             .env_clear()
             .envs(reserved_variables)
+            .envs(network.proxy_envs())
             .env("TZ", TZ_ENV.as_str())
             .env("PATH", PATH_ENV.as_str())
-            .env("BASE_INTERNAL_URL", base_internal_url)
-            .args(cmd_args)
+            .env(
+                "BASE_INTERNAL_URL",
+                network.base_internal_url(base_internal_url),
+            )
+            .args(["--config", "run.config.proto", "--"])
+            .args(network.nsjail_command(&["/bin/bash", "main.sh"]))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?
@@ -268,6 +749,10 @@ pub async fn handle_powershell_job(
     )
     .await?;
 
+    if let Some(output_schema) = output_schema.as_ref() {
+        return read_typed_result(job_dir, output_schema).await;
+    }
+
     let last_line = serde_json::json!(logs
         .lines()
         .last()
@@ -275,3 +760,229 @@ pub async fn handle_powershell_job(
         .unwrap_or_else(String::new));
     Ok(to_raw_value(&last_line))
 }
+
+fn output_type_name(typ: OutputType) -> &'static str {
+    match typ {
+        OutputType::String => "a string",
+        OutputType::Integer => "an integer",
+        OutputType::Number => "a number",
+        OutputType::Boolean => "a boolean",
+        OutputType::Object => "an object",
+        OutputType::Array => "an array",
+    }
+}
+
+fn matches_output_type(typ: OutputType, value: &serde_json::Value) -> bool {
+    match typ {
+        OutputType::String => value.is_string(),
+        OutputType::Integer => value.is_i64() || value.is_u64(),
+        OutputType::Number => value.is_number(),
+        OutputType::Boolean => value.is_boolean(),
+        OutputType::Object => value.is_object(),
+        OutputType::Array => value.is_array(),
+    }
+}
+
+/// Value of a field of a csv or key=value output, which are always read as text
+fn coerce_output_value(typ: OutputType, raw: &str) -> Option<serde_json::Value> {
+    let trimmed = raw.trim();
+    match typ {
+        OutputType::String => Some(json!(raw)),
+        OutputType::Integer => trimmed.parse::<i64>().ok().map(|x| json!(x)),
+        OutputType::Number => trimmed
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(serde_json::Value::Number),
+        OutputType::Boolean => match trimmed.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Some(json!(true)),
+            "false" | "no" | "0" => Some(json!(false)),
+            _ => None,
+        },
+        OutputType::Object | OutputType::Array => serde_json::from_str(trimmed)
+            .ok()
+            .filter(|x| matches_output_type(typ, x)),
+    }
+}
+
+fn coerce_output_fields(
+    fields: &[OutputField],
+    mut object: serde_json::Map<String, serde_json::Value>,
+    ctx: &str,
+) -> Result<serde_json::Value, String> {
+    for field in fields {
+        let raw = match object.get(&field.name) {
+            Some(serde_json::Value::String(raw))
+                if !raw.trim().is_empty() || field.typ == OutputType::String =>
+            {
+                raw.clone()
+            }
+            _ => {
+                object.remove(&field.name);
+                if field.required {
+                    return Err(format!("{ctx}missing required field `{}`", field.name));
+                }
+                continue;
+            }
+        };
+        let value = coerce_output_value(field.typ, &raw).ok_or_else(|| {
+            format!(
+                "{ctx}field `{}` is not {}: `{raw}`",
+                field.name,
+                output_type_name(field.typ)
+            )
+        })?;
+        object.insert(field.name.clone(), value);
+    }
+    Ok(serde_json::Value::Object(object))
+}
+
+fn check_output_fields(
+    fields: &[OutputField],
+    value: &serde_json::Value,
+    ctx: &str,
+) -> Result<(), String> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+    let object = value
+        .as_object()
+        .ok_or_else(|| format!("{ctx}the result is not an object"))?;
+    for field in fields {
+        match object.get(&field.name) {
+            None | Some(serde_json::Value::Null) if field.required => {
+                return Err(format!("{ctx}missing required field `{}`", field.name))
+            }
+            Some(value) if !value.is_null() && !matches_output_type(field.typ, value) => {
+                return Err(format!(
+                    "{ctx}field `{}` is not {}: `{value}`",
+                    field.name,
+                    output_type_name(field.typ)
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Rows of a csv document, with quoted fields possibly containing commas, quotes and newlines
+fn parse_csv(content: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c != '"' {
+                field.push(c);
+            } else if chars.peek() == Some(&'"') {
+                field.push('"');
+                chars.next();
+            } else {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("unterminated quoted csv field".to_string());
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn parse_typed_output(schema: &OutputSchema, stdout: &str) -> Result<serde_json::Value, String> {
+    match schema.format {
+        OutputFormat::Json => {
+            let value = serde_json::from_str::<serde_json::Value>(stdout.trim())
+                .map_err(|e| format!("stdout is not valid JSON: {e}"))?;
+            check_output_fields(&schema.fields, &value, "")?;
+            Ok(value)
+        }
+        OutputFormat::KeyValue => {
+            let mut object = serde_json::Map::new();
+            for (i, line) in stdout.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| format!("line {} is not a key=value pair: `{line}`", i + 1))?;
+                object.insert(key.trim().to_string(), json!(value.trim()));
+            }
+            coerce_output_fields(&schema.fields, object, "")
+        }
+        OutputFormat::Csv => {
+            let mut rows = parse_csv(stdout)?.into_iter();
+            let header = rows.next().unwrap_or_default();
+            let mut result = vec![];
+            for (i, row) in rows.enumerate() {
+                let ctx = format!("row {}: ", i + 1);
+                if row.len() != header.len() {
+                    return Err(format!(
+                        "{ctx}{} columns while the header has {}",
+                        row.len(),
+                        header.len()
+                    ));
+                }
+                let object = header
+                    .iter()
+                    .cloned()
+                    .zip(row.into_iter().map(serde_json::Value::String))
+                    .collect();
+                result.push(coerce_output_fields(&schema.fields, object, &ctx)?);
+            }
+            Ok(serde_json::Value::Array(result))
+        }
+    }
+}
+
+/// Result of a script declaring an output schema: its result.json if it wrote one, its stdout
+/// parsed in the declared format otherwise, validated against the schema in both cases
+async fn read_typed_result(job_dir: &str, schema: &OutputSchema) -> Result<Box<RawValue>, Error> {
+    let result_json = read_file_content(&format!("{job_dir}/result.json")).await?;
+    let result = if !result_json.trim().is_empty() {
+        serde_json::from_str::<serde_json::Value>(&result_json)
+            .map_err(|e| format!("result.json is not valid JSON: {e}"))
+            .and_then(|value| {
+                match (schema.format, value.as_array()) {
+                    (OutputFormat::Csv, Some(rows)) => {
+                        rows.iter().enumerate().try_for_each(|(i, row)| {
+                            check_output_fields(&schema.fields, row, &format!("row {}: ", i + 1))
+                        })
+                    }
+                    (OutputFormat::Csv, None) => Err("the result is not an array".to_string()),
+                    _ => check_output_fields(&schema.fields, &value, ""),
+                }?;
+                Ok(value)
+            })
+    } else {
+        let stdout = read_file_content(&format!("{job_dir}/result.stdout")).await?;
+        parse_typed_output(schema, &ANSI_ESCAPE_RE.replace_all(&stdout, ""))
+    };
+    result.map(|x| to_raw_value(&x)).map_err(|e| {
+        Error::ExecutionErr(format!(
+            "The output of the script does not match its output schema: {e}"
+        ))
+    })
+}
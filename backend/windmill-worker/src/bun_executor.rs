@@ -15,16 +15,19 @@ use crate::{
         create_args_and_out_file, get_reserved_variables, handle_child, read_result, set_logs,
         start_child_process, write_file, write_file_binary,
     },
+    network_policy::JobNetwork,
+    package_mirror::offline_npm_registry_url,
+    zygote::{add_zygote, take_zygote, zygote_dir, zygote_key, zygote_pool_enabled},
     AuthedClientBackgroundTask, BUN_CACHE_DIR, BUN_PATH, DISABLE_NSJAIL, DISABLE_NUSER, HOME_ENV,
     NSJAIL_PATH, PATH_ENV, TZ_ENV,
 };
 
 use tokio::{
-    fs::{remove_dir_all, File},
-    process::Command,
+    fs::{remove_dir_all, DirBuilder, File},
+    process::{Child, Command},
 };
 
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 #[cfg(feature = "enterprise")]
 use tokio::sync::mpsc::Receiver;
@@ -44,6 +47,10 @@ const RELATIVE_BUN_BUILDER: &str = include_str!("../loader_builder.bun.ts");
 
 const NSJAIL_CONFIG_RUN_BUN_CONTENT: &str = include_str!("../nsjail/run.bun.config.proto");
 
+const BUN_ZYGOTE: &str = include_str!("../zygote.bun.ts");
+const ZYGOTE_READY: &str = "ZYGOTE_READY";
+const ZYGOTE_READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub const BUN_LOCKB_SPLIT: &str = "\n//bun.lockb\n";
 pub const EMPTY_FILE: &str = "<empty>";
 
@@ -247,17 +254,31 @@ pub async fn handle_bun_job(
     db: &sqlx::Pool<sqlx::Postgres>,
     client: &AuthedClientBackgroundTask,
     job_dir: &str,
+    worker_dir: &str,
     inner_content: &String,
     base_internal_url: &str,
     worker_name: &str,
     envs: HashMap<String, String>,
     shared_mount: &str,
+    network: &JobNetwork,
 ) -> error::Result<Box<RawValue>> {
     let _ = write_file(job_dir, "main.ts", inner_content).await?;
 
     let common_bun_proc_envs: HashMap<String, String> =
         get_common_bun_proc_envs(&base_internal_url).await;
 
+    // zygotes are only used without nsjail, where jobs with a lockfile run in a process that
+    // already imported their dependencies, unless they need the shared dir of their flow. Trusted
+    // dependencies are installed in the job dir, so they cannot be shared.
+    let zygote = requirements_o
+        .as_ref()
+        .filter(|_| zygote_pool_enabled() && shared_mount.is_empty())
+        .filter(|reqs| {
+            !reqs.contains("trustedDependencies")
+                && !reqs.ends_with(&format!("{BUN_LOCKB_SPLIT}{EMPTY_FILE}"))
+        })
+        .map(|reqs| zygote_key("bun", reqs));
+
     if let Some(reqs) = requirements_o {
         let splitted = reqs.split(BUN_LOCKB_SPLIT).collect::<Vec<&str>>();
         if splitted.len() != 2 {
@@ -418,7 +439,10 @@ plugin(p)
 "#,
                 RELATIVE_BUN_LOADER
                     .replace("W_ID", &job.workspace_id)
-                    .replace("BASE_INTERNAL_URL", base_internal_url)
+                    .replace(
+                        "BASE_INTERNAL_URL",
+                        &network.base_internal_url(base_internal_url),
+                    )
                     .replace("TOKEN", &client.get_token().await)
                     .replace("CURRENT_PATH", job.script_path())
             ),
@@ -434,8 +458,20 @@ plugin(p)
         write_loader_f
     )?;
 
+    let warm_child = if let Some(key) = zygote.as_ref() {
+        let mut job_envs = envs.clone();
+        job_envs.extend(reserved_variables.clone());
+        job_envs.extend(common_bun_proc_envs.clone());
+        take_bun_zygote(key, worker_dir, job_dir, &common_bun_proc_envs, job_envs).await
+    } else {
+        None
+    };
+
     //do not cache local dependencies
-    let child = if !*DISABLE_NSJAIL {
+    let child = if let Some(child) = warm_child {
+        logs.push_str("running in a warm bun process\n");
+        child
+    } else if !*DISABLE_NSJAIL {
         let _ = write_file(
             job_dir,
             "run.config.proto",
@@ -443,7 +479,9 @@ plugin(p)
                 .replace("{JOB_DIR}", job_dir)
                 .replace("{CACHE_DIR}", BUN_CACHE_DIR)
                 .replace("{CLONE_NEWUSER}", &(!*DISABLE_NUSER).to_string())
-                .replace("{SHARED_MOUNT}", shared_mount),
+                .replace("{CLONE_NEWNET}", &network.clone_newnet().to_string())
+                .replace("{SHARED_MOUNT}", shared_mount)
+                .replace("{NETWORK_MOUNTS}", &network.nsjail_mounts()),
         )
         .await?;
 
@@ -454,11 +492,11 @@ plugin(p)
             .envs(envs)
             .envs(reserved_variables)
             .envs(common_bun_proc_envs)
+            .envs(network.proxy_envs())
             .env("PATH", PATH_ENV.as_str())
-            .args(vec![
-                "--config",
-                "run.config.proto",
-                "--",
+            .env("BASE_URL", network.base_internal_url(base_internal_url))
+            .args(["--config", "run.config.proto", "--"])
+            .args(network.nsjail_command(&[
                 &BUN_PATH,
                 "run",
                 "-i",
@@ -466,7 +504,7 @@ plugin(p)
                 "-r",
                 "/tmp/bun/loader.bun.ts",
                 "/tmp/bun/wrapper.ts",
-            ])
+            ]))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
         start_child_process(nsjail_cmd, NSJAIL_PATH.as_str()).await?
@@ -511,8 +549,97 @@ plugin(p)
     read_result(job_dir).await
 }
 
+/// Takes the warm process of the lockfile, if it is ready, and submits the job to it. Another
+/// warm process is spawned right away for the next job of the lockfile.
+async fn take_bun_zygote(
+    key: &str,
+    worker_dir: &str,
+    job_dir: &str,
+    common_bun_proc_envs: &HashMap<String, String>,
+    job_envs: HashMap<String, String>,
+) -> Option<Child> {
+    let warm_child = take_zygote(key);
+
+    let dir = zygote_dir(worker_dir, key);
+    match spawn_bun_zygote(&dir, job_dir, common_bun_proc_envs).await {
+        Ok(child) => add_zygote(key.to_string(), dir, child),
+        Err(e) => tracing::error!("could not spawn bun zygote {key}: {e}"),
+    }
+
+    let mut child = warm_child?;
+    match start_warm_job(&mut child, job_dir, job_envs).await {
+        Ok(()) => Some(child),
+        Err(e) => {
+            tracing::warn!("could not run the job in the warm bun process {key}: {e}");
+            None
+        }
+    }
+}
+
+async fn start_warm_job(
+    child: &mut Child,
+    job_dir: &str,
+    job_envs: HashMap<String, String>,
+) -> error::Result<()> {
+    let stdout = child
+        .stdout
+        .as_mut()
+        .ok_or_else(|| error::Error::InternalErr("warm process without stdout".to_string()))?;
+    // read byte by byte, the output following the ready line is the one of the job
+    let wait_ready = async {
+        let mut line = vec![];
+        loop {
+            match stdout.read_u8().await? {
+                b'\n' if line == ZYGOTE_READY.as_bytes() => return Ok::<(), std::io::Error>(()),
+                b'\n' => line.clear(),
+                b => line.push(b),
+            }
+        }
+    };
+    tokio::time::timeout(ZYGOTE_READY_TIMEOUT, wait_ready)
+        .await
+        .map_err(|_| error::Error::InternalErr("warm process not ready".to_string()))?
+        .map_err(error::Error::IoErr)?;
+
+    let request = serde_json::json!({ "cwd": job_dir, "env": job_envs });
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| error::Error::InternalErr("warm process without stdin".to_string()))?;
+    stdin.write_all(format!("{request}\n").as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+async fn spawn_bun_zygote(
+    dir: &str,
+    job_dir: &str,
+    common_bun_proc_envs: &HashMap<String, String>,
+) -> error::Result<Child> {
+    DirBuilder::new().recursive(true).create(dir).await?;
+    for file in ["package.json", "bun.lockb"] {
+        tokio::fs::copy(format!("{job_dir}/{file}"), format!("{dir}/{file}")).await?;
+    }
+    write_file(dir, "zygote.bun.ts", BUN_ZYGOTE).await?;
+
+    let zygote_ts = format!("{dir}/zygote.bun.ts");
+    let bun_args = ["run", "-i", "--prefer-offline", zygote_ts.as_str()];
+    let mut cmd = Command::new(&*BUN_PATH);
+    // the warm process exits once its stdin is closed, should the worker die without killing it
+    cmd.current_dir(dir)
+        .env_clear()
+        .envs(common_bun_proc_envs)
+        .env("PATH", PATH_ENV.as_str())
+        .args(bun_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    start_child_process(cmd, BUN_PATH.as_str()).await
+}
+
 pub async fn get_common_bun_proc_envs(base_internal_url: &str) -> HashMap<String, String> {
-    let bun_envs: HashMap<String, String> = HashMap::from([
+    let mut bun_envs: HashMap<String, String> = HashMap::from([
         (String::from("PATH"), PATH_ENV.clone()),
         (String::from("HOME"), HOME_ENV.clone()),
         (String::from("TZ"), TZ_ENV.clone()),
@@ -529,6 +656,9 @@ pub async fn get_common_bun_proc_envs(base_internal_url: &str) -> HashMap<String
             BUN_CACHE_DIR.to_string(),
         ),
     ]);
+    if let Some(url) = offline_npm_registry_url().await {
+        bun_envs.insert(String::from("NPM_CONFIG_REGISTRY"), url);
+    }
     return bun_envs;
 }
 
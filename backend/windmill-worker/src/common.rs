@@ -3,7 +3,7 @@ use itertools::Itertools;
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
 use regex::Regex;
-use serde::Serialize;
+use serde::Deserialize;
 use serde_json::value::RawValue;
 use serde_json::{json, Value};
 use sqlx::{Pool, Postgres};
@@ -12,7 +12,7 @@ use tokio::{fs::File, io::AsyncReadExt};
 use windmill_common::worker::{CLOUD_HOSTED, WORKER_CONFIG};
 use windmill_common::{
     error::{self, Error},
-    jobs::QueuedJob,
+    jobs::{JobKind, QueuedJob},
     variables::ContextualVariable,
 };
 
@@ -209,7 +209,7 @@ pub async fn transform_json_value(
         Value::String(y) if y.starts_with("$var:") => {
             let path = y.strip_prefix("$var:").unwrap();
             client
-                .get_variable_value(path)
+                .get_variable_value(path, Some(job.id.to_string()))
                 .await
                 .map(|x| json!(x))
                 .map_err(|e| {
@@ -787,11 +787,23 @@ fn append_with_limit(dst: &mut String, src: &str, limit: &mut usize) {
     }
 }
 
-pub fn hash_args(v: &Option<sqlx::types::Json<HashMap<String, Box<RawValue>>>>) -> String {
+/// Hashes the args of a job, restricted to `key_args` when the cache key is customized
+pub fn hash_args(
+    v: &Option<sqlx::types::Json<HashMap<String, Box<RawValue>>>>,
+    key_args: Option<&[String]>,
+) -> String {
     if let Some(vs) = v {
         let mut dh = DefaultHasher::new();
         let hm = &vs.0;
-        for k in hm.keys().sorted() {
+        if let Some(key_args) = key_args {
+            "cache_key_args".hash(&mut dh);
+            key_args.iter().sorted().for_each(|k| k.hash(&mut dh));
+        }
+        for k in hm
+            .keys()
+            .filter(|k| key_args.map_or(true, |ka| ka.contains(k)))
+            .sorted()
+        {
             k.hash(&mut dh);
             hm.get(k).unwrap().get().hash(&mut dh);
         }
@@ -801,10 +813,81 @@ pub fn hash_args(v: &Option<sqlx::types::Json<HashMap<String, Box<RawValue>>>>)
     }
 }
 
-#[derive(Serialize)]
-struct StoreCachedResource<'a> {
-    expire: i64,
-    value: &'a RawValue,
+#[derive(Deserialize)]
+struct CacheKeyArgs {
+    cache_key_args: Option<Vec<String>>,
+}
+
+/// The args the cache key of a job is restricted to: the setting of the flow step if the job is
+/// one, then the one of the flow or of the script
+pub async fn get_cache_key_args(
+    db: &Pool<Postgres>,
+    job: &QueuedJob,
+) -> error::Result<Option<Vec<String>>> {
+    if let (true, Some(parent_job), Some(step_id)) =
+        (job.is_flow_step, job.parent_job, job.flow_step_id.as_ref())
+    {
+        // the step is looked up by id as the parent can be a branch or a loop of the flow
+        let module_key_args = sqlx::query_scalar::<_, Option<sqlx::types::Json<Vec<String>>>>(
+            "SELECT NULLIF(m->'cache_key_args', 'null'::jsonb) FROM queue, \
+                jsonb_array_elements(raw_flow->'modules' || jsonb_build_array(raw_flow->'failure_module')) m \
+             WHERE id = $1 AND m->>'id' = $2",
+        )
+        .bind(parent_job)
+        .bind(step_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| Error::InternalErr(format!("fetching cache key args of step: {e}")))?
+        .flatten();
+        if let Some(key_args) = module_key_args {
+            return Ok(Some(key_args.0));
+        }
+    }
+
+    if matches!(job.job_kind, JobKind::Flow | JobKind::FlowPreview) {
+        Ok(job
+            .raw_flow
+            .as_ref()
+            .and_then(|f| serde_json::from_str::<CacheKeyArgs>(f.get()).ok())
+            .and_then(|f| f.cache_key_args))
+    } else if let Some(hash) = job.script_hash {
+        Ok(sqlx::query_scalar::<_, Option<Vec<String>>>(
+            "SELECT cache_key_args FROM script WHERE hash = $1 AND workspace_id = $2",
+        )
+        .bind(hash.0)
+        .bind(&job.workspace_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| Error::InternalErr(format!("fetching cache key args of script: {e}")))?
+        .flatten())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Cache keys are of the form `<script or flow path>/cache/...`
+fn cache_key_path(cache_key: &str) -> &str {
+    cache_key
+        .split_once("/cache/")
+        .map(|(path, _)| path)
+        .unwrap_or(cache_key)
+}
+
+/// Returns the cached result if it has not expired and counts the hit
+pub async fn get_cached_result(
+    db: &Pool<Postgres>,
+    w_id: &str,
+    cache_key: &str,
+) -> error::Result<Option<Box<RawValue>>> {
+    let result = sqlx::query_scalar::<_, sqlx::types::Json<Box<RawValue>>>(
+        "UPDATE job_result_cache SET hits = hits + 1, last_hit_at = now() \
+         WHERE workspace_id = $1 AND cache_key = $2 AND expire > now() RETURNING result",
+    )
+    .bind(w_id)
+    .bind(cache_key)
+    .fetch_optional(db)
+    .await?;
+    Ok(result.map(|r| r.0))
 }
 
 pub async fn save_in_cache<'a>(
@@ -813,25 +896,22 @@ pub async fn save_in_cache<'a>(
     cached_path: String,
     r: &'a RawValue,
 ) {
-    let expire = chrono::Utc::now().timestamp() + job.cache_ttl.unwrap() as i64;
-
-    let store_cache_resource = StoreCachedResource { expire, value: r };
-    let raw_json = sqlx::types::Json(store_cache_resource);
-
-    if let Err(e) = sqlx::query!(
-        "INSERT INTO resource
-    (workspace_id, path, value, resource_type)
-    VALUES ($1, $2, $3, $4) ON CONFLICT (workspace_id, path)
-    DO UPDATE SET value = $3",
-        job.workspace_id,
-        cached_path,
-        raw_json as sqlx::types::Json<StoreCachedResource>,
-        "cache"
+    if let Err(e) = sqlx::query(
+        "INSERT INTO job_result_cache (workspace_id, cache_key, path, result, job_id, expire) \
+         VALUES ($1, $2, $3, $4, $5, now() + ($6::bigint || ' s')::interval) \
+         ON CONFLICT (workspace_id, cache_key) DO UPDATE SET result = $4, job_id = $5, \
+         created_at = now(), expire = now() + ($6::bigint || ' s')::interval, hits = 0, last_hit_at = NULL",
     )
+    .bind(&job.workspace_id)
+    .bind(&cached_path)
+    .bind(cache_key_path(&cached_path))
+    .bind(sqlx::types::Json(r))
+    .bind(job.id)
+    .bind(job.cache_ttl.unwrap_or(0) as i64)
     .execute(db)
     .await
     {
-        tracing::error!("Error creating cache resource {e}")
+        tracing::error!("Error saving result in cache {e}")
     }
 }
 
@@ -10,6 +10,8 @@ use crate::{
         create_args_and_out_file, get_reserved_variables, handle_child, read_result, set_logs,
         start_child_process, write_file,
     },
+    network_policy::JobNetwork,
+    package_mirror::offline_npm_registry_url,
     AuthedClientBackgroundTask, DENO_CACHE_DIR, DENO_PATH, DISABLE_NSJAIL, HOME_ENV,
     NPM_CONFIG_REGISTRY, PATH_ENV, TZ_ENV,
 };
@@ -69,7 +71,9 @@ async fn get_common_deno_proc_envs(
         ),
     ]);
 
-    if let Some(ref s) = NPM_CONFIG_REGISTRY.read().await.clone() {
+    if let Some(url) = offline_npm_registry_url().await {
+        deno_envs.insert(String::from("NPM_CONFIG_REGISTRY"), url);
+    } else if let Some(ref s) = NPM_CONFIG_REGISTRY.read().await.clone() {
         deno_envs.insert(String::from("NPM_CONFIG_REGISTRY"), s.clone());
     }
     if DENO_CERT.len() > 0 {
@@ -107,7 +111,9 @@ pub async fn generate_deno_lock(
     write_file(job_dir, "empty.ts", "").await?;
 
     let mut deno_envs = HashMap::new();
-    if let Some(ref s) = NPM_CONFIG_REGISTRY.read().await.clone() {
+    if let Some(url) = offline_npm_registry_url().await {
+        deno_envs.insert(String::from("NPM_CONFIG_REGISTRY"), url);
+    } else if let Some(ref s) = NPM_CONFIG_REGISTRY.read().await.clone() {
         deno_envs.insert(String::from("NPM_CONFIG_REGISTRY"), s.clone());
     }
     let mut child_cmd = Command::new(DENO_PATH.as_str());
@@ -162,6 +168,7 @@ pub async fn handle_deno_job(
     job_dir: &str,
     inner_content: &String,
     base_internal_url: &str,
+    network: &JobNetwork,
     worker_name: &str,
     envs: HashMap<String, String>,
 ) -> error::Result<Box<RawValue>> {
@@ -260,6 +267,8 @@ run().catch(async (e) => {{
         common_deno_proc_envs.insert("HOME".to_string(), job_dir.to_string());
     }
 
+    let allow_net = network.deno_allow_net(base_internal_url, logs);
+
     //do not cache local dependencies
     let child = {
         let reload = format!("--reload={base_internal_url}");
@@ -283,7 +292,13 @@ run().catch(async (e) => {{
             "--allow-read=./,/tmp/windmill/cache/deno/,{}",
             DENO_PATH.as_str()
         );
-        if let Some(deno_flags) = DENO_FLAGS.as_ref() {
+        if let Some(allow_net) = allow_net.as_ref() {
+            // no subprocesses, they would not be bound by the net permissions of the policy
+            args.push(allow_net);
+            args.push(allow_read.as_str());
+            args.push("--allow-write=./");
+            args.push("--allow-env");
+        } else if let Some(deno_flags) = DENO_FLAGS.as_ref() {
             for flag in deno_flags {
                 args.push(flag);
             }
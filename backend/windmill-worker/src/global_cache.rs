@@ -1,37 +1,580 @@
+#[cfg(all(feature = "enterprise", feature = "s3"))]
+use crate::S3_CACHE_BUCKET;
 #[cfg(feature = "enterprise")]
-use crate::{ROOT_CACHE_DIR, ROOT_TMP_CACHE_DIR, TAR_CACHE_RATE, TAR_PIP_TMP_CACHE_DIR, TMP_DIR};
+use crate::{
+    GLOBAL_CACHE_BACKEND, GLOBAL_CACHE_PATH, ROOT_CACHE_DIR, ROOT_TMP_CACHE_DIR, TAR_CACHE_RATE,
+    TAR_PIP_BLOBS_TMP_CACHE_DIR, TAR_PIP_INDEX_TMP_CACHE_DIR, TAR_PIP_TMP_CACHE_DIR, TMP_DIR,
+};
 #[cfg(feature = "enterprise")]
 use itertools::Itertools;
 #[cfg(feature = "enterprise")]
 use rand::Rng;
 #[cfg(feature = "enterprise")]
-use std::process::Stdio;
+use sha2::{Digest, Sha256};
 #[cfg(feature = "enterprise")]
-use windmill_common::DB;
+use sqlx::postgres::types::Oid;
+#[cfg(feature = "enterprise")]
+use std::{collections::HashMap, process::Stdio, sync::atomic::Ordering, sync::Arc};
+#[cfg(feature = "enterprise")]
+use windmill_common::{DB, METRICS_ENABLED};
 
 #[cfg(feature = "enterprise")]
 use windmill_common::global_settings::WORKER_S3_BUCKET_SYNC;
 
 #[cfg(feature = "enterprise")]
-use tokio::{process::Command, sync::mpsc::Sender, time::Instant};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+    sync::mpsc::Sender,
+    time::Instant,
+};
 
 #[cfg(feature = "enterprise")]
 use windmill_common::error;
 
 #[cfg(feature = "enterprise")]
 const TAR_CACHE_FILENAME: &str = "denogocache.tar";
+#[cfg(feature = "enterprise")]
+const TAR_CACHE_PATH: &str = const_format::concatcp!(ROOT_TMP_CACHE_DIR, TAR_CACHE_FILENAME);
+
+/// Folders of the cache kept in sync with the global cache, relative to the cache root
+#[cfg(feature = "enterprise")]
+const SYNCED_FOLDERS: [&str; 4] = ["deno/npm", "deno/deps", "go", "powershell"];
+
+/// Pip tars are content addressed: `tar/pip_blobs/<sha256>.tar` holds the tar and
+/// `tar/pip_index/<venv>` the sha256 of the tar of that venv, so that identical venvs built by
+/// different workers are only stored and transferred once. `tar/pip/<venv>.tar` is the legacy
+/// layout, still pulled from when a venv has no index entry.
+#[cfg(feature = "enterprise")]
+const PIP_TARS_FOLDER: &str = "tar/pip";
+#[cfg(feature = "enterprise")]
+const PIP_INDEX_FOLDER: &str = "tar/pip_index";
+#[cfg(feature = "enterprise")]
+const PIP_BLOBS_FOLDER: &str = "tar/pip_blobs";
+
+#[cfg(feature = "enterprise")]
+const PG_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[cfg(feature = "enterprise")]
+lazy_static::lazy_static! {
+    static ref GLOBAL_CACHE_BYTES_PULLED: Option<prometheus::IntCounterVec> = if METRICS_ENABLED.load(Ordering::Relaxed) {
+        Some(prometheus::register_int_counter_vec!(
+            "worker_global_cache_bytes_pulled",
+            "Number of bytes pulled from the global cache",
+            &["name"]
+        ).expect("register prometheus metric"))
+    } else {
+        None
+    };
+
+    static ref GLOBAL_CACHE_BYTES_PUSHED: Option<prometheus::IntCounterVec> = if METRICS_ENABLED.load(Ordering::Relaxed) {
+        Some(prometheus::register_int_counter_vec!(
+            "worker_global_cache_bytes_pushed",
+            "Number of bytes pushed to the global cache",
+            &["name"]
+        ).expect("register prometheus metric"))
+    } else {
+        None
+    };
+}
+
+#[cfg(feature = "enterprise")]
+fn record_pulled(worker_name: &str, bytes: u64) {
+    if let Some(counter) = GLOBAL_CACHE_BYTES_PULLED.as_ref() {
+        counter.with_label_values(&[worker_name]).inc_by(bytes);
+    }
+}
+
+#[cfg(feature = "enterprise")]
+fn record_pushed(worker_name: &str, bytes: u64) {
+    if let Some(counter) = GLOBAL_CACHE_BYTES_PUSHED.as_ref() {
+        counter.with_label_values(&[worker_name]).inc_by(bytes);
+    }
+}
+
+/// Storage the local caches of the workers are synced with. Keys are `/` separated paths
+/// relative to the root of the cache, e.g. `tar/pip_index/requests==2.31.0`.
+#[cfg(feature = "enterprise")]
+#[async_trait::async_trait]
+pub trait GlobalCacheBackend: Send + Sync {
+    /// Where the cache is stored, for logs
+    fn location(&self) -> String;
+
+    /// Upload a local file at `key`, returns the number of bytes pushed
+    async fn push_file(&self, local_path: &str, key: &str) -> error::Result<u64>;
+
+    /// Download `key` to a local file, fails if `key` does not exist. Returns the number of
+    /// bytes pulled
+    async fn pull_file(&self, key: &str, local_path: &str) -> error::Result<u64>;
+
+    async fn exists(&self, key: &str) -> error::Result<bool>;
+
+    /// Copy the files of `folders` that are missing or have a different size from the cache to
+    /// `local_root`, returns the number of bytes pulled
+    async fn pull_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64>;
+
+    /// Copy the files of `folders` that are missing or have a different size from `local_root`
+    /// to the cache, returns the number of bytes pushed
+    async fn push_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64>;
+}
+
+/// The global cache configured through `GLOBAL_CACHE_BACKEND` (`s3`, `fs` or `postgres`),
+/// defaulting to the `S3_CACHE_BUCKET` bucket when set. The s3 backend requires the `s3`
+/// feature.
+#[cfg(feature = "enterprise")]
+pub fn global_cache_backend(db: &DB) -> Option<Arc<dyn GlobalCacheBackend>> {
+    match GLOBAL_CACHE_BACKEND.as_deref() {
+        #[cfg(feature = "s3")]
+        None | Some("s3") => S3_CACHE_BUCKET
+            .clone()
+            .map(|bucket| Arc::new(S3Cache { bucket }) as Arc<dyn GlobalCacheBackend>),
+        #[cfg(not(feature = "s3"))]
+        None => None,
+        #[cfg(not(feature = "s3"))]
+        Some("s3") => {
+            tracing::warn!("GLOBAL_CACHE_BACKEND is s3 but the s3 feature is disabled");
+            None
+        }
+        Some("fs") => match GLOBAL_CACHE_PATH.clone() {
+            Some(root) => Some(Arc::new(FsCache { root })),
+            None => {
+                tracing::warn!("GLOBAL_CACHE_BACKEND is fs but GLOBAL_CACHE_PATH is not set");
+                None
+            }
+        },
+        Some("postgres") => Some(Arc::new(PgCache { db: db.clone() })),
+        Some(backend) => {
+            tracing::warn!("Unknown GLOBAL_CACHE_BACKEND {backend}, global cache disabled");
+            None
+        }
+    }
+}
+
+#[cfg(feature = "enterprise")]
+fn folder_filters(folders: &[&str]) -> Vec<String> {
+    let mut filters = vec![];
+    for folder in folders {
+        filters.push("--filter".to_string());
+        filters.push(format!("+ {folder}/**"));
+    }
+    filters.push("--filter".to_string());
+    filters.push("- *".to_string());
+    filters
+}
+
+/// Run rclone and return the number of bytes it transferred, read from its final json stats
+#[cfg(feature = "enterprise")]
+async fn execute_rclone(dir: &str, args: Vec<&str>) -> error::Result<u64> {
+    tracing::info!("Executing command: rclone {}", args.iter().join(" "));
+    let output = Command::new("rclone")
+        .current_dir(dir)
+        .args(args.clone())
+        .args(["-v", "--use-json-log"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|e| {
+            error::Error::ExecutionErr(format!(
+                "Failed to apply rclone with args: {}. Error: {e:?}",
+                args.iter().join(" ")
+            ))
+        })?;
+    if !output.status.success() {
+        return Err(error::Error::ExecutionErr(format!(
+            "Failed to apply rclone with args: {}",
+            args.iter().join(" ")
+        )));
+    }
+
+    let bytes = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|log| log.get("stats")?.get("bytes")?.as_u64())
+        .last()
+        .unwrap_or(0);
+    Ok(bytes)
+}
+
+#[cfg(feature = "enterprise")]
+async fn rclone_copy_folders(src: &str, dst: &str, folders: &[&str]) -> error::Result<u64> {
+    let filters = folder_filters(folders);
+    let mut args = vec!["copy", src, dst, "--size-only", "--fast-list"];
+    args.extend(filters.iter().map(|x| x.as_str()));
+    execute_rclone(ROOT_TMP_CACHE_DIR, args).await
+}
+
+#[cfg(feature = "enterprise")]
+async fn file_size(path: &str) -> error::Result<u64> {
+    Ok(tokio::fs::metadata(path).await?.len())
+}
+
+#[cfg(feature = "enterprise")]
+async fn create_parent_dir(path: &str) -> error::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "enterprise", feature = "s3"))]
+pub struct S3Cache {
+    bucket: String,
+}
+
+#[cfg(all(feature = "enterprise", feature = "s3"))]
+impl S3Cache {
+    fn remote(&self, key: &str) -> String {
+        format!(":s3,env_auth=true:{}/{key}", self.bucket)
+    }
+}
+
+#[cfg(all(feature = "enterprise", feature = "s3"))]
+#[async_trait::async_trait]
+impl GlobalCacheBackend for S3Cache {
+    fn location(&self) -> String {
+        format!("bucket {}", self.bucket)
+    }
+
+    async fn push_file(&self, local_path: &str, key: &str) -> error::Result<u64> {
+        execute_rclone(
+            ROOT_TMP_CACHE_DIR,
+            vec![
+                "copyto",
+                local_path,
+                &self.remote(key),
+                "--size-only",
+                "--fast-list",
+                "--s3-no-check-bucket",
+            ],
+        )
+        .await
+    }
+
+    async fn pull_file(&self, key: &str, local_path: &str) -> error::Result<u64> {
+        let bytes = execute_rclone(
+            ROOT_TMP_CACHE_DIR,
+            vec![
+                "copyto",
+                &self.remote(key),
+                local_path,
+                "--size-only",
+                "--fast-list",
+            ],
+        )
+        .await?;
+        // rclone succeeds without copying anything when the source does not exist
+        if tokio::fs::metadata(local_path).await.is_err() {
+            return Err(error::Error::NotFound(format!(
+                "{key} does not exist in the global cache"
+            )));
+        }
+        Ok(bytes)
+    }
+
+    async fn exists(&self, key: &str) -> error::Result<bool> {
+        let output = Command::new("rclone")
+            .args(["lsf", &self.remote(key)])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await?;
+        Ok(output.status.success() && !output.stdout.is_empty())
+    }
+
+    async fn pull_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        rclone_copy_folders(&self.remote(""), local_root, folders).await
+    }
+
+    async fn push_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        rclone_copy_folders(local_root, &self.remote(""), folders).await
+    }
+}
+
+/// Global cache on a filesystem shared by the workers, e.g. an NFS mount
+#[cfg(feature = "enterprise")]
+pub struct FsCache {
+    root: String,
+}
+
+#[cfg(feature = "enterprise")]
+impl FsCache {
+    fn path(&self, key: &str) -> String {
+        format!("{}/{key}", self.root.trim_end_matches('/'))
+    }
+}
 
 #[cfg(feature = "enterprise")]
-pub async fn build_tar_and_push(bucket: &str, folder: String) -> error::Result<()> {
+#[async_trait::async_trait]
+impl GlobalCacheBackend for FsCache {
+    fn location(&self) -> String {
+        format!("path {}", self.root)
+    }
+
+    async fn push_file(&self, local_path: &str, key: &str) -> error::Result<u64> {
+        let target = self.path(key);
+        create_parent_dir(&target).await?;
+        // copy then rename so that other workers never read a partially written file
+        let tmp_target = format!("{target}.{}.tmp", rand::thread_rng().gen::<u32>());
+        let bytes = tokio::fs::copy(local_path, &tmp_target).await?;
+        tokio::fs::rename(&tmp_target, &target).await?;
+        Ok(bytes)
+    }
+
+    async fn pull_file(&self, key: &str, local_path: &str) -> error::Result<u64> {
+        let source = self.path(key);
+        if tokio::fs::metadata(&source).await.is_err() {
+            return Err(error::Error::NotFound(format!(
+                "{key} does not exist in the global cache"
+            )));
+        }
+        create_parent_dir(local_path).await?;
+        Ok(tokio::fs::copy(&source, local_path).await?)
+    }
+
+    async fn exists(&self, key: &str) -> error::Result<bool> {
+        Ok(tokio::fs::metadata(self.path(key)).await.is_ok())
+    }
+
+    async fn pull_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        rclone_copy_folders(&self.root, local_root, folders).await
+    }
+
+    async fn push_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        rclone_copy_folders(local_root, &self.root, folders).await
+    }
+}
+
+/// Global cache stored as large objects of the database, for deployments without an object
+/// storage or a shared filesystem
+#[cfg(feature = "enterprise")]
+pub struct PgCache {
+    db: DB,
+}
+
+#[cfg(feature = "enterprise")]
+impl PgCache {
+    async fn object_size(&self, key: &str) -> error::Result<Option<i64>> {
+        Ok(
+            sqlx::query_scalar::<_, i64>("SELECT size FROM global_cache_object WHERE key = $1")
+                .bind(key)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+}
+
+#[cfg(feature = "enterprise")]
+async fn list_local_files(local_root: &str, folder: &str) -> error::Result<Vec<String>> {
+    let mut files = vec![];
+    let mut dirs = vec![folder.to_string()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match tokio::fs::read_dir(format!("{local_root}{dir}")).await {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let key = format!("{dir}/{}", entry.file_name().to_string_lossy());
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(key);
+            } else if file_type.is_file() {
+                files.push(key);
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(feature = "enterprise")]
+#[async_trait::async_trait]
+impl GlobalCacheBackend for PgCache {
+    fn location(&self) -> String {
+        "database large objects".to_string()
+    }
+
+    async fn push_file(&self, local_path: &str, key: &str) -> error::Result<u64> {
+        let size = file_size(local_path).await?;
+        if self.object_size(key).await? == Some(size as i64) {
+            return Ok(0);
+        }
+
+        let mut tx = self.db.begin().await?;
+        let oid = sqlx::query_scalar::<_, Oid>("SELECT lo_create(0)")
+            .fetch_one(&mut *tx)
+            .await?;
+        let mut file = tokio::fs::File::open(local_path).await?;
+        let mut buf = vec![0u8; PG_CHUNK_SIZE];
+        let mut offset: i64 = 0;
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            sqlx::query("SELECT lo_put($1, $2, $3)")
+                .bind(oid)
+                .bind(offset)
+                .bind(&buf[..n])
+                .execute(&mut *tx)
+                .await?;
+            offset += n as i64;
+        }
+
+        let previous = sqlx::query_scalar::<_, Oid>(
+            "SELECT oid FROM global_cache_object WHERE key = $1 FOR UPDATE",
+        )
+        .bind(key)
+        .fetch_optional(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO global_cache_object (key, oid, size) VALUES ($1, $2, $3) \
+             ON CONFLICT (key) DO UPDATE SET oid = EXCLUDED.oid, size = EXCLUDED.size, updated_at = now()",
+        )
+        .bind(key)
+        .bind(oid)
+        .bind(offset)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(previous) = previous {
+            sqlx::query("SELECT lo_unlink($1)")
+                .bind(previous)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(offset as u64)
+    }
+
+    async fn pull_file(&self, key: &str, local_path: &str) -> error::Result<u64> {
+        // the snapshot keeps the object readable if it is replaced while being pulled
+        let mut tx = self.db.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .execute(&mut *tx)
+            .await?;
+        let (oid, size) = sqlx::query_as::<_, (Oid, i64)>(
+            "SELECT oid, size FROM global_cache_object WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| {
+            error::Error::NotFound(format!("{key} does not exist in the global cache"))
+        })?;
+
+        create_parent_dir(local_path).await?;
+        let mut file = tokio::fs::File::create(local_path).await?;
+        let mut offset: i64 = 0;
+        while offset < size {
+            let chunk = sqlx::query_scalar::<_, Vec<u8>>("SELECT lo_get($1, $2, $3)")
+                .bind(oid)
+                .bind(offset)
+                .bind(PG_CHUNK_SIZE as i32)
+                .fetch_one(&mut *tx)
+                .await?;
+            if chunk.is_empty() {
+                break;
+            }
+            file.write_all(&chunk).await?;
+            offset += chunk.len() as i64;
+        }
+        file.flush().await?;
+        tx.commit().await?;
+
+        Ok(offset as u64)
+    }
+
+    async fn exists(&self, key: &str) -> error::Result<bool> {
+        Ok(self.object_size(key).await?.is_some())
+    }
+
+    async fn pull_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        let mut bytes = 0;
+        for folder in folders {
+            let objects = sqlx::query_as::<_, (String, i64)>(
+                "SELECT key, size FROM global_cache_object WHERE key LIKE $1 || '/%'",
+            )
+            .bind(folder)
+            .fetch_all(&self.db)
+            .await?;
+            for (key, size) in objects {
+                let local_path = format!("{local_root}{key}");
+                if file_size(&local_path).await.ok() != Some(size as u64) {
+                    bytes += self.pull_file(&key, &local_path).await?;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    async fn push_folders(&self, local_root: &str, folders: &[&str]) -> error::Result<u64> {
+        let mut bytes = 0;
+        for folder in folders {
+            let sizes = sqlx::query_as::<_, (String, i64)>(
+                "SELECT key, size FROM global_cache_object WHERE key LIKE $1 || '/%'",
+            )
+            .bind(folder)
+            .fetch_all(&self.db)
+            .await?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+            for key in list_local_files(local_root, folder).await? {
+                let local_path = format!("{local_root}{key}");
+                if sizes.get(&key).map(|x| *x as u64) != file_size(&local_path).await.ok() {
+                    bytes += self.push_file(&local_path, &key).await?;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "enterprise")]
+async fn sha256_file(path: &str) -> error::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(feature = "enterprise")]
+pub async fn build_tar_and_push(
+    cache: Arc<dyn GlobalCacheBackend>,
+    folder: String,
+    worker_name: String,
+) -> error::Result<()> {
     tracing::info!("Started building and pushing piptar {folder}");
     let start = Instant::now();
     let folder_name = folder.split("/").last().unwrap();
     let tar_path = format!("{TAR_PIP_TMP_CACHE_DIR}/{folder_name}.tar",);
 
+    // the tar is made reproducible so that identical venvs have the same hash
     if let Err(e) = execute_command(
         ROOT_TMP_CACHE_DIR,
         "tar",
-        vec!["-c", "-f", &tar_path, "-C", &folder, "."],
+        vec![
+            "--sort=name",
+            "--mtime=@0",
+            "--owner=0",
+            "--group=0",
+            "--numeric-owner",
+            "-c",
+            "-f",
+            &tar_path,
+            "-C",
+            &folder,
+            ".",
+        ],
     )
     .await
     {
@@ -47,27 +590,35 @@ pub async fn build_tar_and_push(bucket: &str, folder: String) -> error::Result<(
         )));
     }
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copyto",
-            &tar_path,
-            &format!(":s3,env_auth=true:{bucket}/tar/pip/{folder_name}.tar"),
-            "-v",
-            "--size-only",
-            "--fast-list",
-            "--s3-no-check-bucket",
-        ],
-    )
-    .await
-    {
-        tracing::info!("Failed to copy piptar {folder} to bucket. Error: {:?}", e);
-        return Err(e);
+    let hash = sha256_file(&tar_path).await?;
+    let blob_key = format!("{PIP_BLOBS_FOLDER}/{hash}.tar");
+    let mut pushed = 0;
+    if !cache.exists(&blob_key).await? {
+        match cache.push_file(&tar_path, &blob_key).await {
+            Ok(bytes) => pushed += bytes,
+            Err(e) => {
+                tracing::info!(
+                    "Failed to copy piptar {folder} to global cache. Error: {:?}",
+                    e
+                );
+                return Err(e);
+            }
+        }
+    } else {
+        tracing::info!("piptar {folder} already in global cache as {hash}");
     }
 
+    let index_path = format!("{TAR_PIP_INDEX_TMP_CACHE_DIR}/{folder_name}");
+    create_parent_dir(&index_path).await?;
+    tokio::fs::write(&index_path, &hash).await?;
+    pushed += cache
+        .push_file(&index_path, &format!("{PIP_INDEX_FOLDER}/{folder_name}"))
+        .await?;
+    record_pushed(&worker_name, pushed);
+
     tracing::info!(
-        "Finished copying piptar {folder} to bucket {bucket} as tar, took: {:?}s. Size of tar: {}",
+        "Finished copying piptar {folder} to {} as tar, took: {:?}s. Size of tar: {}",
+        cache.location(),
         start.elapsed().as_secs(),
         tar_metadata.unwrap().len()
     );
@@ -75,46 +626,46 @@ pub async fn build_tar_and_push(bucket: &str, folder: String) -> error::Result<(
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn pull_from_tar(bucket: &str, folder: String) -> error::Result<()> {
-    use tokio::fs::metadata;
+pub async fn pull_from_tar(
+    cache: Arc<dyn GlobalCacheBackend>,
+    folder: String,
+    worker_name: &str,
+) -> error::Result<()> {
     let folder_name = folder.split("/").last().unwrap();
 
-    tracing::info!("Attempting to pull piptar {folder_name} from bucket");
+    tracing::info!("Attempting to pull piptar {folder_name} from global cache");
 
     let start = Instant::now();
-    let tar_path = format!("tar/pip/{folder_name}.tar");
-    let target = format!("{ROOT_TMP_CACHE_DIR}/{tar_path}.single");
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copyto",
-            &format!(":s3,env_auth=true:{bucket}/{tar_path}"),
-            &target,
-            "-v",
-            "--size-only",
-            "--fast-list",
-        ],
-    )
-    .await
+    let target = format!("{ROOT_TMP_CACHE_DIR}{PIP_TARS_FOLDER}/{folder_name}.tar.single");
+    let index_path = format!("{TAR_PIP_INDEX_TMP_CACHE_DIR}/{folder_name}");
+
+    let mut pulled = 0;
+    let tar_key = match cache
+        .pull_file(&format!("{PIP_INDEX_FOLDER}/{folder_name}"), &index_path)
+        .await
     {
-        tracing::info!(
-            "Failed to copy tar {folder_name} from bucket. Error: {:?}",
-            e
-        );
-        return Err(e);
-    }
+        Ok(bytes) => {
+            pulled += bytes;
+            let hash = tokio::fs::read_to_string(&index_path).await?;
+            format!("{PIP_BLOBS_FOLDER}/{}.tar", hash.trim())
+        }
+        Err(_) => format!("{PIP_TARS_FOLDER}/{folder_name}.tar"),
+    };
 
-    if metadata(&target).await.is_err() {
-        tracing::info!(
-            "piptar {folder_name} not found in bucket. Took {:?}ms",
-            start.elapsed().as_millis()
-        );
-        return Err(error::Error::ExecutionErr(format!(
-            "tar {folder_name} does not exist in bucket"
-        )));
+    match cache.pull_file(&tar_key, &target).await {
+        Ok(bytes) => pulled += bytes,
+        Err(e) => {
+            tracing::info!(
+                "piptar {folder_name} not found in global cache. Took {:?}ms. Error: {:?}",
+                start.elapsed().as_millis(),
+                e
+            );
+            return Err(error::Error::ExecutionErr(format!(
+                "tar {folder_name} does not exist in global cache"
+            )));
+        }
     }
-    // tracing::info!("B: {target} {folder}");
+    record_pulled(worker_name, pulled);
 
     extract_pip_tar(&target, &folder).await?;
     tokio::fs::remove_file(&target).await?;
@@ -127,56 +678,44 @@ pub async fn pull_from_tar(bucket: &str, folder: String) -> error::Result<()> {
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn cache_global(bucket: &str, tx: Sender<()>) -> error::Result<()> {
-    copy_cache_from_bucket(bucket, tx).await?;
-    copy_cache_to_bucket(bucket).await?;
+pub async fn cache_global(
+    cache: Arc<dyn GlobalCacheBackend>,
+    tx: Sender<()>,
+    worker_name: &str,
+) -> error::Result<()> {
+    copy_cache_from_bucket(cache.as_ref(), tx, worker_name).await?;
+    copy_cache_to_bucket(cache.as_ref(), worker_name).await?;
 
     // this is to prevent excessive tar upload. 1/100*15min = each worker sync its tar once per day on average
     if rand::thread_rng().gen_range(0..*TAR_CACHE_RATE) == 0 {
-        copy_cache_to_bucket_as_tar(bucket).await;
+        copy_cache_to_bucket_as_tar(cache.as_ref(), worker_name).await;
     }
     Ok(())
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn copy_cache_from_bucket(bucket: &str, tx: Sender<()>) -> error::Result<()> {
-    tracing::info!("Copying cache from bucket in the background {bucket}");
-    let bucket = bucket.to_string();
+pub async fn copy_cache_from_bucket(
+    cache: &dyn GlobalCacheBackend,
+    tx: Sender<()>,
+    worker_name: &str,
+) -> error::Result<()> {
+    tracing::info!("Copying cache from {} in the background", cache.location());
 
     let start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copy",
-            &format!(":s3,env_auth=true:{bucket}"),
-            &ROOT_TMP_CACHE_DIR,
-            // "-l",
-            "--size-only",
-            "--fast-list",
-            "--filter",
-            "+ deno/npm/**",
-            "--filter",
-            "+ deno/deps/**",
-            // "--filter",
-            // "+ bun/**",
-            "--filter",
-            "+ go/**",
-            "--filter",
-            "+ tar/**",
-            "--filter",
-            "- *",
-        ],
-    )
-    .await
-    {
-        tracing::info!("Failed to copy cache from bucket. Error: {:?}", e);
-        return Err(e);
+    let mut folders = SYNCED_FOLDERS.to_vec();
+    folders.push("tar");
+    match cache.pull_folders(ROOT_TMP_CACHE_DIR, &folders).await {
+        Ok(bytes) => record_pulled(worker_name, bytes),
+        Err(e) => {
+            tracing::info!("Failed to copy cache from global cache. Error: {:?}", e);
+            return Err(e);
+        }
     }
 
     tracing::info!(
-        "Finished copying cache from bucket {bucket}, took {:?}s",
+        "Finished copying cache from {}, took {:?}s",
+        cache.location(),
         start.elapsed().as_secs()
     );
 
@@ -186,39 +725,26 @@ pub async fn copy_cache_from_bucket(bucket: &str, tx: Sender<()>) -> error::Resu
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn copy_cache_to_bucket(bucket: &str) -> error::Result<()> {
-    tracing::info!("Copying cache to bucket {bucket}");
+pub async fn copy_cache_to_bucket(
+    cache: &dyn GlobalCacheBackend,
+    worker_name: &str,
+) -> error::Result<()> {
+    tracing::info!("Copying cache to {}", cache.location());
     let start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copy",
-            &ROOT_TMP_CACHE_DIR,
-            &format!(":s3,env_auth=true:{bucket}"),
-            // "-l",
-            "--size-only",
-            "--fast-list",
-            "--filter",
-            "+ deno/npm/**",
-            "--filter",
-            "+ deno/deps/**",
-            // "--filter",
-            // "+ bun/**",
-            "--filter",
-            "+ go/**",
-            "--filter",
-            "- *",
-        ],
-    )
-    .await
+    match cache
+        .push_folders(ROOT_TMP_CACHE_DIR, &SYNCED_FOLDERS)
+        .await
     {
-        tracing::info!("Failed to copy cache to bucket. Error: {:?}", e);
-        return Err(e);
+        Ok(bytes) => record_pushed(worker_name, bytes),
+        Err(e) => {
+            tracing::info!("Failed to copy cache to global cache. Error: {:?}", e);
+            return Err(e);
+        }
     }
     tracing::info!(
-        "Finished copying cache to bucket {bucket}, took: {:?}s",
+        "Finished copying cache to {}, took: {:?}s",
+        cache.location(),
         start.elapsed().as_secs()
     );
     Ok(())
@@ -244,108 +770,68 @@ pub async fn worker_s3_bucket_sync_enabled(db: &DB) -> bool {
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn copy_cache_to_bucket_as_tar(bucket: &str) {
-    tracing::info!("Copying cache to bucket {bucket} as tar");
+pub async fn copy_cache_to_bucket_as_tar(cache: &dyn GlobalCacheBackend, worker_name: &str) {
+    tracing::info!("Copying cache to {} as tar", cache.location());
     let start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "tar",
-        vec![
-            "-c",
-            "-f",
-            &format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}"),
-            "go",
-            "deno/npm",
-            "deno/deps", // "bun",
-        ],
-    )
-    .await
-    {
+    let mut args = vec!["-c", "-f", TAR_CACHE_PATH];
+    args.extend(SYNCED_FOLDERS);
+    if let Err(e) = execute_command(ROOT_TMP_CACHE_DIR, "tar", args).await {
         tracing::info!("Failed to tar cache. Error: {:?}", e);
         return;
     }
 
-    let tar_metadata =
-        tokio::fs::metadata(format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}")).await;
+    let tar_metadata = tokio::fs::metadata(TAR_CACHE_PATH).await;
     if tar_metadata.is_err() || tar_metadata.as_ref().unwrap().len() == 0 {
         tracing::info!("Failed to tar cache");
         return;
     }
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copyto",
-            &format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}"),
-            &format!(":s3,env_auth=true:{bucket}/{TAR_CACHE_FILENAME}"),
-            "-v",
-            "--size-only",
-            "--fast-list",
-            "--s3-no-check-bucket",
-        ],
-    )
-    .await
-    {
-        tracing::info!("Failed to copy tar to bucket. Error: {:?}", e);
-        return;
+    match cache.push_file(TAR_CACHE_PATH, TAR_CACHE_FILENAME).await {
+        Ok(bytes) => record_pushed(worker_name, bytes),
+        Err(e) => {
+            tracing::info!("Failed to copy tar to global cache. Error: {:?}", e);
+            return;
+        }
     }
 
-    if let Err(e) =
-        tokio::fs::remove_file(format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}")).await
-    {
+    if let Err(e) = tokio::fs::remove_file(TAR_CACHE_PATH).await {
         tracing::info!("Failed to remove tar cache. Error: {:?}", e);
     };
 
     tracing::info!(
-        "Finished copying cache to bucket {bucket} as tar, took: {:?}s. Size of new tar: {}",
+        "Finished copying cache to {} as tar, took: {:?}s. Size of new tar: {}",
+        cache.location(),
         start.elapsed().as_secs(),
         tar_metadata.unwrap().len()
     );
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn copy_denogo_cache_from_bucket_as_tar(bucket: &str) {
-    tracing::info!("Copying deno,go,bun cache from bucket {bucket} as tar");
+pub async fn copy_denogo_cache_from_bucket_as_tar(
+    cache: &dyn GlobalCacheBackend,
+    worker_name: &str,
+) {
+    tracing::info!("Copying deno,go,bun cache from {} as tar", cache.location());
 
     let mut start: Instant = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "rclone",
-        vec![
-            "copyto",
-            &format!(":s3,env_auth=true:{bucket}/{TAR_CACHE_FILENAME}"),
-            &format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}"),
-            "-v",
-            "--size-only",
-            "--fast-list",
-        ],
-    )
-    .await
-    {
-        tracing::info!("Failed copying deno,go,bun tar from cache. Error: {:?}", e);
-        return;
+    match cache.pull_file(TAR_CACHE_FILENAME, TAR_CACHE_PATH).await {
+        Ok(bytes) => record_pulled(worker_name, bytes),
+        Err(e) => {
+            tracing::info!("Failed copying deno,go,bun tar from cache. Error: {:?}", e);
+            return;
+        }
     }
 
     tracing::info!(
-        "Finished copying denogobun tar for from bucket as tar. took {}s",
+        "Finished copying denogobun tar for from global cache as tar. took {}s",
         start.elapsed().as_secs()
     );
 
     start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_CACHE_DIR,
-        "tar",
-        vec![
-            "-xpvf",
-            &format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}"),
-        ],
-    )
-    .await
-    {
+    if let Err(e) = execute_command(ROOT_CACHE_DIR, "tar", vec!["-xpvf", TAR_CACHE_PATH]).await {
         tracing::info!("Failed to untar denogobun tar to cache. Error: {:?}", e);
         return;
     }
@@ -357,15 +843,7 @@ pub async fn copy_denogo_cache_from_bucket_as_tar(bucket: &str) {
 
     start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_TMP_CACHE_DIR,
-        "tar",
-        vec![
-            "-xpvf",
-            &format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}"),
-        ],
-    )
-    .await
+    if let Err(e) = execute_command(ROOT_TMP_CACHE_DIR, "tar", vec!["-xpvf", TAR_CACHE_PATH]).await
     {
         tracing::info!("Failed to untar denogobun tar to tmpcache. Error: {:?}", e);
         return;
@@ -376,39 +854,34 @@ pub async fn copy_denogo_cache_from_bucket_as_tar(bucket: &str) {
         start.elapsed().as_secs()
     );
 
-    if let Err(e) =
-        tokio::fs::remove_file(format!("{ROOT_TMP_CACHE_DIR}{TAR_CACHE_FILENAME}")).await
-    {
+    if let Err(e) = tokio::fs::remove_file(TAR_CACHE_PATH).await {
         tracing::info!("Failed to remove denogobuntar cache. Error: {:?}", e);
     };
 }
 
 #[cfg(feature = "enterprise")]
-pub async fn copy_all_piptars_from_bucket(bucket: &str) {
-    tracing::info!("Copying all piptars cache from bucket {bucket}");
+pub async fn copy_all_piptars_from_bucket(cache: &dyn GlobalCacheBackend, worker_name: &str) {
+    tracing::info!("Copying all piptars cache from {}", cache.location());
 
     let start = Instant::now();
 
-    if let Err(e) = execute_command(
-        ROOT_CACHE_DIR,
-        "rclone",
-        vec![
-            "copy",
-            &format!(":s3,env_auth=true:{bucket}/tar/pip/"),
-            &TAR_PIP_TMP_CACHE_DIR,
-            "-v",
-            "--size-only",
-            "--fast-list",
-        ],
-    )
-    .await
+    match cache
+        .pull_folders(
+            ROOT_TMP_CACHE_DIR,
+            &[PIP_TARS_FOLDER, PIP_INDEX_FOLDER, PIP_BLOBS_FOLDER],
+        )
+        .await
     {
-        tracing::info!("Failed transferring all piptars from cache. Error: {:?}", e);
-        return;
+        Ok(bytes) => record_pulled(worker_name, bytes),
+        Err(e) => {
+            tracing::info!("Failed transferring all piptars from cache. Error: {:?}", e);
+            return;
+        }
     }
 
     tracing::info!(
-        "Finished transferring piptars from bucket {bucket} as tar, took: {:?}s",
+        "Finished transferring piptars from {} as tar, took: {:?}s",
+        cache.location(),
         start.elapsed().as_secs()
     );
 }
@@ -430,27 +903,10 @@ pub async fn copy_all_piptars_from_bucket(bucket: &str) {
 #[cfg(feature = "enterprise")]
 pub async fn copy_tmp_cache_to_cache() -> error::Result<()> {
     let start: Instant = Instant::now();
-    execute_command(
-        TMP_DIR,
-        "rclone",
-        vec![
-            "sync",
-            ROOT_TMP_CACHE_DIR,
-            ROOT_CACHE_DIR,
-            // "-l",
-            "--filter",
-            "+ deno/npm/**",
-            "--filter",
-            "+ deno/deps/**",
-            // "--filter",
-            // "+ bun/**",
-            "--filter",
-            "+ go/**",
-            "--filter",
-            "- *",
-        ],
-    )
-    .await?;
+    let filters = folder_filters(&SYNCED_FOLDERS);
+    let mut args = vec!["sync", ROOT_TMP_CACHE_DIR, ROOT_CACHE_DIR];
+    args.extend(filters.iter().map(|x| x.as_str()));
+    execute_command(TMP_DIR, "rclone", args).await?;
 
     tracing::info!(
         "Finished copying local tmp cache to local cache. Took {}ms",
@@ -472,6 +928,28 @@ pub async fn untar_all_piptars() -> error::Result<()> {
 
     let start: Instant = Instant::now();
 
+    // content addressed piptars take precedence over the legacy ones
+    if let Ok(mut entries) = fs::read_dir(TAR_PIP_INDEX_TMP_CACHE_DIR).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if let Err(e) = {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let folder = format!("{PIP_CACHE_DIR}/{name}");
+                if metadata(&folder).await.is_ok() {
+                    continue;
+                }
+                let hash = fs::read_to_string(entry.path()).await?;
+                let tar = format!("{TAR_PIP_BLOBS_TMP_CACHE_DIR}/{}.tar", hash.trim());
+                if metadata(&tar).await.is_err() {
+                    continue;
+                }
+                extract_pip_tar(&tar, &folder).await?;
+                Ok(()) as error::Result<()>
+            } {
+                tracing::info!("Failed to extract pip tar. Error: {:?}", e);
+            }
+        }
+    }
+
     let mut entries = fs::read_dir(TAR_PIP_TMP_CACHE_DIR).await?;
     while let Some(entry) = entries.next_entry().await? {
         if let Err(e) = {
@@ -527,27 +1005,10 @@ pub async fn extract_pip_tar(tar: &str, folder: &str) -> error::Result<()> {
 #[cfg(feature = "enterprise")]
 pub async fn copy_cache_to_tmp_cache() -> error::Result<()> {
     let start: Instant = Instant::now();
-    execute_command(
-        TMP_DIR,
-        "rclone",
-        vec![
-            "sync",
-            ROOT_CACHE_DIR,
-            ROOT_TMP_CACHE_DIR,
-            // "-l",
-            "--filter",
-            "+ deno/npm/**",
-            "--filter",
-            "+ deno/deps/**",
-            // "--filter",
-            // "+ bun/**",
-            "--filter",
-            "+ go/**",
-            "--filter",
-            "- *",
-        ],
-    )
-    .await?;
+    let filters = folder_filters(&SYNCED_FOLDERS);
+    let mut args = vec!["sync", ROOT_CACHE_DIR, ROOT_TMP_CACHE_DIR];
+    args.extend(filters.iter().map(|x| x.as_str()));
+    execute_command(TMP_DIR, "rclone", args).await?;
     tracing::info!(
         "Finished copying local cache to local tmp cache. Took {}ms",
         start.elapsed().as_millis()
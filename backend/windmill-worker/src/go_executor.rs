@@ -16,11 +16,16 @@ use windmill_common::{
 use windmill_parser_go::{parse_go_imports, REQUIRE_PARSE};
 use windmill_queue::CanceledBy;
 
+#[cfg(feature = "s3")]
+use crate::job_artifacts::ARTIFACTS_DIR;
+
 use crate::{
     common::{
         capitalize, create_args_and_out_file, get_reserved_variables, handle_child, read_result,
         set_logs, start_child_process, write_file,
     },
+    network_policy::JobNetwork,
+    package_mirror::offline_go_proxy_url,
     AuthedClientBackgroundTask, DISABLE_NSJAIL, DISABLE_NUSER, GOPRIVATE, GOPROXY,
     GO_BIN_CACHE_DIR, GO_CACHE_DIR, HOME_ENV, NSJAIL_PATH, PATH_ENV, TZ_ENV,
 };
@@ -44,6 +49,7 @@ pub async fn handle_go_job(
     job_dir: &str,
     requirements_o: Option<String>,
     shared_mount: &str,
+    network: &JobNetwork,
     base_internal_url: &str,
     worker_name: &str,
     envs: HashMap<String, String>,
@@ -97,8 +103,6 @@ pub async fn handle_go_job(
         set_logs(logs, &job.id, db).await;
         create_args_and_out_file(client, job, job_dir, db).await?;
         {
-            let sig = windmill_parser_go::parse_go_sig(&inner_content)?;
-
             const WRAPPER_CONTENT: &str = r#"package main
 
 import (
@@ -147,66 +151,19 @@ func main() {{
 
             write_file(job_dir, "main.go", WRAPPER_CONTENT).await?;
 
-            {
-                let spread = &sig
-                    .args
-                    .clone()
-                    .into_iter()
-                    .map(|x| format!("req.{}", capitalize(&x.name)))
-                    .join(", ");
-                let req_body = &sig
-                    .args
-                    .into_iter()
-                    .map(|x| {
-                        format!(
-                            "{} {} `json:\"{}\"`",
-                            capitalize(&x.name),
-                            windmill_parser_go::otyp_to_string(x.otyp),
-                            x.name
-                        )
-                    })
-                    .join("\n");
-                let runner_content: String = format!(
-                    r#"package inner
-type Req struct {{
-    {req_body}
-}}
-
-func Run(req Req) (interface{{}}, error){{
-    return main({spread})
-}}
-
-"#,
-                );
-                write_file(&format!("{job_dir}/inner"), "runner.go", &runner_content).await?;
-            }
+            write_go_runner(job_dir, inner_content).await?;
         }
 
-        let mut build_go_cmd = Command::new(GO_PATH.as_str());
-        build_go_cmd
-            .current_dir(job_dir)
-            .env_clear()
-            .env("PATH", PATH_ENV.as_str())
-            .env("BASE_INTERNAL_URL", base_internal_url)
-            .env("GOPATH", GO_CACHE_DIR)
-            .env("HOME", HOME_ENV.as_str())
-            .args(vec!["build", "main.go"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-        let build_go_process = start_child_process(build_go_cmd, GO_PATH.as_str()).await?;
-        handle_child(
+        build_go_binary(
             &job.id,
             db,
             logs,
             mem_peak,
             canceled_by,
-            build_go_process,
-            false,
+            job_dir,
+            base_internal_url,
             worker_name,
             &job.workspace_id,
-            "go build",
-            None,
-            false,
         )
         .await?;
 
@@ -228,6 +185,14 @@ func Run(req Req) (interface{{}}, error){{
         create_args_and_out_file(client, job, job_dir, db).await?;
     }
 
+    // scripts run from the go subdir but their artifacts are collected from the job dir
+    #[cfg(feature = "s3")]
+    tokio::fs::symlink(
+        format!("../{ARTIFACTS_DIR}"),
+        format!("{job_dir}/{ARTIFACTS_DIR}"),
+    )
+    .await?;
+
     let client = &client.get_authed().await;
 
     let reserved_variables = get_reserved_variables(job, &client.token, db).await?;
@@ -240,7 +205,9 @@ func Run(req Req) (interface{{}}, error){{
                 .replace("{JOB_DIR}", job_dir)
                 .replace("{CACHE_DIR}", GO_CACHE_DIR)
                 .replace("{CLONE_NEWUSER}", &(!*DISABLE_NUSER).to_string())
-                .replace("{SHARED_MOUNT}", shared_mount),
+                .replace("{CLONE_NEWNET}", &network.clone_newnet().to_string())
+                .replace("{SHARED_MOUNT}", shared_mount)
+                .replace("{NETWORK_MOUNTS}", &network.nsjail_mounts()),
         )
         .await?;
         let mut nsjail_cmd = Command::new(NSJAIL_PATH.as_str());
@@ -249,10 +216,15 @@ func Run(req Req) (interface{{}}, error){{
             .env_clear()
             .envs(envs)
             .envs(reserved_variables)
+            .envs(network.proxy_envs())
             .env("PATH", PATH_ENV.as_str())
             .env("TZ", TZ_ENV.as_str())
-            .env("BASE_INTERNAL_URL", base_internal_url)
-            .args(vec!["--config", "run.config.proto", "--", "/tmp/go/main"])
+            .env(
+                "BASE_INTERNAL_URL",
+                network.base_internal_url(base_internal_url),
+            )
+            .args(["--config", "run.config.proto", "--"])
+            .args(network.nsjail_command(&["/tmp/go/main"]))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
         start_child_process(nsjail_cmd, NSJAIL_PATH.as_str()).await?
@@ -298,6 +270,88 @@ func Run(req Req) (interface{{}}, error){{
     read_result(job_dir).await
 }
 
+async fn build_go_binary(
+    job_id: &Uuid,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    base_internal_url: &str,
+    worker_name: &str,
+    w_id: &str,
+) -> error::Result<()> {
+    let mut build_go_cmd = Command::new(GO_PATH.as_str());
+    build_go_cmd
+        .current_dir(job_dir)
+        .env_clear()
+        .env("PATH", PATH_ENV.as_str())
+        .env("BASE_INTERNAL_URL", base_internal_url)
+        .env("GOPATH", GO_CACHE_DIR)
+        .env("HOME", HOME_ENV.as_str())
+        .args(vec!["build", "main.go"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(url) = offline_go_proxy_url().await {
+        build_go_cmd.env("GOPROXY", url).env("GOSUMDB", "off");
+    }
+    let build_go_process = start_child_process(build_go_cmd, GO_PATH.as_str()).await?;
+    handle_child(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        build_go_process,
+        false,
+        worker_name,
+        w_id,
+        "go build",
+        None,
+        false,
+    )
+    .await?;
+    Ok(())
+}
+
+// the inner package exposes the arguments of main as a Req struct so that the wrapper can
+// deserialize them from json
+async fn write_go_runner(job_dir: &str, inner_content: &str) -> error::Result<()> {
+    let sig = windmill_parser_go::parse_go_sig(&inner_content)?;
+    let spread = &sig
+        .args
+        .clone()
+        .into_iter()
+        .map(|x| format!("req.{}", capitalize(&x.name)))
+        .join(", ");
+    let req_body = &sig
+        .args
+        .into_iter()
+        .map(|x| {
+            format!(
+                "{} {} `json:\"{}\"`",
+                capitalize(&x.name),
+                windmill_parser_go::otyp_to_string(x.otyp),
+                x.name
+            )
+        })
+        .join("\n");
+    let runner_content: String = format!(
+        r#"package inner
+type Req struct {{
+    {req_body}
+}}
+
+func Run(req Req) (interface{{}}, error){{
+    return main({spread})
+}}
+
+"#,
+    );
+    write_file(&format!("{job_dir}/inner"), "runner.go", &runner_content).await?;
+    Ok(())
+}
+
 async fn gen_go_mod(
     inner_content: &str,
     job_dir: &str,
@@ -406,6 +460,9 @@ pub async fn install_go_dependencies(
         .args(vec!["mod", mod_command])
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
+    if let Some(url) = offline_go_proxy_url().await {
+        child_cmd.env("GOPROXY", url).env("GOSUMDB", "off");
+    }
     let child_process = start_child_process(child_cmd, GO_PATH.as_str()).await?;
 
     handle_child(
@@ -471,3 +528,201 @@ async fn gen_go_mymod(code: &str, job_dir: &str) -> error::Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "enterprise")]
+use crate::{
+    common::build_envs_map, dedicated_worker::handle_dedicated_process, JobCompletedSender,
+};
+#[cfg(feature = "enterprise")]
+use std::sync::Arc;
+#[cfg(feature = "enterprise")]
+use tokio::sync::mpsc::Receiver;
+#[cfg(feature = "enterprise")]
+use windmill_common::variables;
+
+// reads the args of each job as a json line on stdin and answers on stdout, see dedicated_worker.rs
+#[cfg(feature = "enterprise")]
+const DEDICATED_WRAPPER_CONTENT: &str = r#"package main
+
+import (
+    "bufio"
+    "encoding/json"
+    "fmt"
+    "os"
+    "mymod/inner"
+)
+
+func run(line string) (res_json []byte, err error) {
+    defer func() {
+        if r := recover(); r != nil {
+            err = fmt.Errorf("panic: %v", r)
+        }
+    }()
+    var req inner.Req
+    if err := json.Unmarshal([]byte(line), &req); err != nil {
+        return nil, err
+    }
+    res, err := inner.Run(req)
+    if err != nil {
+        return nil, err
+    }
+    return json.Marshal(res)
+}
+
+func main() {
+    reader := bufio.NewReader(os.Stdin)
+    fmt.Println("start")
+    for {
+        line, err := reader.ReadString('\n')
+        if err != nil || line == "end\n" {
+            return
+        }
+        res_json, err := run(line)
+        if err != nil {
+            res_json, _ = json.Marshal(map[string]interface{}{
+                "error": map[string]string{"name": "ExecutionErr", "message": err.Error()},
+            })
+        }
+        fmt.Println("wm_res:" + string(res_json))
+    }
+}"#;
+
+#[cfg(feature = "enterprise")]
+pub async fn start_worker(
+    requirements_o: Option<String>,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    inner_content: &str,
+    base_internal_url: &str,
+    job_dir: &str,
+    worker_name: &str,
+    envs: HashMap<String, String>,
+    w_id: &str,
+    script_path: &str,
+    token: &str,
+    job_completed_tx: JobCompletedSender,
+    jobs_rx: Receiver<Arc<QueuedJob>>,
+    killpill_rx: tokio::sync::broadcast::Receiver<()>,
+) -> error::Result<()> {
+    let mut logs = "".to_string();
+    let mut mem_peak: i32 = 0;
+    let mut canceled_by: Option<CanceledBy> = None;
+    let context = variables::get_reserved_variables(
+        w_id,
+        &token,
+        "dedicated_worker@windmill.dev",
+        "dedicated_worker",
+        "NOT_AVAILABLE",
+        "dedicated_worker",
+        Some(script_path.to_string()),
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .to_vec();
+
+    let context_envs = build_envs_map(context).await;
+
+    let hash = calculate_hash(&format!(
+        "dedicated{}{}",
+        inner_content,
+        requirements_o
+            .as_ref()
+            .map(|x| x.to_string())
+            .unwrap_or_default()
+    ));
+    let bin_path = format!("{}/{}", GO_BIN_CACHE_DIR, hash);
+    // one directory per script as the steps of a flow share the same dedicated job_dir
+    let job_dir = &format!("{job_dir}/go_{hash}");
+    let _ = tokio::fs::remove_dir_all(job_dir).await;
+    DirBuilder::new().recursive(true).create(job_dir).await?;
+
+    if tokio::fs::metadata(&bin_path).await.is_err() {
+        logs.push_str("\n\n--- GO DEPENDENCIES SETUP ---\n");
+        let (skip_go_mod, skip_tidy) = if let Some(requirements) = requirements_o {
+            gen_go_mod(inner_content, job_dir, &requirements).await?
+        } else {
+            (false, false)
+        };
+        install_go_dependencies(
+            &Uuid::nil(),
+            inner_content,
+            &mut logs,
+            &mut mem_peak,
+            &mut canceled_by,
+            job_dir,
+            db,
+            true,
+            skip_go_mod,
+            skip_tidy,
+            worker_name,
+            w_id,
+        )
+        .await?;
+
+        write_file(job_dir, "main.go", DEDICATED_WRAPPER_CONTENT).await?;
+        write_go_runner(job_dir, inner_content).await?;
+        build_go_binary(
+            &Uuid::nil(),
+            db,
+            &mut logs,
+            &mut mem_peak,
+            &mut canceled_by,
+            job_dir,
+            base_internal_url,
+            worker_name,
+            w_id,
+        )
+        .await?;
+
+        DirBuilder::new().recursive(true).create(&bin_path).await?;
+        tokio::fs::copy(format!("{job_dir}/main"), format!("{bin_path}/main")).await?;
+        logs.push_str(&format!("write cached binary: {}\n", bin_path));
+    } else {
+        tokio::fs::copy(format!("{bin_path}/main"), format!("{job_dir}/main")).await?;
+        logs.push_str(&format!("found cached binary: {bin_path}/main\n"));
+    }
+    logs.push_str("\n\n--- GO CODE EXECUTION ---\n");
+    set_logs(&logs, &Uuid::nil(), db).await;
+
+    let reserved_variables = windmill_common::variables::get_reserved_variables(
+        w_id,
+        token,
+        "dedicated_worker",
+        "dedicated_worker",
+        Uuid::nil().to_string().as_str(),
+        "dedicated_worker",
+        Some(script_path.to_string()),
+        None,
+        None,
+        None,
+        None,
+    )
+    .await;
+
+    let mut proc_envs = HashMap::new();
+    proc_envs.insert("PATH".to_string(), PATH_ENV.to_string());
+    proc_envs.insert("TZ".to_string(), TZ_ENV.to_string());
+    proc_envs.insert(
+        "BASE_INTERNAL_URL".to_string(),
+        base_internal_url.to_string(),
+    );
+    proc_envs.insert("GOPATH".to_string(), GO_CACHE_DIR.to_string());
+    proc_envs.insert("HOME".to_string(), HOME_ENV.to_string());
+    handle_dedicated_process(
+        &format!("{job_dir}/main"),
+        job_dir,
+        context_envs,
+        envs,
+        reserved_variables,
+        proc_envs,
+        vec![],
+        killpill_rx,
+        job_completed_tx,
+        token,
+        jobs_rx,
+        worker_name,
+    )
+    .await
+}
@@ -0,0 +1,371 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use aws_sdk_s3::{
+    config::{BehaviorVersion, Credentials, Region},
+    primitives::ByteStream,
+};
+use itertools::Itertools;
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use windmill_common::{
+    error::{self, Error},
+    jobs::QueuedJob,
+    DB,
+};
+
+use crate::{
+    create_token_for_owner, AuthedClient, AuthedClientBackgroundTask, SCRIPT_TOKEN_EXPIRY,
+};
+
+/// Directory, relative to the working directory of scripts, whose files are kept as artifacts
+pub const ARTIFACTS_DIR: &str = "artifacts";
+const ARTIFACTS_S3_PREFIX: &str = "windmill_artifacts";
+
+lazy_static::lazy_static! {
+    static ref MAX_ARTIFACTS_COUNT: usize = std::env::var("JOB_ARTIFACTS_MAX_COUNT")
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(100);
+    static ref MAX_ARTIFACTS_SIZE: u64 = std::env::var("JOB_ARTIFACTS_MAX_SIZE_MB")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(100) * 1024 * 1024;
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum LargeFileStorage {
+    S3Storage { s3_resource_path: String },
+}
+
+#[derive(Deserialize)]
+struct S3Resource {
+    bucket: String,
+    region: String,
+    #[serde(rename = "endPoint")]
+    endpoint: String,
+    #[serde(rename = "useSSL")]
+    use_ssl: bool,
+    #[serde(rename = "accessKey")]
+    access_key: Option<String>,
+    #[serde(rename = "secretKey")]
+    secret_key: Option<String>,
+    #[serde(rename = "pathStyle")]
+    path_style: bool,
+}
+
+pub async fn create_artifacts_dir(job_dir: &str) -> error::Result<()> {
+    tokio::fs::create_dir_all(format!("{job_dir}/{ARTIFACTS_DIR}")).await?;
+    Ok(())
+}
+
+/// Uploads the files written by the job in its artifacts dir to the large file storage of the
+/// workspace, or to the database when none is set, and links them to the job. Failing to do so
+/// is reported in the logs but does not fail the job.
+pub async fn store_job_artifacts(
+    db: &DB,
+    client: &AuthedClientBackgroundTask,
+    job: &QueuedJob,
+    job_dir: &str,
+    logs: &mut String,
+) {
+    match upload_artifacts(db, client, job, job_dir).await {
+        Ok(0) => (),
+        Ok(n) => logs.push_str(&format!("\nstored {n} artifacts of the job\n")),
+        Err(e) => {
+            tracing::error!(job_id = %job.id, "could not store the artifacts of the job: {e}");
+            logs.push_str(&format!(
+                "\nCould not store the artifacts of the job: {e}\n"
+            ))
+        }
+    }
+}
+
+/// Regular files of the artifacts dir, recursively, with their path relative to it. The dir
+/// itself must be a real directory and symlinks are skipped, so that a job cannot make the
+/// worker upload files from outside its job dir.
+async fn list_artifacts(dir: &str) -> error::Result<Vec<String>> {
+    match tokio::fs::symlink_metadata(dir).await {
+        Ok(metadata) if metadata.is_dir() => (),
+        Ok(_) => {
+            return Err(Error::ExecutionErr(format!(
+                "{ARTIFACTS_DIR} must be a directory"
+            )))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    }
+    let mut files = vec![];
+    let mut dirs = vec![String::new()];
+    while let Some(rel) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(format!("{dir}/{rel}")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let rel_path = if rel.is_empty() {
+                name
+            } else {
+                format!("{rel}/{name}")
+            };
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(rel_path);
+            } else if file_type.is_file() {
+                files.push(rel_path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads an artifact, at most `limit` bytes of it. The job may still have processes swapping
+/// its files, so every directory of the path is checked again and the file is opened without
+/// following symlinks.
+async fn read_artifact(dir: &str, name: &str, limit: u64) -> error::Result<Vec<u8>> {
+    if let Some((parents, _)) = name.rsplit_once('/') {
+        let mut parent = dir.to_string();
+        for component in parents.split('/') {
+            parent = format!("{parent}/{component}");
+            if !tokio::fs::symlink_metadata(&parent).await?.is_dir() {
+                return Err(Error::ExecutionErr(format!(
+                    "{parent} is no longer a directory"
+                )));
+            }
+        }
+    }
+    // non blocking so that opening a fifo does not wait for a writer, it is then rejected
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW | nix::libc::O_NONBLOCK)
+        .open(format!("{dir}/{name}"))
+        .await
+        .map_err(|e| Error::ExecutionErr(format!("could not open the artifact {name}: {e}")))?;
+    if !file.metadata().await?.is_file() {
+        return Err(Error::ExecutionErr(format!(
+            "the artifact {name} is no longer a regular file"
+        )));
+    }
+    let mut content = vec![];
+    file.take(limit).read_to_end(&mut content).await?;
+    Ok(content)
+}
+
+async fn upload_artifacts(
+    db: &DB,
+    client: &AuthedClientBackgroundTask,
+    job: &QueuedJob,
+    job_dir: &str,
+) -> error::Result<usize> {
+    let dir = format!("{job_dir}/{ARTIFACTS_DIR}");
+    let names = list_artifacts(&dir).await?;
+    if names.is_empty() {
+        return Ok(0);
+    }
+    if names.len() > *MAX_ARTIFACTS_COUNT {
+        return Err(Error::ExecutionErr(format!(
+            "{} files are more than the {} artifacts a job can keep",
+            names.len(),
+            *MAX_ARTIFACTS_COUNT
+        )));
+    }
+
+    // sizes are those of the bytes read, metadata cannot be trusted (procfs reports 0)
+    let mut files = vec![];
+    let mut total_size = 0;
+    for name in names {
+        let content = read_artifact(&dir, &name, *MAX_ARTIFACTS_SIZE - total_size + 1).await?;
+        total_size += content.len() as u64;
+        if total_size > *MAX_ARTIFACTS_SIZE {
+            return Err(Error::ExecutionErr(format!(
+                "artifacts weigh more than the limit of {} bytes",
+                *MAX_ARTIFACTS_SIZE
+            )));
+        }
+        files.push((name, content));
+    }
+
+    let s3 = get_workspace_s3_resource(db, &client.get_authed().await, &job.workspace_id)
+        .await?
+        .map(|resource| (build_s3_client(&resource), resource.bucket));
+
+    let count = files.len();
+    for (name, content) in files {
+        let size = content.len() as i64;
+        let (s3_key, content) = if let Some((s3_client, bucket)) = s3.as_ref() {
+            let key = format!("{ARTIFACTS_S3_PREFIX}/{}/{name}", job.id);
+            s3_client
+                .put_object()
+                .bucket(bucket)
+                .key(&key)
+                .body(ByteStream::from(content))
+                .send()
+                .await
+                .map_err(|e| Error::InternalErr(format!("could not upload {name}: {e}")))?;
+            (Some(key), None)
+        } else {
+            (None, Some(content))
+        };
+        sqlx::query(
+            "INSERT INTO job_artifact \
+             (workspace_id, job_id, name, size, s3_key, content, permissioned_as, email) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (job_id, name) DO UPDATE \
+             SET size = EXCLUDED.size, s3_key = EXCLUDED.s3_key, content = EXCLUDED.content, \
+             created_at = now()",
+        )
+        .bind(&job.workspace_id)
+        .bind(job.id)
+        .bind(&name)
+        .bind(size)
+        .bind(s3_key)
+        .bind(content)
+        .bind(&job.permissioned_as)
+        .bind(&job.email)
+        .execute(db)
+        .await?;
+    }
+    Ok(count)
+}
+
+/// Deletes the artifacts of the jobs that were deleted, notably once past their retention period,
+/// along with their objects in the large file storage of the workspace
+pub async fn delete_expired_artifacts(db: &DB, base_internal_url: &str) {
+    let deleted = sqlx::query_as::<_, (String, String, String, Option<String>)>(
+        "DELETE FROM job_artifact WHERE created_at <= now() - interval '1 hour' \
+         AND NOT EXISTS (SELECT 1 FROM completed_job WHERE completed_job.id = job_artifact.job_id) \
+         AND NOT EXISTS (SELECT 1 FROM queue WHERE queue.id = job_artifact.job_id) \
+         RETURNING workspace_id, permissioned_as, email, s3_key",
+    )
+    .fetch_all(db)
+    .await;
+
+    let deleted = match deleted {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            tracing::error!("Error deleting expired job artifacts: {e}");
+            return;
+        }
+    };
+    if deleted.is_empty() {
+        return;
+    }
+    tracing::info!("deleted {} expired job artifacts", deleted.len());
+
+    let s3_keys = deleted
+        .into_iter()
+        .filter_map(|(w_id, owner, email, s3_key)| Some(((w_id, owner, email), s3_key?)))
+        .into_group_map();
+    for ((w_id, owner, email), keys) in s3_keys {
+        if let Err(e) =
+            delete_s3_artifacts(db, base_internal_url, &w_id, &owner, &email, &keys).await
+        {
+            tracing::error!(
+                "Error deleting {} expired job artifacts from the storage of {w_id}: {e}",
+                keys.len()
+            );
+        }
+    }
+}
+
+async fn delete_s3_artifacts(
+    db: &DB,
+    base_internal_url: &str,
+    w_id: &str,
+    owner: &str,
+    email: &str,
+    keys: &[String],
+) -> error::Result<()> {
+    let token = create_token_for_owner(
+        db,
+        w_id,
+        owner,
+        "ephemeral-script",
+        *SCRIPT_TOKEN_EXPIRY,
+        email,
+    )
+    .await?;
+    let client = AuthedClient {
+        base_internal_url: base_internal_url.to_string(),
+        workspace: w_id.to_string(),
+        token,
+        force_client: None,
+    };
+    let Some(resource) = get_workspace_s3_resource(db, &client, w_id).await? else {
+        return Ok(());
+    };
+    let s3_client = build_s3_client(&resource);
+    for key in keys {
+        s3_client
+            .delete_object()
+            .bucket(&resource.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| Error::InternalErr(format!("could not delete {key}: {e}")))?;
+    }
+    Ok(())
+}
+
+async fn get_workspace_s3_resource(
+    db: &DB,
+    client: &AuthedClient,
+    w_id: &str,
+) -> error::Result<Option<S3Resource>> {
+    let raw_lfs = sqlx::query_scalar::<_, Option<serde_json::Value>>(
+        "SELECT large_file_storage FROM workspace_settings WHERE workspace_id = $1",
+    )
+    .bind(w_id)
+    .fetch_optional(db)
+    .await?
+    .flatten();
+
+    let Some(raw_lfs) = raw_lfs else {
+        return Ok(None);
+    };
+    let LargeFileStorage::S3Storage { s3_resource_path } = serde_json::from_value(raw_lfs)
+        .map_err(|e| Error::InternalErr(format!("invalid large file storage setting: {e}")))?;
+    let path = s3_resource_path
+        .strip_prefix("$res:")
+        .unwrap_or(&s3_resource_path);
+    let resource = client
+        .get_resource_value_interpolated::<S3Resource>(path, None)
+        .await
+        .map_err(|e| {
+            Error::InternalErr(format!(
+                "could not fetch the large file storage resource {path}: {e}"
+            ))
+        })?;
+    Ok(Some(resource))
+}
+
+fn build_s3_client(s3_resource: &S3Resource) -> aws_sdk_s3::Client {
+    let endpoint = if s3_resource.endpoint.starts_with("http://")
+        || s3_resource.endpoint.starts_with("https://")
+    {
+        s3_resource.endpoint.clone()
+    } else if s3_resource.use_ssl {
+        format!("https://{}", s3_resource.endpoint)
+    } else {
+        format!("http://{}", s3_resource.endpoint)
+    };
+    let mut s3_config_builder = aws_sdk_s3::Config::builder()
+        .endpoint_url(endpoint)
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new(s3_resource.region.clone()))
+        .force_path_style(s3_resource.path_style);
+    if let Some(access_key) = s3_resource.access_key.as_ref() {
+        s3_config_builder = s3_config_builder.credentials_provider(Credentials::new(
+            access_key,
+            s3_resource.secret_key.clone().unwrap_or_default(),
+            None,
+            None,
+            "s3_storage",
+        ));
+    }
+    aws_sdk_s3::Client::from_conf(s3_config_builder.build())
+}
@@ -98,6 +98,9 @@ impl TimersPermission for PermissionsContainer {
 
 pub struct OptAuthedClient(Option<AuthedClient>);
 
+/// Flow whose expression is evaluated, which the variables and resources it reads are audited with
+struct FlowJobId(Option<Uuid>);
+
 pub async fn eval_timeout(
     expr: String,
     transform_context: HashMap<String, Arc<Box<RawValue>>>,
@@ -214,6 +217,7 @@ pub async fn eval_timeout(
                     .build().unwrap());
                 }
                 op_state.put(OptAuthedClient(client));
+                op_state.put(FlowJobId(by_id.as_ref().map(|x| x.flow_job)));
                 op_state.put(TransformContext {
                     flow_input: if has_flow_input { flow_input } else { None },
                     envs: transform_context
@@ -430,8 +434,11 @@ async fn op_variable(
 ) -> Result<String, anyhow::Error> {
     let path = &args[0];
     let client = op_state.borrow().borrow::<OptAuthedClient>().0.clone();
+    let flow_job_id = op_state.borrow().borrow::<FlowJobId>().0;
     if let Some(client) = client {
-        Ok(client.get_variable_value(path).await?)
+        Ok(client
+            .get_variable_value(path, flow_job_id.map(|x| x.to_string()))
+            .await?)
     } else {
         anyhow::bail!("No client found in op state");
     }
@@ -486,8 +493,11 @@ async fn op_resource(
     let path = &args[0];
 
     let client = op_state.borrow().borrow::<OptAuthedClient>().0.clone();
+    let flow_job_id = op_state.borrow().borrow::<FlowJobId>().0;
     if let Some(client) = client {
-        client.get_resource_value_interpolated(path, None).await
+        client
+            .get_resource_value_interpolated(path, flow_job_id.map(|x| x.to_string()))
+            .await
     } else {
         anyhow::bail!("No client found in op state");
     }
@@ -15,10 +15,15 @@ mod deno_executor;
 mod global_cache;
 mod go_executor;
 mod graphql_executor;
+#[cfg(feature = "s3")]
+pub mod job_artifacts;
 mod js_eval;
 mod mysql_executor;
+mod network_policy;
+mod package_mirror;
 mod pg_executor;
 mod python_executor;
 mod worker;
 mod worker_flow;
+mod zygote;
 pub use worker::*;
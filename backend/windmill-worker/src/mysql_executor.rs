@@ -31,6 +31,35 @@ pub async fn do_mysql(
     query: &str,
     db: &sqlx::Pool<sqlx::Postgres>,
 ) -> windmill_common::error::Result<Box<RawValue>> {
+    let (database, _) = get_mysql_database(job, client, db).await?;
+
+    let pool = mysql_async::Pool::new(mysql_opts(database));
+    let mut conn = pool.get_conn().await.map_err(to_anyhow)?;
+
+    let statement_values = mysql_statement_values(job, query)?;
+    let rows: Vec<Row> = conn
+        .exec(query, statement_values)
+        .await
+        .map_err(to_anyhow)?;
+    let rows = rows
+        .into_iter()
+        .map(|x| convert_row_to_value(x))
+        .collect::<Vec<serde_json::Value>>();
+
+    drop(conn);
+
+    pool.disconnect().await.map_err(to_anyhow)?;
+
+    // And then check that we got back the same string we sent over.
+    return Ok(windmill_common::worker::to_raw_value(&json!(rows)));
+}
+
+// also returns the raw database argument, used to know when a pinned connection can be reused
+async fn get_mysql_database(
+    job: &QueuedJob,
+    client: &AuthedClientBackgroundTask,
+    db: &sqlx::Pool<sqlx::Postgres>,
+) -> windmill_common::error::Result<(MysqlDatabase, String)> {
     let args = build_args_map(job, client, db).await?.map(Json);
     let job_args = if args.is_some() {
         args.as_ref()
@@ -38,13 +67,16 @@ pub async fn do_mysql(
         job.args.as_ref()
     };
 
-    let database = if let Some(db) = job_args.and_then(|x| x.get("database")) {
-        serde_json::from_str::<MysqlDatabase>(db.get())
-            .map_err(|e| Error::ExecutionErr(e.to_string()))?
+    if let Some(db) = job_args.and_then(|x| x.get("database")) {
+        let database = serde_json::from_str::<MysqlDatabase>(db.get())
+            .map_err(|e| Error::ExecutionErr(e.to_string()))?;
+        Ok((database, db.get().to_string()))
     } else {
-        return Err(Error::BadRequest("Missing database argument".to_string()));
-    };
+        Err(Error::BadRequest("Missing database argument".to_string()))
+    }
+}
 
+fn mysql_opts(database: MysqlDatabase) -> OptsBuilder {
     let opts = OptsBuilder::default()
         .db_name(Some(database.database))
         .user(database.user)
@@ -52,7 +84,7 @@ pub async fn do_mysql(
         .ip_or_hostname(database.host)
         .tcp_port(database.port.unwrap_or(3306));
 
-    let opts = if database.ssl.unwrap_or(false) {
+    if database.ssl.unwrap_or(false) {
         opts.ssl_opts({
             SslOpts::default()
                 .with_danger_skip_domain_validation(true)
@@ -60,11 +92,10 @@ pub async fn do_mysql(
         })
     } else {
         opts
-    };
-
-    let pool = mysql_async::Pool::new(opts);
-    let mut conn = pool.get_conn().await.map_err(to_anyhow)?;
+    }
+}
 
+fn mysql_statement_values(job: &QueuedJob, query: &str) -> windmill_common::error::Result<Params> {
     let sig = parse_mysql_sig(&query)
         .map_err(|x| Error::ExecutionErr(x.to_string()))?
         .args;
@@ -126,28 +157,7 @@ pub async fn do_mysql(
             _ => {}
         }
     }
-    let rows: Vec<Row> = conn
-        .exec(
-            query,
-            match statement_values {
-                Params::Positional(v) => Params::Positional(v),
-                Params::Named(m) => Params::Named(m),
-                _ => Params::Empty,
-            },
-        )
-        .await
-        .map_err(to_anyhow)?;
-    let rows = rows
-        .into_iter()
-        .map(|x| convert_row_to_value(x))
-        .collect::<Vec<serde_json::Value>>();
-
-    drop(conn);
-
-    pool.disconnect().await.map_err(to_anyhow)?;
-
-    // And then check that we got back the same string we sent over.
-    return Ok(windmill_common::worker::to_raw_value(&json!(rows)));
+    Ok(statement_values)
 }
 
 fn string_date_to_mysql_date(s: &str) -> mysql_async::Value {
@@ -223,3 +233,120 @@ fn convert_mysql_value_to_json(v: mysql_async::Value, c: ColumnType) -> serde_js
         },
     };
 }
+
+#[cfg(feature = "enterprise")]
+use crate::{worker::process_result, JobCompletedSender};
+#[cfg(feature = "enterprise")]
+use mysql_async::{Conn, Statement};
+#[cfg(feature = "enterprise")]
+use std::sync::Arc;
+#[cfg(feature = "enterprise")]
+use tokio::sync::{mpsc::Receiver, RwLock};
+
+#[cfg(feature = "enterprise")]
+struct PinnedMysqlConnection {
+    database: String,
+    conn: Conn,
+    statement: Statement,
+}
+
+#[cfg(feature = "enterprise")]
+async fn run_pinned_query(
+    job: &QueuedJob,
+    client: &AuthedClientBackgroundTask,
+    query: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    pinned: &mut Option<PinnedMysqlConnection>,
+) -> windmill_common::error::Result<Box<RawValue>> {
+    let (database, database_raw) = get_mysql_database(job, client, db).await?;
+
+    if !pinned.as_ref().is_some_and(|p| p.database == database_raw) {
+        if let Some(previous) = pinned.take() {
+            let _ = previous.conn.disconnect().await;
+        }
+        tracing::info!("Creating new pinned connection for dedicated worker");
+        let mut conn = Conn::new(mysql_opts(database)).await.map_err(to_anyhow)?;
+        let statement = conn.prep(query).await.map_err(to_anyhow)?;
+        *pinned = Some(PinnedMysqlConnection { database: database_raw, conn, statement });
+    }
+    let pinned_conn = pinned.as_mut().unwrap();
+
+    let statement_values = mysql_statement_values(job, query)?;
+    let rows: Vec<Row> = match pinned_conn
+        .conn
+        .exec(&pinned_conn.statement, statement_values)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            // the connection may be broken, the next job will open a new one
+            *pinned = None;
+            return Err(to_anyhow(e).into());
+        }
+    };
+    let rows = rows
+        .into_iter()
+        .map(|x| convert_row_to_value(x))
+        .collect::<Vec<serde_json::Value>>();
+
+    Ok(windmill_common::worker::to_raw_value(&json!(rows)))
+}
+
+#[cfg(feature = "enterprise")]
+/// Runs the jobs of a mysql dedicated worker. The connection to the database is pinned
+/// across jobs and the query is only prepared once per connection.
+pub async fn start_worker(
+    query: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    base_internal_url: &str,
+    job_dir: &str,
+    worker_name: &str,
+    w_id: &str,
+    token: &str,
+    job_completed_tx: JobCompletedSender,
+    mut jobs_rx: Receiver<Arc<QueuedJob>>,
+    mut killpill_rx: tokio::sync::broadcast::Receiver<()>,
+) -> windmill_common::error::Result<()> {
+    let client = AuthedClientBackgroundTask {
+        base_internal_url: base_internal_url.to_string(),
+        workspace: w_id.to_string(),
+        token: Arc::new(RwLock::new(token.to_string())),
+    };
+    let mut pinned: Option<PinnedMysqlConnection> = None;
+    let init_log = format!("dedicated worker: {worker_name}\n\n");
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = killpill_rx.recv() => {
+                tracing::info!("received killpill for dedicated mysql worker");
+                break;
+            },
+            job = jobs_rx.recv() => {
+                if let Some(job) = job {
+                    let result = run_pinned_query(&job, &client, query, db, &mut pinned).await;
+                    process_result(
+                        job,
+                        result,
+                        job_dir,
+                        job_completed_tx.clone(),
+                        init_log.clone(),
+                        0,
+                        None,
+                        None,
+                        token.to_string(),
+                    )
+                    .await?;
+                } else {
+                    tracing::debug!("job channel closed");
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(pinned) = pinned {
+        let _ = pinned.conn.disconnect().await;
+    }
+    Ok(())
+}
@@ -0,0 +1,469 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    os::unix::fs::PermissionsExt,
+    sync::{Arc, Mutex},
+};
+
+use itertools::Itertools;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UnixListener, UnixStream},
+    task::JoinHandle,
+};
+use windmill_common::{
+    error::{self, Error},
+    jobs::QueuedJob,
+    network_policy::{get_network_policy, parse_allowlist, AllowEntry, NetworkMode},
+    scripts::ScriptLang,
+    DB,
+};
+
+use crate::{DISABLE_NSJAIL, SOCAT_PATH};
+
+/// Port the egress proxy is reachable at from within the network namespace of the job
+const EGRESS_PROXY_PORT: u16 = 3128;
+const EGRESS_SOCKET: &str = "egress.sock";
+const INTERNAL_API_SOCKET: &str = "windmill.sock";
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Network access of a job, resolved from the network policy of its script or folder.
+///
+/// Jobs with a restricted policy run in their own network namespace where only the loopback
+/// interface exists. Two unix sockets bind mounted in the sandbox are bridged to loopback ports:
+/// the egress proxy, which jobs reach through HTTP(S)_PROXY and which only connects to the hosts
+/// of the allowlist, and the internal API of windmill, so that the wmill clients keep working.
+pub struct JobNetwork {
+    policy_path: String,
+    mode: NetworkMode,
+    allowlist: Arc<Vec<AllowEntry>>,
+    socket_dir: String,
+    internal_port: u16,
+    violations: Arc<Mutex<Vec<String>>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl JobNetwork {
+    fn full() -> Self {
+        JobNetwork {
+            policy_path: String::new(),
+            mode: NetworkMode::Full,
+            allowlist: Arc::new(vec![]),
+            socket_dir: String::new(),
+            internal_port: 0,
+            violations: Arc::new(Mutex::new(vec![])),
+            tasks: vec![],
+        }
+    }
+
+    pub async fn start(
+        db: &DB,
+        job: &QueuedJob,
+        job_dir: &str,
+        base_internal_url: &str,
+        logs: &mut String,
+    ) -> error::Result<Self> {
+        let policy = match get_network_policy(db, &job.workspace_id, job.script_path()).await? {
+            Some(policy) if policy.mode != NetworkMode::Full => policy,
+            _ => return Ok(Self::full()),
+        };
+        let allowlist = Arc::new(parse_allowlist(&policy.allowlist)?);
+        logs.push_str(&format!(
+            "\nnetwork policy of {}: {}\n",
+            policy.path,
+            match policy.mode {
+                NetworkMode::Allowlist =>
+                    format!("egress allowed to {}", policy.allowlist.join(", ")),
+                _ => "no egress".to_string(),
+            }
+        ));
+
+        let mut network = JobNetwork {
+            policy_path: policy.path,
+            mode: policy.mode,
+            allowlist: allowlist.clone(),
+            socket_dir: job_dir.to_string(),
+            internal_port: 0,
+            violations: Arc::new(Mutex::new(vec![])),
+            tasks: vec![],
+        };
+
+        // deno enforces the policy with its own net permissions
+        if job.language == Some(ScriptLang::Deno) {
+            return Ok(network);
+        }
+        if *DISABLE_NSJAIL {
+            return Err(Error::ExecutionErr(format!(
+                "the network policy of {} requires nsjail, which is disabled on this worker",
+                network.policy_path
+            )));
+        }
+
+        let internal_url = reqwest::Url::parse(base_internal_url).map_err(|e| {
+            Error::InternalErr(format!(
+                "invalid base internal url {base_internal_url}: {e}"
+            ))
+        })?;
+        network.internal_port = internal_url.port_or_known_default().unwrap_or(80);
+        let internal_addr = format!(
+            "{}:{}",
+            internal_url.host_str().unwrap_or("localhost"),
+            network.internal_port
+        );
+
+        let egress = bind_socket(job_dir, EGRESS_SOCKET).await?;
+        let violations = network.violations.clone();
+        network.tasks.push(tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = egress.accept().await else {
+                    break;
+                };
+                let allowlist = allowlist.clone();
+                let violations = violations.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_egress(stream, &allowlist, &violations).await {
+                        tracing::debug!("egress proxy connection failed: {e}");
+                    }
+                });
+            }
+        }));
+
+        let internal_api = bind_socket(job_dir, INTERNAL_API_SOCKET).await?;
+        network.tasks.push(tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = internal_api.accept().await else {
+                    break;
+                };
+                let internal_addr = internal_addr.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut upstream) = TcpStream::connect(&internal_addr).await {
+                        let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+                    }
+                });
+            }
+        }));
+
+        Ok(network)
+    }
+
+    /// Dedicated workers run their script in a long-lived process outside of nsjail, where no
+    /// network policy can be enforced, so they refuse scripts whose policy is restricted
+    pub async fn check_dedicated_worker(
+        db: &DB,
+        w_id: &str,
+        script_path: &str,
+    ) -> error::Result<()> {
+        let Some(policy) = get_network_policy(db, w_id, script_path).await? else {
+            return Ok(());
+        };
+        if policy.mode == NetworkMode::Full {
+            return Ok(());
+        }
+        let reason = if *DISABLE_NSJAIL {
+            "requires nsjail, which is disabled on this worker"
+        } else {
+            "cannot be enforced in a dedicated worker"
+        };
+        Err(Error::ExecutionErr(format!(
+            "the network policy of {} {reason}, remove the dedicated worker setting of {script_path} \
+             or its network restriction",
+            policy.path
+        )))
+    }
+
+    fn is_restricted(&self) -> bool {
+        self.mode != NetworkMode::Full
+    }
+
+    /// Whether the job runs in its own network namespace, bridged to the egress proxy
+    fn is_bridged(&self) -> bool {
+        !self.tasks.is_empty()
+    }
+
+    pub fn clone_newnet(&self) -> bool {
+        self.is_bridged()
+    }
+
+    /// nsjail mounts of the sockets bridged in the sandbox
+    pub fn nsjail_mounts(&self) -> String {
+        if !self.is_bridged() {
+            return String::new();
+        }
+        [EGRESS_SOCKET, INTERNAL_API_SOCKET]
+            .iter()
+            .map(|socket| {
+                format!(
+                    r#"
+mount {{
+    src: "{}/{socket}"
+    dst: "/tmp/{socket}"
+    is_bind: true
+    rw: true
+}}
+"#,
+                    self.socket_dir
+                )
+            })
+            .join("")
+    }
+
+    /// Base internal url as seen from within the sandbox of the job
+    pub fn base_internal_url(&self, base_internal_url: &str) -> String {
+        if !self.is_bridged() {
+            return base_internal_url.to_string();
+        }
+        match reqwest::Url::parse(base_internal_url) {
+            Ok(mut url) if url.set_host(Some("127.0.0.1")).is_ok() => {
+                url.as_str().trim_end_matches('/').to_string()
+            }
+            _ => base_internal_url.to_string(),
+        }
+    }
+
+    pub fn proxy_envs(&self) -> Vec<(&'static str, String)> {
+        if !self.is_bridged() {
+            return vec![];
+        }
+        let proxy = format!("http://127.0.0.1:{EGRESS_PROXY_PORT}");
+        vec![
+            ("HTTP_PROXY", proxy.clone()),
+            ("HTTPS_PROXY", proxy.clone()),
+            ("http_proxy", proxy.clone()),
+            ("https_proxy", proxy),
+            ("NO_PROXY", "localhost,127.0.0.1".to_string()),
+            ("no_proxy", "localhost,127.0.0.1".to_string()),
+        ]
+    }
+
+    /// Command to run in nsjail, started after the loopback bridges of the sockets are listening
+    pub fn nsjail_command(&self, command: &[&str]) -> Vec<String> {
+        let command = command.iter().map(|x| x.to_string());
+        if !self.is_bridged() {
+            return command.collect();
+        }
+        let socat = SOCAT_PATH.as_str();
+        let bridges = [
+            (EGRESS_PROXY_PORT, EGRESS_SOCKET),
+            (self.internal_port, INTERNAL_API_SOCKET),
+        ];
+        let mut script = String::new();
+        for (port, socket) in bridges {
+            script.push_str(&format!(
+                "{socat} TCP-LISTEN:{port},bind=127.0.0.1,fork,reuseaddr \
+                 UNIX-CONNECT:/tmp/{socket} &\n\
+                 i=0; until {socat} -u /dev/null TCP:127.0.0.1:{port} 2>/dev/null \
+                 || [ $i -ge 200 ]; do i=$((i+1)); sleep 0.01; done\n"
+            ));
+        }
+        script.push_str("exec \"$@\"");
+        [
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            script,
+            "sh".to_string(),
+        ]
+        .into_iter()
+        .chain(command)
+        .collect()
+    }
+
+    /// Net permissions of deno jobs, which cannot be restricted to wildcard domains or CIDRs
+    pub fn deno_allow_net(&self, base_internal_url: &str, logs: &mut String) -> Option<String> {
+        if !self.is_restricted() {
+            return None;
+        }
+        let mut hosts = vec![];
+        if let Ok(url) = reqwest::Url::parse(base_internal_url) {
+            if let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) {
+                hosts.push(format!("{host}:{port}"));
+            }
+        }
+        for entry in self.allowlist.iter() {
+            match entry {
+                AllowEntry::Host(host) if !host.starts_with("*.") => hosts.push(host.clone()),
+                AllowEntry::Cidr(IpAddr::V4(ip), 32) => hosts.push(ip.to_string()),
+                AllowEntry::Cidr(IpAddr::V6(ip), 128) => hosts.push(format!("[{ip}]")),
+                _ => logs.push_str(&format!(
+                    "\nnetwork policy entry {entry} is not supported by deno and is ignored"
+                )),
+            }
+        }
+        Some(format!("--allow-net={}", hosts.join(",")))
+    }
+
+    /// Stop the bridges of the job and report the connections the egress proxy refused
+    pub fn finish(self, logs: &mut String) {
+        let violations = self
+            .violations
+            .lock()
+            .map(|x| x.clone())
+            .unwrap_or_default();
+        for violation in violations {
+            logs.push_str(&format!(
+                "\nnetwork policy of {} blocked egress to {violation}",
+                self.policy_path
+            ));
+        }
+    }
+}
+
+impl Drop for JobNetwork {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn bind_socket(job_dir: &str, name: &str) -> error::Result<UnixListener> {
+    let path = format!("{job_dir}/{name}");
+    let _ = tokio::fs::remove_file(&path).await;
+    let listener = UnixListener::bind(&path)?;
+    // the user of the sandbox may be mapped to another uid than the one of the worker
+    tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o777)).await?;
+    Ok(listener)
+}
+
+fn parse_authority(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        None
+    } else {
+        Some((host.to_string(), port))
+    }
+}
+
+/// Addresses to connect to for a destination, as long as the allowlist permits it. Hostnames
+/// allowed through a CIDR are resolved by the proxy so that all their addresses get checked
+async fn resolve_allowed(
+    allowlist: &[AllowEntry],
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return if allowlist.iter().any(|x| x.matches_ip(&ip)) {
+            Ok(vec![SocketAddr::new(ip, port)])
+        } else {
+            Err("address not in the allowlist".to_string())
+        };
+    }
+    let host_allowed = allowlist.iter().any(|x| x.matches_host(host));
+    if !host_allowed && !allowlist.iter().any(|x| matches!(x, AllowEntry::Cidr(..))) {
+        return Err("host not in the allowlist".to_string());
+    }
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("could not resolve host: {e}"))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        Err("could not resolve host".to_string())
+    } else if host_allowed
+        || addrs
+            .iter()
+            .all(|addr| allowlist.iter().any(|x| x.matches_ip(&addr.ip())))
+    {
+        Ok(addrs)
+    } else {
+        Err("host not in the allowlist".to_string())
+    }
+}
+
+/// Serve one connection of the egress proxy: CONNECT tunnels for https and absolute-form
+/// requests for plain http. Both are bound to the destination checked against the allowlist
+async fn handle_egress(
+    mut stream: UnixStream,
+    allowlist: &[AllowEntry],
+    violations: &Mutex<Vec<String>>,
+) -> error::Result<()> {
+    let mut buf = Vec::with_capacity(4096);
+    let head_len = loop {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_REQUEST_HEAD {
+            stream
+                .write_all(b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let tunnel = method.eq_ignore_ascii_case("CONNECT");
+    let destination = if tunnel {
+        parse_authority(target, 443)
+    } else {
+        reqwest::Url::parse(target)
+            .ok()
+            .filter(|url| url.scheme() == "http")
+            .and_then(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)))
+    };
+    let Some((host, port)) = destination else {
+        stream
+            .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\nunsupported proxy request\n")
+            .await?;
+        return Ok(());
+    };
+
+    let addrs = match resolve_allowed(allowlist, &host, port).await {
+        Ok(addrs) => addrs,
+        Err(reason) => {
+            if let Ok(mut violations) = violations.lock() {
+                violations.push(format!("{host}:{port} ({reason})"));
+            }
+            let body =
+                format!("egress to {host}:{port} is blocked by the network policy: {reason}\n");
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 403 Forbidden\r\nContent-Type: text/plain\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let mut upstream = match TcpStream::connect(&addrs[..]).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            stream
+                .write_all(format!("HTTP/1.1 502 Bad Gateway\r\n\r\n{e}\n").as_bytes())
+                .await?;
+            return Ok(());
+        }
+    };
+    if tunnel {
+        stream
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+        upstream.write_all(&buf[head_len..]).await?;
+    } else {
+        upstream.write_all(&buf).await?;
+    }
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}
@@ -0,0 +1,201 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+use std::{collections::HashSet, sync::atomic::Ordering};
+
+use windmill_common::{
+    error::{self, to_anyhow, Error},
+    package_mirror::{artifact_exists, get_or_create_mirror_secret, insert_artifact, GO, NPM},
+    DB,
+};
+
+use crate::{GO_CACHE_DIR, NPM_CONFIG_REGISTRY, OFFLINE_MODE, PACKAGE_MIRROR_URL};
+
+const DEFAULT_NPM_REGISTRY: &str = "https://registry.npmjs.org";
+
+pub async fn init_package_mirror_url(db: &DB, base_internal_url: &str) {
+    match get_or_create_mirror_secret(db).await {
+        Ok(secret) => {
+            *PACKAGE_MIRROR_URL.write().await =
+                Some(format!("{base_internal_url}/api/mirror/{secret}"));
+        }
+        Err(e) => tracing::error!("Could not load the package mirror secret: {e}"),
+    }
+}
+
+/// Url of the server package mirror, only set when workers are in offline mode
+pub async fn offline_mirror_url() -> Option<String> {
+    if OFFLINE_MODE.load(Ordering::Relaxed) {
+        PACKAGE_MIRROR_URL.read().await.clone()
+    } else {
+        None
+    }
+}
+
+pub async fn offline_pip_index_url() -> Option<String> {
+    offline_mirror_url()
+        .await
+        .map(|url| format!("{url}/pip/simple/"))
+}
+
+pub async fn offline_npm_registry_url() -> Option<String> {
+    offline_mirror_url().await.map(|url| format!("{url}/npm/"))
+}
+
+pub async fn offline_go_proxy_url() -> Option<String> {
+    offline_mirror_url().await.map(|url| format!("{url}/go"))
+}
+
+/// Store all the files of a directory as artifacts of the given kind, named after their filename
+pub async fn capture_dir_artifacts(db: &DB, kind: &str, dir: &str) -> error::Result<usize> {
+    let mut captured = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let filename = entry.file_name().to_string_lossy().to_string();
+        let content = tokio::fs::read(entry.path()).await?;
+        if insert_artifact(db, kind, &filename, &content, None).await? {
+            captured += 1;
+        }
+    }
+    Ok(captured)
+}
+
+/// Go module proxies escape uppercase letters of module paths and versions as `!` + lowercase
+fn escape_go_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c.is_ascii_uppercase() {
+            escaped.push('!');
+            escaped.push(c.to_ascii_lowercase());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Store the modules listed in the go.sum of the job from the module download cache
+pub async fn capture_go_artifacts(db: &DB, job_dir: &str) -> error::Result<usize> {
+    let go_sum = match tokio::fs::read_to_string(format!("{job_dir}/go.sum")).await {
+        Ok(go_sum) => go_sum,
+        Err(_) => return Ok(0),
+    };
+
+    let mut seen = HashSet::new();
+    let mut captured = 0;
+    for line in go_sum.lines() {
+        let mut parts = line.split_whitespace();
+        let (module, version) = match (parts.next(), parts.next()) {
+            (Some(module), Some(version)) => (module, version.trim_end_matches("/go.mod")),
+            _ => continue,
+        };
+        if !seen.insert((module.to_string(), version.to_string())) {
+            continue;
+        }
+        let prefix = format!("{}/@v/{}", escape_go_path(module), escape_go_path(version));
+        for ext in ["info", "mod", "zip"] {
+            let path = format!("{prefix}.{ext}");
+            let file = format!("{GO_CACHE_DIR}/pkg/mod/cache/download/{path}");
+            if let Ok(content) = tokio::fs::read(&file).await {
+                if insert_artifact(db, GO, &path, &content, None).await? {
+                    captured += 1;
+                }
+            }
+        }
+    }
+    Ok(captured)
+}
+
+async fn installed_npm_packages(job_dir: &str) -> error::Result<HashSet<(String, String)>> {
+    let mut packages = HashSet::new();
+    let mut dirs = vec![format!("{job_dir}/node_modules")];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path().to_string_lossy().to_string();
+            if name.starts_with('.') || !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if name.starts_with('@') {
+                dirs.push(path);
+                continue;
+            }
+            if let Ok(manifest) = tokio::fs::read(format!("{path}/package.json")).await {
+                if let Ok(manifest) = serde_json::from_slice::<serde_json::Value>(&manifest) {
+                    if let (Some(name), Some(version)) = (
+                        manifest.get("name").and_then(|x| x.as_str()),
+                        manifest.get("version").and_then(|x| x.as_str()),
+                    ) {
+                        packages.insert((name.to_string(), version.to_string()));
+                    }
+                }
+            }
+            dirs.push(format!("{path}/node_modules"));
+        }
+    }
+    Ok(packages)
+}
+
+/// Fetch the tarballs of the packages installed in the node_modules of the job from the
+/// upstream registry and store them along their version manifest
+pub async fn capture_npm_artifacts(db: &DB, job_dir: &str) -> error::Result<usize> {
+    let registry = NPM_CONFIG_REGISTRY
+        .read()
+        .await
+        .clone()
+        .unwrap_or_else(|| DEFAULT_NPM_REGISTRY.to_string());
+    let registry = registry.trim_end_matches('/');
+    let client = reqwest::Client::new();
+
+    let mut captured = 0;
+    for (name, version) in installed_npm_packages(job_dir).await? {
+        let basename = name.rsplit('/').next().unwrap_or(&name);
+        let path = format!("{name}/-/{basename}-{version}.tgz");
+        if artifact_exists(db, NPM, &path).await? {
+            continue;
+        }
+
+        let manifest = client
+            .get(format!("{registry}/{}/{version}", name.replace('/', "%2f")))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(to_anyhow)?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(to_anyhow)?;
+        let tarball = manifest
+            .get("dist")
+            .and_then(|x| x.get("tarball"))
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| {
+                Error::InternalErr(format!("no tarball in the manifest of {name}@{version}"))
+            })?;
+        let content = client
+            .get(tarball)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(to_anyhow)?
+            .bytes()
+            .await
+            .map_err(to_anyhow)?;
+
+        if insert_artifact(db, NPM, &path, &content, Some(manifest)).await? {
+            captured += 1;
+        }
+    }
+    Ok(captured)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
@@ -14,10 +15,11 @@ use serde_json::value::RawValue;
 use serde_json::Map;
 use serde_json::{json, Value};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tokio_postgres::types::IsNull;
 use tokio_postgres::{
     types::{to_sql_checked, ToSql},
-    NoTls, Row,
+    NoTls, Row, RowStream,
 };
 use tokio_postgres::{
     types::{FromSql, Type},
@@ -61,22 +63,8 @@ pub async fn do_postgresql(
 ) -> error::Result<Box<RawValue>> {
     let pg_args = build_args_values(job, client, db).await?;
 
-    let database = if let Some(db) = pg_args.get("database") {
-        serde_json::from_value::<PgDatabase>(db.clone())
-            .map_err(|e| Error::ExecutionErr(e.to_string()))?
-    } else {
-        return Err(Error::BadRequest("Missing database argument".to_string()));
-    };
-    let sslmode = database.sslmode.unwrap_or("prefer".to_string());
-    let database_string = format!(
-        "postgres://{user}:{password}@{host}:{port}/{dbname}?sslmode={sslmode}",
-        user = encode(&database.user.unwrap_or("postgres".to_string())),
-        password = encode(&database.password.unwrap_or("".to_string())),
-        host = encode(&database.host),
-        port = database.port.unwrap_or(5432),
-        dbname = database.dbname,
-        sslmode = sslmode
-    );
+    let database = get_pg_database(&pg_args)?;
+    let (database_string, sslmode) = database.connection_string();
     let database_string_clone = database_string.clone();
 
     RUNNING.store(true, std::sync::atomic::Ordering::Relaxed);
@@ -97,82 +85,20 @@ pub async fn do_postgresql(
     let new_client = if has_cached_con {
         tracing::info!("Using cached connection");
         None
-    } else if sslmode == "require" {
-        tracing::info!("Creating new connection");
-        let mut connector = TlsConnector::builder();
-        if let Some(root_certificate_pem) = database.root_certificate_pem {
-            if !root_certificate_pem.is_empty() {
-                connector.add_root_certificate(
-                    Certificate::from_pem(root_certificate_pem.as_bytes())
-                        .map_err(|e| error::Error::BadConfig(format!("Invalid Certs: {e}")))?,
-                );
-            } else {
-                connector.danger_accept_invalid_certs(true);
-                connector.danger_accept_invalid_hostnames(true);
-            }
-        } else {
-            connector
-                .danger_accept_invalid_certs(true)
-                .danger_accept_invalid_hostnames(true);
-        }
-
-        let (client, connection) = tokio_postgres::connect(
-            &database_string,
-            MakeTlsConnector::new(connector.build().map_err(to_anyhow)?),
-        )
-        .await
-        .map_err(to_anyhow)?;
-
-        let handle = tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                let mut mtex = CONNECTION_CACHE.lock().await;
-                *mtex = None;
-                tracing::error!("connection error: {}", e);
-            }
-        });
-        Some((client, handle))
     } else {
         tracing::info!("Creating new connection");
-        let (client, connection) = tokio_postgres::connect(&database_string, NoTls)
-            .await
-            .map_err(to_anyhow)?;
-        let handle = tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                let mut mtex = CONNECTION_CACHE.lock().await;
-                *mtex = None;
-                tracing::error!("connection error: {}", e);
-            }
-        });
-        Some((client, handle))
+        Some(
+            new_pg_client(
+                &database_string,
+                &sslmode,
+                database.root_certificate_pem,
+                true,
+            )
+            .await?,
+        )
     };
 
-    let mut statement_values: Vec<serde_json::Value> = vec![];
-
-    let sig = parse_pgsql_sig(&query)
-        .map_err(|x| Error::ExecutionErr(x.to_string()))?
-        .args;
-
-    for arg in &sig {
-        statement_values.push(
-            pg_args
-                .get(&arg.name)
-                .map(|x| x.to_owned())
-                .unwrap_or_else(|| serde_json::Value::Null),
-        );
-    }
-
-    let query_params = statement_values
-        .iter()
-        .enumerate()
-        .map(|(i, value)| {
-            let arg_t = &sig[i]
-                .otyp
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Missing otyp for pg arg"))?
-                .to_owned();
-            convert_val(value, arg_t)
-        })
-        .collect::<windmill_common::error::Result<Vec<_>>>()?;
+    let query_params = pg_query_params(query, &pg_args)?;
 
     let (client, handle) = if let Some((client, handle)) = new_client.as_ref() {
         (client, Some(handle))
@@ -186,13 +112,7 @@ pub async fn do_postgresql(
         .await
         .map_err(to_anyhow)?;
 
-    let result = json!(rows
-        .try_collect::<Vec<Row>>()
-        .await
-        .map_err(to_anyhow)?
-        .into_iter()
-        .map(postgres_row_to_json_value)
-        .collect::<Result<Vec<_>, _>>()?);
+    let result = rows_to_json(rows).await?;
     RUNNING.store(false, std::sync::atomic::Ordering::Relaxed);
 
     if let Some(handle) = handle {
@@ -246,6 +166,131 @@ pub async fn do_postgresql(
     return Ok(to_raw_value(&result));
 }
 
+impl PgDatabase {
+    /// Returns the connection string and the sslmode it uses
+    fn connection_string(&self) -> (String, String) {
+        let sslmode = self.sslmode.clone().unwrap_or("prefer".to_string());
+        let database_string = format!(
+            "postgres://{user}:{password}@{host}:{port}/{dbname}?sslmode={sslmode}",
+            user = encode(self.user.as_deref().unwrap_or("postgres")),
+            password = encode(self.password.as_deref().unwrap_or("")),
+            host = encode(&self.host),
+            port = self.port.unwrap_or(5432),
+            dbname = self.dbname,
+            sslmode = sslmode
+        );
+        (database_string, sslmode)
+    }
+}
+
+fn get_pg_database(pg_args: &HashMap<String, Value>) -> error::Result<PgDatabase> {
+    if let Some(db) = pg_args.get("database") {
+        serde_json::from_value::<PgDatabase>(db.clone())
+            .map_err(|e| Error::ExecutionErr(e.to_string()))
+    } else {
+        Err(Error::BadRequest("Missing database argument".to_string()))
+    }
+}
+
+// when reset_cache is set, the shared connection cache is cleared if the connection errors
+async fn new_pg_client(
+    database_string: &str,
+    sslmode: &str,
+    root_certificate_pem: Option<String>,
+    reset_cache: bool,
+) -> error::Result<(tokio_postgres::Client, JoinHandle<()>)> {
+    if sslmode == "require" {
+        let mut connector = TlsConnector::builder();
+        if let Some(root_certificate_pem) = root_certificate_pem {
+            if !root_certificate_pem.is_empty() {
+                connector.add_root_certificate(
+                    Certificate::from_pem(root_certificate_pem.as_bytes())
+                        .map_err(|e| error::Error::BadConfig(format!("Invalid Certs: {e}")))?,
+                );
+            } else {
+                connector.danger_accept_invalid_certs(true);
+                connector.danger_accept_invalid_hostnames(true);
+            }
+        } else {
+            connector
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        let (client, connection) = tokio_postgres::connect(
+            database_string,
+            MakeTlsConnector::new(connector.build().map_err(to_anyhow)?),
+        )
+        .await
+        .map_err(to_anyhow)?;
+
+        let handle = tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                if reset_cache {
+                    let mut mtex = CONNECTION_CACHE.lock().await;
+                    *mtex = None;
+                }
+                tracing::error!("connection error: {}", e);
+            }
+        });
+        Ok((client, handle))
+    } else {
+        let (client, connection) = tokio_postgres::connect(database_string, NoTls)
+            .await
+            .map_err(to_anyhow)?;
+        let handle = tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                if reset_cache {
+                    let mut mtex = CONNECTION_CACHE.lock().await;
+                    *mtex = None;
+                }
+                tracing::error!("connection error: {}", e);
+            }
+        });
+        Ok((client, handle))
+    }
+}
+
+fn pg_query_params(query: &str, pg_args: &HashMap<String, Value>) -> error::Result<Vec<PgType>> {
+    let mut statement_values: Vec<serde_json::Value> = vec![];
+
+    let sig = parse_pgsql_sig(&query)
+        .map_err(|x| Error::ExecutionErr(x.to_string()))?
+        .args;
+
+    for arg in &sig {
+        statement_values.push(
+            pg_args
+                .get(&arg.name)
+                .map(|x| x.to_owned())
+                .unwrap_or_else(|| serde_json::Value::Null),
+        );
+    }
+
+    statement_values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let arg_t = &sig[i]
+                .otyp
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Missing otyp for pg arg"))?
+                .to_owned();
+            convert_val(value, arg_t)
+        })
+        .collect::<windmill_common::error::Result<Vec<_>>>()
+}
+
+async fn rows_to_json(rows: RowStream) -> error::Result<Value> {
+    Ok(json!(rows
+        .try_collect::<Vec<Row>>()
+        .await
+        .map_err(to_anyhow)?
+        .into_iter()
+        .map(postgres_row_to_json_value)
+        .collect::<Result<Vec<_>, _>>()?))
+}
+
 #[derive(Debug)]
 enum PgType {
     String(String),
@@ -552,3 +597,125 @@ impl FromSql<'_> for StringCollector {
         true
     }
 }
+
+#[cfg(feature = "enterprise")]
+use crate::{worker::process_result, JobCompletedSender};
+#[cfg(feature = "enterprise")]
+use tokio::sync::{mpsc::Receiver, RwLock};
+#[cfg(feature = "enterprise")]
+use tokio_postgres::Statement;
+
+#[cfg(feature = "enterprise")]
+struct PinnedPgConnection {
+    database_string: String,
+    client: tokio_postgres::Client,
+    handle: JoinHandle<()>,
+    statement: Statement,
+}
+
+#[cfg(feature = "enterprise")]
+async fn run_pinned_query(
+    job: &QueuedJob,
+    client: &AuthedClientBackgroundTask,
+    query: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    pinned: &mut Option<PinnedPgConnection>,
+) -> error::Result<Box<RawValue>> {
+    let pg_args = build_args_values(job, client, db).await?;
+    let database = get_pg_database(&pg_args)?;
+    let (database_string, sslmode) = database.connection_string();
+
+    let reusable = pinned
+        .as_ref()
+        .is_some_and(|p| p.database_string == database_string && !p.client.is_closed());
+    if !reusable {
+        if let Some(previous) = pinned.take() {
+            previous.handle.abort();
+        }
+        tracing::info!("Creating new pinned connection for dedicated worker");
+        let (client, handle) = new_pg_client(
+            &database_string,
+            &sslmode,
+            database.root_certificate_pem,
+            false,
+        )
+        .await?;
+        let statement = match client.prepare(query).await {
+            Ok(statement) => statement,
+            Err(e) => {
+                handle.abort();
+                return Err(to_anyhow(e).into());
+            }
+        };
+        *pinned = Some(PinnedPgConnection { database_string, client, handle, statement });
+    }
+    let pinned = pinned.as_ref().unwrap();
+
+    let query_params = pg_query_params(query, &pg_args)?;
+    let rows = pinned
+        .client
+        .query_raw(&pinned.statement, query_params)
+        .await
+        .map_err(to_anyhow)?;
+
+    Ok(to_raw_value(&rows_to_json(rows).await?))
+}
+
+#[cfg(feature = "enterprise")]
+/// Runs the jobs of a postgresql dedicated worker. The connection to the database is pinned
+/// across jobs and the query is only prepared once per connection.
+pub async fn start_worker(
+    query: &str,
+    db: &sqlx::Pool<sqlx::Postgres>,
+    base_internal_url: &str,
+    job_dir: &str,
+    worker_name: &str,
+    w_id: &str,
+    token: &str,
+    job_completed_tx: JobCompletedSender,
+    mut jobs_rx: Receiver<Arc<QueuedJob>>,
+    mut killpill_rx: tokio::sync::broadcast::Receiver<()>,
+) -> error::Result<()> {
+    let client = AuthedClientBackgroundTask {
+        base_internal_url: base_internal_url.to_string(),
+        workspace: w_id.to_string(),
+        token: Arc::new(RwLock::new(token.to_string())),
+    };
+    let mut pinned: Option<PinnedPgConnection> = None;
+    let init_log = format!("dedicated worker: {worker_name}\n\n");
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = killpill_rx.recv() => {
+                tracing::info!("received killpill for dedicated postgresql worker");
+                break;
+            },
+            job = jobs_rx.recv() => {
+                if let Some(job) = job {
+                    let result = run_pinned_query(&job, &client, query, db, &mut pinned).await;
+                    process_result(
+                        job,
+                        result,
+                        job_dir,
+                        job_completed_tx.clone(),
+                        init_log.clone(),
+                        0,
+                        None,
+                        None,
+                        token.to_string(),
+                    )
+                    .await?;
+                } else {
+                    tracing::debug!("job channel closed");
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(pinned) = pinned {
+        pinned.handle.abort();
+    }
+    Ok(())
+}
@@ -7,7 +7,7 @@ use sqlx::{Pool, Postgres};
 use tokio::{
     fs::{metadata, DirBuilder, File},
     io::AsyncReadExt,
-    process::Command,
+    process::{Child, Command},
 };
 use uuid::Uuid;
 #[cfg(feature = "enterprise")]
@@ -15,6 +15,7 @@ use windmill_common::ee::{get_license_plan, LicensePlan};
 use windmill_common::{
     error::{self, Error},
     jobs::QueuedJob,
+    package_mirror::PIP,
     utils::calculate_hash,
     worker::WORKER_CONFIG,
     DB,
@@ -37,23 +38,29 @@ lazy_static::lazy_static! {
 
     static ref RELATIVE_IMPORT_REGEX: Regex = Regex::new(r#"(import|from)\s(((u|f)\.)|\.)"#).unwrap();
 
+    static ref TOP_LEVEL_IMPORT_REGEX: Regex =
+        Regex::new(r"(?m)^(?:import|from)\s+([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+
 }
 
 const NSJAIL_CONFIG_DOWNLOAD_PY_CONTENT: &str = include_str!("../nsjail/download.py.config.proto");
 const NSJAIL_CONFIG_RUN_PYTHON3_CONTENT: &str = include_str!("../nsjail/run.python3.config.proto");
 const RELATIVE_PYTHON_LOADER: &str = include_str!("../loader.py");
+const PYTHON_ZYGOTE: &str = include_str!("../zygote.py");
+const PYTHON_ZYGOTE_CLIENT: &str = include_str!("../zygote_client.py");
+const ZYGOTE_SOCKET: &str = "zygote.sock";
 
 #[cfg(feature = "enterprise")]
-use crate::global_cache::{build_tar_and_push, pull_from_tar};
-
-#[cfg(feature = "enterprise")]
-use crate::S3_CACHE_BUCKET;
+use crate::global_cache::{build_tar_and_push, global_cache_backend, pull_from_tar};
 
 use crate::{
     common::{
         create_args_and_out_file, get_reserved_variables, handle_child, read_result, set_logs,
         start_child_process, write_file,
     },
+    network_policy::JobNetwork,
+    package_mirror::{capture_dir_artifacts, offline_pip_index_url},
+    zygote::{add_zygote, find_zygote, zygote_dir, zygote_key, zygote_pool_enabled},
     AuthedClientBackgroundTask, DISABLE_NSJAIL, DISABLE_NUSER, HTTPS_PROXY, HTTP_PROXY,
     LOCK_CACHE_DIR, NO_PROXY, NSJAIL_PATH, PATH_ENV, PIP_CACHE_DIR, PIP_EXTRA_INDEX_URL, TZ_ENV,
 };
@@ -115,11 +122,16 @@ pub async fn pip_compile(
 
     let mut args = vec!["-q", "--no-header", file, "--resolver=backtracking"];
     let pip_extra_index_url = PIP_EXTRA_INDEX_URL.read().await.clone();
-    if let Some(url) = pip_extra_index_url.as_ref() {
-        args.extend(["--extra-index-url", url]);
-    }
-    if let Some(url) = PIP_INDEX_URL.as_ref() {
+    let pip_mirror_url = offline_pip_index_url().await;
+    if let Some(url) = pip_mirror_url.as_ref() {
         args.extend(["--index-url", url]);
+    } else {
+        if let Some(url) = pip_extra_index_url.as_ref() {
+            args.extend(["--extra-index-url", url]);
+        }
+        if let Some(url) = PIP_INDEX_URL.as_ref() {
+            args.extend(["--index-url", url]);
+        }
     }
     if let Some(host) = PIP_TRUSTED_HOST.as_ref() {
         args.extend(["--trusted-host", host]);
@@ -169,6 +181,89 @@ pub async fn pip_compile(
     Ok(lockfile)
 }
 
+/// Download the wheels and sdists of a lockfile to store them in the package mirror
+pub async fn capture_pip_artifacts(
+    job_id: &Uuid,
+    lockfile: &str,
+    logs: &mut String,
+    mem_peak: &mut i32,
+    canceled_by: &mut Option<CanceledBy>,
+    job_dir: &str,
+    db: &Pool<Postgres>,
+    worker_name: &str,
+    w_id: &str,
+) -> error::Result<usize> {
+    let requirements = lockfile
+        .lines()
+        .filter(|x| !x.trim().is_empty() && !x.starts_with("--"))
+        .join("\n");
+    write_file(job_dir, "mirror_requirements.txt", &requirements).await?;
+    let mirror_dir = format!("{job_dir}/mirror");
+
+    let mut args = vec![
+        "-m",
+        "pip",
+        "download",
+        "--no-deps",
+        "--no-color",
+        "--disable-pip-version-check",
+        "-d",
+        &mirror_dir,
+        "-r",
+        "mirror_requirements.txt",
+    ];
+    let pip_extra_index_url = PIP_EXTRA_INDEX_URL.read().await.clone();
+    if let Some(url) = pip_extra_index_url.as_ref() {
+        args.extend(["--extra-index-url", url]);
+    }
+    if let Some(url) = PIP_INDEX_URL.as_ref() {
+        args.extend(["--index-url", url]);
+    }
+    if let Some(host) = PIP_TRUSTED_HOST.as_ref() {
+        args.extend(["--trusted-host", host]);
+    }
+    if let Some(cert_path) = PIP_INDEX_CERT.as_ref() {
+        args.extend(["--cert", cert_path]);
+    }
+    let mut envs = vec![("PATH", PATH_ENV.as_str())];
+    if let Some(http_proxy) = HTTP_PROXY.as_ref() {
+        envs.push(("HTTP_PROXY", http_proxy));
+    }
+    if let Some(https_proxy) = HTTPS_PROXY.as_ref() {
+        envs.push(("HTTPS_PROXY", https_proxy));
+    }
+    if let Some(no_proxy) = NO_PROXY.as_ref() {
+        envs.push(("NO_PROXY", no_proxy));
+    }
+
+    let mut child_cmd = Command::new(PYTHON_PATH.as_str());
+    child_cmd
+        .current_dir(job_dir)
+        .env_clear()
+        .envs(envs)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child_process = start_child_process(child_cmd, PYTHON_PATH.as_str()).await?;
+    handle_child(
+        job_id,
+        db,
+        logs,
+        mem_peak,
+        canceled_by,
+        child_process,
+        false,
+        worker_name,
+        w_id,
+        "pip download",
+        None,
+        false,
+    )
+    .await?;
+
+    capture_dir_artifacts(db, PIP, &mirror_dir).await
+}
+
 #[tracing::instrument(level = "trace", skip_all)]
 pub async fn handle_python_job(
     requirements_o: Option<String>,
@@ -183,6 +278,7 @@ pub async fn handle_python_job(
     client: &AuthedClientBackgroundTask,
     inner_content: &String,
     shared_mount: &str,
+    network: &JobNetwork,
     base_internal_url: &str,
     envs: HashMap<String, String>,
 ) -> windmill_common::error::Result<Box<RawValue>> {
@@ -277,29 +373,25 @@ except Exception as e:
     let mut reserved_variables = get_reserved_variables(job, &client.token, db).await?;
     let additional_python_paths_folders = additional_python_paths.iter().join(":");
 
+    // zygotes are only used without nsjail, where jobs sharing their dependencies are forked from
+    // a zygote that already imported them, unless the job needs the shared dir of its flow
+    let zygote = if zygote_pool_enabled() && shared_mount.is_empty() {
+        get_python_zygote(worker_dir, &additional_python_paths, inner_content).await
+    } else {
+        None
+    };
+
     if !*DISABLE_NSJAIL {
-        let shared_deps = additional_python_paths
-            .into_iter()
-            .map(|pp| {
-                format!(
-                    r#"
-mount {{
-    src: "{pp}"
-    dst: "{pp}"
-    is_bind: true
-    rw: false
-}}
-        "#
-                )
-            })
-            .join("\n");
+        let shared_deps = shared_dependencies_mounts(&additional_python_paths);
         let _ = write_file(
             job_dir,
             "run.config.proto",
             &NSJAIL_CONFIG_RUN_PYTHON3_CONTENT
                 .replace("{JOB_DIR}", job_dir)
                 .replace("{CLONE_NEWUSER}", &(!*DISABLE_NUSER).to_string())
+                .replace("{CLONE_NEWNET}", &network.clone_newnet().to_string())
                 .replace("{SHARED_MOUNT}", shared_mount)
+                .replace("{NETWORK_MOUNTS}", &network.nsjail_mounts())
                 .replace("{SHARED_DEPENDENCIES}", shared_deps.as_str())
                 .replace("{MAIN}", format!("{dirs}/{last}").as_str())
                 .replace(
@@ -309,7 +401,10 @@ mount {{
         )
         .await?;
     } else {
-        reserved_variables.insert("PYTHONPATH".to_string(), additional_python_paths_folders);
+        reserved_variables.insert(
+            "PYTHONPATH".to_string(),
+            additional_python_paths_folders.clone(),
+        );
     }
 
     tracing::info!(
@@ -319,26 +414,42 @@ mount {{
         "started python code execution {}",
         job.id
     );
-    let child = if !*DISABLE_NSJAIL {
+    let child = if let Some(zygote_dir) = zygote.as_ref() {
+        logs.push_str("forking from a warm zygote\n");
+        let client_py = format!("{zygote_dir}/zygote_client.py");
+        let socket = format!("{zygote_dir}/{ZYGOTE_SOCKET}");
+        let mut client_cmd = Command::new(PYTHON_PATH.as_str());
+        // the environment of the client is the one of the job forked by the zygote
+        client_cmd
+            .current_dir(job_dir)
+            .env_clear()
+            .envs(envs)
+            .envs(reserved_variables)
+            .env("PATH", PATH_ENV.as_str())
+            .env("TZ", TZ_ENV.as_str())
+            .env("BASE_INTERNAL_URL", base_internal_url)
+            .env("BASE_URL", base_internal_url)
+            .args(["-I", "-S", client_py.as_str(), socket.as_str(), "wrapper"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        start_child_process(client_cmd, PYTHON_PATH.as_str()).await?
+    } else if !*DISABLE_NSJAIL {
         let mut nsjail_cmd = Command::new(NSJAIL_PATH.as_str());
         nsjail_cmd
             .current_dir(job_dir)
             .env_clear()
             // inject PYTHONPATH here - for some reason I had to do it in nsjail conf
             .envs(reserved_variables)
+            .envs(network.proxy_envs())
             .env("PATH", PATH_ENV.as_str())
             .env("TZ", TZ_ENV.as_str())
-            .env("BASE_INTERNAL_URL", base_internal_url)
-            .env("BASE_URL", base_internal_url)
-            .args(vec![
-                "--config",
-                "run.config.proto",
-                "--",
-                PYTHON_PATH.as_str(),
-                "-u",
-                "-m",
-                "wrapper",
-            ])
+            .env(
+                "BASE_INTERNAL_URL",
+                network.base_internal_url(base_internal_url),
+            )
+            .env("BASE_URL", network.base_internal_url(base_internal_url))
+            .args(["--config", "run.config.proto", "--"])
+            .args(network.nsjail_command(&[PYTHON_PATH.as_str(), "-u", "-m", "wrapper"]))
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
         start_child_process(nsjail_cmd, NSJAIL_PATH.as_str()).await?
@@ -376,6 +487,82 @@ mount {{
     read_result(job_dir).await
 }
 
+fn shared_dependencies_mounts(additional_python_paths: &[String]) -> String {
+    additional_python_paths
+        .iter()
+        .map(|pp| {
+            format!(
+                r#"
+mount {{
+    src: "{pp}"
+    dst: "{pp}"
+    is_bind: true
+    rw: false
+}}
+        "#
+            )
+        })
+        .join("\n")
+}
+
+/// Dir of the zygote of the dependencies once it is ready to fork jobs. If there is none, one
+/// is spawned in the background for the next jobs.
+async fn get_python_zygote(
+    worker_dir: &str,
+    additional_python_paths: &[String],
+    inner_content: &str,
+) -> Option<String> {
+    let key = zygote_key("py", &additional_python_paths.join(":"));
+    if let Some(dir) = find_zygote(&key) {
+        // the socket is only created once the zygote is done importing the dependencies
+        let ready = metadata(format!("{dir}/{ZYGOTE_SOCKET}")).await.is_ok();
+        return ready.then_some(dir);
+    }
+
+    let dir = zygote_dir(worker_dir, &key);
+    match spawn_python_zygote(&dir, additional_python_paths, inner_content).await {
+        Ok(child) => add_zygote(key, dir, child),
+        Err(e) => tracing::error!("could not spawn python zygote {key}: {e}"),
+    }
+    None
+}
+
+async fn spawn_python_zygote(
+    dir: &str,
+    additional_python_paths: &[String],
+    inner_content: &str,
+) -> error::Result<Child> {
+    DirBuilder::new().recursive(true).create(dir).await?;
+    write_file(dir, "zygote.py", PYTHON_ZYGOTE).await?;
+    write_file(dir, "zygote_client.py", PYTHON_ZYGOTE_CLIENT).await?;
+
+    // the top level imports of the first script are the modules worth preloading for the others
+    let modules = TOP_LEVEL_IMPORT_REGEX
+        .captures_iter(inner_content)
+        .map(|x| x[1].to_string())
+        .filter(|x| x != "f" && x != "u")
+        .unique();
+    let zygote_py = format!("{dir}/zygote.py");
+    let socket = format!("{dir}/{ZYGOTE_SOCKET}");
+    let zygote_args = ["-u", zygote_py.as_str(), socket.as_str()]
+        .into_iter()
+        .map(|x| x.to_string())
+        .chain(modules);
+
+    let mut cmd = Command::new(PYTHON_PATH.as_str());
+    // the zygote exits once its stdin is closed, should the worker die without killing it
+    cmd.current_dir(dir)
+        .env_clear()
+        .env("PYTHONPATH", additional_python_paths.join(":"))
+        .env("PATH", PATH_ENV.as_str())
+        .env("TZ", TZ_ENV.as_str())
+        .args(zygote_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true);
+    start_child_process(cmd, PYTHON_PATH.as_str()).await
+}
+
 async fn prepare_wrapper(
     job_dir: &str,
     inner_content: &str,
@@ -602,14 +789,19 @@ pub async fn handle_python_reqs(
     let mut req_paths: Vec<String> = vec![];
     let mut vars = vec![("PATH", PATH_ENV.as_str())];
     let pip_extra_index_url;
+    let pip_mirror_url = offline_pip_index_url().await;
 
     if !*DISABLE_NSJAIL {
         pip_extra_index_url = PIP_EXTRA_INDEX_URL.read().await.clone();
-        if let Some(url) = pip_extra_index_url.as_ref() {
-            vars.push(("EXTRA_INDEX_URL", url));
-        }
-        if let Some(url) = PIP_INDEX_URL.as_ref() {
+        if let Some(url) = pip_mirror_url.as_ref() {
             vars.push(("INDEX_URL", url));
+        } else {
+            if let Some(url) = pip_extra_index_url.as_ref() {
+                vars.push(("EXTRA_INDEX_URL", url));
+            }
+            if let Some(url) = PIP_INDEX_URL.as_ref() {
+                vars.push(("INDEX_URL", url));
+            }
         }
         if let Some(cert_path) = PIP_INDEX_CERT.as_ref() {
             vars.push(("PIP_INDEX_CERT", cert_path));
@@ -650,14 +842,17 @@ pub async fn handle_python_reqs(
         }
 
         #[cfg(feature = "enterprise")]
-        if let Some(ref bucket) = *S3_CACHE_BUCKET {
+        if let Some(cache) = global_cache_backend(db) {
             if matches!(get_license_plan().await, LicensePlan::Pro) {
-                tracing::warn!("S3 cache not available in the pro plan");
+                tracing::warn!("Global cache not available in the pro plan");
             } else {
                 sqlx::query_scalar!("UPDATE queue SET last_ping = now() WHERE id = $1", job_id)
                     .execute(db)
                     .await?;
-                if pull_from_tar(bucket, venv_p.clone()).await.is_ok() {
+                if pull_from_tar(cache, venv_p.clone(), worker_name)
+                    .await
+                    .is_ok()
+                {
                     req_paths.push(venv_p.clone());
                     continue;
                 }
@@ -711,11 +906,15 @@ pub async fn handle_python_reqs(
                 venv_p.as_str(),
             ];
             let pip_extra_index_url = PIP_EXTRA_INDEX_URL.read().await.clone();
-            if let Some(url) = pip_extra_index_url.as_ref() {
-                command_args.extend(["--extra-index-url", url]);
-            }
-            if let Some(url) = PIP_INDEX_URL.as_ref() {
+            if let Some(url) = pip_mirror_url.as_ref() {
                 command_args.extend(["--index-url", url]);
+            } else {
+                if let Some(url) = pip_extra_index_url.as_ref() {
+                    command_args.extend(["--extra-index-url", url]);
+                }
+                if let Some(url) = PIP_INDEX_URL.as_ref() {
+                    command_args.extend(["--index-url", url]);
+                }
             }
             if let Some(cert_path) = PIP_INDEX_CERT.as_ref() {
                 command_args.extend(["--cert", cert_path]);
@@ -775,12 +974,12 @@ pub async fn handle_python_reqs(
         child?;
 
         #[cfg(feature = "enterprise")]
-        if let Some(ref bucket) = *S3_CACHE_BUCKET {
+        if let Some(cache) = global_cache_backend(db) {
             if matches!(get_license_plan().await, LicensePlan::Pro) {
-                tracing::warn!("S3 cache not available in the pro plan");
+                tracing::warn!("Global cache not available in the pro plan");
             } else {
                 let venv_p = venv_p.clone();
-                tokio::spawn(build_tar_and_push(bucket, venv_p));
+                tokio::spawn(build_tar_and_push(cache, venv_p, worker_name.to_string()));
             }
         }
         req_paths.push(venv_p);
@@ -68,29 +68,41 @@ use rand::Rng;
 #[cfg(feature = "enterprise")]
 use crate::global_cache::{
     cache_global, copy_all_piptars_from_bucket, copy_cache_to_tmp_cache,
-    copy_denogo_cache_from_bucket_as_tar, copy_tmp_cache_to_cache,
+    copy_denogo_cache_from_bucket_as_tar, copy_tmp_cache_to_cache, global_cache_backend,
 };
 
 use windmill_queue::{add_completed_job, add_completed_job_error};
 
 use crate::{
-    bash_executor::{handle_bash_job, handle_powershell_job, ANSI_ESCAPE_RE},
+    bash_executor::{
+        handle_bash_job, handle_powershell_job, resolve_bash_requirements,
+        resolve_powershell_modules, ANSI_ESCAPE_RE,
+    },
     bun_executor::{gen_lockfile, get_trusted_deps, handle_bun_job},
-    common::{build_args_map, hash_args, read_result, save_in_cache, write_file},
+    common::{
+        build_args_map, get_cache_key_args, get_cached_result, hash_args, read_result,
+        save_in_cache, write_file,
+    },
     deno_executor::{generate_deno_lock, handle_deno_job},
     go_executor::{handle_go_job, install_go_dependencies},
     graphql_executor::do_graphql,
     js_eval::{eval_fetch_timeout, transpile_ts},
     mysql_executor::do_mysql,
+    network_policy::JobNetwork,
+    package_mirror::{capture_go_artifacts, capture_npm_artifacts, init_package_mirror_url},
     pg_executor::do_postgresql,
     python_executor::{
-        create_dependencies_dir, handle_python_job, handle_python_reqs, pip_compile,
+        capture_pip_artifacts, create_dependencies_dir, handle_python_job, handle_python_reqs,
+        pip_compile,
     },
     worker_flow::{
         handle_flow, update_flow_status_after_job_completion, update_flow_status_in_progress,
     },
 };
 
+#[cfg(feature = "s3")]
+use crate::job_artifacts::{create_artifacts_dir, store_job_artifacts};
+
 #[cfg(feature = "enterprise")]
 use crate::{
     bigquery_executor::do_bigquery, mssql_executor::do_mssql, snowflake_executor::do_snowflake,
@@ -178,12 +190,17 @@ pub const GO_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "go");
 pub const BUN_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "bun");
 pub const HUB_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "hub");
 pub const GO_BIN_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "gobin");
+pub const POWERSHELL_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "powershell");
+pub const BASH_CACHE_DIR: &str = concatcp!(ROOT_CACHE_DIR, "bash");
 
 pub const TAR_PIP_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "tar/pip");
+pub const TAR_PIP_INDEX_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "tar/pip_index");
+pub const TAR_PIP_BLOBS_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "tar/pip_blobs");
 pub const DENO_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "deno");
 pub const BUN_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "bun");
 pub const GO_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "go");
 pub const HUB_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "hub");
+pub const POWERSHELL_TMP_CACHE_DIR: &str = concatcp!(ROOT_TMP_CACHE_DIR, "powershell");
 pub const DENO_TMP_CACHE_DIR_DEPS: &str = concatcp!(ROOT_TMP_CACHE_DIR, "deno/deps");
 pub const DENO_TMP_CACHE_DIR_NPM: &str = concatcp!(ROOT_TMP_CACHE_DIR, "deno/npm");
 const NUM_SECS_PING: u64 = 5;
@@ -232,6 +249,7 @@ lazy_static::lazy_static! {
     pub static ref DENO_PATH: String = std::env::var("DENO_PATH").unwrap_or_else(|_| "/usr/bin/deno".to_string());
     pub static ref BUN_PATH: String = std::env::var("BUN_PATH").unwrap_or_else(|_| "/usr/bin/bun".to_string());
     pub static ref NSJAIL_PATH: String = std::env::var("NSJAIL_PATH").unwrap_or_else(|_| "nsjail".to_string());
+    pub static ref SOCAT_PATH: String = std::env::var("SOCAT_PATH").unwrap_or_else(|_| "socat".to_string());
     pub static ref PATH_ENV: String = std::env::var("PATH").unwrap_or_else(|_| String::new());
     pub static ref HOME_ENV: String = std::env::var("HOME").unwrap_or_else(|_| String::new());
     pub static ref TZ_ENV: String = std::env::var("TZ").unwrap_or_else(|_| String::new());
@@ -243,6 +261,10 @@ lazy_static::lazy_static! {
     pub static ref NPM_CONFIG_REGISTRY: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
     pub static ref PIP_EXTRA_INDEX_URL: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
 
+    pub static ref OFFLINE_MODE: AtomicBool = AtomicBool::new(false);
+    pub static ref MIRROR_CAPTURE: AtomicBool = AtomicBool::new(false);
+    pub static ref PACKAGE_MIRROR_URL: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
+
     pub static ref TAR_CACHE_RATE: i32 = std::env::var("TAR_CACHE_RATE")
         .ok()
         .and_then(|x| x.parse::<i32>().ok())
@@ -286,6 +308,9 @@ lazy_static::lazy_static! {
         .map(|e| Some(e))
         .unwrap_or(None);
 
+    pub static ref GLOBAL_CACHE_BACKEND: Option<String> = std::env::var("GLOBAL_CACHE_BACKEND").ok();
+    pub static ref GLOBAL_CACHE_PATH: Option<String> = std::env::var("GLOBAL_CACHE_PATH").ok();
+
 
     pub static ref EXIT_AFTER_NO_JOB_FOR_SECS: Option<u64> = std::env::var("EXIT_AFTER_NO_JOB_FOR_SECS")
         .ok()
@@ -361,12 +386,20 @@ impl AuthedClient {
         }
     }
 
-    pub async fn get_variable_value(&self, path: &str) -> anyhow::Result<String> {
+    pub async fn get_variable_value(
+        &self,
+        path: &str,
+        job_id: Option<String>,
+    ) -> anyhow::Result<String> {
         let url = format!(
             "{}/api/w/{}/variables/get_value/{}",
             self.base_internal_url, self.workspace, path
         );
-        let response = self.get(&url, vec![]).await?;
+        let mut query = Vec::with_capacity(1usize);
+        if let Some(v) = &job_id {
+            query.push(("job_id", v.to_string()));
+        }
+        let response = self.get(&url, query).await?;
         match response.status().as_u16() {
             200u16 => Ok(response.json::<String>().await?),
             _ => Err(anyhow::anyhow!(response.text().await.unwrap_or_default())),
@@ -551,6 +584,12 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
         );
     }
 
+    if *crate::zygote::ZYGOTE_POOL_SIZE > 0 && !*DISABLE_NSJAIL {
+        tracing::warn!(
+            "ZYGOTE_POOL_SIZE is ignored, zygotes are only used by workers running without nsjail"
+        );
+    }
+
     let start_time = Instant::now();
 
     let worker_dir = format!("{TMP_DIR}/{worker_name}");
@@ -576,6 +615,8 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
     )
     .await;
 
+    init_package_mirror_url(db, base_internal_url).await;
+
     let mut last_ping = Instant::now() - Duration::from_secs(NUM_SECS_PING + 1);
 
     update_ping(worker_instance, &worker_name, ip, db).await;
@@ -848,22 +889,25 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
     #[cfg(feature = "enterprise")]
     let mut handles = Vec::with_capacity(2);
 
+    #[cfg(feature = "enterprise")]
+    let global_cache = global_cache_backend(db);
+
     #[cfg(feature = "enterprise")]
     if i_worker == 1 {
-        if let Some(ref s) = S3_CACHE_BUCKET.clone() {
+        if let Some(ref cache) = global_cache {
             if matches!(get_license_plan().await, LicensePlan::Pro) {
-                tracing::warn!("S3 cache not available in the pro plan");
+                tracing::warn!("Global cache not available in the pro plan");
             } else if crate::global_cache::worker_s3_bucket_sync_enabled(&db).await {
-                let bucket = s.to_string();
+                let cache2 = cache.clone();
                 let worker_name2 = worker_name.clone();
 
                 //piptars can be fetched in background
                 handles.push(tokio::task::spawn(async move {
                     tracing::info!(worker = %worker_name2, "Started initial piptar sync in background");
-                    copy_all_piptars_from_bucket(&bucket).await;
+                    copy_all_piptars_from_bucket(cache2.as_ref(), &worker_name2).await;
                 }));
                 //denogocache.tar need to be fetched in foreground, block workers until they fetched it
-                copy_denogo_cache_from_bucket_as_tar(s).await;
+                copy_denogo_cache_from_bucket_as_tar(cache.as_ref(), &worker_name).await;
             }
         }
     }
@@ -892,31 +936,50 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
 
     #[cfg(feature = "benchmark")]
     {
-        if is_dedicated_worker {
+        if let Some(wp) = WORKER_CONFIG.read().await.dedicated_worker.clone() {
             // you need to create the script first, check https://github.com/windmill-labs/windmill/blob/b76a92cfe454c686f005c65f534e29e039f3c706/benchmarks/lib.ts#L47
-            let hash = sqlx::query_scalar!(
-                "SELECT hash FROM script WHERE path = $1 AND workspace_id = $2",
-                "f/benchmarks/dedicated",
-                "admins"
+            // the jobs target the script the worker is dedicated to and use its language so that
+            // every dedicated worker language can be benchmarked (e.g. DEDICATED_WORKER=admins:f/benchmarks/dedicated_go).
+            // The defaults of the schema are used as args so that sql scripts can get their database resource
+            let (hash, language, schema) = sqlx::query_as::<_, (i64, ScriptLang, Option<serde_json::Value>)>(
+                "SELECT hash, language, schema FROM script WHERE path = $1 AND workspace_id = $2 ORDER BY created_at DESC LIMIT 1",
             )
+            .bind(&wp.path)
+            .bind(&wp.workspace_id)
             .fetch_one(db)
             .await
             .unwrap_or_else(|_e| panic!("failed to insert dedicated jobs"));
-            sqlx::query!("INSERT INTO queue (id, script_hash, script_path, job_kind, language, tag, created_by, permissioned_as, email, scheduled_for, workspace_id) (SELECT gen_random_uuid(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 FROM generate_series(1, $11))",
-                    hash,
-                    "f/benchmarks/dedicated",
-                    JobKind::Script as JobKind,
-                    ScriptLang::Bun as ScriptLang,
-                    "admins:f/benchmarks/dedicated",
-                    "admin",
-                    "u/admin",
-                    "admin@windmill.dev",
-                    chrono::Utc::now(),
-                    "admins",
-                    jobs
-                )
+            let args = schema
+                .as_ref()
+                .and_then(|s| s.get("properties"))
+                .and_then(|p| p.as_object())
+                .map(|props| {
+                    props
+                        .iter()
+                        .filter_map(|(k, v)| {
+                            v.get("default")
+                                .filter(|d| !d.is_null())
+                                .map(|d| (k.clone(), d.clone()))
+                        })
+                        .collect::<serde_json::Map<String, serde_json::Value>>()
+                })
+                .unwrap_or_default();
+            sqlx::query("INSERT INTO queue (id, script_hash, script_path, job_kind, language, tag, created_by, permissioned_as, email, scheduled_for, workspace_id, args) (SELECT gen_random_uuid(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $12 FROM generate_series(1, $11))")
+                .bind(hash)
+                .bind(&wp.path)
+                .bind(JobKind::Script)
+                .bind(language)
+                .bind(format!("{}:{}", wp.workspace_id, wp.path))
+                .bind("admin")
+                .bind("u/admin")
+                .bind("admin@windmill.dev")
+                .bind(chrono::Utc::now())
+                .bind(&wp.workspace_id)
+                .bind(jobs)
+                .bind(sqlx::types::Json(args))
                 .execute(db)
-                .await.unwrap_or_else(|_e| panic!("failed to insert dedicated jobs"));
+                .await
+                .unwrap_or_else(|_e| panic!("failed to insert dedicated jobs"));
         } else {
             sqlx::query!("INSERT INTO queue (id, script_hash, script_path, job_kind, language, tag, created_by, permissioned_as, email, scheduled_for, workspace_id) (SELECT gen_random_uuid(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 FROM generate_series(1, $11))",
             None::<i64>,
@@ -1251,9 +1314,9 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
         }
 
         #[cfg(feature = "enterprise")]
-        if i_worker == 1 && S3_CACHE_BUCKET.is_some() {
+        if i_worker == 1 && global_cache.is_some() {
             if matches!(get_license_plan().await, LicensePlan::Pro) {
-                tracing::warn!("S3 cache not available in the pro plan");
+                tracing::warn!("Global cache not available in the pro plan");
             } else if last_sync.elapsed().as_secs() > *GLOBAL_CACHE_INTERVAL
                 && (copy_cache_from_bucket_handle.is_none()
                     || copy_cache_from_bucket_handle
@@ -1274,9 +1337,11 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
                     if let Err(e) = copy_cache_to_tmp_cache().await {
                         tracing::error!("failed to copy cache to tmp cache: {}", e);
                     } else {
+                        let cache = global_cache.clone();
+                        let worker_name2 = worker_name.clone();
                         copy_cache_from_bucket_handle = Some(tokio::task::spawn(async move {
-                            if let Some(ref s) = S3_CACHE_BUCKET.clone() {
-                                if let Err(e) = cache_global(s, copy_tx).await {
+                            if let Some(cache) = cache {
+                                if let Err(e) = cache_global(cache, copy_tx, &worker_name2).await {
                                     tracing::error!("failed to sync cache: {}", e);
                                 }
                             }
@@ -1466,6 +1531,7 @@ pub async fn run_worker<R: rsmq_async::RsmqConnection + Send + Sync + Clone + 's
                             logs: String::new(),
                             mem_peak: 0,
                             cached_res_path: None,
+                            cache_hit: false,
                             token: "".to_string(),
                             canceled_by: None,
                         })
@@ -1823,7 +1889,6 @@ enum SpawnWorker {
 
 // spawn one dedicated worker and return the key, the channel sender and the join handle
 // note that for it will return none for language that do not support dedicated workers
-// note that go using cache binary does not need dedicated workers so all languages are supported
 async fn spawn_dedicated_worker(
     sw: SpawnWorker,
     w_id: &str,
@@ -1903,10 +1968,21 @@ async fn spawn_dedicated_worker(
         };
 
         match language {
-            Some(ScriptLang::Python3) | Some(ScriptLang::Bun) | Some(ScriptLang::Deno) => {}
+            Some(ScriptLang::Python3)
+            | Some(ScriptLang::Bun)
+            | Some(ScriptLang::Deno)
+            | Some(ScriptLang::Go)
+            | Some(ScriptLang::Postgresql)
+            | Some(ScriptLang::Mysql) => {}
             _ => return None,
         }
 
+        if let Err(e) = JobNetwork::check_dedicated_worker(&db, &w_id, &path).await {
+            tracing::error!("Refusing to start the dedicated worker of {path}: {e}");
+            killpill_tx.send(()).expect("send");
+            return None;
+        }
+
         let handle = tokio::spawn(async move {
             let token = if let Some(token) = JOB_TOKEN.as_ref() {
                 token.clone()
@@ -1985,6 +2061,54 @@ async fn spawn_dedicated_worker(
                     )
                     .await
                 }
+                Some(ScriptLang::Go) => {
+                    crate::go_executor::start_worker(
+                        lock,
+                        &db,
+                        &content,
+                        &base_internal_url,
+                        &job_dir,
+                        &worker_name,
+                        worker_envs,
+                        &w_id,
+                        &path,
+                        &token,
+                        job_completed_tx,
+                        dedicated_worker_rx,
+                        killpill_rx,
+                    )
+                    .await
+                }
+                Some(ScriptLang::Postgresql) => {
+                    crate::pg_executor::start_worker(
+                        &content,
+                        &db,
+                        &base_internal_url,
+                        &job_dir,
+                        &worker_name,
+                        &w_id,
+                        &token,
+                        job_completed_tx,
+                        dedicated_worker_rx,
+                        killpill_rx,
+                    )
+                    .await
+                }
+                Some(ScriptLang::Mysql) => {
+                    crate::mysql_executor::start_worker(
+                        &content,
+                        &db,
+                        &base_internal_url,
+                        &job_dir,
+                        &worker_name,
+                        &w_id,
+                        &token,
+                        job_completed_tx,
+                        dedicated_worker_rx,
+                        killpill_rx,
+                    )
+                    .await
+                }
                 _ => unreachable!("Non supported language for dedicated worker"),
             } {
                 tracing::error!("error in dedicated worker: {:?}", e);
@@ -2067,6 +2191,7 @@ pub async fn process_completed_job<R: rsmq_async::RsmqConnection + Send + Sync +
         mem_peak,
         success,
         cached_res_path,
+        cache_hit,
         canceled_by,
         ..
     }: JobCompleted,
@@ -2102,6 +2227,13 @@ pub async fn process_completed_job<R: rsmq_async::RsmqConnection + Send + Sync +
         .await?;
         timer.map(|x| x.stop_and_record());
 
+        if cache_hit {
+            sqlx::query("UPDATE completed_job SET cache_hit = true WHERE id = $1")
+                .bind(job.id)
+                .execute(db)
+                .await?;
+        }
+
         if job.is_flow_step {
             if let Some(parent_job) = job.parent_job {
                 let timer = worker_flow_transition_duration
@@ -2308,6 +2440,7 @@ pub struct JobCompleted {
     pub mem_peak: i32,
     pub success: bool,
     pub cached_res_path: Option<String>,
+    pub cache_hit: bool,
     pub token: String,
     pub canceled_by: Option<CanceledBy>,
 }
@@ -2330,12 +2463,6 @@ async fn do_nativets(
     Ok((result.0, [logs, result.1].join("\n\n")))
 }
 
-#[derive(Deserialize)]
-struct CachedResource {
-    expire: i64,
-    value: Box<RawValue>,
-}
-
 #[derive(Deserialize, Serialize, Default)]
 pub struct PreviousResult<'a> {
     #[serde(borrow)]
@@ -2397,7 +2524,8 @@ async fn handle_queued_job<R: rsmq_async::RsmqConnection + Send + Sync + Clone>(
         } else {
             "none".to_string()
         };
-        let args_hash = hash_args(&job.args);
+        let cache_key_args = get_cache_key_args(db, &job).await?;
+        let args_hash = hash_args(&job.args, cache_key_args.as_deref());
         if job.is_flow_step {
             let flow_path = sqlx::query_scalar!(
                 "SELECT script_path FROM queue WHERE id = $1",
@@ -2421,34 +2549,25 @@ async fn handle_queued_job<R: rsmq_async::RsmqConnection + Send + Sync + Clone>(
     };
 
     if let Some(cached_res_path) = cached_res_path.clone() {
-        let authed_client = client.get_authed().await;
-        let resource = authed_client
-            .get_resource_value::<CachedResource>(&cached_res_path)
-            .await;
+        if let Some(result) = get_cached_result(db, &job.workspace_id, &cached_res_path).await? {
+            let logs = "Job skipped because args & path found in cache and not expired".to_string();
 
-        if let Ok(resource) = resource {
-            let expire = resource.expire;
-            if expire > chrono::Utc::now().timestamp() {
-                let result = resource.value;
-                let logs =
-                    "Job skipped because args & path found in cache and not expired".to_string();
-
-                job_completed_tx
-                    .send(JobCompleted {
-                        job: job,
-                        result,
-                        logs,
-                        mem_peak: 0,
-                        canceled_by: None,
-                        success: true,
-                        cached_res_path: None,
-                        token: authed_client.token,
-                    })
-                    .await
-                    .expect("send job completed");
+            job_completed_tx
+                .send(JobCompleted {
+                    job: job,
+                    result,
+                    logs,
+                    mem_peak: 0,
+                    canceled_by: None,
+                    success: true,
+                    cached_res_path: None,
+                    cache_hit: true,
+                    token: client.get_token().await,
+                })
+                .await
+                .expect("send job completed");
 
-                return Ok(());
-            }
+            return Ok(());
         }
     };
     match job.job_kind {
@@ -2589,7 +2708,7 @@ async fn handle_queued_job<R: rsmq_async::RsmqConnection + Send + Sync + Clone>(
     Ok(())
 }
 
-async fn process_result(
+pub(crate) async fn process_result(
     job: Arc<QueuedJob>,
     result: error::Result<Box<RawValue>>,
     job_dir: &str,
@@ -2611,6 +2730,7 @@ async fn process_result(
                     canceled_by,
                     success: true,
                     cached_res_path,
+                    cache_hit: false,
                     token: token,
                 })
                 .await
@@ -2654,6 +2774,7 @@ async fn process_result(
                     canceled_by,
                     success: false,
                     cached_res_path,
+                    cache_hit: false,
                     token: token,
                 })
                 .await
@@ -2909,6 +3030,9 @@ mount {{
     // println!("handle lang job {:?}",  SystemTime::now());
 
     let envs = build_envs(envs)?;
+    #[cfg(feature = "s3")]
+    create_artifacts_dir(job_dir).await?;
+    let network = JobNetwork::start(db, job, job_dir, base_internal_url, logs).await?;
 
     let result: error::Result<Box<RawValue>> = match language {
         None => {
@@ -2930,6 +3054,7 @@ mount {{
                 client,
                 &inner_content,
                 &shared_mount,
+                &network,
                 base_internal_url,
                 envs,
             )
@@ -2947,6 +3072,7 @@ mount {{
                 job_dir,
                 &inner_content,
                 base_internal_url,
+                &network,
                 worker_name,
                 envs,
             )
@@ -2962,11 +3088,13 @@ mount {{
                 db,
                 client,
                 job_dir,
+                worker_dir,
                 &inner_content,
                 base_internal_url,
                 worker_name,
                 envs,
                 &shared_mount,
+                &network,
             )
             .await
         }
@@ -2982,6 +3110,7 @@ mount {{
                 job_dir,
                 requirements_o,
                 &shared_mount,
+                &network,
                 base_internal_url,
                 worker_name,
                 envs,
@@ -2997,8 +3126,10 @@ mount {{
                 db,
                 client,
                 &inner_content,
+                requirements_o,
                 job_dir,
                 &shared_mount,
+                &network,
                 base_internal_url,
                 worker_name,
                 envs,
@@ -3014,8 +3145,10 @@ mount {{
                 db,
                 client,
                 &inner_content,
+                requirements_o,
                 job_dir,
                 &shared_mount,
+                &network,
                 base_internal_url,
                 worker_name,
                 envs,
@@ -3024,6 +3157,9 @@ mount {{
         }
         _ => panic!("unreachable, language is not supported: {language:#?}"),
     };
+    network.finish(logs);
+    #[cfg(feature = "s3")]
+    store_job_artifacts(db, client, job, job_dir, logs).await;
     tracing::info!(
         worker_name = %worker_name,
         job_id = %job.id,
@@ -3559,6 +3695,20 @@ async fn handle_app_dependency_job(
     }
 }
 
+fn log_mirror_capture(logs: &mut String, captured: error::Result<usize>) {
+    match captured {
+        Ok(n) => logs.push_str(&format!(
+            "\n{n} new artifacts captured in the package mirror\n"
+        )),
+        Err(e) => {
+            tracing::error!("Failed to capture dependencies in the package mirror: {e}");
+            logs.push_str(&format!(
+                "\nFailed to capture dependencies in the package mirror: {e}\n"
+            ));
+        }
+    }
+}
+
 async fn capture_dependency_job(
     job_id: &Uuid,
     job_language: &ScriptLang,
@@ -3613,11 +3763,27 @@ async fn capture_dependency_job(
                         logs
                     );
                 }
+
+                if MIRROR_CAPTURE.load(Ordering::Relaxed) {
+                    let captured = capture_pip_artifacts(
+                        job_id,
+                        req,
+                        logs,
+                        mem_peak,
+                        canceled_by,
+                        job_dir,
+                        db,
+                        worker_name,
+                        w_id,
+                    )
+                    .await;
+                    log_mirror_capture(logs, captured);
+                }
             }
             req
         }
         ScriptLang::Go => {
-            install_go_dependencies(
+            let req = install_go_dependencies(
                 job_id,
                 job_raw_code,
                 logs,
@@ -3631,7 +3797,11 @@ async fn capture_dependency_job(
                 worker_name,
                 w_id,
             )
-            .await
+            .await?;
+            if MIRROR_CAPTURE.load(Ordering::Relaxed) {
+                log_mirror_capture(logs, capture_go_artifacts(db, job_dir).await);
+            }
+            Ok(req)
         }
         ScriptLang::Deno => {
             generate_deno_lock(
@@ -3668,6 +3838,9 @@ async fn capture_dependency_job(
                 trusted_deps,
             )
             .await?;
+            if MIRROR_CAPTURE.load(Ordering::Relaxed) {
+                log_mirror_capture(logs, capture_npm_artifacts(db, job_dir).await);
+            }
             Ok(req.unwrap_or_else(String::new))
         }
         ScriptLang::Postgresql => Ok("".to_owned()),
@@ -3676,8 +3849,34 @@ async fn capture_dependency_job(
         ScriptLang::Snowflake => Ok("".to_owned()),
         ScriptLang::Mssql => Ok("".to_owned()),
         ScriptLang::Graphql => Ok("".to_owned()),
-        ScriptLang::Bash => Ok("".to_owned()),
-        ScriptLang::Powershell => Ok("".to_owned()),
+        ScriptLang::Bash => {
+            resolve_bash_requirements(
+                job_id,
+                job_raw_code,
+                logs,
+                mem_peak,
+                canceled_by,
+                job_dir,
+                db,
+                worker_name,
+                w_id,
+            )
+            .await
+        }
+        ScriptLang::Powershell => {
+            resolve_powershell_modules(
+                job_id,
+                job_raw_code,
+                logs,
+                mem_peak,
+                canceled_by,
+                job_dir,
+                db,
+                worker_name,
+                w_id,
+            )
+            .await
+        }
         ScriptLang::Nativets => Ok("".to_owned()),
     }
 }
@@ -13,7 +13,7 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::common::{hash_args, save_in_cache};
+use crate::common::{get_cache_key_args, hash_args, save_in_cache};
 use crate::js_eval::{eval_timeout, IdContext};
 use crate::{AuthedClient, PreviousResult, KEEP_JOB_DIR};
 use anyhow::Context;
@@ -633,7 +633,8 @@ pub async fn update_flow_status_after_job_completion_internal<
         } else {
             if flow_job.cache_ttl.is_some() {
                 let cached_res_path = {
-                    let args_hash = hash_args(&flow_job.args);
+                    let cache_key_args = get_cache_key_args(db, &flow_job).await?;
+                    let args_hash = hash_args(&flow_job.args, cache_key_args.as_deref());
                     let flow_path = flow_job.script_path();
                     let version_hash = if let Some(rc) = flow_job.raw_flow.as_ref() {
                         use std::hash::Hasher;
@@ -2390,6 +2391,7 @@ async fn compute_next_flow_transform(
                                         concurrency_time_window_s: None,
                                         skip_expr: None,
                                         cache_ttl: None,
+                                        cache_key_args: None,
                                         priority: None,
                                         early_return: None,
                                     },
@@ -2430,6 +2432,7 @@ async fn compute_next_flow_transform(
                                         concurrency_time_window_s: None,
                                         skip_expr: None,
                                         cache_ttl: None,
+                                        cache_key_args: None,
                                         priority: None,
                                         early_return: None,
                                     },
@@ -2527,6 +2530,7 @@ async fn compute_next_flow_transform(
                             concurrency_time_window_s: None,
                             skip_expr: None,
                             cache_ttl: None,
+                            cache_key_args: None,
                             priority: None,
                             early_return: None,
                         },
@@ -2577,6 +2581,7 @@ async fn compute_next_flow_transform(
                                                     concurrency_time_window_s: None,
                                                     skip_expr: None,
                                                     cache_ttl: None,
+                                                    cache_key_args: None,
                                                     priority: None,
                                                     early_return: None,
                                                 },
@@ -2643,6 +2648,7 @@ async fn compute_next_flow_transform(
                             concurrency_time_window_s: None,
                             skip_expr: None,
                             cache_ttl: None,
+                            cache_key_args: None,
                             priority: None,
                             early_return: None,
                         },
@@ -0,0 +1,186 @@
+/*
+ * Author: Ruben Fiszel
+ * Copyright: Windmill Labs, Inc 2023
+ * This file and its contents are licensed under the AGPLv3 License.
+ * Please see the included NOTICE for copyright information and
+ * LICENSE-AGPL for a copy of the license.
+ */
+
+//! Pool of interpreters kept warm between jobs sharing the same lockfile, to spare them the cold
+//! start of importing their dependencies. Python zygotes fork a process per job, while bun
+//! processes are single use and replaced after each job. The pool is bounded, evicting the least
+//! recently used zygotes first.
+//!
+//! The pool is only used by workers running without nsjail: a zygote is spawned before the jobs
+//! it runs are known, so it cannot be sandboxed to the job dir and the limits of each job.
+//! Workers with nsjail keep spawning a sandboxed interpreter per job.
+
+use std::sync::Mutex;
+
+use tokio::process::Child;
+
+use crate::DISABLE_NSJAIL;
+
+lazy_static::lazy_static! {
+    pub static ref ZYGOTE_POOL_SIZE: usize = std::env::var("ZYGOTE_POOL_SIZE")
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    static ref ZYGOTES: Mutex<ZygotePool> = Mutex::new(ZygotePool::new(*ZYGOTE_POOL_SIZE));
+}
+
+struct Zygote {
+    key: String,
+    dir: String,
+    // killed when evicted from the pool. Its stdin is kept open for the lifetime of the worker,
+    // so that the zygote exits by itself if the worker dies without killing it
+    child: Child,
+}
+
+struct ZygotePool {
+    size: usize,
+    // least recently used first
+    zygotes: Vec<Zygote>,
+}
+
+impl ZygotePool {
+    fn new(size: usize) -> Self {
+        Self { size, zygotes: vec![] }
+    }
+
+    fn find(&mut self, key: &str) -> Option<String> {
+        let i = self.zygotes.iter().position(|z| z.key == key)?;
+        let mut zygote = self.zygotes.remove(i);
+        if !matches!(zygote.child.try_wait(), Ok(None)) {
+            tracing::warn!("zygote {key} exited, it will be spawned again");
+            return None;
+        }
+        let dir = zygote.dir.clone();
+        self.zygotes.push(zygote);
+        Some(dir)
+    }
+
+    fn take(&mut self, key: &str) -> Option<Child> {
+        let i = self.zygotes.iter().position(|z| z.key == key)?;
+        let mut zygote = self.zygotes.remove(i);
+        match zygote.child.try_wait() {
+            Ok(None) => Some(zygote.child),
+            _ => {
+                tracing::warn!("zygote {key} exited before being used");
+                None
+            }
+        }
+    }
+
+    // returns the evicted zygotes, for their dirs to be removed outside of the lock
+    fn add(&mut self, key: String, dir: String, child: Child) -> Vec<Zygote> {
+        self.zygotes.retain(|z| z.key != key);
+        self.zygotes.push(Zygote { key, dir, child });
+        let excess = self.zygotes.len().saturating_sub(self.size);
+        self.zygotes.drain(..excess).collect()
+    }
+}
+
+pub fn zygote_pool_enabled() -> bool {
+    *ZYGOTE_POOL_SIZE > 0 && *DISABLE_NSJAIL
+}
+
+/// Short key of a lockfile, keeping the unix socket paths of the zygotes under their length limit
+pub fn zygote_key(prefix: &str, lockfile: &str) -> String {
+    let hash = windmill_common::utils::calculate_hash(lockfile);
+    format!("{prefix}-{}", &hash[..16])
+}
+
+pub fn zygote_dir(worker_dir: &str, key: &str) -> String {
+    format!("{worker_dir}/zygotes/{key}")
+}
+
+/// Dir of the running zygote of the lockfile, if any, marking it as the most recently used
+pub fn find_zygote(key: &str) -> Option<String> {
+    ZYGOTES.lock().unwrap().find(key)
+}
+
+/// Takes the running zygote of the lockfile out of the pool, for the zygotes that are single use
+pub fn take_zygote(key: &str) -> Option<Child> {
+    ZYGOTES.lock().unwrap().take(key)
+}
+
+/// Adds a zygote to the pool, evicting the least recently used ones beyond the pool size
+pub fn add_zygote(key: String, dir: String, child: Child) {
+    let evicted = ZYGOTES.lock().unwrap().add(key, dir, child);
+    for zygote in evicted {
+        tracing::info!("evicting zygote {} from the pool", zygote.key);
+        remove_zygote_dir(zygote.dir);
+    }
+}
+
+fn remove_zygote_dir(dir: String) {
+    // synchronously, so that the dir is gone before a zygote of the same key can be spawned again
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        tracing::warn!("could not remove zygote dir {dir}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_zygote(cmd: &str) -> Child {
+        tokio::process::Command::new(cmd)
+            .arg("60")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap()
+    }
+
+    fn keys(pool: &ZygotePool) -> Vec<&str> {
+        pool.zygotes.iter().map(|z| z.key.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let mut pool = ZygotePool::new(2);
+        for key in ["a", "b"] {
+            assert!(pool
+                .add(key.to_string(), format!("dir_{key}"), spawn_zygote("sleep"))
+                .is_empty());
+        }
+        // a is used again so b is the least recently used one
+        assert_eq!(pool.find("a"), Some("dir_a".to_string()));
+
+        let evicted = pool.add("c".to_string(), "dir_c".to_string(), spawn_zygote("sleep"));
+        assert_eq!(
+            evicted.iter().map(|z| z.key.as_str()).collect::<Vec<_>>(),
+            vec!["b"]
+        );
+        assert_eq!(keys(&pool), vec!["a", "c"]);
+        assert_eq!(pool.find("b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reuses_live_zygote() {
+        let mut pool = ZygotePool::new(2);
+        pool.add("a".to_string(), "dir_a".to_string(), spawn_zygote("sleep"));
+
+        // a live zygote is reused as long as it runs
+        assert_eq!(pool.find("a"), Some("dir_a".to_string()));
+        assert_eq!(pool.find("a"), Some("dir_a".to_string()));
+        assert_eq!(keys(&pool), vec!["a"]);
+
+        // single use zygotes leave the pool once taken
+        assert!(pool.take("a").is_some());
+        assert!(pool.take("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drops_exited_zygote() {
+        let mut pool = ZygotePool::new(2);
+        let mut child = spawn_zygote("true");
+        child.wait().await.unwrap();
+        pool.add("a".to_string(), "dir_a".to_string(), child);
+
+        assert_eq!(pool.find("a"), None);
+        assert!(keys(&pool).is_empty());
+    }
+}
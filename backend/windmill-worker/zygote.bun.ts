@@ -0,0 +1,35 @@
+// Warm bun process for the jobs sharing a lockfile: it imports their dependencies ahead of time,
+// then waits on its stdin for the job to run. Bun cannot fork, so it runs a single job and the
+// worker spawns another one for the next job.
+import { readFileSync } from "fs";
+import { createInterface } from "readline";
+
+const { dependencies } = JSON.parse(readFileSync("package.json", "utf8"));
+for (const dep of Object.keys(dependencies ?? {})) {
+  try {
+    await import(dep);
+  } catch {}
+}
+console.log("ZYGOTE_READY");
+
+const lines = createInterface({ input: process.stdin });
+const line: string | undefined = await new Promise((resolve) => {
+  lines.once("line", resolve);
+  lines.once("close", () => resolve(undefined));
+});
+// the worker closed stdin without sending a job
+if (line === undefined) {
+  process.exit(0);
+}
+lines.close();
+
+const { cwd, env } = JSON.parse(line);
+process.chdir(cwd);
+for (const key of Object.keys(process.env)) {
+  if (!(key in env)) {
+    delete process.env[key];
+  }
+}
+Object.assign(process.env, env);
+await import(`${cwd}/loader.bun.ts`);
+await import(`${cwd}/wrapper.ts`);
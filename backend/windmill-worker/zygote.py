@@ -0,0 +1,130 @@
+# Zygote of the python jobs sharing a lockfile: it imports their dependencies once, then forks a
+# process per job. Jobs are submitted by zygote_client.py, which hands over its stdio so that the
+# logs of the job flow to the worker as usual, and exits with the exit code of the job.
+# The worker holds the write end of the stdin of the zygote, which exits once it is closed.
+import json
+import os
+import select
+import signal
+import socket
+import sys
+import traceback
+
+MAX_REQUEST_SIZE = 16 * 1024 * 1024
+
+
+def preload(modules):
+    for module in modules:
+        try:
+            __import__(module)
+        except BaseException as e:
+            print(f"could not preload {module}: {e}", file=sys.stderr)
+
+
+def read_request(conn):
+    data, fds, _, _ = socket.recv_fds(conn, 65536, 3)
+    while not data.endswith(b"\n"):
+        chunk = conn.recv(65536)
+        if not chunk or len(data) > MAX_REQUEST_SIZE:
+            raise ValueError("incomplete job request")
+        data += chunk
+    if len(fds) != 3:
+        raise ValueError("job request without stdio")
+    return json.loads(data), fds
+
+
+def run_job(listener, conn, request, fds):
+    os.chdir(request["cwd"])
+    os.environ.clear()
+    os.environ.update(request["env"])
+    for target, fd in enumerate(fds):
+        os.dup2(fd, target)
+        os.close(fd)
+    listener.close()
+    conn.close()
+    signal.signal(signal.SIGTERM, signal.SIG_DFL)
+    sys.path.insert(0, request["cwd"])
+    sys.argv = [request["module"]]
+    code = 0
+    try:
+        import runpy
+
+        runpy.run_module(request["module"], run_name="__main__", alter_sys=True)
+    except SystemExit as e:
+        code = e.code if isinstance(e.code, int) else (0 if e.code is None else 1)
+    except BaseException:
+        traceback.print_exc()
+        code = 1
+    try:
+        sys.stdout.flush()
+        sys.stderr.flush()
+    finally:
+        os._exit(code)
+
+
+def wait_job(conn, pid):
+    """Waits for the job to exit, killing it if the client disconnects first"""
+    try:
+        pidfd = os.pidfd_open(pid)
+    except (AttributeError, OSError):
+        pidfd = None
+    while True:
+        readable, _, _ = select.select(
+            [conn] + ([pidfd] if pidfd is not None else []), [], [], 0.01 if pidfd is None else None
+        )
+        done, status = os.waitpid(pid, os.WNOHANG)
+        if done:
+            break
+        if conn in readable and not conn.recv(1):
+            os.kill(pid, signal.SIGKILL)
+            os.waitpid(pid, 0)
+            status = None
+            break
+    if pidfd is not None:
+        os.close(pidfd)
+    return None if status is None else os.waitstatus_to_exitcode(status)
+
+
+def main():
+    socket_path, modules = sys.argv[1], sys.argv[2:]
+    preload(modules)
+
+    # the socket is only renamed to its path once listening, so that clients never get refused
+    listener = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    tmp_path = f"{socket_path}.tmp"
+    for path in (socket_path, tmp_path):
+        if os.path.exists(path):
+            os.unlink(path)
+    listener.bind(tmp_path)
+    os.chmod(tmp_path, 0o777)
+    listener.listen(8)
+    os.rename(tmp_path, socket_path)
+
+    while True:
+        readable, _, _ = select.select([listener, sys.stdin], [], [])
+        if sys.stdin in readable and not os.read(sys.stdin.fileno(), 1):
+            os.unlink(socket_path)
+            return
+        if listener not in readable:
+            continue
+        conn, _ = listener.accept()
+        try:
+            request, fds = read_request(conn)
+            sys.stdout.flush()
+            sys.stderr.flush()
+            pid = os.fork()
+            if pid == 0:
+                run_job(listener, conn, request, fds)
+            for fd in fds:
+                os.close(fd)
+            code = wait_job(conn, pid)
+            if code is not None:
+                conn.sendall(json.dumps({"exit": code}).encode() + b"\n")
+        except Exception:
+            traceback.print_exc()
+        finally:
+            conn.close()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,23 @@
+# Submits the job of its working directory to a zygote and exits with the exit code of the job.
+# Its stdio is handed over to the process forked for the job. If it is killed, the zygote notices
+# the closed connection and kills the job.
+import json
+import os
+import socket
+import sys
+
+socket_path, module = sys.argv[1], sys.argv[2]
+conn = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+conn.connect(socket_path)
+request = {"cwd": os.getcwd(), "env": dict(os.environ), "module": module}
+socket.send_fds(conn, [json.dumps(request).encode() + b"\n"], [0, 1, 2])
+
+response = b""
+while not response.endswith(b"\n"):
+    chunk = conn.recv(4096)
+    if not chunk:
+        print("the zygote exited before the job completed", file=sys.stderr)
+        sys.exit(1)
+    response += chunk
+code = json.loads(response)["exit"]
+sys.exit(code if code >= 0 else 128 - code)
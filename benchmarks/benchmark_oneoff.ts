@@ -119,7 +119,7 @@ export async function main({
     return completedJobs - pastJobs;
   }
 
-  if (["deno", "python", "go", "bash", "dedicated", "bun", "nativets"].includes(kind)) {
+  if (["deno", "python", "go", "bash", "dedicated", "dedicated_go", "bun", "nativets"].includes(kind)) {
     await createBenchScript(kind, workspace);
   }
 
@@ -135,7 +135,7 @@ export async function main({
       kind: "noop",
     });
   } else if (
-    ["deno", "python", "go", "bash", "dedicated", "bun", "nativets"].includes(kind)
+    ["deno", "python", "go", "bash", "dedicated", "dedicated_go", "bun", "nativets"].includes(kind)
   ) {
     body = JSON.stringify({
       kind: "script",
@@ -295,7 +295,7 @@ if (import.meta.main) {
     )
     .option(
       "--kind <kind:string>",
-      "Specifiy the benchmark kind among: deno, identity, python, go, bash, dedicated, bun, noop, 2steps, nativets",
+      "Specifiy the benchmark kind among: deno, identity, python, go, bash, dedicated, dedicated_go, bun, noop, 2steps, nativets",
       {
         required: true,
       }
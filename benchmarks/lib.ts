@@ -84,6 +84,13 @@ export async function createBenchScript(
     schemaProperties = {
       uuid: { default: null, description: "", type: "string" },
     };
+  } else if (scriptPattern === "dedicated_go") {
+    scriptContent =
+      "package inner\nfunc main(uuid string) (string, error) { return uuid, nil }";
+    language = "go";
+    schemaProperties = {
+      uuid: { default: null, description: "", type: "string" },
+    };
   } else if (scriptPattern === "deno") {
     scriptContent =
       'export function main(){ return Deno.env.get("WM_JOB_ID"); }';
@@ -106,7 +113,7 @@ export async function createBenchScript(
       summary: scriptPattern + " benchmark",
       description: "",
       language: language as api.NewScript.language,
-      dedicated_worker: scriptPattern === "dedicated",
+      dedicated_worker: scriptPattern.startsWith("dedicated"),
       schema: {
         $schema: "https://json-schema.org/draft/2020-12/schema",
         properties: schemaProperties,
@@ -120,7 +127,7 @@ export async function createBenchScript(
 
   console.log("Created benchmark script at path", path);
 
-  if (scriptPattern === "dedicated") {
+  if (scriptPattern.startsWith("dedicated")) {
     await waitForDedicatedWorker(workspace, path);
   }
 }
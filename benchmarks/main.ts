@@ -175,7 +175,7 @@ export async function main({
   if (
     !useFlows &&
     (scriptPattern === undefined ||
-      ["deno", "python", "go", "bash", "bun", "dedicated"].includes(
+      ["deno", "python", "go", "bash", "bun", "dedicated", "dedicated_go"].includes(
         scriptPattern
       ))
   ) {
@@ -478,7 +478,7 @@ if (import.meta.main) {
     )
     .option(
       "--script-pattern <pattern:string>",
-      "Use a different script pattern among: deno, identity, python, go, bash, dedicated, bun (Default deno)"
+      "Use a different script pattern among: deno, identity, python, go, bash, dedicated, dedicated_go, bun (Default deno)"
     )
     .option("--custom <custom_path:string>", "Use custom actions during bench")
     .option(